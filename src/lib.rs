@@ -1,21 +1,28 @@
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
 use std::future::Future;
-use std::io::{Read, Write};
+use std::io::{BufRead, BufReader, Read, Write};
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::sync::{
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicBool, AtomicU64, Ordering},
     Arc, Mutex, OnceLock,
 };
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use agent_client_protocol as acp;
+use encoding_rs::{Encoding, UTF_8};
+use futures_util::stream::{SplitSink, SplitStream};
 use futures_util::{SinkExt, StreamExt};
 use portable_pty::{native_pty_system, CommandBuilder, PtySize};
+use regex::Regex;
 use serde_json::{json, Map, Value};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::{mpsc, oneshot, Mutex as TokioMutex};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::sync::{mpsc, oneshot, watch, Mutex as TokioMutex};
 use tokio::task::JoinHandle;
 use tokio::time::timeout;
 use tokio_tungstenite::tungstenite::handshake::server::{
@@ -23,15 +30,546 @@ use tokio_tungstenite::tungstenite::handshake::server::{
 };
 use tokio_tungstenite::tungstenite::http::header::{HeaderValue, ORIGIN, SEC_WEBSOCKET_PROTOCOL};
 use tokio_tungstenite::tungstenite::http::{Response as HttpResponse, StatusCode};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
 use tokio_tungstenite::tungstenite::protocol::Message;
-use tokio_tungstenite::{accept_hdr_async, tungstenite, WebSocketStream};
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, WebSocketConfig};
+use tokio_tungstenite::{accept_hdr_async_with_config, tungstenite, WebSocketStream};
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct BridgeConfig {
-    pub bind_addr: SocketAddr,
+    pub bind_addrs: Vec<SocketAddr>,
+    /// Ports tried in order, on the same IP, when a `bind_addrs` entry's
+    /// port is already in use.
+    pub fallback_ports: Vec<u16>,
+    /// How many more rounds of binding to attempt, `bind_retry_delay` apart,
+    /// while every candidate port is in use (e.g. lingering in `TIME_WAIT`).
+    pub bind_retries: u32,
+    pub bind_retry_delay: Duration,
     pub allowed_origins: Vec<String>,
+    /// Decides which `Origin`s may connect in place of `allowed_origins`,
+    /// for checks a static list cannot express.
+    pub origin_validator: Option<OriginValidator>,
+    /// Accept handshakes with no `Origin` header at all, as sent by native
+    /// (non-browser) clients. An `Origin` that is present must still be
+    /// allow-listed.
+    pub allow_missing_origin: bool,
     pub expected_subprotocol: String,
+    /// Further subprotocols accepted alongside `expected_subprotocol`. The
+    /// bridge echoes the first protocol in the client's offer that it
+    /// supports, so the client's preference order wins.
+    pub additional_subprotocols: Vec<String>,
+    /// Match offered subprotocols exactly, as RFC 6455 tokens are
+    /// case-sensitive. Turning this off also accepts mis-cased offers, which
+    /// are echoed back as the client spelled them.
+    pub subprotocol_case_sensitive: bool,
+    /// Which handshake check runs first, and so which rejection a client
+    /// failing both of them sees.
+    pub handshake_check_order: HandshakeCheckOrder,
+    /// Reported as `bridgeId` in response `_meta` so clients can tell
+    /// bridges apart. Must not be empty; `with_generated_id` fills in a
+    /// fresh UUID.
     pub bridge_id: String,
+    pub duplicate_session_policy: DuplicateSessionPolicy,
+    /// `protocolVersion`s a client may request in `initialize`. Any other is
+    /// refused with the supported list instead of reaching the agent; empty
+    /// accepts every version. Advertised as `supportedProtocolVersions` in
+    /// the `initialize` `_meta` and in `bridge/capabilities`.
+    pub supported_protocol_versions: Vec<acp::ProtocolVersion>,
+    /// Largest incoming WebSocket message accepted; bigger ones close the
+    /// connection. `None` accepts messages of any size.
+    pub max_message_bytes: Option<usize>,
+    /// Upper bound for whole-file `fs/read_text_file` reads; ranged reads
+    /// stream and are not subject to it. `None` disables the check.
+    pub max_read_bytes: Option<u64>,
+    /// Files `fs/read_text_file` keeps in memory and serves again while
+    /// their mtime and size are unchanged. Only whole-file reads use it, and
+    /// only for files within both `max_read_bytes` and 1 MiB. 0 disables the
+    /// cache.
+    pub read_cache_capacity: usize,
+    /// Most paths one `fs/read_text_files` call may ask for.
+    pub max_read_batch: usize,
+    /// Largest `fs/write_text_file` content accepted, in bytes; checked
+    /// before any permission prompt. `None` disables the check.
+    pub max_write_bytes: Option<u64>,
+    /// Let `fs/write_text_file` change registered `mem://` files, without a
+    /// permission prompt. Off by default: virtual files belong to the agent
+    /// and clients may only read them.
+    pub allow_virtual_writes: bool,
+    /// Write to a temporary file beside the target and rename it into place,
+    /// so a failed write never leaves a partial file.
+    pub atomic_writes: bool,
+    /// JSON-RPC code for a write denied once. A standing denial uses the next
+    /// code down and a cancelled prompt the one after that, so the default
+    /// `-32010` yields -32010, -32011 and -32012.
+    pub permission_error_code_base: i32,
+    /// Lines longer than this are cut short in `fs/read_text_file` results and
+    /// reported via `truncatedLines`. `None` returns lines whole.
+    pub max_line_bytes: Option<usize>,
+    /// Longest `session/prompt` accepted, in chars summed across all text
+    /// blocks. `None` forwards prompts of any length.
+    pub max_prompt_chars: Option<usize>,
+    /// Working directory of this bridge: the project root when
+    /// `project_roots` is empty, and where the login CLI runs. Empty means
+    /// the process working directory.
+    pub project_root: PathBuf,
+    /// Directories walked by tree-wide methods such as `project/recent_files`.
+    /// Empty means `project_root`.
+    pub project_roots: Vec<PathBuf>,
+    /// Project roots for connections from a given (allowed) `Origin`, used
+    /// instead of `project_roots` for every fs method on those connections.
+    pub origin_project_roots: HashMap<String, Vec<PathBuf>>,
+    /// Directories `fs/write_text_file` may write under, enforced instead of
+    /// the project roots for writes only. Empty means the project roots.
+    pub write_roots: Vec<PathBuf>,
+    /// Rewrites absolute paths in error messages and `data` sent to clients:
+    /// relative to a project root when under one, `<path>` otherwise.
+    pub redact_paths: bool,
+    /// Consulted before every request is served.
+    pub request_policy: Arc<dyn RequestPolicy>,
+    /// Extra methods served over the same socket, keyed by method name.
+    /// Built-in methods always win; these only answer names the bridge
+    /// would otherwise reject as not found.
+    pub method_handlers: HashMap<String, Arc<dyn MethodHandler>>,
+    /// File-name globs (`*` and `?`) skipped during tree walks.
+    pub walk_ignore: Vec<String>,
+    /// Maximum number of directory entries a single walk visits.
+    pub max_walk_entries: usize,
+    /// Descend into symlinked directories during tree walks. Loops and links
+    /// that leave the project roots are skipped.
+    pub follow_symlinks: bool,
+    /// Regex that every incoming `sessionId` must match in full. `None`
+    /// accepts any id.
+    pub session_id_pattern: Option<String>,
+    /// Also print the startup summary as a single JSON line on stdout, for
+    /// supervisors that wait on structured readiness output.
+    pub startup_json_line: bool,
+    /// Who answers the permission prompts that gate writes.
+    pub permission_target: PermissionTarget,
+    /// What those prompts offer for single-file writes.
+    pub permission_options: PermissionOptions,
+    /// Keeps remembered permission decisions across restarts. `None` keeps
+    /// them in memory only.
+    pub permission_store: Option<Arc<dyn PermissionStore>>,
+    /// Refuse `fs/write_text_file` without a `sessionId`. Turning this off
+    /// suits trusted single-user setups: such writes are answered with
+    /// `sessionless_write_decision` instead of a prompt.
+    pub require_session_for_write: bool,
+    /// The answer for sessionless writes to paths with no remembered
+    /// decision; a remembered one still wins.
+    pub sessionless_write_decision: PermissionDecision,
+    /// How long `initialize`, `session/new`, `session/prompt` and
+    /// `session/set_mode` wait on the transport before failing with an
+    /// "agent timeout" (-32008) error. `None` waits indefinitely.
+    pub agent_call_timeout: Option<Duration>,
+    /// Replaces `agent_call_timeout`, and any per-connection override of it,
+    /// for `initialize` alone.
+    pub initialize_timeout: Option<Duration>,
+    /// Replaces `agent_call_timeout`, and any per-connection override of it,
+    /// for `session/new` alone.
+    pub new_session_timeout: Option<Duration>,
+    /// Replaces `agent_call_timeout`, and any per-connection override of it,
+    /// for `session/prompt` alone; `prompt_timeout_mode` says what it bounds.
+    pub prompt_timeout: Option<Duration>,
+    pub prompt_timeout_mode: PromptTimeoutMode,
+    /// Ceiling applied to per-connection `agentCallTimeoutMs` overrides sent
+    /// in the `initialize` request's `_meta`.
+    pub max_agent_call_timeout: Duration,
+    /// Send a `session/update` with a `{"type": "heartbeat"}` update whenever
+    /// a running prompt has produced no notification for this long, so
+    /// clients can tell a thinking agent from a hung one. `None` sends none.
+    pub prompt_heartbeat_interval: Option<Duration>,
+    /// Close a connection once nothing has crossed it, in either direction,
+    /// for this long. Clients can stay connected by pinging, and silent
+    /// prompts by `prompt_heartbeat_interval`. `None` never closes one.
+    pub connection_idle_timeout: Option<Duration>,
+    /// Notifications each connection queues while its client is slow to read.
+    /// Must be at least 1.
+    pub notification_queue_capacity: usize,
+    /// What pushing onto a full notification queue does.
+    pub notification_overflow: NotificationOverflow,
+    /// Serve `bridge/reset_permission`, which lets clients drop remembered
+    /// permission decisions.
+    pub enable_permission_reset: bool,
+    /// Answer `bridge/ping` liveness probes, which work before `initialize`.
+    pub enable_ping: bool,
+    /// Let `bridge/list_sessions` report the sessions of every connection
+    /// rather than only the caller's own.
+    pub list_all_sessions: bool,
+    /// Serve `auth/cli_login` and advertise it as the `cli_login` entry in
+    /// the `initialize` response's `authMethods`.
+    pub enable_cli_login: bool,
+    /// Finds the CLI driven by `auth/cli_login`.
+    pub cli_locator: Arc<dyn CliLocator>,
+    /// Input `auth/cli_login` types into the login CLI until the login URL
+    /// appears; `None` leaves the PTY untouched.
+    pub login_automation: Option<LoginAutomation>,
+}
+
+impl BridgeConfig {
+    /// The default configuration with a random UUID as its `bridge_id`.
+    pub fn with_generated_id() -> Self {
+        Self {
+            bridge_id: uuid::Uuid::new_v4().to_string(),
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for BridgeConfig {
+    fn default() -> Self {
+        Self {
+            bind_addrs: vec![SocketAddr::from(([127, 0, 0, 1], 0))],
+            fallback_ports: Vec::new(),
+            bind_retries: 0,
+            bind_retry_delay: Duration::from_millis(100),
+            allowed_origins: Vec::new(),
+            origin_validator: None,
+            allow_missing_origin: false,
+            expected_subprotocol: "acp.jsonrpc.v1".to_string(),
+            additional_subprotocols: Vec::new(),
+            subprotocol_case_sensitive: true,
+            handshake_check_order: HandshakeCheckOrder::default(),
+            bridge_id: String::new(),
+            duplicate_session_policy: DuplicateSessionPolicy::default(),
+            supported_protocol_versions: vec![acp::VERSION],
+            max_message_bytes: Some(64 << 20),
+            max_read_bytes: None,
+            read_cache_capacity: 0,
+            max_read_batch: 64,
+            max_write_bytes: None,
+            allow_virtual_writes: false,
+            atomic_writes: false,
+            permission_error_code_base: -32010,
+            max_line_bytes: None,
+            max_prompt_chars: None,
+            project_root: PathBuf::new(),
+            project_roots: Vec::new(),
+            origin_project_roots: HashMap::new(),
+            write_roots: Vec::new(),
+            redact_paths: false,
+            request_policy: Arc::new(AllowAllPolicy),
+            method_handlers: HashMap::new(),
+            walk_ignore: vec![".git".into(), "node_modules".into(), "target".into()],
+            max_walk_entries: 10_000,
+            follow_symlinks: false,
+            session_id_pattern: None,
+            startup_json_line: false,
+            permission_target: PermissionTarget::default(),
+            permission_options: PermissionOptions::default(),
+            permission_store: None,
+            require_session_for_write: true,
+            sessionless_write_decision: PermissionDecision::AllowAlways,
+            agent_call_timeout: None,
+            initialize_timeout: None,
+            new_session_timeout: None,
+            prompt_timeout: None,
+            prompt_timeout_mode: PromptTimeoutMode::default(),
+            max_agent_call_timeout: Duration::from_secs(600),
+            prompt_heartbeat_interval: None,
+            connection_idle_timeout: None,
+            notification_queue_capacity: 256,
+            notification_overflow: NotificationOverflow::default(),
+            enable_permission_reset: true,
+            enable_ping: true,
+            list_all_sessions: false,
+            enable_cli_login: true,
+            cli_locator: Arc::new(ClaudeCliLocator),
+            login_automation: Some(LoginAutomation::default()),
+        }
+    }
+}
+
+impl std::fmt::Debug for BridgeConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BridgeConfig")
+            .field("bind_addrs", &self.bind_addrs)
+            .field("fallback_ports", &self.fallback_ports)
+            .field("bind_retries", &self.bind_retries)
+            .field("bind_retry_delay", &self.bind_retry_delay)
+            .field("allowed_origins", &self.allowed_origins)
+            .field("origin_validator", &self.origin_validator.is_some())
+            .field("allow_missing_origin", &self.allow_missing_origin)
+            .field("expected_subprotocol", &self.expected_subprotocol)
+            .field("additional_subprotocols", &self.additional_subprotocols)
+            .field(
+                "subprotocol_case_sensitive",
+                &self.subprotocol_case_sensitive,
+            )
+            .field("handshake_check_order", &self.handshake_check_order)
+            .field("bridge_id", &self.bridge_id)
+            .field("duplicate_session_policy", &self.duplicate_session_policy)
+            .field(
+                "supported_protocol_versions",
+                &self.supported_protocol_versions,
+            )
+            .field("max_message_bytes", &self.max_message_bytes)
+            .field("max_read_bytes", &self.max_read_bytes)
+            .field("read_cache_capacity", &self.read_cache_capacity)
+            .field("max_read_batch", &self.max_read_batch)
+            .field("max_write_bytes", &self.max_write_bytes)
+            .field("allow_virtual_writes", &self.allow_virtual_writes)
+            .field("atomic_writes", &self.atomic_writes)
+            .field(
+                "permission_error_code_base",
+                &self.permission_error_code_base,
+            )
+            .field("max_line_bytes", &self.max_line_bytes)
+            .field("max_prompt_chars", &self.max_prompt_chars)
+            .field("project_root", &self.project_root)
+            .field("project_roots", &self.project_roots)
+            .field("origin_project_roots", &self.origin_project_roots)
+            .field("write_roots", &self.write_roots)
+            .field("redact_paths", &self.redact_paths)
+            .field(
+                "method_handlers",
+                &self.method_handlers.keys().collect::<Vec<_>>(),
+            )
+            .field("walk_ignore", &self.walk_ignore)
+            .field("max_walk_entries", &self.max_walk_entries)
+            .field("follow_symlinks", &self.follow_symlinks)
+            .field("session_id_pattern", &self.session_id_pattern)
+            .field("startup_json_line", &self.startup_json_line)
+            .field("permission_target", &self.permission_target)
+            .field("permission_options", &self.permission_options)
+            .field("require_session_for_write", &self.require_session_for_write)
+            .field(
+                "sessionless_write_decision",
+                &self.sessionless_write_decision,
+            )
+            .field("agent_call_timeout", &self.agent_call_timeout)
+            .field("initialize_timeout", &self.initialize_timeout)
+            .field("new_session_timeout", &self.new_session_timeout)
+            .field("prompt_timeout", &self.prompt_timeout)
+            .field("prompt_timeout_mode", &self.prompt_timeout_mode)
+            .field("max_agent_call_timeout", &self.max_agent_call_timeout)
+            .field("prompt_heartbeat_interval", &self.prompt_heartbeat_interval)
+            .field("connection_idle_timeout", &self.connection_idle_timeout)
+            .field(
+                "notification_queue_capacity",
+                &self.notification_queue_capacity,
+            )
+            .field("notification_overflow", &self.notification_overflow)
+            .field("enable_permission_reset", &self.enable_permission_reset)
+            .field("enable_ping", &self.enable_ping)
+            .field("list_all_sessions", &self.list_all_sessions)
+            .field("enable_cli_login", &self.enable_cli_login)
+            .field("login_automation", &self.login_automation)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Consulted with each handshake's `Origin` header; `true` lets it connect.
+pub type OriginValidator = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+/// What the handshake established about a connection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// The validated `Origin` header; `None` only when
+    /// `allow_missing_origin` let a client in without one.
+    pub origin: Option<String>,
+    /// The negotiated `Sec-WebSocket-Protocol`.
+    pub subprotocol: String,
+    /// The client's TCP address, for auditing and per-IP rules; `None` for
+    /// clients that came in over a Unix socket.
+    pub peer_addr: Option<SocketAddr>,
+}
+
+/// Decides whether a connection may make a request, so embedders can apply
+/// per-origin rules such as read-only clients.
+pub trait RequestPolicy: Send + Sync {
+    fn check(&self, connection: &ConnectionInfo, method: &str) -> Result<(), acp::Error>;
+}
+
+/// Allows every request.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AllowAllPolicy;
+
+impl RequestPolicy for AllowAllPolicy {
+    fn check(&self, _connection: &ConnectionInfo, _method: &str) -> Result<(), acp::Error> {
+        Ok(())
+    }
+}
+
+/// Denies `fs/write_text_file` to connections from the listed origins.
+#[derive(Clone, Debug, Default)]
+pub struct ReadOnlyOrigins {
+    pub origins: Vec<String>,
+}
+
+impl RequestPolicy for ReadOnlyOrigins {
+    fn check(&self, connection: &ConnectionInfo, method: &str) -> Result<(), acp::Error> {
+        let read_only = connection
+            .origin
+            .as_ref()
+            .is_some_and(|origin| self.origins.contains(origin));
+        if method == "fs/write_text_file" && read_only {
+            return Err(permission_error(
+                ErrorKind::PermissionDenied,
+                "origin is read-only",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Serves a method the embedder adds through `BridgeConfig::method_handlers`.
+/// Returns the JSON-RPC result, or the error to answer with.
+pub trait MethodHandler: Send + Sync {
+    /// `connection` describes the calling client; the returned future must
+    /// copy whatever it needs from it.
+    fn handle(
+        &self,
+        connection: &ConnectionInfo,
+        params: Value,
+    ) -> Pin<Box<dyn Future<Output = Result<Value, acp::Error>> + Send>>;
+}
+
+/// A command `auth/cli_login` runs in a pseudo-terminal; it is expected to
+/// print a login URL.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoginCommand {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+}
+
+/// Input written to the login CLI's PTY at a fixed interval to advance its
+/// prompts. The default presses Enter every 250ms, which is what Claude
+/// Code's `/login` flow needs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoginAutomation {
+    pub input: Vec<u8>,
+    pub interval: Duration,
+}
+
+impl Default for LoginAutomation {
+    fn default() -> Self {
+        Self {
+            input: b"\r".to_vec(),
+            interval: Duration::from_millis(250),
+        }
+    }
+}
+
+/// Locates the login CLI for the agent the bridge fronts, so embedders can
+/// swap the Claude-specific discovery for their own.
+pub trait CliLocator: Send + Sync {
+    fn locate(&self) -> Result<LoginCommand, acp::Error>;
+}
+
+/// Finds Claude Code via the `TEST_CLAUDE_CLI_PATH` and `CLAUDE_ACP_BIN`
+/// overrides, installed node_modules, or `claude` on `PATH`, and runs
+/// `/login`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ClaudeCliLocator;
+
+impl CliLocator for ClaudeCliLocator {
+    fn locate(&self) -> Result<LoginCommand, acp::Error> {
+        let (program, mut args) = resolve_claude_login_command()?;
+        args.push("/login".to_string());
+        Ok(LoginCommand { program, args })
+    }
+}
+
+/// How `session/new` reacts when the transport hands back a session id the
+/// bridge is already tracking.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateSessionPolicy {
+    /// Fail the request with an internal error so the transport bug surfaces.
+    #[default]
+    Reject,
+    /// Hand the client a bridge-generated alias that maps back to the agent's id.
+    Alias,
+}
+
+/// The order of the `Origin` and subprotocol checks during the WebSocket
+/// handshake. A client failing both only learns about the first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HandshakeCheckOrder {
+    /// Reject disallowed origins with 403 before looking at the subprotocol.
+    #[default]
+    OriginFirst,
+    /// Reject a missing subprotocol with 426 first, so clients without it
+    /// cannot tell whether an origin check exists.
+    SubprotocolFirst,
+}
+
+/// What a connection's notification queue does with a new notification when
+/// the client has fallen `notification_queue_capacity` behind.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NotificationOverflow {
+    /// Wait for room, holding up whatever is producing the notifications.
+    #[default]
+    Block,
+    /// Discard the oldest queued notification to make room.
+    DropOldest,
+    /// Fail the send with an internal error.
+    Error,
+}
+
+/// What the prompt timeout measures.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PromptTimeoutMode {
+    /// The whole prompt, however much it streams along the way.
+    #[default]
+    Overall,
+    /// The longest gap between updates from the agent. Bridge heartbeats do
+    /// not count, or they would keep a hung agent alive.
+    Idle,
+}
+
+/// Where write permission prompts are sent.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PermissionTarget {
+    /// Ask the agent through `AgentTransport::request_permission`.
+    #[default]
+    Agent,
+    /// Send `session/request_permission` to the connected WebSocket client and
+    /// wait for its response.
+    Client,
+}
+
+/// The choices offered when `fs/write_text_file` asks for permission. Ids and
+/// names are free-form; each option's `kind` decides what selecting it does.
+/// `permission/request_batch` offers only the `AllowAlways` and
+/// `RejectAlways` ones, since its answer is remembered per path.
+#[derive(Clone, Debug)]
+pub struct PermissionOptions {
+    pub options: Vec<acp::PermissionOption>,
+}
+
+impl Default for PermissionOptions {
+    fn default() -> Self {
+        let option = |id: &str, name: &str, kind| acp::PermissionOption {
+            id: acp::PermissionOptionId(id.into()),
+            name: name.to_string(),
+            kind,
+            meta: None,
+        };
+        Self {
+            options: vec![
+                option(
+                    "allow_once",
+                    "Allow this write operation",
+                    acp::PermissionOptionKind::AllowOnce,
+                ),
+                option(
+                    "allow_always",
+                    "Allow all write operations",
+                    acp::PermissionOptionKind::AllowAlways,
+                ),
+                option(
+                    "reject_once",
+                    "Reject this write operation",
+                    acp::PermissionOptionKind::RejectOnce,
+                ),
+                option(
+                    "reject_always",
+                    "Reject all write operations",
+                    acp::PermissionOptionKind::RejectAlways,
+                ),
+            ],
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -40,12 +578,83 @@ pub enum PermissionDecision {
     RejectAlways,
 }
 
+impl PermissionDecision {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PermissionDecision::AllowAlways => "allow_always",
+            PermissionDecision::RejectAlways => "reject_always",
+        }
+    }
+
+    fn from_str(value: &str) -> Option<Self> {
+        match value {
+            "allow_always" => Some(PermissionDecision::AllowAlways),
+            "reject_always" => Some(PermissionDecision::RejectAlways),
+            _ => None,
+        }
+    }
+}
+
 pub type PermissionCache = Arc<TokioMutex<HashMap<String, PermissionDecision>>>;
 
+/// Backing storage that keeps `allow_always` / `reject_always` decisions
+/// across restarts. The cache is loaded once at startup and saved whole after
+/// every remembered decision.
+pub trait PermissionStore: Send + Sync {
+    fn load(&self) -> HashMap<String, PermissionDecision>;
+    fn save(&self, decisions: &HashMap<String, PermissionDecision>) -> std::io::Result<()>;
+}
+
+/// Stores decisions as a JSON object mapping cache keys to decisions.
+#[derive(Clone, Debug)]
+pub struct JsonFilePermissionStore {
+    path: PathBuf,
+}
+
+impl JsonFilePermissionStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl PermissionStore for JsonFilePermissionStore {
+    // A missing or corrupt file loads as empty rather than failing startup;
+    // the next save overwrites it.
+    fn load(&self) -> HashMap<String, PermissionDecision> {
+        let Ok(text) = std::fs::read_to_string(&self.path) else {
+            return HashMap::new();
+        };
+        let Ok(Value::Object(entries)) = serde_json::from_str::<Value>(&text) else {
+            tracing::warn!(path = %self.path.display(), "ignoring corrupt permission store");
+            return HashMap::new();
+        };
+        entries
+            .iter()
+            .filter_map(|(key, decision)| {
+                let decision = PermissionDecision::from_str(decision.as_str()?)?;
+                Some((key.clone(), decision))
+            })
+            .collect()
+    }
+
+    fn save(&self, decisions: &HashMap<String, PermissionDecision>) -> std::io::Result<()> {
+        let entries: Map<String, Value> = decisions
+            .iter()
+            .map(|(key, decision)| (key.clone(), json!(decision.as_str())))
+            .collect();
+        let text = serde_json::to_string_pretty(&entries).map_err(std::io::Error::other)?;
+        // Written aside and renamed so a crash mid-save leaves the old file.
+        let staging = self.path.with_extension("tmp");
+        std::fs::write(&staging, text)?;
+        std::fs::rename(&staging, &self.path)
+    }
+}
+
 #[derive(Debug)]
 pub enum BridgeError {
     Io(std::io::Error),
     Task(tokio::task::JoinError),
+    Config(String),
 }
 
 impl From<std::io::Error> for BridgeError {
@@ -65,6 +674,11 @@ pub enum AgentTransportError {
     Protocol(acp::Error),
     Internal(String),
     NotImplemented,
+    // The client connection went away; whatever was being produced for it
+    // can stop.
+    Disconnected,
+    // The bridge stopped waiting on the agent after this long.
+    Timeout(Duration),
 }
 
 impl From<acp::Error> for AgentTransportError {
@@ -80,874 +694,5784 @@ impl AgentTransportError {
             AgentTransportError::Internal(message) => {
                 acp::Error::internal_error().with_data(message)
             }
+            // Distinct from internal failures so clients can detect
+            // operations the agent simply does not support.
             AgentTransportError::NotImplemented => {
-                acp::Error::internal_error().with_data("agent transport not implemented")
+                acp::Error::method_not_found().with_data("agent transport not implemented")
+            }
+            AgentTransportError::Disconnected => {
+                acp::Error::internal_error().with_data("client disconnected")
+            }
+            AgentTransportError::Timeout(limit) => {
+                acp::Error::new((-32008, "agent timeout".to_string())).with_data(format!(
+                    "agent call timed out after {} ms",
+                    limit.as_millis()
+                ))
             }
         }
     }
 }
 
+/// Cancelled once the bridge starts shutting down, by `BridgeHandle::shutdown`
+/// or by dropping the handle.
+#[derive(Clone, Debug)]
+pub struct ShutdownToken {
+    receiver: watch::Receiver<bool>,
+}
+
+impl ShutdownToken {
+    pub fn is_cancelled(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Resolves once the token is cancelled, at once if it already is.
+    pub async fn cancelled(&self) {
+        let mut receiver = self.receiver.clone();
+        // A dropped sender means the handle is gone, which is a shutdown too.
+        let _ = receiver.wait_for(|stop| *stop).await;
+    }
+}
+
+/// In-memory files the agent serves under `mem://` paths, such as unsaved
+/// editor buffers. `fs/read_text_file` answers these from memory without
+/// touching the disk sandbox; clones share the same files.
+#[derive(Clone, Debug, Default)]
+pub struct VirtualFiles {
+    files: Arc<std::sync::RwLock<HashMap<String, String>>>,
+}
+
+impl VirtualFiles {
+    /// Serves `content` at `path`, replacing whatever was there. Returns
+    /// false, registering nothing, unless `path` is a valid `mem://` path.
+    pub fn register(&self, path: &str, content: impl Into<String>) -> bool {
+        if !matches!(virtual_path(path), Some(Ok(_))) {
+            return false;
+        }
+        self.files
+            .write()
+            .expect("virtual files lock poisoned")
+            .insert(path.to_string(), content.into());
+        true
+    }
+
+    pub fn unregister(&self, path: &str) -> Option<String> {
+        self.files
+            .write()
+            .expect("virtual files lock poisoned")
+            .remove(path)
+    }
+
+    pub fn get(&self, path: &str) -> Option<String> {
+        self.files
+            .read()
+            .expect("virtual files lock poisoned")
+            .get(path)
+            .cloned()
+    }
+
+    fn replace(&self, path: &str, content: &str, mode: WriteMode) -> Option<bool> {
+        let mut files = self.files.write().expect("virtual files lock poisoned");
+        let current = files.get_mut(path)?;
+        let updated = match mode {
+            WriteMode::Overwrite => content.to_string(),
+            WriteMode::Append => format!("{current}{content}"),
+        };
+        let changed = *current != updated;
+        *current = updated;
+        Some(changed)
+    }
+}
+
+const VIRTUAL_PATH_PREFIX: &str = "mem://";
+
+// `None` for ordinary paths. Virtual names are their own namespace: letters,
+// digits and `-_./`, with no empty, `.` or `..` segments, so a name can never
+// be mistaken for (or smuggled into) a filesystem path.
+fn virtual_path(path: &str) -> Option<Result<&str, acp::Error>> {
+    let name = path.strip_prefix(VIRTUAL_PATH_PREFIX)?;
+    let valid = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/'))
+        && name
+            .split('/')
+            .all(|segment| !matches!(segment, "" | "." | ".."));
+    Some(if valid {
+        Ok(name)
+    } else {
+        Err(acp::Error::invalid_params()
+            .with_data(error_data(ErrorKind::InvalidPath, "invalid virtual path")))
+    })
+}
+
+fn virtual_not_found() -> acp::Error {
+    acp::Error::internal_error()
+        .with_data(error_data(ErrorKind::NotFound, "virtual file not found"))
+}
+
 pub struct BridgeHandle {
-    local_addr: SocketAddr,
-    shutdown: Option<oneshot::Sender<()>>,
-    join_handle: Option<JoinHandle<()>>,
+    local_addrs: Vec<SocketAddr>,
+    local_path: Option<PathBuf>,
+    bridge_id: String,
+    shutdown: Option<watch::Sender<bool>>,
+    shutdown_token: ShutdownToken,
+    virtual_files: VirtualFiles,
+    join_handles: Vec<JoinHandle<()>>,
+    metrics: Arc<BridgeMetrics>,
+}
+
+/// Point-in-time copy of the bridge's counters.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BridgeMetricsSnapshot {
+    pub active_connections: u64,
+    /// Requests seen per method; methods the bridge does not serve are
+    /// counted under `"other"`.
+    pub requests_by_method: BTreeMap<String, u64>,
+    pub handshake_rejections: u64,
+    pub permission_grants: u64,
+    pub permission_denials: u64,
+    /// Connections whose handler panicked.
+    pub connection_panics: u64,
+    /// Closed connections per reason: `normal`, `idle_timeout`,
+    /// `invalid_json`, `protocol_error`, `io_error` or `internal_error`.
+    pub closes_by_reason: BTreeMap<String, u64>,
+}
+
+#[derive(Default)]
+struct BridgeMetrics {
+    active_connections: AtomicU64,
+    requests_by_method: std::sync::Mutex<BTreeMap<&'static str, u64>>,
+    handshake_rejections: AtomicU64,
+    permission_grants: AtomicU64,
+    permission_denials: AtomicU64,
+    connection_panics: AtomicU64,
+    closes_by_reason: std::sync::Mutex<BTreeMap<&'static str, u64>>,
+}
+
+// Why `handle_websocket` let a connection go.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CloseReason {
+    // The client closed the socket, or the bridge had nothing left to serve.
+    Normal,
+    IdleTimeout,
+    InvalidJson,
+    ProtocolError,
+    IoError,
+    InternalError,
+}
+
+impl CloseReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            CloseReason::Normal => "normal",
+            CloseReason::IdleTimeout => "idle_timeout",
+            CloseReason::InvalidJson => "invalid_json",
+            CloseReason::ProtocolError => "protocol_error",
+            CloseReason::IoError => "io_error",
+            CloseReason::InternalError => "internal_error",
+        }
+    }
+}
+
+const BRIDGE_METHODS: &[&str] = &[
+    "initialize",
+    "session/new",
+    "session/prompt",
+    "fs/read_text_file",
+    "fs/read_text_files",
+    "fs/write_text_file",
+    "permission/request_batch",
+    "fs/stat",
+    "fs/exists",
+    "fs/sandbox_info",
+    "project/recent_files",
+    "auth/cli_login",
+    "bridge/ping",
+    "bridge/reset_permission",
+    "bridge/list_sessions",
+    "session/set_mode",
+    "session/close",
+    "bridge/capabilities",
+];
+
+impl BridgeMetrics {
+    fn record_request(&self, method: &str) {
+        // Bucketing unknown methods keeps the map bounded whatever clients send.
+        let key = BRIDGE_METHODS
+            .iter()
+            .find(|known| **known == method)
+            .copied()
+            .unwrap_or("other");
+        let mut requests = self
+            .requests_by_method
+            .lock()
+            .expect("metrics lock poisoned");
+        *requests.entry(key).or_default() += 1;
+    }
+
+    fn record_close(&self, reason: CloseReason) {
+        let mut closes = self.closes_by_reason.lock().expect("metrics lock poisoned");
+        *closes.entry(reason.as_str()).or_default() += 1;
+    }
+
+    fn record_permission_outcome(
+        &self,
+        outcome: &acp::RequestPermissionOutcome,
+        options: &[acp::PermissionOption],
+    ) {
+        if let acp::RequestPermissionOutcome::Selected { option_id } = outcome {
+            match permission_option_kind(options, option_id) {
+                Some(
+                    acp::PermissionOptionKind::AllowOnce | acp::PermissionOptionKind::AllowAlways,
+                ) => {
+                    self.permission_grants.fetch_add(1, Ordering::Relaxed);
+                }
+                Some(
+                    acp::PermissionOptionKind::RejectOnce | acp::PermissionOptionKind::RejectAlways,
+                ) => {
+                    self.permission_denials.fetch_add(1, Ordering::Relaxed);
+                }
+                None => {}
+            }
+        }
+    }
+
+    fn snapshot(&self) -> BridgeMetricsSnapshot {
+        let requests_by_method = self
+            .requests_by_method
+            .lock()
+            .expect("metrics lock poisoned")
+            .iter()
+            .map(|(method, count)| (method.to_string(), *count))
+            .collect();
+        BridgeMetricsSnapshot {
+            active_connections: self.active_connections.load(Ordering::Relaxed),
+            requests_by_method,
+            handshake_rejections: self.handshake_rejections.load(Ordering::Relaxed),
+            permission_grants: self.permission_grants.load(Ordering::Relaxed),
+            permission_denials: self.permission_denials.load(Ordering::Relaxed),
+            connection_panics: self.connection_panics.load(Ordering::Relaxed),
+            closes_by_reason: self
+                .closes_by_reason
+                .lock()
+                .expect("metrics lock poisoned")
+                .iter()
+                .map(|(reason, count)| (reason.to_string(), *count))
+                .collect(),
+        }
+    }
 }
 
 impl BridgeHandle {
-    pub fn local_addr(&self) -> SocketAddr {
-        self.local_addr
+    /// The first bound address, in `bind_addrs` order; `None` for a bridge
+    /// started with `serve_unix`, which has none.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addrs.first().copied()
+    }
+
+    /// Every bound address, one per configured `bind_addrs` entry; empty
+    /// for a bridge started with `serve_unix`.
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    /// The socket path of a bridge started with `serve_unix`.
+    pub fn local_path(&self) -> Option<&Path> {
+        self.local_path.as_deref()
+    }
+
+    pub fn metrics(&self) -> BridgeMetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    /// A token cancelled when this bridge shuts down; the same one every
+    /// transport is handed through `AgentTransport::attach_shutdown`.
+    pub fn shutdown_token(&self) -> ShutdownToken {
+        self.shutdown_token.clone()
+    }
+
+    /// The `mem://` files this bridge serves; the same registry every
+    /// transport is handed through `AgentTransport::attach_virtual_files`.
+    pub fn virtual_files(&self) -> VirtualFiles {
+        self.virtual_files.clone()
     }
 
     pub fn shutdown(
         mut self,
     ) -> Pin<Box<dyn Future<Output = Result<(), BridgeError>> + Send + 'static>> {
         let shutdown = self.shutdown.take();
-        let join_handle = self.join_handle.take();
+        let join_handles = std::mem::take(&mut self.join_handles);
+        let bridge_id = self.bridge_id.clone();
 
         Box::pin(async move {
             if let Some(sender) = shutdown {
-                let _ = sender.send(());
+                let _ = sender.send(true);
             }
 
-            if let Some(handle) = join_handle {
-                handle.await?;
+            let mut joined = Ok(());
+            for handle in join_handles {
+                joined = joined.and(handle.await);
             }
+            // `Drop` sees the sender already taken, so the id is released
+            // here once the listeners are done, even if one of them failed.
+            release_bridge_id(&bridge_id);
+            joined?;
 
             Ok(())
         })
     }
 }
 
-pub trait NotificationSender: Send + Sync {
-    fn send_notification(
-        &self,
-        method: &str,
-        params: Value,
-    ) -> Pin<Box<dyn Future<Output = Result<(), AgentTransportError>> + Send>>;
+/// Dropping the handle without calling `shutdown` still stops the bridge;
+/// the listeners close shortly after, but nothing waits for them.
+impl Drop for BridgeHandle {
+    fn drop(&mut self) {
+        if let Some(sender) = self.shutdown.take() {
+            let _ = sender.send(true);
+            release_bridge_id(&self.bridge_id);
+        }
+    }
 }
 
-struct WebSocketNotificationSender {
-    stream: Arc<TokioMutex<WebSocketStream<TcpStream>>>,
+// Ids of the bridges running in this process, with how many share each.
+// Clients attribute sessions by id, so a second bridge reusing one is worth a
+// warning even though it still starts.
+fn live_bridge_ids() -> &'static Mutex<HashMap<String, usize>> {
+    static LIVE: OnceLock<Mutex<HashMap<String, usize>>> = OnceLock::new();
+    LIVE.get_or_init(Default::default)
 }
 
-impl WebSocketNotificationSender {
-    fn new(stream: Arc<TokioMutex<WebSocketStream<TcpStream>>>) -> Self {
-        Self { stream }
+fn register_bridge_id(bridge_id: &str) {
+    let mut live = live_bridge_ids()
+        .lock()
+        .expect("bridge id registry poisoned");
+    let count = live.entry(bridge_id.to_string()).or_default();
+    if *count > 0 {
+        tracing::warn!(
+            bridge_id = %bridge_id,
+            "another bridge in this process already uses this bridge_id"
+        );
     }
+    *count += 1;
 }
 
-impl NotificationSender for WebSocketNotificationSender {
+fn release_bridge_id(bridge_id: &str) {
+    let mut live = live_bridge_ids()
+        .lock()
+        .expect("bridge id registry poisoned");
+    if let Some(count) = live.get_mut(bridge_id) {
+        *count -= 1;
+        if *count == 0 {
+            live.remove(bridge_id);
+        }
+    }
+}
+
+// Outbound JSON is serialized into `buffer`, which lives as long as the
+// connection. Streaming sends a `session/update` per token, and building each
+// frame from a warm buffer costs one exact-size allocation instead of the
+// repeated regrowth `serde_json::to_string` does from an empty `String`.
+struct WsSink {
+    sink: SplitSink<WebSocketStream<ClientStream>, Message>,
+    buffer: Vec<u8>,
+    redaction: Option<PathRedaction>,
+    // When a frame last went out, so outbound traffic keeps a connection
+    // from counting as idle.
+    last_sent: Instant,
+}
+
+// A single huge response should not pin its buffer for the rest of the
+// connection.
+const MAX_RETAINED_SEND_BUFFER: usize = 1 << 20;
+
+impl WsSink {
+    fn new(
+        sink: SplitSink<WebSocketStream<ClientStream>, Message>,
+        redaction: Option<PathRedaction>,
+    ) -> Self {
+        Self {
+            sink,
+            buffer: Vec::new(),
+            redaction,
+            last_sent: Instant::now(),
+        }
+    }
+
+    async fn send(&mut self, message: Message) -> Result<(), tungstenite::Error> {
+        self.last_sent = Instant::now();
+        self.sink.send(message).await
+    }
+
+    async fn feed(&mut self, message: Message) -> Result<(), tungstenite::Error> {
+        self.last_sent = Instant::now();
+        self.sink.feed(message).await
+    }
+
+    fn encode_json(&mut self, payload: &Value) -> std::io::Result<Message> {
+        if self.buffer.capacity() > MAX_RETAINED_SEND_BUFFER {
+            self.buffer = Vec::new();
+        }
+        self.buffer.clear();
+        serde_json::to_writer(&mut self.buffer, payload)?;
+        let text = std::str::from_utf8(&self.buffer)
+            .map_err(std::io::Error::other)?
+            .to_owned();
+        Ok(Message::Text(text))
+    }
+}
+
+// Write half of a connection, shared by the request worker and anything that
+// pushes notifications or bridge-originated requests to the client.
+type SharedSink = Arc<TokioMutex<WsSink>>;
+
+pub trait NotificationSender: Send + Sync {
     fn send_notification(
         &self,
         method: &str,
         params: Value,
+    ) -> Pin<Box<dyn Future<Output = Result<(), AgentTransportError>> + Send>>;
+
+    /// Serves a request the agent makes of the client while this prompt
+    /// runs, such as `session/request_permission` or `fs/read_text_file`.
+    /// The default refuses every one as not found.
+    fn agent_request(
+        &self,
+        _method: &str,
+        _params: Value,
+    ) -> Pin<Box<dyn Future<Output = Result<Value, acp::Error>> + Send>> {
+        Box::pin(async { Err(acp::Error::method_not_found()) })
+    }
+
+    /// Sends several `(method, params)` notifications in order, stopping at
+    /// the first failure. Each still reaches the client as its own message;
+    /// implementations may cut the per-message overhead of getting them
+    /// there. The default just sends them one by one.
+    fn send_batch(
+        &self,
+        notifications: Vec<(String, Value)>,
     ) -> Pin<Box<dyn Future<Output = Result<(), AgentTransportError>> + Send>> {
-        let stream = self.stream.clone();
-        let method = method.to_string();
+        let sends: Vec<_> = notifications
+            .into_iter()
+            .map(|(method, params)| self.send_notification(&method, params))
+            .collect();
         Box::pin(async move {
-            let payload = json!({
-                "jsonrpc": "2.0",
-                "method": method,
-                "params": params,
-            });
-
-            let mut guard = stream.lock().await;
-            send_json(&mut guard, payload).await.map_err(|_| {
-                AgentTransportError::Internal("Failed to send notification".to_string())
-            })?;
+            for send in sends {
+                send.await?;
+            }
             Ok(())
         })
     }
 }
 
-pub trait AgentTransport: Send + Sync + 'static {
-    fn initialize(
-        &self,
-        request: acp::InitializeRequest,
-    ) -> Pin<Box<dyn Future<Output = Result<acp::InitializeResponse, AgentTransportError>> + Send>>;
-    fn new_session(
-        &self,
-        request: acp::NewSessionRequest,
-    ) -> Pin<Box<dyn Future<Output = Result<acp::NewSessionResponse, AgentTransportError>> + Send>>;
-    fn prompt(
-        &self,
-        request: acp::PromptRequest,
-        notification_sender: Arc<dyn NotificationSender>,
-    ) -> Pin<Box<dyn Future<Output = Result<acp::PromptResponse, AgentTransportError>> + Send>>;
-    fn request_permission(
-        &self,
-        request: acp::RequestPermissionRequest,
-    ) -> Pin<
-        Box<
-            dyn Future<Output = Result<acp::RequestPermissionResponse, AgentTransportError>> + Send,
-        >,
-    >;
+enum Outbound {
+    Notification(Value),
+    // Answered once everything queued before it has been written.
+    Flush(oneshot::Sender<()>),
 }
 
-pub fn serve(
-    config: BridgeConfig,
-    transport: Arc<dyn AgentTransport>,
-) -> Pin<Box<dyn Future<Output = Result<BridgeHandle, BridgeError>> + Send>> {
-    Box::pin(async move {
-        let BridgeConfig {
-            bind_addr,
-            allowed_origins,
-            expected_subprotocol,
-            bridge_id,
-        } = config;
-
-        let listener = TcpListener::bind(bind_addr).await?;
-        let local_addr = listener.local_addr()?;
-
-        let shared = Arc::new(BridgeSharedConfig {
-            allowed_origins,
-            expected_subprotocol,
-            bridge_id,
-            permission_cache: Arc::new(TokioMutex::new(HashMap::new())),
-        });
-
-        let (shutdown_tx, shutdown_rx) = oneshot::channel();
-
-        let join_handle =
-            spawn_accept_loop(listener, shutdown_rx, shared.clone(), transport.clone());
-
-        Ok(BridgeHandle {
-            local_addr,
-            shutdown: Some(shutdown_tx),
-            join_handle: Some(join_handle),
-        })
-    })
+// A connection's notifications wait here for `write_notifications`, so a slow
+// client holds up its own socket rather than the agent producing for it. The
+// receiver is shared so `DropOldest` can evict from the front.
+#[derive(Clone)]
+struct NotificationQueue {
+    sender: mpsc::Sender<Outbound>,
+    receiver: Arc<TokioMutex<mpsc::Receiver<Outbound>>>,
+    overflow: NotificationOverflow,
 }
 
-struct BridgeSharedConfig {
-    allowed_origins: Vec<String>,
-    expected_subprotocol: String,
-    bridge_id: String,
-    permission_cache: PermissionCache,
-}
+impl NotificationQueue {
+    fn new(capacity: usize, overflow: NotificationOverflow) -> Self {
+        let (sender, receiver) = mpsc::channel(capacity);
+        Self {
+            sender,
+            receiver: Arc::new(TokioMutex::new(receiver)),
+            overflow,
+        }
+    }
 
-fn spawn_accept_loop(
-    listener: TcpListener,
-    mut shutdown_rx: oneshot::Receiver<()>,
-    shared: Arc<BridgeSharedConfig>,
-    transport: Arc<dyn AgentTransport>,
-) -> JoinHandle<()> {
-    tokio::spawn(async move {
-        loop {
-            tokio::select! {
-                _ = &mut shutdown_rx => {
+    async fn push(&self, payloads: Vec<Value>) -> Result<(), AgentTransportError> {
+        for payload in payloads {
+            let mut item = Outbound::Notification(payload);
+            loop {
+                if self.overflow == NotificationOverflow::Block {
+                    self.sender
+                        .send(item)
+                        .await
+                        .map_err(|_| AgentTransportError::Disconnected)?;
                     break;
                 }
-                accept_result = listener.accept() => {
-                    let (stream, _) = match accept_result {
-                        Ok(pair) => pair,
-                        Err(_) => break,
-                    };
-                    let shared = shared.clone();
-                    let transport = transport.clone();
-                    tokio::spawn(async move {
-                        if let Err(err) = handle_client(stream, shared, transport).await {
-                            match err {
-                                ClientError::Handshake(error) | ClientError::WebSocket(error) => {
-                                    drop(error); // TODO: replace with structured logging
-                                }
-                            }
-                        }
-                    });
+                match self.sender.try_send(item) {
+                    Ok(()) => break,
+                    Err(mpsc::error::TrySendError::Closed(_)) => {
+                        return Err(AgentTransportError::Disconnected);
+                    }
+                    Err(mpsc::error::TrySendError::Full(_))
+                        if self.overflow == NotificationOverflow::Error =>
+                    {
+                        return Err(AgentTransportError::Internal(
+                            "notification queue full".to_string(),
+                        ));
+                    }
+                    Err(mpsc::error::TrySendError::Full(rejected)) => {
+                        item = rejected;
+                        self.drop_oldest().await;
+                    }
                 }
             }
         }
-    })
+        Ok(())
+    }
+
+    async fn drop_oldest(&self) {
+        match self.receiver.lock().await.try_recv() {
+            Ok(Outbound::Notification(_)) => {
+                tracing::debug!("notification queue full, dropped the oldest notification");
+            }
+            // Everything ahead of the marker has already been taken by the
+            // writer, which holds the sink until it is written, so whoever
+            // waits on the marker cannot overtake it.
+            Ok(Outbound::Flush(done)) => {
+                let _ = done.send(());
+            }
+            Err(_) => {}
+        }
+    }
+
+    /// Waits until every notification queued so far has been written, or the
+    /// writer has given up.
+    async fn flush(&self) {
+        let (done, written) = oneshot::channel();
+        if self.sender.send(Outbound::Flush(done)).await.is_ok() {
+            let _ = written.await;
+        }
+    }
 }
 
-enum ClientError {
-    Handshake(tungstenite::Error),
-    WebSocket(tungstenite::Error),
+// Drains a connection's notification queue until the connection closes,
+// writing whatever is already queued (up to `capacity` messages) before each
+// flush of the socket.
+async fn write_notifications(
+    stream: SharedSink,
+    queue: NotificationQueue,
+    capacity: usize,
+    mut closed: watch::Receiver<bool>,
+) {
+    let drain = async {
+        loop {
+            let Some(first) = queue.receiver.lock().await.recv().await else {
+                return Ok(());
+            };
+            let mut sink = stream.lock().await;
+            let mut next = Some(first);
+            let mut written = 0;
+            while let Some(item) = next.take() {
+                match item {
+                    Outbound::Notification(payload) => {
+                        let message = sink.encode_json(&payload)?;
+                        sink.feed(message).await?;
+                        written += 1;
+                    }
+                    Outbound::Flush(done) => {
+                        sink.sink.flush().await?;
+                        let _ = done.send(());
+                    }
+                }
+                if written < capacity {
+                    next = queue.receiver.lock().await.try_recv().ok();
+                }
+            }
+            sink.sink.flush().await?;
+        }
+    };
+    tokio::select! {
+        result = drain => {
+            if let Err(err) = result {
+                let err: tungstenite::Error = err;
+                tracing::debug!(error = %err, "failed to write notification");
+            }
+        }
+        _ = closed.wait_for(|closed| *closed) => {}
+    }
+    // Later pushes fail instead of waiting on a queue nobody drains, and
+    // dropping what is left releases anyone waiting on a flush.
+    let mut receiver = queue.receiver.lock().await;
+    receiver.close();
+    while receiver.try_recv().is_ok() {}
 }
 
-async fn handle_client(
-    stream: TcpStream,
+// Serves the requests an agent makes of the client during a prompt, against
+// the connection that sent the prompt: permission prompts go to the client,
+// and file access goes through the same sandbox the client's own requests do.
+struct AgentRequestContext {
     shared: Arc<BridgeSharedConfig>,
-    transport: Arc<dyn AgentTransport>,
-) -> Result<(), ClientError> {
-    let ws_stream = accept_client(stream, shared.clone())
-        .await
-        .map_err(ClientError::Handshake)?;
-    handle_websocket(ws_stream, shared, transport)
-        .await
-        .map_err(ClientError::WebSocket)
+    connection: ConnectionInfo,
+    client: ClientRequester,
+    // The agent knows the session by its own id; the client by this one.
+    client_session_id: String,
 }
 
-async fn accept_client(
-    stream: TcpStream,
-    shared: Arc<BridgeSharedConfig>,
-) -> Result<WebSocketStream<TcpStream>, tungstenite::Error> {
-    let allowed_origins = shared.allowed_origins.clone();
-    let expected_subprotocol = shared.expected_subprotocol.clone();
+impl AgentRequestContext {
+    async fn serve(&self, method: &str, mut params: Value) -> Result<Value, acp::Error> {
+        self.shared.request_policy.check(&self.connection, method)?;
+        match method {
+            "session/request_permission" => {
+                if let Some(params) = params.as_object_mut() {
+                    params.insert("sessionId".to_string(), json!(self.client_session_id));
+                }
+                self.client.request(method, params).await
+            }
+            "fs/read_text_file" => {
+                let path = agent_path_param(&params)?;
+                let line = |name| {
+                    params
+                        .get(name)
+                        .and_then(Value::as_u64)
+                        .map(|value| value as u32)
+                };
+                let window = handle_read_text_file(
+                    &self.shared,
+                    &self.connection,
+                    None,
+                    path,
+                    line("line"),
+                    line("limit"),
+                    None,
+                )?;
+                Ok(json!({ "content": window.content }))
+            }
+            "fs/write_text_file" => {
+                let path = agent_path_param(&params)?;
+                let content = params
+                    .get("content")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| {
+                        acp::Error::invalid_params()
+                            .with_data("missing or invalid content parameter")
+                    })?;
+                write_for_agent(&self.shared, &self.connection, path, content).await?;
+                Ok(json!({}))
+            }
+            _ => Err(acp::Error::method_not_found()),
+        }
+    }
+}
 
-    accept_hdr_async(
-        stream,
-        move |request: &Request, mut response: HandshakeResponse| {
-            validate_origin(request, &allowed_origins)?;
-            validate_subprotocol(request, &mut response, &expected_subprotocol)?;
-            Ok(response)
-        },
-    )
-    .await
+fn agent_path_param(params: &Value) -> Result<&str, acp::Error> {
+    params
+        .get("path")
+        .and_then(Value::as_str)
+        .ok_or_else(|| acp::Error::invalid_params().with_data("missing or invalid path parameter"))
 }
 
-#[allow(clippy::result_large_err)]
-fn validate_origin(request: &Request, allowed_origins: &[String]) -> Result<(), ErrorResponse> {
-    let origin = request
-        .headers()
-        .get(ORIGIN)
-        .and_then(|value| value.to_str().ok());
-    match origin {
-        Some(origin_value)
-            if allowed_origins
-                .iter()
-                .any(|allowed| allowed == origin_value) =>
-        {
-            Ok(())
+// ACP has the agent ask for permission itself before it edits, so its writes
+// are not prompted for again. They still have to stay inside the write roots
+// and under `max_write_bytes`, and a standing rejection of the path holds.
+async fn write_for_agent(
+    shared: &BridgeSharedConfig,
+    connection: &ConnectionInfo,
+    path: &str,
+    content: &str,
+) -> Result<(), acp::Error> {
+    if let Some(max_write_bytes) = shared.max_write_bytes {
+        if content.len() as u64 > max_write_bytes {
+            return Err(acp::Error::invalid_params().with_data(error_data(
+                ErrorKind::TooLarge,
+                format!("content exceeds max_write_bytes ({max_write_bytes} bytes)"),
+            )));
         }
-        _ => Err(handshake_error(StatusCode::FORBIDDEN, "Origin not allowed")),
     }
+    if let Some(name) = virtual_path(path) {
+        name?;
+        // The agent registered these files, so it may always change them.
+        return shared
+            .virtual_files
+            .replace(path, content, WriteMode::Overwrite)
+            .map(|_| ())
+            .ok_or_else(virtual_not_found);
+    }
+    let roots = shared.sandbox_roots(connection)?;
+    let canonical_path = resolve_against_base(&roots, None, path, true)?;
+    if canonical_path.is_dir() {
+        return Err(acp::Error::invalid_params()
+            .with_data(error_data(ErrorKind::IsDirectory, "path is a directory")));
+    }
+    let cache_key = permission_cache_key(&canonical_path.to_string_lossy(), WriteMode::Overwrite);
+    let cached_decision = shared
+        .permission_cache
+        .lock()
+        .await
+        .get(&cache_key)
+        .cloned();
+    if cached_decision == Some(PermissionDecision::RejectAlways) {
+        return Err(shared.write_refusal(WriteRefusal::DeniedAlways));
+    }
+    write_contents(
+        &canonical_path,
+        content,
+        WriteMode::Overwrite,
+        shared.atomic_writes,
+    )?;
+    if let Some(cache) = &shared.read_cache {
+        cache.invalidate(&canonical_path);
+    }
+    Ok(())
 }
 
-#[allow(clippy::result_large_err)]
-fn validate_subprotocol(
-    request: &Request,
-    response: &mut HandshakeResponse,
-    expected: &str,
-) -> Result<(), ErrorResponse> {
-    let header = request
-        .headers()
-        .get(SEC_WEBSOCKET_PROTOCOL)
-        .and_then(|value| value.to_str().ok());
+struct WebSocketNotificationSender {
+    queue: NotificationQueue,
+    request_id: Option<Value>,
+    closed: watch::Receiver<bool>,
+    last_sent: Arc<Mutex<Instant>>,
+    // Like `last_sent`, but only for what the agent sent.
+    last_update: Arc<Mutex<Instant>>,
+    agent_requests: Option<Arc<AgentRequestContext>>,
+}
 
-    let has_expected = header.map(|value| {
-        value
-            .split(',')
-            .any(|candidate| candidate.trim().eq_ignore_ascii_case(expected))
-    });
+impl WebSocketNotificationSender {
+    /// Tags every notification with `_meta.requestId` so clients can tie
+    /// streamed updates back to the request that produced them.
+    fn correlated(
+        queue: NotificationQueue,
+        request_id: Value,
+        closed: watch::Receiver<bool>,
+    ) -> Self {
+        Self {
+            queue,
+            request_id: Some(request_id),
+            closed,
+            last_sent: Arc::new(Mutex::new(Instant::now())),
+            last_update: Arc::new(Mutex::new(Instant::now())),
+            agent_requests: None,
+        }
+    }
 
-    if has_expected != Some(true) {
-        return Err(handshake_error(
-            StatusCode::UPGRADE_REQUIRED,
-            "Missing required subprotocol",
-        ));
+    /// Lets the agent make its own requests of this connection's client.
+    fn serving_agent_requests(mut self, context: AgentRequestContext) -> Self {
+        self.agent_requests = Some(Arc::new(context));
+        self
     }
 
-    let header_value = HeaderValue::from_str(expected)
-        .map_err(|_| handshake_error(StatusCode::BAD_REQUEST, "Invalid subprotocol"))?;
-    response
-        .headers_mut()
-        .insert(SEC_WEBSOCKET_PROTOCOL, header_value);
-    Ok(())
-}
+    /// Resolves once nothing has been sent for `interval`; never, if `None`.
+    async fn silence(&self, interval: Option<Duration>) -> Duration {
+        quiet_for(&self.last_sent, interval).await
+    }
 
-fn handshake_error(status: StatusCode, message: &str) -> ErrorResponse {
-    HttpResponse::builder()
-        .status(status)
-        .body(Some(message.to_owned()))
-        .unwrap_or_else(|_| HttpResponse::builder().status(status).body(None).unwrap())
-}
+    /// Like `silence`, but heartbeats do not count as activity.
+    async fn agent_silence(&self, interval: Option<Duration>) -> Duration {
+        quiet_for(&self.last_update, interval).await
+    }
 
-async fn handle_websocket(
-    stream: WebSocketStream<TcpStream>,
-    shared: Arc<BridgeSharedConfig>,
-    transport: Arc<dyn AgentTransport>,
-) -> Result<(), tungstenite::Error> {
-    let stream = Arc::new(TokioMutex::new(stream));
-    let mut initialized = false;
+    async fn send_heartbeat(&self, params: Value) -> Result<(), AgentTransportError> {
+        if *self.closed.borrow() {
+            return Err(AgentTransportError::Disconnected);
+        }
+        let payload = self.notification_payload("session/update", params);
+        self.queue.push(vec![payload]).await?;
+        mark_sent(&self.last_sent);
+        Ok(())
+    }
+}
 
+async fn quiet_for(stamp: &Mutex<Instant>, interval: Option<Duration>) -> Duration {
+    let Some(interval) = interval else {
+        return std::future::pending().await;
+    };
+    // Activity may happen while this sleeps, pushing the deadline back.
     loop {
-        let message = {
-            let mut stream_guard = stream.lock().await;
-            stream_guard.next().await
-        };
-
-        match message {
-            Some(Ok(Message::Text(text))) => {
-                let value: Value = match serde_json::from_str(&text) {
-                    Ok(value) => value,
-                    Err(_) => {
-                        let mut stream_guard = stream.lock().await;
-                        send_error(&mut stream_guard, Value::Null, acp::Error::parse_error())
-                            .await?;
-                        continue;
-                    }
-                };
-                process_request(stream.clone(), &shared, &transport, &mut initialized, value)
-                    .await?;
-            }
-            Some(Ok(Message::Binary(bytes))) => {
-                let value: Value = match serde_json::from_slice(&bytes) {
-                    Ok(value) => value,
-                    Err(_) => {
-                        let mut stream_guard = stream.lock().await;
-                        send_error(&mut stream_guard, Value::Null, acp::Error::parse_error())
-                            .await?;
-                        continue;
-                    }
-                };
-                process_request(stream.clone(), &shared, &transport, &mut initialized, value)
-                    .await?;
-            }
-            Some(Ok(Message::Ping(payload))) => {
-                let mut stream_guard = stream.lock().await;
-                stream_guard.send(Message::Pong(payload)).await?;
-            }
-            Some(Ok(Message::Pong(_))) => {}
-            Some(Ok(Message::Close(_))) | None => {
-                break;
-            }
-            Some(Ok(Message::Frame(_))) => {}
-            Some(Err(e)) => return Err(e),
+        let due = *stamp.lock().expect("activity lock poisoned") + interval;
+        if Instant::now() >= due {
+            return interval;
         }
+        tokio::time::sleep_until(due.into()).await;
     }
+}
 
-    Ok(())
+fn mark_sent(stamp: &Mutex<Instant>) {
+    *stamp.lock().expect("activity lock poisoned") = Instant::now();
 }
 
-async fn process_request(
-    stream: Arc<TokioMutex<WebSocketStream<TcpStream>>>,
-    shared: &BridgeSharedConfig,
-    transport: &Arc<dyn AgentTransport>,
-    initialized: &mut bool,
-    value: Value,
-) -> Result<(), tungstenite::Error> {
-    let id = value.get("id").cloned().unwrap_or(Value::Null);
-    let method = value.get("method").and_then(|value| value.as_str());
+fn attach_request_id(params: &mut Value, request_id: &Value) {
+    let Some(params) = params.as_object_mut() else {
+        return;
+    };
+    let meta = params
+        .entry("_meta")
+        .or_insert_with(|| Value::Object(serde_json::Map::new()));
+    if let Some(meta) = meta.as_object_mut() {
+        meta.insert("requestId".to_string(), request_id.clone());
+    }
+}
 
-    let method = match method {
-        Some(method) => method,
-        None => {
-            send_error_shared(&stream, id, acp::Error::invalid_request()).await?;
-            return Ok(());
+impl WebSocketNotificationSender {
+    fn notification_payload(&self, method: &str, mut params: Value) -> Value {
+        if let Some(request_id) = &self.request_id {
+            attach_request_id(&mut params, request_id);
         }
-    };
+        json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+        })
+    }
+}
 
-    match method {
-        "initialize" => {
-            let params = value.get("params").cloned().unwrap_or_else(|| json!({}));
-            let request: acp::InitializeRequest = match serde_json::from_value(params) {
-                Ok(request) => request,
-                Err(err) => {
-                    send_error_shared(
-                        &stream,
-                        id,
-                        acp::Error::invalid_params().with_data(err.to_string()),
-                    )
-                    .await?;
-                    return Ok(());
-                }
-            };
+impl NotificationSender for WebSocketNotificationSender {
+    fn agent_request(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> Pin<Box<dyn Future<Output = Result<Value, acp::Error>> + Send>> {
+        let Some(context) = self.agent_requests.clone() else {
+            return Box::pin(async { Err(acp::Error::method_not_found()) });
+        };
+        let method = method.to_string();
+        Box::pin(async move { context.serve(&method, params).await })
+    }
 
-            let response = transport.initialize(request).await;
-            match response {
-                Ok(mut response) => {
-                    ensure_bridge_meta(&mut response, &shared.bridge_id);
-                    let result = serde_json::to_value(response)
-                        .map_err(|err| tungstenite::Error::Io(std::io::Error::other(err)))?;
-                    send_result_shared(&stream, id, result).await?;
-                    *initialized = true;
-                }
-                Err(err) => {
-                    let error = err.into_rpc_error();
-                    send_error_shared(&stream, id, error).await?;
-                }
-            }
+    fn send_notification(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> Pin<Box<dyn Future<Output = Result<(), AgentTransportError>> + Send>> {
+        // Writes to a dropped socket can still succeed while the OS buffers
+        // them, so report the disconnect rather than waiting for one to fail.
+        if *self.closed.borrow() {
+            return Box::pin(async { Err(AgentTransportError::Disconnected) });
         }
-        "session/new" => {
-            if !*initialized {
-                let error = acp::Error::method_not_found();
-                send_error_shared(&stream, id, error).await?;
-                return Ok(());
-            }
-
-            let params = value.get("params").cloned().unwrap_or_else(|| json!({}));
-            let request: acp::NewSessionRequest = match serde_json::from_value(params) {
-                Ok(request) => request,
-                Err(err) => {
-                    send_error_shared(
-                        &stream,
-                        id,
-                        acp::Error::invalid_params().with_data(err.to_string()),
-                    )
-                    .await?;
-                    return Ok(());
-                }
-            };
+        let queue = self.queue.clone();
+        let last_sent = self.last_sent.clone();
+        let last_update = self.last_update.clone();
+        let payload = self.notification_payload(method, params);
+        Box::pin(async move {
+            queue.push(vec![payload]).await?;
+            mark_sent(&last_sent);
+            mark_sent(&last_update);
+            Ok(())
+        })
+    }
 
-            let response = transport.new_session(request).await;
-            match response {
-                Ok(response) => {
-                    let result = serde_json::to_value(response)
-                        .map_err(|err| tungstenite::Error::Io(std::io::Error::other(err)))?;
-                    send_result_shared(&stream, id, result).await?;
-                }
-                Err(err) => {
-                    let error = err.into_rpc_error();
-                    send_error_shared(&stream, id, error).await?;
-                }
-            }
+    // Queued back to back, so the writer usually sends the whole burst with a
+    // single flush of the socket.
+    fn send_batch(
+        &self,
+        notifications: Vec<(String, Value)>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), AgentTransportError>> + Send>> {
+        if *self.closed.borrow() {
+            return Box::pin(async { Err(AgentTransportError::Disconnected) });
         }
-        "session/prompt" => {
-            if !*initialized {
-                let error = acp::Error::method_not_found();
-                send_error_shared(&stream, id, error).await?;
-                return Ok(());
-            }
+        let queue = self.queue.clone();
+        let payloads: Vec<Value> = notifications
+            .into_iter()
+            .map(|(method, params)| self.notification_payload(&method, params))
+            .collect();
+        let last_sent = self.last_sent.clone();
+        let last_update = self.last_update.clone();
+        Box::pin(async move {
+            queue.push(payloads).await?;
+            mark_sent(&last_sent);
+            mark_sent(&last_update);
+            Ok(())
+        })
+    }
+}
 
-            let params = value.get("params").cloned().unwrap_or_else(|| json!({}));
+pub trait AgentTransport: Send + Sync + 'static {
+    fn initialize(
+        &self,
+        request: acp::InitializeRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::InitializeResponse, AgentTransportError>> + Send>>;
+    fn new_session(
+        &self,
+        request: acp::NewSessionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::NewSessionResponse, AgentTransportError>> + Send>>;
+    fn prompt(
+        &self,
+        request: acp::PromptRequest,
+        notification_sender: Arc<dyn NotificationSender>,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::PromptResponse, AgentTransportError>> + Send>>;
+    fn request_permission(
+        &self,
+        request: acp::RequestPermissionRequest,
+    ) -> Pin<
+        Box<
+            dyn Future<Output = Result<acp::RequestPermissionResponse, AgentTransportError>> + Send,
+        >,
+    >;
+    /// Switches a session to one of the modes its `session/new` response
+    /// advertised. Agents without modes can leave this unimplemented.
+    fn set_mode(
+        &self,
+        _request: acp::SetSessionModeRequest,
+    ) -> Pin<
+        Box<dyn Future<Output = Result<acp::SetSessionModeResponse, AgentTransportError>> + Send>,
+    > {
+        Box::pin(async { Err(AgentTransportError::NotImplemented) })
+    }
+    /// Called once by `serve` with a token that is cancelled when the bridge
+    /// shuts down, so transports running long work can abort it. The
+    /// default ignores it.
+    fn attach_shutdown(&self, _shutdown: ShutdownToken) {}
+    /// Called once by `serve` with the bridge's `mem://` files, which the
+    /// transport registers in-memory content with for clients to read. The
+    /// default ignores it.
+    fn attach_virtual_files(&self, _files: VirtualFiles) {}
+    /// Tells the agent a client closed one of its sessions, so it can free
+    /// whatever it holds for it. Agents that keep nothing per session can
+    /// leave this unimplemented.
+    fn close_session(
+        &self,
+        _session_id: acp::SessionId,
+    ) -> Pin<Box<dyn Future<Output = Result<(), AgentTransportError>> + Send>> {
+        Box::pin(async { Err(AgentTransportError::NotImplemented) })
+    }
+}
+
+/// Wraps another transport and retries `initialize`, `new_session` and
+/// `prompt` when they fail with `AgentTransportError::Internal`, doubling the
+/// delay after each attempt. `request_permission` is passed through untouched
+/// so a user is never asked the same question twice.
+pub struct RetryingTransport {
+    inner: Arc<dyn AgentTransport>,
+    max_retries: u32,
+    initial_backoff: Duration,
+}
+
+impl RetryingTransport {
+    pub fn new(
+        inner: Arc<dyn AgentTransport>,
+        max_retries: u32,
+        initial_backoff: Duration,
+    ) -> Self {
+        Self {
+            inner,
+            max_retries,
+            initial_backoff,
+        }
+    }
+
+    fn retry<T, F, Fut>(
+        &self,
+        mut call: F,
+    ) -> Pin<Box<dyn Future<Output = Result<T, AgentTransportError>> + Send>>
+    where
+        T: Send + 'static,
+        F: FnMut(Arc<dyn AgentTransport>) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T, AgentTransportError>> + Send,
+    {
+        let inner = self.inner.clone();
+        let max_retries = self.max_retries;
+        let mut backoff = self.initial_backoff;
+        Box::pin(async move {
+            let mut attempt = 0;
+            loop {
+                match call(inner.clone()).await {
+                    Err(AgentTransportError::Internal(message)) if attempt < max_retries => {
+                        attempt += 1;
+                        tracing::debug!(attempt, %message, "retrying agent call");
+                        tokio::time::sleep(backoff).await;
+                        backoff = backoff.saturating_mul(2);
+                    }
+                    result => return result,
+                }
+            }
+        })
+    }
+}
+
+impl AgentTransport for RetryingTransport {
+    fn initialize(
+        &self,
+        request: acp::InitializeRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::InitializeResponse, AgentTransportError>> + Send>>
+    {
+        self.retry(move |inner| inner.initialize(request.clone()))
+    }
+
+    fn new_session(
+        &self,
+        request: acp::NewSessionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::NewSessionResponse, AgentTransportError>> + Send>>
+    {
+        self.retry(move |inner| inner.new_session(request.clone()))
+    }
+
+    fn prompt(
+        &self,
+        request: acp::PromptRequest,
+        notification_sender: Arc<dyn NotificationSender>,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::PromptResponse, AgentTransportError>> + Send>>
+    {
+        self.retry(move |inner| inner.prompt(request.clone(), notification_sender.clone()))
+    }
+
+    fn request_permission(
+        &self,
+        request: acp::RequestPermissionRequest,
+    ) -> Pin<
+        Box<
+            dyn Future<Output = Result<acp::RequestPermissionResponse, AgentTransportError>> + Send,
+        >,
+    > {
+        self.inner.request_permission(request)
+    }
+
+    fn set_mode(
+        &self,
+        request: acp::SetSessionModeRequest,
+    ) -> Pin<
+        Box<dyn Future<Output = Result<acp::SetSessionModeResponse, AgentTransportError>> + Send>,
+    > {
+        self.inner.set_mode(request)
+    }
+
+    fn attach_shutdown(&self, shutdown: ShutdownToken) {
+        self.inner.attach_shutdown(shutdown)
+    }
+
+    fn attach_virtual_files(&self, files: VirtualFiles) {
+        self.inner.attach_virtual_files(files)
+    }
+
+    fn close_session(
+        &self,
+        session_id: acp::SessionId,
+    ) -> Pin<Box<dyn Future<Output = Result<(), AgentTransportError>> + Send>> {
+        self.inner.close_session(session_id)
+    }
+}
+
+// Calls awaiting a response from the agent process, keyed by JSON-RPC id.
+// Becomes `None` once the process's stdout closes so late calls fail fast
+// instead of waiting for an answer that can never arrive.
+type PendingAgentCalls =
+    Arc<Mutex<Option<HashMap<u64, oneshot::Sender<Result<Value, acp::Error>>>>>>;
+type SessionNotificationSenders = Arc<Mutex<HashMap<String, Arc<dyn NotificationSender>>>>;
+type SharedAgentStdin = Arc<TokioMutex<tokio::process::ChildStdin>>;
+
+/// Runs an ACP agent as a child process and speaks newline-delimited
+/// JSON-RPC over its stdin and stdout.
+///
+/// `session/update` notifications are forwarded to the notification sender of
+/// the prompt running for that session; notifications for any other session
+/// are dropped. Requests the agent sends during a prompt are served for that
+/// prompt's connection: `session/request_permission` is relayed to the
+/// WebSocket client, and `fs/read_text_file` and `fs/write_text_file` are
+/// answered inside the connection's sandbox. Anything else, and any request
+/// outside a prompt, gets `method_not_found`.
+///
+/// The agent is never asked for permission itself, since in ACP it is the one
+/// asking. Bridge-side write prompts reach `request_permission`, which answers
+/// through `with_permission_handler` or reports `NotImplemented`; set
+/// `PermissionTarget::Client` to send those prompts to the client instead.
+///
+/// The process is killed when the transport is dropped.
+pub struct StdioAgentTransport {
+    stdin: SharedAgentStdin,
+    next_id: AtomicU64,
+    pending: PendingAgentCalls,
+    sessions: SessionNotificationSenders,
+    permission_handler: Option<PermissionHandler>,
+    _child: tokio::process::Child,
+}
+
+/// Answers the bridge's own write permission prompts for a transport that
+/// cannot ask its agent, such as `StdioAgentTransport`.
+pub type PermissionHandler = Arc<
+    dyn Fn(
+            acp::RequestPermissionRequest,
+        ) -> Pin<
+            Box<
+                dyn Future<Output = Result<acp::RequestPermissionResponse, AgentTransportError>>
+                    + Send,
+            >,
+        > + Send
+        + Sync,
+>;
+
+impl StdioAgentTransport {
+    /// Spawns `program` with `args`. Must be called inside a Tokio runtime;
+    /// the agent's stderr is inherited so its logs stay visible.
+    pub fn spawn<I, S>(program: impl AsRef<std::ffi::OsStr>, args: I) -> std::io::Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<std::ffi::OsStr>,
+    {
+        let mut child = tokio::process::Command::new(program)
+            .args(args)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()?;
+        let stdin = child.stdin.take().expect("agent stdin is piped");
+        let stdout = child.stdout.take().expect("agent stdout is piped");
+
+        let stdin = Arc::new(TokioMutex::new(stdin));
+        let pending: PendingAgentCalls = Arc::new(Mutex::new(Some(HashMap::new())));
+        let sessions: SessionNotificationSenders = Arc::new(Mutex::new(HashMap::new()));
+        tokio::spawn(route_agent_output(
+            stdout,
+            stdin.clone(),
+            pending.clone(),
+            sessions.clone(),
+        ));
+
+        Ok(Self {
+            stdin,
+            next_id: AtomicU64::new(0),
+            pending,
+            sessions,
+            permission_handler: None,
+            _child: child,
+        })
+    }
+
+    /// Answers `request_permission` with `handler` instead of reporting it
+    /// unimplemented.
+    pub fn with_permission_handler(mut self, handler: PermissionHandler) -> Self {
+        self.permission_handler = Some(handler);
+        self
+    }
+
+    fn call<P, T>(
+        &self,
+        method: &'static str,
+        params: P,
+    ) -> Pin<Box<dyn Future<Output = Result<T, AgentTransportError>> + Send>>
+    where
+        P: serde::Serialize,
+        T: serde::de::DeserializeOwned + Send + 'static,
+    {
+        let params = match serde_json::to_value(params) {
+            Ok(params) => params,
+            Err(err) => {
+                let message = format!("failed to encode {method} params: {err}");
+                return Box::pin(async move { Err(AgentTransportError::Internal(message)) });
+            }
+        };
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (response_tx, response_rx) = oneshot::channel();
+        let registered = match self
+            .pending
+            .lock()
+            .expect("agent call registry poisoned")
+            .as_mut()
+        {
+            Some(calls) => {
+                calls.insert(id, response_tx);
+                true
+            }
+            None => false,
+        };
+        let stdin = self.stdin.clone();
+        let pending = self.pending.clone();
+        Box::pin(async move {
+            if !registered {
+                return Err(agent_process_exited());
+            }
+            let request = json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": method,
+                "params": params,
+            });
+            if let Err(err) = write_agent_line(&stdin, &request).await {
+                if let Some(calls) = pending
+                    .lock()
+                    .expect("agent call registry poisoned")
+                    .as_mut()
+                {
+                    calls.remove(&id);
+                }
+                return Err(AgentTransportError::Internal(format!(
+                    "failed to write to agent: {err}"
+                )));
+            }
+            let result = response_rx.await.map_err(|_| agent_process_exited())??;
+            serde_json::from_value(result).map_err(|err| {
+                AgentTransportError::Internal(format!(
+                    "invalid {method} response from agent: {err}"
+                ))
+            })
+        })
+    }
+}
+
+fn agent_process_exited() -> AgentTransportError {
+    AgentTransportError::Internal("agent process exited".to_string())
+}
+
+async fn write_agent_line(stdin: &SharedAgentStdin, message: &Value) -> std::io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    let mut stdin = stdin.lock().await;
+    stdin.write_all(&line).await?;
+    stdin.flush().await
+}
+
+// Removes a prompt's notification sender once the prompt finishes or its
+// future is dropped, so later updates for the session are not delivered to a
+// connection that stopped listening. Only its own sender is removed, in case
+// the session has been registered again since.
+struct SessionRegistration {
+    sessions: SessionNotificationSenders,
+    session_id: String,
+    sender: Arc<dyn NotificationSender>,
+}
+
+impl Drop for SessionRegistration {
+    fn drop(&mut self) {
+        let mut sessions = self
+            .sessions
+            .lock()
+            .expect("agent session registry poisoned");
+        if sessions
+            .get(&self.session_id)
+            .is_some_and(|sender| Arc::ptr_eq(sender, &self.sender))
+        {
+            sessions.remove(&self.session_id);
+        }
+    }
+}
+
+async fn route_agent_output(
+    stdout: tokio::process::ChildStdout,
+    stdin: SharedAgentStdin,
+    pending: PendingAgentCalls,
+    sessions: SessionNotificationSenders,
+) {
+    use tokio::io::AsyncBufReadExt;
+
+    let mut lines = tokio::io::BufReader::new(stdout).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let message: Value = match serde_json::from_str(&line) {
+            Ok(message) => message,
+            Err(err) => {
+                tracing::warn!(%err, "ignoring unparseable line from agent");
+                continue;
+            }
+        };
+        let id = message.get("id").cloned();
+        match (message.get("method").and_then(Value::as_str), id) {
+            (Some(method), Some(id)) => {
+                let params = message.get("params").cloned().unwrap_or(Value::Null);
+                let sender =
+                    params
+                        .get("sessionId")
+                        .and_then(Value::as_str)
+                        .and_then(|session_id| {
+                            sessions
+                                .lock()
+                                .expect("agent session registry poisoned")
+                                .get(session_id)
+                                .cloned()
+                        });
+                let Some(sender) = sender else {
+                    tracing::debug!(%method, "rejecting request from agent outside a prompt");
+                    let response = json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": acp::Error::method_not_found(),
+                    });
+                    if write_agent_line(&stdin, &response).await.is_err() {
+                        break;
+                    }
+                    continue;
+                };
+                // Answered on its own task: a permission prompt can wait on a
+                // human while the agent keeps streaming updates.
+                let answer = sender.agent_request(method, params);
+                let stdin = stdin.clone();
+                tokio::spawn(async move {
+                    let response = match answer.await {
+                        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+                        Err(error) => json!({"jsonrpc": "2.0", "id": id, "error": error}),
+                    };
+                    if let Err(err) = write_agent_line(&stdin, &response).await {
+                        tracing::debug!(%err, "failed to answer agent request");
+                    }
+                });
+            }
+            (Some(method), None) => {
+                let params = message.get("params").cloned().unwrap_or(Value::Null);
+                let sender =
+                    params
+                        .get("sessionId")
+                        .and_then(Value::as_str)
+                        .and_then(|session_id| {
+                            sessions
+                                .lock()
+                                .expect("agent session registry poisoned")
+                                .get(session_id)
+                                .cloned()
+                        });
+                match sender {
+                    Some(sender) if method == "session/update" => {
+                        let _ = sender.send_notification(method, params).await;
+                    }
+                    _ => tracing::debug!(%method, "dropping notification from agent"),
+                }
+            }
+            (None, Some(id)) => {
+                let Some(id) = id.as_u64() else {
+                    continue;
+                };
+                let waiter = pending
+                    .lock()
+                    .expect("agent call registry poisoned")
+                    .as_mut()
+                    .and_then(|calls| calls.remove(&id));
+                let Some(waiter) = waiter else {
+                    continue;
+                };
+                let outcome = match message.get("error") {
+                    Some(error) => Err(serde_json::from_value(error.clone())
+                        .unwrap_or_else(|_| acp::Error::internal_error().with_data(error.clone()))),
+                    None => Ok(message.get("result").cloned().unwrap_or(Value::Null)),
+                };
+                let _ = waiter.send(outcome);
+            }
+            (None, None) => tracing::warn!("ignoring malformed message from agent"),
+        }
+    }
+    // Dropping the waiters fails every outstanding call.
+    pending.lock().expect("agent call registry poisoned").take();
+}
+
+impl AgentTransport for StdioAgentTransport {
+    fn initialize(
+        &self,
+        request: acp::InitializeRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::InitializeResponse, AgentTransportError>> + Send>>
+    {
+        self.call("initialize", request)
+    }
+
+    fn new_session(
+        &self,
+        request: acp::NewSessionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::NewSessionResponse, AgentTransportError>> + Send>>
+    {
+        self.call("session/new", request)
+    }
+
+    fn prompt(
+        &self,
+        request: acp::PromptRequest,
+        notification_sender: Arc<dyn NotificationSender>,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::PromptResponse, AgentTransportError>> + Send>>
+    {
+        let session_id = request.session_id.0.to_string();
+        // Agent output is routed by session id alone, so a second prompt for
+        // the same session would take over the first one's updates.
+        {
+            let mut sessions = self
+                .sessions
+                .lock()
+                .expect("agent session registry poisoned");
+            if sessions.contains_key(&session_id) {
+                return Box::pin(async move {
+                    Err(AgentTransportError::Protocol(
+                        acp::Error::invalid_request().with_data(format!(
+                            "session {session_id} already has a prompt running"
+                        )),
+                    ))
+                });
+            }
+            sessions.insert(session_id.clone(), notification_sender.clone());
+        }
+        let registration = SessionRegistration {
+            sessions: self.sessions.clone(),
+            session_id,
+            sender: notification_sender,
+        };
+        let call = self.call("session/prompt", request);
+        Box::pin(async move {
+            let _registration = registration;
+            call.await
+        })
+    }
+
+    fn request_permission(
+        &self,
+        request: acp::RequestPermissionRequest,
+    ) -> Pin<
+        Box<
+            dyn Future<Output = Result<acp::RequestPermissionResponse, AgentTransportError>> + Send,
+        >,
+    > {
+        match &self.permission_handler {
+            Some(handler) => handler(request),
+            None => Box::pin(async { Err(AgentTransportError::NotImplemented) }),
+        }
+    }
+
+    fn set_mode(
+        &self,
+        request: acp::SetSessionModeRequest,
+    ) -> Pin<
+        Box<dyn Future<Output = Result<acp::SetSessionModeResponse, AgentTransportError>> + Send>,
+    > {
+        self.call("session/set_mode", request)
+    }
+}
+
+pub fn serve(
+    config: BridgeConfig,
+    transport: Arc<dyn AgentTransport>,
+) -> Pin<Box<dyn Future<Output = Result<BridgeHandle, BridgeError>> + Send>> {
+    serve_on(Bind::Tcp, config, transport)
+}
+
+/// Like `serve`, but listens on a Unix domain socket at `path` instead of
+/// `bind_addrs`, so access is gated by filesystem permissions. Binding fails
+/// if `path` already exists; the socket file is removed on shutdown.
+/// Origin and subprotocol checks apply as they do over TCP.
+#[cfg(unix)]
+pub fn serve_unix(
+    path: impl Into<PathBuf>,
+    config: BridgeConfig,
+    transport: Arc<dyn AgentTransport>,
+) -> Pin<Box<dyn Future<Output = Result<BridgeHandle, BridgeError>> + Send>> {
+    serve_on(Bind::Unix(path.into()), config, transport)
+}
+
+/// Like `serve`, but accepts on a listener the caller already bound, as
+/// with systemd socket activation, instead of binding `bind_addrs`. A
+/// `std::net::TcpListener` from an inherited descriptor can be converted
+/// with `TcpListener::from_std` after setting it non-blocking.
+pub fn serve_with_listener(
+    listener: TcpListener,
+    config: BridgeConfig,
+    transport: Arc<dyn AgentTransport>,
+) -> Pin<Box<dyn Future<Output = Result<BridgeHandle, BridgeError>> + Send>> {
+    serve_on(Bind::Listener(listener), config, transport)
+}
+
+enum Bind {
+    Tcp,
+    Listener(TcpListener),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+fn serve_on(
+    bind: Bind,
+    config: BridgeConfig,
+    transport: Arc<dyn AgentTransport>,
+) -> Pin<Box<dyn Future<Output = Result<BridgeHandle, BridgeError>> + Send>> {
+    Box::pin(async move {
+        let BridgeConfig {
+            bind_addrs,
+            fallback_ports,
+            bind_retries,
+            bind_retry_delay,
+            allowed_origins,
+            origin_validator,
+            allow_missing_origin,
+            expected_subprotocol,
+            additional_subprotocols,
+            subprotocol_case_sensitive,
+            handshake_check_order,
+            bridge_id,
+            duplicate_session_policy,
+            supported_protocol_versions,
+            max_message_bytes,
+            max_read_bytes,
+            read_cache_capacity,
+            max_read_batch,
+            max_write_bytes,
+            allow_virtual_writes,
+            atomic_writes,
+            permission_error_code_base,
+            max_line_bytes,
+            max_prompt_chars,
+            project_root,
+            project_roots,
+            origin_project_roots,
+            write_roots,
+            redact_paths,
+            request_policy,
+            method_handlers,
+            walk_ignore,
+            max_walk_entries,
+            follow_symlinks,
+            session_id_pattern,
+            startup_json_line,
+            permission_target,
+            permission_options,
+            permission_store,
+            require_session_for_write,
+            sessionless_write_decision,
+            agent_call_timeout,
+            initialize_timeout,
+            new_session_timeout,
+            prompt_timeout,
+            prompt_timeout_mode,
+            max_agent_call_timeout,
+            prompt_heartbeat_interval,
+            connection_idle_timeout,
+            notification_queue_capacity,
+            notification_overflow,
+            enable_permission_reset,
+            enable_ping,
+            list_all_sessions,
+            enable_cli_login,
+            cli_locator,
+            login_automation,
+        } = config;
+
+        if matches!(bind, Bind::Tcp) && bind_addrs.is_empty() {
+            return Err(BridgeError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "no bind addresses configured",
+            )));
+        }
+
+        if bridge_id.trim().is_empty() {
+            return Err(BridgeError::Config(
+                "bridge_id must not be empty".to_string(),
+            ));
+        }
+
+        if notification_queue_capacity == 0 {
+            return Err(BridgeError::Config(
+                "notification_queue_capacity must be at least 1".to_string(),
+            ));
+        }
+
+        let session_id_pattern = session_id_pattern
+            .map(|pattern| {
+                Regex::new(&format!("^(?:{pattern})$")).map_err(|err| {
+                    BridgeError::Config(format!("invalid session_id_pattern: {err}"))
+                })
+            })
+            .transpose()?;
+
+        let mut listeners = Vec::new();
+        let mut local_addrs = Vec::new();
+        let mut local_path = None;
+        match bind {
+            Bind::Tcp => {
+                for bind_addr in bind_addrs {
+                    let listener =
+                        bind_listener(bind_addr, &fallback_ports, bind_retries, bind_retry_delay)
+                            .await?;
+                    local_addrs.push(listener.local_addr()?);
+                    listeners.push(Listener::Tcp(listener));
+                }
+            }
+            Bind::Listener(listener) => {
+                local_addrs.push(listener.local_addr()?);
+                listeners.push(Listener::Tcp(listener));
+            }
+            #[cfg(unix)]
+            Bind::Unix(path) => {
+                listeners.push(Listener::Unix(UnixListener::bind(&path)?, path.clone()));
+                local_path = Some(path);
+            }
+        }
+
+        let initial_decisions = permission_store
+            .as_ref()
+            .map(|store| store.load())
+            .unwrap_or_default();
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+        let shared = Arc::new(BridgeSharedConfig {
+            allowed_origins,
+            origin_validator,
+            allow_missing_origin,
+            expected_subprotocol,
+            additional_subprotocols,
+            subprotocol_case_sensitive,
+            handshake_check_order,
+            bridge_id,
+            duplicate_session_policy,
+            supported_protocol_versions,
+            max_message_bytes,
+            max_read_bytes,
+            read_cache: (read_cache_capacity > 0).then(|| ReadCache::new(read_cache_capacity)),
+            max_read_batch,
+            max_write_bytes,
+            allow_virtual_writes,
+            virtual_files: VirtualFiles::default(),
+            atomic_writes,
+            permission_error_code_base,
+            max_line_bytes,
+            max_prompt_chars,
+            project_root,
+            project_roots,
+            origin_project_roots,
+            write_roots,
+            redact_paths,
+            request_policy,
+            method_handlers,
+            walk_ignore,
+            max_walk_entries,
+            follow_symlinks,
+            session_id_pattern,
+            permission_target,
+            permission_options,
+            permission_store,
+            require_session_for_write,
+            sessionless_write_decision,
+            agent_call_timeout,
+            initialize_timeout,
+            new_session_timeout,
+            prompt_timeout,
+            prompt_timeout_mode,
+            max_agent_call_timeout,
+            prompt_heartbeat_interval,
+            connection_idle_timeout,
+            notification_queue_capacity,
+            notification_overflow,
+            enable_permission_reset,
+            enable_ping,
+            list_all_sessions,
+            enable_cli_login,
+            cli_locator,
+            login_automation,
+            permission_cache: Arc::new(TokioMutex::new(initial_decisions)),
+            pending_permissions: Mutex::new(HashMap::new()),
+            sessions: Arc::new(TokioMutex::new(HashMap::new())),
+            next_connection_id: AtomicU64::new(1),
+            next_session_alias: AtomicU64::new(1),
+            trace_prefix: trace_prefix(),
+            next_trace_id: AtomicU64::new(1),
+            shutdown: shutdown_rx.clone(),
+            metrics: Arc::new(BridgeMetrics::default()),
+        });
+
+        let summary = startup_summary(&shared, &local_addrs, local_path.as_deref());
+        tracing::info!(
+            port = local_addrs.first().map(SocketAddr::port),
+            local_addrs = ?local_addrs,
+            socket_path = ?local_path,
+            bridge_id = %shared.bridge_id,
+            summary = %summary,
+            "bridge listening"
+        );
+        if startup_json_line {
+            println!("{summary}");
+        }
+
+        let shutdown_token = ShutdownToken {
+            receiver: shutdown_rx.clone(),
+        };
+        transport.attach_shutdown(shutdown_token.clone());
+        transport.attach_virtual_files(shared.virtual_files.clone());
+
+        let join_handles = listeners
+            .into_iter()
+            .map(|listener| {
+                spawn_accept_loop(
+                    listener,
+                    shutdown_rx.clone(),
+                    shared.clone(),
+                    transport.clone(),
+                )
+            })
+            .collect();
+
+        register_bridge_id(&shared.bridge_id);
+        Ok(BridgeHandle {
+            local_addrs,
+            local_path,
+            bridge_id: shared.bridge_id.clone(),
+            shutdown: Some(shutdown_tx),
+            shutdown_token,
+            virtual_files: shared.virtual_files.clone(),
+            join_handles,
+            metrics: shared.metrics.clone(),
+        })
+    })
+}
+
+fn startup_summary(
+    shared: &BridgeSharedConfig,
+    local_addrs: &[SocketAddr],
+    local_path: Option<&Path>,
+) -> Value {
+    let mut features = Vec::new();
+    if shared.duplicate_session_policy == DuplicateSessionPolicy::Alias {
+        features.push("duplicate_session_alias");
+    }
+    if shared.max_read_bytes.is_some() {
+        features.push("max_read_bytes");
+    }
+    if shared.max_line_bytes.is_some() {
+        features.push("max_line_bytes");
+    }
+    if shared.max_prompt_chars.is_some() {
+        features.push("max_prompt_chars");
+    }
+    if shared.follow_symlinks {
+        features.push("follow_symlinks");
+    }
+    if shared.session_id_pattern.is_some() {
+        features.push("session_id_pattern");
+    }
+    if shared.permission_target == PermissionTarget::Client {
+        features.push("client_permissions");
+    }
+
+    let addrs: Vec<String> = local_addrs.iter().map(ToString::to_string).collect();
+    let roots: Vec<String> = shared
+        .project_roots
+        .iter()
+        .map(|root| root.to_string_lossy().to_string())
+        .collect();
+    let mut summary = json!({
+        "event": "bridge_listening",
+        "localAddrs": addrs,
+        "bridgeId": shared.bridge_id,
+        "features": features,
+        "projectRoots": roots,
+    });
+    if let Some(path) = local_path {
+        summary["socketPath"] = Value::String(path.to_string_lossy().to_string());
+    }
+    summary
+}
+
+// What `bridge/capabilities` reports, derived from the live configuration so
+// clients need not probe. Methods switched off in the config are left out.
+fn bridge_capabilities(shared: &BridgeSharedConfig, connection: &ConnectionInfo) -> Value {
+    let mut methods: Vec<&str> = BRIDGE_METHODS
+        .iter()
+        .copied()
+        .filter(|method| match *method {
+            "bridge/ping" => shared.enable_ping,
+            "bridge/reset_permission" => shared.enable_permission_reset,
+            "auth/cli_login" => shared.enable_cli_login,
+            _ => true,
+        })
+        .collect();
+    let mut custom_methods: Vec<&str> = shared.method_handlers.keys().map(String::as_str).collect();
+    custom_methods.sort_unstable();
+    methods.extend(custom_methods);
+    json!({
+        "bridgeId": shared.bridge_id,
+        "version": env!("CARGO_PKG_VERSION"),
+        "supportedProtocolVersions": shared.supported_protocol_versions,
+        "methods": methods,
+        "limits": {
+            "maxMessageBytes": shared.max_message_bytes,
+            "maxReadBytes": shared.max_read_bytes,
+            "maxReadBatch": shared.max_read_batch,
+            "maxWriteBytes": shared.max_write_bytes,
+            "maxLineBytes": shared.max_line_bytes,
+            "maxPromptChars": shared.max_prompt_chars,
+            "maxWalkEntries": shared.max_walk_entries,
+        },
+        "features": {
+            "subprotocol": connection.subprotocol,
+            "compression": false,
+            "atomicWrites": shared.atomic_writes,
+            "clientPermissions": shared.permission_target == PermissionTarget::Client,
+        },
+    })
+}
+
+struct BridgeSharedConfig {
+    allowed_origins: Vec<String>,
+    origin_validator: Option<OriginValidator>,
+    allow_missing_origin: bool,
+    expected_subprotocol: String,
+    additional_subprotocols: Vec<String>,
+    subprotocol_case_sensitive: bool,
+    handshake_check_order: HandshakeCheckOrder,
+    bridge_id: String,
+    duplicate_session_policy: DuplicateSessionPolicy,
+    supported_protocol_versions: Vec<acp::ProtocolVersion>,
+    max_message_bytes: Option<usize>,
+    max_read_bytes: Option<u64>,
+    read_cache: Option<ReadCache>,
+    max_read_batch: usize,
+    max_write_bytes: Option<u64>,
+    allow_virtual_writes: bool,
+    virtual_files: VirtualFiles,
+    atomic_writes: bool,
+    permission_error_code_base: i32,
+    max_line_bytes: Option<usize>,
+    max_prompt_chars: Option<usize>,
+    project_root: PathBuf,
+    project_roots: Vec<PathBuf>,
+    origin_project_roots: HashMap<String, Vec<PathBuf>>,
+    write_roots: Vec<PathBuf>,
+    redact_paths: bool,
+    request_policy: Arc<dyn RequestPolicy>,
+    method_handlers: HashMap<String, Arc<dyn MethodHandler>>,
+    walk_ignore: Vec<String>,
+    max_walk_entries: usize,
+    follow_symlinks: bool,
+    session_id_pattern: Option<Regex>,
+    permission_target: PermissionTarget,
+    permission_options: PermissionOptions,
+    permission_store: Option<Arc<dyn PermissionStore>>,
+    require_session_for_write: bool,
+    sessionless_write_decision: PermissionDecision,
+    agent_call_timeout: Option<Duration>,
+    initialize_timeout: Option<Duration>,
+    new_session_timeout: Option<Duration>,
+    prompt_timeout: Option<Duration>,
+    prompt_timeout_mode: PromptTimeoutMode,
+    max_agent_call_timeout: Duration,
+    prompt_heartbeat_interval: Option<Duration>,
+    connection_idle_timeout: Option<Duration>,
+    notification_queue_capacity: usize,
+    notification_overflow: NotificationOverflow,
+    enable_permission_reset: bool,
+    enable_ping: bool,
+    list_all_sessions: bool,
+    enable_cli_login: bool,
+    cli_locator: Arc<dyn CliLocator>,
+    login_automation: Option<LoginAutomation>,
+    permission_cache: PermissionCache,
+    pending_permissions: Mutex<HashMap<String, watch::Receiver<Option<PermissionOutcome>>>>,
+    sessions: Arc<TokioMutex<HashMap<String, SessionState>>>,
+    next_connection_id: AtomicU64,
+    next_session_alias: AtomicU64,
+    trace_prefix: String,
+    next_trace_id: AtomicU64,
+    shutdown: watch::Receiver<bool>,
+    metrics: Arc<BridgeMetrics>,
+}
+
+/// Allocates ids for JSON-RPC requests the bridge originates on a connection.
+///
+/// Ids take the form `bridge-{connection}-{sequence}`: the prefix keeps them
+/// apart from client-chosen ids, and the connection component keeps them
+/// unique across reconnects.
+#[derive(Debug)]
+pub struct RequestIdAllocator {
+    connection_id: u64,
+    next: AtomicU64,
+}
+
+impl RequestIdAllocator {
+    pub fn new(connection_id: u64) -> Self {
+        Self {
+            connection_id,
+            next: AtomicU64::new(1),
+        }
+    }
+
+    pub fn next_id(&self) -> String {
+        let sequence = self.next.fetch_add(1, Ordering::Relaxed);
+        format!("bridge-{}-{sequence}", self.connection_id)
+    }
+}
+
+type PendingClientRequests = Arc<std::sync::Mutex<HashMap<String, oneshot::Sender<Value>>>>;
+
+// Prompts a client may still cancel with `$/cancelRequest`, keyed by their
+// serialized JSON-RPC id. `read_loop` adds an entry as it queues a prompt, so
+// a cancel that overtakes the worker still lands; the worker removes it once
+// the prompt is answered.
+type CancellableRequests = Arc<std::sync::Mutex<HashMap<String, watch::Sender<bool>>>>;
+
+const CANCEL_REQUEST_METHOD: &str = "$/cancelRequest";
+
+// Per-socket state threaded through request handling.
+struct ConnectionState {
+    id: u64,
+    // The handshake's origin selects per-origin project roots and policy.
+    info: ConnectionInfo,
+    initialized: bool,
+    agent_call_timeout: Option<Duration>,
+    request_ids: Arc<RequestIdAllocator>,
+    pending_client_requests: PendingClientRequests,
+    cancellable_requests: CancellableRequests,
+    // Flipped once the socket stops being read, to cancel in-flight prompts.
+    closed: Arc<watch::Sender<bool>>,
+    notifications: NotificationQueue,
+    // Sessions this connection ended with `session/close`, so later prompts
+    // to them fail as unknown instead of reaching the agent.
+    closed_sessions: HashSet<String>,
+}
+
+impl ConnectionState {
+    fn new(
+        id: u64,
+        info: ConnectionInfo,
+        agent_call_timeout: Option<Duration>,
+        notifications: NotificationQueue,
+    ) -> Self {
+        Self {
+            id,
+            info,
+            initialized: false,
+            agent_call_timeout,
+            request_ids: Arc::new(RequestIdAllocator::new(id)),
+            pending_client_requests: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            cancellable_requests: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            closed: Arc::new(watch::channel(false).0),
+            notifications,
+            closed_sessions: HashSet::new(),
+        }
+    }
+
+    // Sends a bridge-originated request to the client and waits for the
+    // response, which `read_loop` routes back by id.
+    async fn request_client(
+        &self,
+        stream: &SharedSink,
+        method: &str,
+        params: Value,
+    ) -> Result<Value, acp::Error> {
+        self.client_requester(stream).request(method, params).await
+    }
+
+    fn client_requester(&self, stream: &SharedSink) -> ClientRequester {
+        ClientRequester {
+            stream: stream.clone(),
+            request_ids: self.request_ids.clone(),
+            pending: self.pending_client_requests.clone(),
+        }
+    }
+}
+
+// What sending a bridge-originated request needs, detached from the
+// `ConnectionState` so work outliving a handler's borrow can still ask.
+#[derive(Clone)]
+struct ClientRequester {
+    stream: SharedSink,
+    request_ids: Arc<RequestIdAllocator>,
+    pending: PendingClientRequests,
+}
+
+impl ClientRequester {
+    async fn request(&self, method: &str, params: Value) -> Result<Value, acp::Error> {
+        let id = self.request_ids.next_id();
+        let (response_tx, response_rx) = oneshot::channel();
+        self.pending
+            .lock()
+            .expect("pending client requests lock poisoned")
+            .insert(id.clone(), response_tx);
+
+        let payload = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        });
+        let sent = {
+            let mut guard = self.stream.lock().await;
+            send_json(&mut guard, payload).await
+        };
+        if sent.is_err() {
+            self.pending
+                .lock()
+                .expect("pending client requests lock poisoned")
+                .remove(&id);
+            return Err(acp::Error::internal_error().with_data("failed to reach client"));
+        }
+
+        let response = response_rx
+            .await
+            .map_err(|_| acp::Error::internal_error().with_data("client disconnected"))?;
+        if let Some(error) = response.get("error") {
+            return Err(serde_json::from_value(error.clone()).unwrap_or_else(|_| {
+                acp::Error::internal_error().with_data("malformed client error")
+            }));
+        }
+        Ok(response.get("result").cloned().unwrap_or(Value::Null))
+    }
+}
+
+// Sessions are keyed by the id the client sees; `agent_session_id` differs
+// only when the id was aliased because the agent reused one.
+struct SessionState {
+    agent_session_id: String,
+    connection_id: u64,
+    cwd: PathBuf,
+    created_ms: u64,
+    last_activity_ms: u64,
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+impl BridgeSharedConfig {
+    // Clients may ask for their own agent call timeout in `initialize`
+    // `_meta.agentCallTimeoutMs`; it replaces the global one, clamped.
+    fn connection_agent_call_timeout(&self, meta: Option<&Value>) -> Option<Duration> {
+        let requested = meta
+            .and_then(|meta| meta.get("agentCallTimeoutMs"))
+            .and_then(|ms| ms.as_u64())
+            .map(Duration::from_millis);
+        match requested {
+            Some(requested) => Some(requested.min(self.max_agent_call_timeout)),
+            None => self.agent_call_timeout,
+        }
+    }
+
+    fn origin_allowed(&self, origin: &str) -> bool {
+        match &self.origin_validator {
+            Some(validator) => validator(origin),
+            None => self.allowed_origins.iter().any(|allowed| allowed == origin),
+        }
+    }
+
+    // Versions the bridge does not speak are refused up front, listing the
+    // ones it does so the client can retry with one of them.
+    fn check_protocol_version(&self, requested: &acp::ProtocolVersion) -> Result<(), acp::Error> {
+        if self.supported_protocol_versions.is_empty()
+            || self.supported_protocol_versions.contains(requested)
+        {
+            return Ok(());
+        }
+        let mut data = error_data(
+            ErrorKind::UnsupportedProtocolVersion,
+            "unsupported protocol version",
+        );
+        data["requestedVersion"] = json!(requested);
+        data["supportedVersions"] = json!(self.supported_protocol_versions);
+        Err(acp::Error::invalid_params().with_data(data))
+    }
+
+    // Runs before any transport call so malformed ids never reach the agent.
+    fn check_session_id(&self, session_id: &str) -> Result<(), acp::Error> {
+        match &self.session_id_pattern {
+            Some(pattern) if !pattern.is_match(session_id) => {
+                Err(acp::Error::invalid_params().with_data("malformed session id"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // The agent's id for a session `session/new` handed out, if any.
+    async fn tracked_agent_session_id(&self, session_id: &str) -> Option<String> {
+        let sessions = self.sessions.lock().await;
+        sessions
+            .get(session_id)
+            .map(|session| session.agent_session_id.clone())
+    }
+
+    async fn agent_session_id(&self, session_id: &str) -> String {
+        let sessions = self.sessions.lock().await;
+        sessions
+            .get(session_id)
+            .map(|session| session.agent_session_id.clone())
+            .unwrap_or_else(|| session_id.to_string())
+    }
+
+    async fn register_session(
+        &self,
+        agent_session_id: &str,
+        connection_id: u64,
+        cwd: PathBuf,
+    ) -> Result<String, acp::Error> {
+        let mut sessions = self.sessions.lock().await;
+        let session_id = if sessions.contains_key(agent_session_id) {
+            match self.duplicate_session_policy {
+                DuplicateSessionPolicy::Reject => {
+                    return Err(acp::Error::internal_error()
+                        .with_data("agent returned duplicate session id"));
+                }
+                DuplicateSessionPolicy::Alias => loop {
+                    let alias = format!(
+                        "{agent_session_id}~{}",
+                        self.next_session_alias.fetch_add(1, Ordering::Relaxed)
+                    );
+                    if !sessions.contains_key(&alias) {
+                        break alias;
+                    }
+                },
+            }
+        } else {
+            agent_session_id.to_string()
+        };
+
+        let created_ms = unix_ms();
+        sessions.insert(
+            session_id.clone(),
+            SessionState {
+                agent_session_id: agent_session_id.to_string(),
+                connection_id,
+                cwd,
+                created_ms,
+                last_activity_ms: created_ms,
+            },
+        );
+        Ok(session_id)
+    }
+
+    // Marks a session as used by the current request, if the bridge tracks it.
+    async fn touch_session(&self, session_id: &str) {
+        if let Some(session) = self.sessions.lock().await.get_mut(session_id) {
+            session.last_activity_ms = unix_ms();
+        }
+    }
+
+    // Oldest first; only the caller's own sessions unless `list_all_sessions`.
+    async fn list_sessions(&self, connection_id: u64) -> Value {
+        let sessions = self.sessions.lock().await;
+        let mut listed: Vec<(&String, &SessionState)> = sessions
+            .iter()
+            .filter(|(_, session)| self.list_all_sessions || session.connection_id == connection_id)
+            .collect();
+        listed.sort_by(|(a_id, a), (b_id, b)| (a.created_ms, a_id).cmp(&(b.created_ms, b_id)));
+        listed
+            .into_iter()
+            .map(|(session_id, session)| {
+                json!({
+                    "sessionId": session_id,
+                    "cwd": session.cwd.to_string_lossy(),
+                    "createdMs": session.created_ms,
+                    "lastActivityMs": session.last_activity_ms,
+                })
+            })
+            .collect()
+    }
+
+    fn effective_project_roots(
+        &self,
+        connection: &ConnectionInfo,
+    ) -> Result<Vec<PathBuf>, acp::Error> {
+        if let Some(roots) = connection
+            .origin
+            .as_ref()
+            .and_then(|origin| self.origin_project_roots.get(origin))
+        {
+            return Ok(roots.clone());
+        }
+        if !self.project_roots.is_empty() {
+            return Ok(self.project_roots.clone());
+        }
+        Ok(vec![self.working_dir()?])
+    }
+
+    fn sandbox_roots(&self, connection: &ConnectionInfo) -> Result<SandboxRoots, acp::Error> {
+        let read = self.effective_project_roots(connection)?;
+        let write = if self.write_roots.is_empty() {
+            read.clone()
+        } else {
+            self.write_roots.clone()
+        };
+        Ok(SandboxRoots { read, write })
+    }
+
+    fn working_dir(&self) -> Result<PathBuf, acp::Error> {
+        if !self.project_root.as_os_str().is_empty() {
+            return Ok(self.project_root.clone());
+        }
+        std::env::current_dir().map_err(|_| {
+            acp::Error::internal_error()
+                .with_data(error_data(ErrorKind::Io, "failed to get current directory"))
+        })
+    }
+
+    // Caches a standing decision for each key and persists the cache, if a
+    // store is configured. A failed save only costs the decision its
+    // persistence.
+    async fn remember_decision(
+        &self,
+        keys: impl IntoIterator<Item = String>,
+        decision: PermissionDecision,
+    ) {
+        let mut cache = self.permission_cache.lock().await;
+        for key in keys {
+            cache.insert(key, decision.clone());
+        }
+        if let Some(store) = &self.permission_store {
+            if let Err(err) = store.save(&cache) {
+                tracing::warn!(error = %err, "failed to persist permission decisions");
+            }
+        }
+    }
+
+    // Drops the cached decisions `forget` selects and persists the rest.
+    async fn forget_decisions(&self, forget: impl Fn(&str) -> bool) -> usize {
+        let mut cache = self.permission_cache.lock().await;
+        let before = cache.len();
+        cache.retain(|key, _| !forget(key));
+        let removed = before - cache.len();
+        if removed > 0 {
+            if let Some(store) = &self.permission_store {
+                if let Err(err) = store.save(&cache) {
+                    tracing::warn!(error = %err, "failed to persist permission decisions");
+                }
+            }
+        }
+        removed
+    }
+
+    // Stops tracking a session the connection owns and hands back the agent's
+    // id for it. Permission decisions are cached per path, not per session,
+    // so they outlive it.
+    async fn close_session(&self, session_id: &str, connection_id: u64) -> Option<String> {
+        let mut sessions = self.sessions.lock().await;
+        match sessions.get(session_id) {
+            Some(session) if session.connection_id == connection_id => sessions
+                .remove(session_id)
+                .map(|session| session.agent_session_id),
+            _ => None,
+        }
+    }
+
+    async fn release_connection_sessions(&self, connection_id: u64) {
+        let mut sessions = self.sessions.lock().await;
+        sessions.retain(|_, session| session.connection_id != connection_id);
+    }
+}
+
+// Any byte stream a WebSocket can run over: TCP, or a Unix socket.
+trait ClientIo: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> ClientIo for T {}
+
+type ClientStream = Box<dyn ClientIo>;
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    async fn accept(&self) -> std::io::Result<(ClientStream, Option<SocketAddr>)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, peer_addr) = listener.accept().await?;
+                Ok((Box::new(stream), Some(peer_addr)))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => {
+                let (stream, _) = listener.accept().await?;
+                Ok((Box::new(stream), None))
+            }
+        }
+    }
+
+    fn close(self) {
+        #[cfg(unix)]
+        if let Listener::Unix(listener, path) = self {
+            drop(listener);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn spawn_accept_loop(
+    listener: Listener,
+    mut shutdown_rx: watch::Receiver<bool>,
+    shared: Arc<BridgeSharedConfig>,
+    transport: Arc<dyn AgentTransport>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = shutdown_rx.wait_for(|stop| *stop) => {
+                    break;
+                }
+                accept_result = listener.accept() => {
+                    let (stream, peer_addr) = match accept_result {
+                        Ok(pair) => pair,
+                        Err(_) => break,
+                    };
+                    let shared = shared.clone();
+                    let transport = transport.clone();
+                    tokio::spawn(async move {
+                        let metrics = shared.metrics.clone();
+                        // The handler runs on its own task so a panic surfaces
+                        // here as a `JoinError` instead of vanishing with it.
+                        let client = tokio::spawn(handle_client(stream, peer_addr, shared, transport));
+                        match client.await {
+                            Ok(Ok(())) => {}
+                            Ok(Err(ClientError::Handshake(error))) => {
+                                metrics.handshake_rejections.fetch_add(1, Ordering::Relaxed);
+                                tracing::debug!(error = %error, "websocket handshake rejected");
+                            }
+                            Ok(Err(ClientError::WebSocket(error))) => {
+                                tracing::debug!(error = %error, "websocket connection failed");
+                            }
+                            Err(err) if err.is_panic() => {
+                                metrics.connection_panics.fetch_add(1, Ordering::Relaxed);
+                                tracing::error!(
+                                    panic = %panic_message(err.into_panic().as_ref()),
+                                    "connection handler panicked"
+                                );
+                            }
+                            Err(_) => {}
+                        }
+                    });
+                }
+            }
+        }
+        listener.close();
+    })
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("non-string panic payload")
+}
+
+enum ClientError {
+    Handshake(tungstenite::Error),
+    WebSocket(tungstenite::Error),
+}
+
+async fn handle_client(
+    stream: ClientStream,
+    peer_addr: Option<SocketAddr>,
+    shared: Arc<BridgeSharedConfig>,
+    transport: Arc<dyn AgentTransport>,
+) -> Result<(), ClientError> {
+    let Some(stream) = answer_health_check(stream, &shared)
+        .await
+        .map_err(|err| ClientError::Handshake(tungstenite::Error::Io(err)))?
+    else {
+        return Ok(());
+    };
+    let (ws_stream, info) = accept_client(stream, peer_addr, shared.clone())
+        .await
+        .map_err(ClientError::Handshake)?;
+    handle_websocket(ws_stream, info, shared, transport)
+        .await
+        .map_err(ClientError::WebSocket)
+}
+
+const HEALTH_CHECK_PATH: &str = "/healthz";
+
+// Longest request head read while looking for a health check; anything
+// bigger is left for the WebSocket handshake to reject.
+const MAX_REQUEST_HEAD_BYTES: usize = 16 * 1024;
+
+// Liveness probes cannot speak WebSocket, so a plain `GET /healthz` without
+// an `Upgrade` header is answered here and the connection closed. Any other
+// request is handed back, with the bytes read so far replayed, for the
+// handshake.
+async fn answer_health_check(
+    mut stream: ClientStream,
+    shared: &BridgeSharedConfig,
+) -> std::io::Result<Option<ClientStream>> {
+    let mut head = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while head.len() < MAX_REQUEST_HEAD_BYTES && !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            break;
+        }
+        head.extend_from_slice(&chunk[..read]);
+    }
+
+    if !is_health_check(&head) {
+        return Ok(Some(Box::new(Rewound {
+            prefix: head,
+            position: 0,
+            inner: stream,
+        })));
+    }
+    let body = json!({
+        "status": "ok",
+        "bridgeId": shared.bridge_id,
+        "version": env!("CARGO_PKG_VERSION"),
+    })
+    .to_string();
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(None)
+}
+
+fn is_health_check(head: &[u8]) -> bool {
+    let Some(end) = head.windows(4).position(|w| w == b"\r\n\r\n") else {
+        return false;
+    };
+    let Ok(head) = std::str::from_utf8(&head[..end]) else {
+        return false;
+    };
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    if request_line.next() != Some("GET") || request_line.next() != Some(HEALTH_CHECK_PATH) {
+        return false;
+    }
+    !lines.any(|line| {
+        line.split_once(':')
+            .is_some_and(|(name, _)| name.trim().eq_ignore_ascii_case("upgrade"))
+    })
+}
+
+// A stream that yields `prefix` before reading on from `inner`, so bytes
+// consumed while peeking are not lost to the next reader.
+struct Rewound {
+    prefix: Vec<u8>,
+    position: usize,
+    inner: ClientStream,
+}
+
+impl AsyncRead for Rewound {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        if self.position < self.prefix.len() {
+            let remaining = &self.prefix[self.position..];
+            let len = remaining.len().min(buf.remaining());
+            buf.put_slice(&remaining[..len]);
+            self.position += len;
+            return std::task::Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Rewound {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[allow(clippy::result_large_err)]
+async fn accept_client(
+    stream: ClientStream,
+    peer_addr: Option<SocketAddr>,
+    shared: Arc<BridgeSharedConfig>,
+) -> Result<(WebSocketStream<ClientStream>, ConnectionInfo), tungstenite::Error> {
+    let supported_subprotocols: Vec<String> = std::iter::once(&shared.expected_subprotocol)
+        .chain(&shared.additional_subprotocols)
+        .cloned()
+        .collect();
+    let case_sensitive = shared.subprotocol_case_sensitive;
+    let check_order = shared.handshake_check_order;
+    let mut info = None;
+
+    // No `Sec-WebSocket-Extensions` are negotiated. tungstenite 0.21 has no
+    // permessage-deflate support and rejects frames with RSV1 set, so offers
+    // of compression are left unanswered and the client falls back to plain
+    // frames, as RFC 7692 requires.
+    let config = WebSocketConfig {
+        max_message_size: shared.max_message_bytes,
+        ..WebSocketConfig::default()
+    };
+    let ws_stream = accept_hdr_async_with_config(
+        stream,
+        |request: &Request, mut response: HandshakeResponse| {
+            let (origin, subprotocol) = match check_order {
+                HandshakeCheckOrder::OriginFirst => {
+                    let origin = validate_origin(request, &shared)?;
+                    let subprotocol = validate_subprotocol(
+                        request,
+                        &mut response,
+                        &supported_subprotocols,
+                        case_sensitive,
+                    )?;
+                    (origin, subprotocol)
+                }
+                HandshakeCheckOrder::SubprotocolFirst => {
+                    let subprotocol = validate_subprotocol(
+                        request,
+                        &mut response,
+                        &supported_subprotocols,
+                        case_sensitive,
+                    )?;
+                    let origin = validate_origin(request, &shared)?;
+                    (origin, subprotocol)
+                }
+            };
+            info = Some(ConnectionInfo {
+                origin,
+                subprotocol,
+                peer_addr,
+            });
+            Ok(response)
+        },
+        Some(config),
+    )
+    .await?;
+    let info = info.ok_or(tungstenite::Error::Protocol(
+        tungstenite::error::ProtocolError::HandshakeIncomplete,
+    ))?;
+    Ok((ws_stream, info))
+}
+
+#[allow(clippy::result_large_err)]
+fn validate_origin(
+    request: &Request,
+    shared: &BridgeSharedConfig,
+) -> Result<Option<String>, ErrorResponse> {
+    let Some(origin) = request.headers().get(ORIGIN) else {
+        return if shared.allow_missing_origin {
+            Ok(None)
+        } else {
+            Err(handshake_error(StatusCode::FORBIDDEN, "Origin not allowed"))
+        };
+    };
+    match origin.to_str() {
+        Ok(origin_value) if shared.origin_allowed(origin_value) => {
+            Ok(Some(origin_value.to_string()))
+        }
+        _ => Err(handshake_error(StatusCode::FORBIDDEN, "Origin not allowed")),
+    }
+}
+
+#[allow(clippy::result_large_err)]
+fn validate_subprotocol(
+    request: &Request,
+    response: &mut HandshakeResponse,
+    supported: &[String],
+    case_sensitive: bool,
+) -> Result<String, ErrorResponse> {
+    let header = request
+        .headers()
+        .get(SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|value| value.to_str().ok());
+
+    // RFC 6455 lets the server pick; honouring the client's order means a
+    // client offering a newer protocol first gets it.
+    let selected = header.and_then(|value| {
+        value.split(',').find_map(|candidate| {
+            let candidate = candidate.trim();
+            supported
+                .iter()
+                .any(|protocol| {
+                    if case_sensitive {
+                        candidate == protocol.as_str()
+                    } else {
+                        candidate.eq_ignore_ascii_case(protocol)
+                    }
+                })
+                .then_some(candidate)
+        })
+    });
+
+    let Some(selected) = selected else {
+        return Err(handshake_error(
+            StatusCode::UPGRADE_REQUIRED,
+            "Missing required subprotocol",
+        ));
+    };
+
+    let header_value = HeaderValue::from_str(selected)
+        .map_err(|_| handshake_error(StatusCode::BAD_REQUEST, "Invalid subprotocol"))?;
+    response
+        .headers_mut()
+        .insert(SEC_WEBSOCKET_PROTOCOL, header_value);
+    Ok(selected.to_string())
+}
+
+// Tries `addr`, then each fallback port on the same IP, for up to
+// `retries + 1` rounds. Only "address in use" moves on to the next candidate;
+// any other error is returned immediately.
+async fn bind_listener(
+    addr: SocketAddr,
+    fallback_ports: &[u16],
+    retries: u32,
+    retry_delay: Duration,
+) -> std::io::Result<TcpListener> {
+    let candidates: Vec<SocketAddr> = std::iter::once(addr)
+        .chain(
+            fallback_ports
+                .iter()
+                .map(|&port| SocketAddr::new(addr.ip(), port)),
+        )
+        .collect();
+    let mut attempt = 0;
+    loop {
+        let mut last_error = None;
+        for &candidate in &candidates {
+            match TcpListener::bind(candidate).await {
+                Ok(listener) => return Ok(listener),
+                Err(err) if err.kind() == std::io::ErrorKind::AddrInUse => {
+                    last_error = Some(err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        if attempt >= retries {
+            return Err(last_error.expect("at least one candidate was tried"));
+        }
+        attempt += 1;
+        tracing::debug!(%addr, attempt, "bind address in use, retrying");
+        tokio::time::sleep(retry_delay).await;
+    }
+}
+
+fn handshake_error(status: StatusCode, message: &str) -> ErrorResponse {
+    HttpResponse::builder()
+        .status(status)
+        .body(Some(message.to_owned()))
+        .unwrap_or_else(|_| HttpResponse::builder().status(status).body(None).unwrap())
+}
+
+async fn handle_websocket(
+    stream: WebSocketStream<ClientStream>,
+    info: ConnectionInfo,
+    shared: Arc<BridgeSharedConfig>,
+    transport: Arc<dyn AgentTransport>,
+) -> Result<(), tungstenite::Error> {
+    shared
+        .metrics
+        .active_connections
+        .fetch_add(1, Ordering::Relaxed);
+    let connection = ConnectionState::new(
+        shared.next_connection_id.fetch_add(1, Ordering::Relaxed),
+        info,
+        shared.agent_call_timeout,
+        NotificationQueue::new(
+            shared.notification_queue_capacity,
+            shared.notification_overflow,
+        ),
+    );
+    let redaction = shared.redact_paths.then(|| {
+        let roots = shared
+            .effective_project_roots(&connection.info)
+            .unwrap_or_default();
+        PathRedaction::new(&roots)
+    });
+    let (sink, mut source) = stream.split();
+    let sink = Arc::new(TokioMutex::new(WsSink::new(sink, redaction)));
+    let connection_id = connection.id;
+    let pending = connection.pending_client_requests.clone();
+    let cancellable = connection.cancellable_requests.clone();
+    let closed = connection.closed.clone();
+    let writer = tokio::spawn(write_notifications(
+        sink.clone(),
+        connection.notifications.clone(),
+        shared.notification_queue_capacity,
+        closed.subscribe(),
+    ));
+
+    // Requests are still handled one at a time, but on their own task so the
+    // socket keeps being read while a handler waits on the client to answer
+    // a bridge-originated request.
+    let (request_tx, request_rx) = mpsc::unbounded_channel();
+    let mut worker = tokio::spawn(request_worker(
+        sink.clone(),
+        shared.clone(),
+        transport,
+        connection,
+        request_rx,
+    ));
+
+    // The worker only finishes first after a failed send or a panic; either
+    // way nothing more can be served, so reading stops too.
+    let (read_result, worker_result) = tokio::select! {
+        read_result = read_loop(&mut source, &sink, &pending, &cancellable, &request_tx, shared.connection_idle_timeout) => (read_result, None),
+        worker_result = &mut worker => (Ok(CloseReason::Normal), Some(worker_result)),
+    };
+    let close = match (&read_result, &worker_result) {
+        (Err(err), _) => close_frame_for(err),
+        (_, Some(Err(err))) if err.is_panic() => {
+            Some(close_frame(CloseCode::Error, "internal error"))
+        }
+        (Ok(CloseReason::IdleTimeout), _) => Some(close_frame(CloseCode::Away, "idle timeout")),
+        _ => None,
+    };
+    if let Some(close) = close {
+        // Best effort: the peer may already be gone.
+        let _ = sink.lock().await.send(Message::Close(Some(close))).await;
+    }
+    drop(request_tx);
+    closed.send_replace(true);
+    // Dropping the senders fails any handler still waiting on the client.
+    pending
+        .lock()
+        .expect("pending client requests lock poisoned")
+        .clear();
+    let worker_result = match worker_result {
+        Some(worker_result) => worker_result,
+        None => worker.await,
+    };
+    let _ = writer.await;
+
+    shared.release_connection_sessions(connection_id).await;
+    shared
+        .metrics
+        .active_connections
+        .fetch_sub(1, Ordering::Relaxed);
+    shared
+        .metrics
+        .record_close(close_reason(&read_result, &worker_result));
+    match worker_result {
+        Ok(worker_result) => read_result.and(worker_result),
+        // Re-raised once the connection is cleaned up so it is reported as
+        // a crashed connection rather than an ordinary socket error.
+        Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+        Err(err) => Err(tungstenite::Error::Io(std::io::Error::other(err))),
+    }
+}
+
+async fn request_worker(
+    stream: SharedSink,
+    shared: Arc<BridgeSharedConfig>,
+    transport: Arc<dyn AgentTransport>,
+    mut connection: ConnectionState,
+    mut requests: mpsc::UnboundedReceiver<Value>,
+) -> Result<(), tungstenite::Error> {
+    while let Some(mut value) = requests.recv().await {
+        let method = value
+            .get("method")
+            .and_then(Value::as_str)
+            .unwrap_or("")
+            .to_string();
+        let trace_id = trace_request(&shared, &mut value, &method);
+        tracing::debug!(
+            peer = %peer_label(&connection.info),
+            method = %method,
+            trace_id = %trace_id,
+            "request received"
+        );
+        let started = Instant::now();
+        let cancel_key = value.get("id").map(Value::to_string);
+        process_request(stream.clone(), &shared, &transport, &mut connection, value).await?;
+        if let Some(cancel_key) = cancel_key {
+            connection
+                .cancellable_requests
+                .lock()
+                .expect("cancellable requests lock poisoned")
+                .remove(&cancel_key);
+        }
+        tracing::debug!(
+            peer = %peer_label(&connection.info),
+            method = %method,
+            trace_id = %trace_id,
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "request handled"
+        );
+    }
+    Ok(())
+}
+
+// Methods whose params are handed to the agent, and so can carry a trace id
+// it can log alongside its own work.
+const FORWARDED_METHODS: &[&str] = &[
+    "initialize",
+    "session/new",
+    "session/prompt",
+    "session/set_mode",
+];
+
+// Startup time keeps trace ids from repeating across bridge restarts.
+fn trace_prefix() -> String {
+    let started_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis())
+        .unwrap_or_default();
+    format!("{started_ms:x}")
+}
+
+// Picks the trace id logged for a request and, for forwarded methods, records
+// it as `params._meta.traceId` next to whatever `_meta` the client sent. A
+// trace id the client supplied itself is kept so its logs line up too.
+fn trace_request(shared: &BridgeSharedConfig, value: &mut Value, method: &str) -> String {
+    let next = || {
+        let sequence = shared.next_trace_id.fetch_add(1, Ordering::Relaxed);
+        format!("{}-{sequence}", shared.trace_prefix)
+    };
+    if !FORWARDED_METHODS.contains(&method) {
+        return next();
+    }
+    let meta = value
+        .as_object_mut()
+        .map(|request| request.entry("params").or_insert_with(|| json!({})))
+        .and_then(Value::as_object_mut)
+        .map(|params| params.entry("_meta").or_insert_with(|| json!({})))
+        .and_then(Value::as_object_mut);
+    let Some(meta) = meta else {
+        return next();
+    };
+    if let Some(Value::String(trace_id)) = meta.get("traceId") {
+        return trace_id.clone();
+    }
+    let trace_id = next();
+    meta.insert("traceId".to_string(), json!(trace_id));
+    trace_id
+}
+
+// How request logs name the client: its address, or `unix` for clients
+// without one.
+fn peer_label(info: &ConnectionInfo) -> String {
+    info.peer_addr
+        .map_or_else(|| "unix".to_string(), |addr| addr.to_string())
+}
+
+// Sorts a finished connection by the first thing that ended it.
+fn close_reason(
+    read_result: &Result<CloseReason, tungstenite::Error>,
+    worker_result: &Result<Result<(), tungstenite::Error>, tokio::task::JoinError>,
+) -> CloseReason {
+    match (read_result, worker_result) {
+        (Err(err), _) if close_frame_for(err).is_some() => CloseReason::ProtocolError,
+        (Err(_), _) => CloseReason::IoError,
+        (_, Err(_)) => CloseReason::InternalError,
+        (_, Ok(Err(_))) => CloseReason::IoError,
+        (Ok(reason), Ok(Ok(()))) => *reason,
+    }
+}
+
+async fn read_loop(
+    source: &mut SplitStream<WebSocketStream<ClientStream>>,
+    stream: &SharedSink,
+    pending: &PendingClientRequests,
+    cancellable: &CancellableRequests,
+    requests: &mpsc::UnboundedSender<Value>,
+    idle_timeout: Option<Duration>,
+) -> Result<CloseReason, tungstenite::Error> {
+    loop {
+        let next = match idle_timeout {
+            None => source.next().await,
+            Some(idle_timeout) => match timeout(idle_timeout, source.next()).await {
+                Ok(next) => next,
+                // Quiet inbound, but the bridge may still be sending.
+                Err(_) if stream.lock().await.last_sent.elapsed() < idle_timeout => continue,
+                Err(_) => return Ok(CloseReason::IdleTimeout),
+            },
+        };
+        let value: Value = match next {
+            Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                Ok(value) => value,
+                Err(_) => {
+                    reject_unparseable(stream).await?;
+                    return Ok(CloseReason::InvalidJson);
+                }
+            },
+            Some(Ok(Message::Binary(bytes))) => match serde_json::from_slice(&bytes) {
+                Ok(value) => value,
+                Err(_) => {
+                    reject_unparseable(stream).await?;
+                    return Ok(CloseReason::InvalidJson);
+                }
+            },
+            Some(Ok(Message::Ping(payload))) => {
+                let mut stream_guard = stream.lock().await;
+                stream_guard.send(Message::Pong(payload)).await?;
+                continue;
+            }
+            Some(Ok(Message::Pong(_))) => continue,
+            // tungstenite reassembles fragmented text and binary messages
+            // before they get here and fails the read on a continuation frame
+            // that has nothing to continue, so raw frames never surface from
+            // a read. Should one appear anyway, treat it as the protocol
+            // violation it is rather than dropping part of a request.
+            Some(Ok(Message::Frame(_))) => {
+                return Err(tungstenite::Error::Protocol(
+                    tungstenite::error::ProtocolError::UnexpectedContinueFrame,
+                ))
+            }
+            Some(Ok(Message::Close(_))) | None => break,
+            Some(Err(e)) => return Err(e),
+        };
+
+        if let Some(value) = route_client_response(pending, value) {
+            // Handled here rather than by the worker, which is busy with the
+            // very prompt being cancelled.
+            if !track_cancellation(cancellable, &value) {
+                continue;
+            }
+            if requests.send(value).is_err() {
+                // The worker stopped after a failed send; nothing left to serve.
+                break;
+            }
+        }
+    }
+
+    Ok(CloseReason::Normal)
+}
+
+// A message that is not JSON gets the JSON-RPC parse error and then a close
+// frame saying why the connection is ending.
+async fn reject_unparseable(stream: &SharedSink) -> Result<(), tungstenite::Error> {
+    let mut stream_guard = stream.lock().await;
+    send_error(&mut stream_guard, Value::Null, acp::Error::parse_error()).await?;
+    stream_guard
+        .send(Message::Close(Some(close_frame(
+            CloseCode::Invalid,
+            "invalid JSON",
+        ))))
+        .await
+}
+
+fn close_frame(code: CloseCode, reason: &'static str) -> CloseFrame<'static> {
+    CloseFrame {
+        code,
+        reason: reason.into(),
+    }
+}
+
+// The close frame to send before dropping a connection whose read failed, or
+// `None` when the socket is already unusable.
+fn close_frame_for(error: &tungstenite::Error) -> Option<CloseFrame<'static>> {
+    match error {
+        tungstenite::Error::Protocol(_) => Some(close_frame(CloseCode::Protocol, "protocol error")),
+        tungstenite::Error::Capacity(_) => Some(close_frame(CloseCode::Size, "message too big")),
+        tungstenite::Error::Utf8 => Some(close_frame(CloseCode::Invalid, "invalid UTF-8")),
+        _ => None,
+    }
+}
+
+// Hands responses to bridge-originated requests to whoever is waiting on them
+// and returns every other message for normal processing.
+fn route_client_response(pending: &PendingClientRequests, value: Value) -> Option<Value> {
+    if value.get("method").is_some() {
+        return Some(value);
+    }
+    let waiter = value.get("id").and_then(|id| id.as_str()).and_then(|id| {
+        pending
+            .lock()
+            .expect("pending client requests lock poisoned")
+            .remove(id)
+    });
+    match waiter {
+        Some(waiter) => {
+            let _ = waiter.send(value);
+            None
+        }
+        None => Some(value),
+    }
+}
+
+// Applies a `$/cancelRequest` notification, returning false so it is not
+// processed further, and registers prompts so they can be cancelled.
+fn track_cancellation(cancellable: &CancellableRequests, value: &Value) -> bool {
+    let method = value.get("method").and_then(Value::as_str);
+    let id = value.get("id");
+    let mut cancellable = cancellable
+        .lock()
+        .expect("cancellable requests lock poisoned");
+    match (method, id) {
+        (Some(CANCEL_REQUEST_METHOD), None) => {
+            // Unknown ids are ignored: the request already finished, or
+            // never existed.
+            if let Some(cancel) = value
+                .pointer("/params/id")
+                .and_then(|id| cancellable.get(&id.to_string()))
+            {
+                cancel.send_replace(true);
+            }
+            false
+        }
+        (Some("session/prompt"), Some(id)) => {
+            cancellable.insert(id.to_string(), watch::channel(false).0);
+            true
+        }
+        _ => true,
+    }
+}
+
+async fn with_agent_timeout<T>(
+    limit: Option<Duration>,
+    call: impl Future<Output = Result<T, AgentTransportError>>,
+) -> Result<T, AgentTransportError> {
+    let Some(limit) = limit else {
+        return call.await;
+    };
+    timeout(limit, call)
+        .await
+        .unwrap_or(Err(AgentTransportError::Timeout(limit)))
+}
+
+// JSON-RPC notifications carry no `id` and must never be answered, not even
+// with an error. Beyond `$/cancelRequest`, which `read_loop` consumes, the
+// bridge defines none, so every one is dropped.
+fn process_notification(method: Option<&str>) {
+    tracing::debug!(
+        method = method.unwrap_or(""),
+        "dropping unknown notification"
+    );
+}
+
+async fn process_request(
+    stream: SharedSink,
+    shared: &Arc<BridgeSharedConfig>,
+    transport: &Arc<dyn AgentTransport>,
+    connection: &mut ConnectionState,
+    value: Value,
+) -> Result<(), tungstenite::Error> {
+    let method = value.get("method").and_then(|value| value.as_str());
+    let id = match value.get("id") {
+        Some(id) => id.clone(),
+        None => {
+            process_notification(method);
+            return Ok(());
+        }
+    };
+
+    if value.get("jsonrpc").and_then(|version| version.as_str()) != Some("2.0") {
+        send_error_shared(&stream, id, acp::Error::invalid_request()).await?;
+        return Ok(());
+    }
+
+    let method = match method {
+        Some(method) => method,
+        None => {
+            send_error_shared(&stream, id, acp::Error::invalid_request()).await?;
+            return Ok(());
+        }
+    };
+
+    shared.metrics.record_request(method);
+
+    if let Err(error) = shared.request_policy.check(&connection.info, method) {
+        send_error_shared(&stream, id, error).await?;
+        return Ok(());
+    }
+
+    match method {
+        "bridge/ping" if shared.enable_ping => {
+            let result = json!({
+                "pong": true,
+                "bridgeId": shared.bridge_id,
+                "version": env!("CARGO_PKG_VERSION"),
+            });
+            send_result_shared(&stream, id, result).await?;
+        }
+        "bridge/capabilities" => {
+            let result = bridge_capabilities(shared, &connection.info);
+            send_result_shared(&stream, id, result).await?;
+        }
+        "initialize" => {
+            // Re-initializing would leave sessions created under the first
+            // handshake attached to a connection with new settings, so a
+            // connection is initialized exactly once.
+            if connection.initialized {
+                let error =
+                    acp::Error::invalid_request().with_data("connection already initialized");
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            }
+
+            let params = value.get("params").cloned().unwrap_or_else(|| json!({}));
+            let request: acp::InitializeRequest = match serde_json::from_value(params) {
+                Ok(request) => request,
+                Err(err) => {
+                    send_error_shared(
+                        &stream,
+                        id,
+                        acp::Error::invalid_params().with_data(err.to_string()),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+            };
+
+            if let Err(error) = shared.check_protocol_version(&request.protocol_version) {
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            }
+
+            connection.agent_call_timeout =
+                shared.connection_agent_call_timeout(request.meta.as_ref());
+            let response = with_agent_timeout(
+                shared.initialize_timeout.or(connection.agent_call_timeout),
+                transport.initialize(request),
+            )
+            .await;
+            match response {
+                Ok(mut response) => {
+                    ensure_bridge_meta(&mut response, shared);
+                    if shared.enable_cli_login {
+                        ensure_cli_login_auth_method(&mut response);
+                    }
+                    match response_value(serde_json::to_value(response)) {
+                        Ok(result) => {
+                            send_result_shared(&stream, id, result).await?;
+                            connection.initialized = true;
+                        }
+                        Err(error) => send_error_shared(&stream, id, error).await?,
+                    }
+                }
+                Err(err) => {
+                    let error = err.into_rpc_error();
+                    send_error_shared(&stream, id, error).await?;
+                }
+            }
+        }
+        "session/new" => {
+            if !connection.initialized {
+                let error = acp::Error::method_not_found();
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            }
+
+            let params = value.get("params").cloned().unwrap_or_else(|| json!({}));
+            let request: acp::NewSessionRequest = match serde_json::from_value(params) {
+                Ok(request) => request,
+                Err(err) => {
+                    send_error_shared(
+                        &stream,
+                        id,
+                        acp::Error::invalid_params().with_data(err.to_string()),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+            };
+
+            let cwd = request.cwd.clone();
+            let response = with_agent_timeout(
+                shared.new_session_timeout.or(connection.agent_call_timeout),
+                transport.new_session(request),
+            )
+            .await;
+            match response {
+                Ok(mut response) => {
+                    let session_id = match shared
+                        .register_session(&response.session_id.0, connection.id, cwd)
+                        .await
+                    {
+                        Ok(session_id) => session_id,
+                        Err(error) => {
+                            send_error_shared(&stream, id, error).await?;
+                            return Ok(());
+                        }
+                    };
+                    response.session_id = acp::SessionId(session_id.into());
+                    match response_value(serde_json::to_value(response)) {
+                        Ok(mut result) => {
+                            ensure_bridge_meta_value(&mut result, &shared.bridge_id);
+                            send_result_shared(&stream, id, result).await?
+                        }
+                        Err(error) => send_error_shared(&stream, id, error).await?,
+                    }
+                }
+                Err(err) => {
+                    let error = err.into_rpc_error();
+                    send_error_shared(&stream, id, error).await?;
+                }
+            }
+        }
+        "session/prompt" => {
+            if !connection.initialized {
+                let error = acp::Error::method_not_found();
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            }
+
+            let params = value.get("params").cloned().unwrap_or_else(|| json!({}));
+
+            let mut request = match parse_prompt_params(&params) {
+                Ok(request) => request,
+                Err(error) => {
+                    send_error_shared(&stream, id, error).await?;
+                    return Ok(());
+                }
+            };
+
+            if let Err(error) = shared.check_session_id(&request.session_id.0) {
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            }
+            if connection
+                .closed_sessions
+                .contains(request.session_id.0.as_ref())
+            {
+                let error = acp::Error::invalid_params().with_data("unknown session id");
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            }
+
+            if let Some(max_prompt_chars) = shared.max_prompt_chars {
+                if prompt_char_count(&request.prompt) > max_prompt_chars {
+                    let error = acp::Error::invalid_params().with_data("prompt too long");
+                    send_error_shared(&stream, id, error).await?;
+                    return Ok(());
+                }
+            }
+
+            let client_session_id = request.session_id.0.to_string();
+            shared.touch_session(&client_session_id).await;
+            let session_id = shared.agent_session_id(&request.session_id.0).await;
+            request.session_id = acp::SessionId(session_id.into());
+
+            let mut closed = connection.closed.subscribe();
+            let notification_sender = Arc::new(
+                WebSocketNotificationSender::correlated(
+                    connection.notifications.clone(),
+                    id.clone(),
+                    closed.clone(),
+                )
+                .serving_agent_requests(AgentRequestContext {
+                    shared: shared.clone(),
+                    connection: connection.info.clone(),
+                    client: connection.client_requester(&stream),
+                    client_session_id: client_session_id.clone(),
+                }),
+            );
+            let prompt_timeout = shared.prompt_timeout.or(connection.agent_call_timeout);
+            let (overall_timeout, idle_timeout) = match shared.prompt_timeout_mode {
+                PromptTimeoutMode::Overall => (prompt_timeout, None),
+                PromptTimeoutMode::Idle => (None, prompt_timeout),
+            };
+            let mut cancelled = connection
+                .cancellable_requests
+                .lock()
+                .expect("cancellable requests lock poisoned")
+                .get(&id.to_string())
+                .map(watch::Sender::subscribe);
+            let prompt = with_agent_timeout(
+                overall_timeout,
+                transport.prompt(request, notification_sender.clone()),
+            );
+            tokio::pin!(prompt);
+            // Nobody is left to answer once the client disconnects, so the
+            // prompt future is dropped instead of running to completion.
+            let response = loop {
+                tokio::select! {
+                    response = &mut prompt => break response,
+                    // The agent's future is dropped, as on disconnect, and the
+                    // client gets the stop reason ACP uses for cancellation.
+                    () = async {
+                        let fired = match &mut cancelled {
+                            Some(cancelled) => cancelled.wait_for(|cancelled| *cancelled).await.is_ok(),
+                            None => false,
+                        };
+                        if !fired {
+                            std::future::pending::<()>().await;
+                        }
+                    } => {
+                        break Ok(acp::PromptResponse {
+                            stop_reason: acp::StopReason::Cancelled,
+                            meta: None,
+                        });
+                    }
+                    // Mapped to `()` so the `!Send` borrow it returns is not
+                    // held across the heartbeat send below.
+                    () = async { let _ = closed.wait_for(|closed| *closed).await; } => {
+                        tracing::debug!(connection = connection.id, "client disconnected mid-prompt");
+                        return Ok(());
+                    }
+                    limit = notification_sender.agent_silence(idle_timeout) => {
+                        break Err(AgentTransportError::Timeout(limit));
+                    }
+                    _ = notification_sender.silence(shared.prompt_heartbeat_interval) => {
+                        let params = json!({
+                            "sessionId": client_session_id,
+                            "update": {"type": "heartbeat"},
+                        });
+                        if let Err(err) = notification_sender
+                            .send_heartbeat(params)
+                            .await
+                        {
+                            tracing::debug!(connection = connection.id, error = ?err, "failed to send prompt heartbeat");
+                        }
+                    }
+                }
+            };
+            // Updates the prompt produced must reach the client before its
+            // result does.
+            connection.notifications.flush().await;
+            match response {
+                Ok(response) => match response_value(serde_json::to_value(response)) {
+                    Ok(mut result) => {
+                        ensure_bridge_meta_value(&mut result, &shared.bridge_id);
+                        send_result_shared(&stream, id, result).await?
+                    }
+                    Err(error) => send_error_shared(&stream, id, error).await?,
+                },
+                Err(err) => {
+                    let error = err.into_rpc_error();
+                    send_error_shared(&stream, id, error).await?;
+                }
+            }
+        }
+        "session/set_mode" => {
+            if !connection.initialized {
+                let error = acp::Error::method_not_found();
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            }
+
+            let params = value.get("params").cloned().unwrap_or_else(|| json!({}));
+            let mut request: acp::SetSessionModeRequest = match serde_json::from_value(params) {
+                Ok(request) => request,
+                Err(err) => {
+                    send_error_shared(
+                        &stream,
+                        id,
+                        acp::Error::invalid_params().with_data(err.to_string()),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+            };
+
+            if let Err(error) = shared.check_session_id(&request.session_id.0) {
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            }
+            let Some(agent_session_id) =
+                shared.tracked_agent_session_id(&request.session_id.0).await
+            else {
+                let error = acp::Error::invalid_params().with_data("unknown session id");
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            };
+            shared.touch_session(&request.session_id.0).await;
+            request.session_id = acp::SessionId(agent_session_id.into());
+
+            let response =
+                with_agent_timeout(connection.agent_call_timeout, transport.set_mode(request))
+                    .await;
+            match response {
+                Ok(response) => match response_value(serde_json::to_value(response)) {
+                    Ok(result) => send_result_shared(&stream, id, result).await?,
+                    Err(error) => send_error_shared(&stream, id, error).await?,
+                },
+                Err(err) => {
+                    let error = err.into_rpc_error();
+                    send_error_shared(&stream, id, error).await?;
+                }
+            }
+        }
+        "session/close" => {
+            if !connection.initialized {
+                let error = acp::Error::method_not_found();
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            }
+
+            let Some(session_id) = value.pointer("/params/sessionId").and_then(Value::as_str)
+            else {
+                let error = acp::Error::invalid_params().with_data("expected a sessionId");
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            };
+            if let Err(error) = shared.check_session_id(session_id) {
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            }
+            let Some(agent_session_id) = shared.close_session(session_id, connection.id).await
+            else {
+                let error = acp::Error::invalid_params().with_data("unknown session id");
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            };
+            connection.closed_sessions.insert(session_id.to_string());
+
+            // The session is gone on the bridge's side whatever the agent
+            // answers, so a failed hook is only logged.
+            let closed = with_agent_timeout(
+                connection.agent_call_timeout,
+                transport.close_session(acp::SessionId(agent_session_id.into())),
+            )
+            .await;
+            match closed {
+                Ok(()) | Err(AgentTransportError::NotImplemented) => {}
+                Err(err) => {
+                    tracing::debug!(connection = connection.id, error = ?err, "agent failed to close session");
+                }
+            }
+            send_result_shared(&stream, id, json!({})).await?;
+        }
+        "fs/read_text_file" => {
+            if !connection.initialized {
+                let error = acp::Error::method_not_found();
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            }
+
+            let params = value.get("params").cloned().unwrap_or_else(|| json!({}));
+
+            // Extract parameters
+            let path = match params.get("path").and_then(|v| v.as_str()) {
+                Some(path) => path,
+                None => {
+                    send_error_shared(
+                        &stream,
+                        id,
+                        acp::Error::invalid_params().with_data("missing or invalid path parameter"),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+            };
+
+            let base = match base_param(&params) {
+                Ok(base) => base,
+                Err(error) => {
+                    send_error_shared(&stream, id, error).await?;
+                    return Ok(());
+                }
+            };
+
+            let line_offset = params
+                .get("line_offset")
+                .and_then(|v| v.as_u64())
+                .map(|v| v as u32);
+
+            let line_limit = params
+                .get("line_limit")
+                .and_then(|v| v.as_u64())
+                .map(|v| v as u32);
+
+            let with_line_numbers = params
+                .get("with_line_numbers")
+                .and_then(Value::as_bool)
+                .unwrap_or(false);
+
+            let encoding = match params.get("encoding") {
+                None | Some(Value::Null) => None,
+                Some(label) => match label
+                    .as_str()
+                    .and_then(|label| Encoding::for_label(label.as_bytes()))
+                {
+                    // UTF-8 keeps the default path, with its BOM sniffing.
+                    Some(encoding) if encoding == UTF_8 => None,
+                    Some(encoding) => Some(encoding),
+                    None => {
+                        let error = acp::Error::invalid_params()
+                            .with_data(format!("unsupported encoding: {label}"));
+                        send_error_shared(&stream, id, error).await?;
+                        return Ok(());
+                    }
+                },
+            };
+
+            let read = handle_read_text_file(
+                shared,
+                &connection.info,
+                base,
+                path,
+                line_offset,
+                line_limit,
+                encoding,
+            );
+            match read {
+                Ok(window) => {
+                    let result = text_window_result(shared, window, line_offset, with_line_numbers);
+                    send_result_shared(&stream, id, result).await?;
+                }
+                Err(error) => {
+                    send_error_shared(&stream, id, error).await?;
+                }
+            }
+        }
+        "fs/read_text_files" => {
+            if !connection.initialized {
+                let error = acp::Error::method_not_found();
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            }
+
+            let params = value.get("params").cloned().unwrap_or_else(|| json!({}));
+
+            let paths: Option<Vec<&str>> = params
+                .get("paths")
+                .and_then(Value::as_array)
+                .and_then(|paths| paths.iter().map(Value::as_str).collect());
+            let Some(paths) = paths else {
+                send_error_shared(
+                    &stream,
+                    id,
+                    acp::Error::invalid_params().with_data("missing or invalid paths parameter"),
+                )
+                .await?;
+                return Ok(());
+            };
+            if paths.len() > shared.max_read_batch {
+                let error = acp::Error::invalid_params().with_data(error_data(
+                    ErrorKind::TooLarge,
+                    format!(
+                        "batch exceeds max_read_batch ({} paths)",
+                        shared.max_read_batch
+                    ),
+                ));
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            }
+
+            let base = match base_param(&params) {
+                Ok(base) => base,
+                Err(error) => {
+                    send_error_shared(&stream, id, error).await?;
+                    return Ok(());
+                }
+            };
+
+            // Each path succeeds or fails on its own, with the error the
+            // single-file method would have answered with.
+            let results: Vec<Value> = paths
+                .into_iter()
+                .map(|path| {
+                    let read = handle_read_text_file(
+                        shared,
+                        &connection.info,
+                        base,
+                        path,
+                        None,
+                        None,
+                        None,
+                    );
+                    let mut result = match read {
+                        Ok(window) => text_window_result(shared, window, None, false),
+                        Err(error) => json!({ "error": error }),
+                    };
+                    result["path"] = json!(path);
+                    result
+                })
+                .collect();
+            send_result_shared(&stream, id, json!({ "results": results })).await?;
+        }
+        "fs/write_text_file" => {
+            if !connection.initialized {
+                let error = acp::Error::method_not_found();
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            }
+
+            let params = value.get("params").cloned().unwrap_or_else(|| json!({}));
+
+            // Extract parameters
+            let session_id = match params.get("sessionId") {
+                Some(Value::String(session_id)) => Some(session_id.as_str()),
+                None if !shared.require_session_for_write => None,
+                _ => {
+                    send_error_shared(
+                        &stream,
+                        id,
+                        acp::Error::invalid_params()
+                            .with_data("missing or invalid sessionId parameter"),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+            };
+
+            if let Some(Err(error)) =
+                session_id.map(|session_id| shared.check_session_id(session_id))
+            {
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            }
+
+            let path = match params.get("path").and_then(|v| v.as_str()) {
+                Some(path) => path,
+                None => {
+                    send_error_shared(
+                        &stream,
+                        id,
+                        acp::Error::invalid_params().with_data("missing or invalid path parameter"),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+            };
+
+            let content = match params.get("content").and_then(|v| v.as_str()) {
+                Some(content) => content,
+                None => {
+                    send_error_shared(
+                        &stream,
+                        id,
+                        acp::Error::invalid_params()
+                            .with_data("missing or invalid content parameter"),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+            };
+
+            let base = match base_param(&params) {
+                Ok(base) => base,
+                Err(error) => {
+                    send_error_shared(&stream, id, error).await?;
+                    return Ok(());
+                }
+            };
+
+            let mode = match WriteMode::from_params(&params) {
+                Ok(mode) => mode,
+                Err(error) => {
+                    send_error_shared(&stream, id, error).await?;
+                    return Ok(());
+                }
+            };
+
+            let skip_if_unchanged = params
+                .get("skip_if_unchanged")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false);
+
+            let request = WriteTextFileRequest {
+                session_id,
+                base,
+                path,
+                content,
+                mode,
+                skip_if_unchanged,
+            };
+            match handle_write_text_file(&stream, shared, transport, connection, request).await {
+                Ok(outcome) => {
+                    if let Some(cache) = &shared.read_cache {
+                        cache.invalidate(&outcome.canonical_path);
+                    }
+                    let result = json!({
+                        "bytesWritten": outcome.bytes_written,
+                        "canonicalPath": outcome.canonical_path.to_string_lossy(),
+                        "written": outcome.written,
+                        "unchanged": !outcome.written,
+                    });
+                    send_result_shared(&stream, id, result).await?;
+                }
+                Err(error) => {
+                    send_error_shared(&stream, id, error).await?;
+                }
+            }
+        }
+        "permission/request_batch" => {
+            if !connection.initialized {
+                let error = acp::Error::method_not_found();
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            }
+
+            let params = value.get("params").cloned().unwrap_or_else(|| json!({}));
+
+            let session_id = match params.get("sessionId").and_then(|v| v.as_str()) {
+                Some(session_id) => session_id,
+                None => {
+                    send_error_shared(
+                        &stream,
+                        id,
+                        acp::Error::invalid_params()
+                            .with_data("missing or invalid sessionId parameter"),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+            };
+
+            if let Err(error) = shared.check_session_id(session_id) {
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            }
+
+            let paths: Option<Vec<&str>> = params
+                .get("paths")
+                .and_then(|v| v.as_array())
+                .and_then(|paths| paths.iter().map(|path| path.as_str()).collect());
+            let paths = match paths {
+                Some(paths) if !paths.is_empty() => paths,
+                _ => {
+                    send_error_shared(
+                        &stream,
+                        id,
+                        acp::Error::invalid_params()
+                            .with_data("paths must be a non-empty array of strings"),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+            };
+
+            let mode = match WriteMode::from_params(&params) {
+                Ok(mode) => mode,
+                Err(error) => {
+                    send_error_shared(&stream, id, error).await?;
+                    return Ok(());
+                }
+            };
+
+            match handle_permission_request_batch(
+                &stream, shared, transport, connection, session_id, &paths, mode,
+            )
+            .await
+            {
+                Ok(result) => {
+                    send_result_shared(&stream, id, result).await?;
+                }
+                Err(error) => {
+                    send_error_shared(&stream, id, error).await?;
+                }
+            }
+        }
+        "bridge/reset_permission" if shared.enable_permission_reset => {
+            if !connection.initialized {
+                let error = acp::Error::method_not_found();
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            }
+
+            let params = value.get("params").cloned().unwrap_or_else(|| json!({}));
+            match handle_reset_permission(shared, connection, &params).await {
+                Ok(result) => {
+                    send_result_shared(&stream, id, result).await?;
+                }
+                Err(error) => {
+                    send_error_shared(&stream, id, error).await?;
+                }
+            }
+        }
+        "bridge/list_sessions" => {
+            if !connection.initialized {
+                let error = acp::Error::method_not_found();
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            }
+
+            let result = shared.list_sessions(connection.id).await;
+            send_result_shared(&stream, id, result).await?;
+        }
+        "fs/stat" => {
+            if !connection.initialized {
+                let error = acp::Error::method_not_found();
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            }
+
+            let params = value.get("params").cloned().unwrap_or_else(|| json!({}));
+
+            let path = match params.get("path").and_then(|v| v.as_str()) {
+                Some(path) => path,
+                None => {
+                    send_error_shared(
+                        &stream,
+                        id,
+                        acp::Error::invalid_params().with_data("missing or invalid path parameter"),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+            };
+
+            match handle_stat(shared, connection, path) {
+                Ok(result) => {
+                    send_result_shared(&stream, id, result).await?;
+                }
+                Err(error) => {
+                    send_error_shared(&stream, id, error).await?;
+                }
+            }
+        }
+        "fs/exists" => {
+            if !connection.initialized {
+                let error = acp::Error::method_not_found();
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            }
+
+            let params = value.get("params").cloned().unwrap_or_else(|| json!({}));
+
+            let path = match params.get("path").and_then(|v| v.as_str()) {
+                Some(path) => path,
+                None => {
+                    send_error_shared(
+                        &stream,
+                        id,
+                        acp::Error::invalid_params().with_data("missing or invalid path parameter"),
+                    )
+                    .await?;
+                    return Ok(());
+                }
+            };
+
+            match handle_exists(shared, connection, path) {
+                Ok(result) => {
+                    send_result_shared(&stream, id, result).await?;
+                }
+                Err(error) => {
+                    send_error_shared(&stream, id, error).await?;
+                }
+            }
+        }
+        "fs/sandbox_info" => {
+            if !connection.initialized {
+                let error = acp::Error::method_not_found();
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            }
+
+            match handle_sandbox_info(shared, connection) {
+                Ok(result) => {
+                    send_result_shared(&stream, id, result).await?;
+                }
+                Err(error) => {
+                    send_error_shared(&stream, id, error).await?;
+                }
+            }
+        }
+        "project/recent_files" => {
+            if !connection.initialized {
+                let error = acp::Error::method_not_found();
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            }
+
+            let params = value.get("params").cloned().unwrap_or_else(|| json!({}));
+            let limit = params
+                .get("limit")
+                .and_then(|v| v.as_u64())
+                .map(|v| v as usize)
+                .unwrap_or(DEFAULT_RECENT_FILES_LIMIT);
+
+            match handle_recent_files(shared, connection, limit) {
+                Ok(result) => {
+                    send_result_shared(&stream, id, result).await?;
+                }
+                Err(error) => {
+                    send_error_shared(&stream, id, error).await?;
+                }
+            }
+        }
+        "auth/cli_login" if shared.enable_cli_login => match handle_auth_cli_login(shared).await {
+            Ok(LoginOutcome::Started { login_url }) => {
+                let result = json!({
+                    "status": "started",
+                    "loginUrl": login_url,
+                });
+                send_result_shared(&stream, id, result).await?;
+            }
+            Ok(LoginOutcome::AlreadyAuthenticated) => {
+                let result = json!({ "status": "already_authenticated" });
+                send_result_shared(&stream, id, result).await?;
+            }
+            Err(error) => {
+                send_error_shared(&stream, id, error).await?;
+            }
+        },
+        _ => {
+            let handler = shared.method_handlers.get(method);
+            let Some(handler) = handler.filter(|_| connection.initialized) else {
+                let error = acp::Error::method_not_found();
+                send_error_shared(&stream, id, error).await?;
+                return Ok(());
+            };
+            let params = value.get("params").cloned().unwrap_or(Value::Null);
+            match handler.handle(&connection.info, params).await {
+                Ok(result) => send_result_shared(&stream, id, result).await?,
+                Err(error) => send_error_shared(&stream, id, error).await?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Known-sensitive locations. These no longer gate access (containment in the
+// project roots does); they only tag outside-root errors so clients and
+// operators can tell probing of system files apart from ordinary mistakes.
+const SENSITIVE_SYSTEM_PREFIXES: &[&str] = &[
+    "/etc",
+    "/var",
+    "/root",
+    "/usr",
+    "/boot",
+    "/proc",
+    "/sys",
+    "/dev",
+    "/bin",
+    "/sbin",
+    "/lib",
+    "/private/etc",
+    "/private/var",
+];
+
+/// Stable categories reported as `data.kind` by fs and auth errors, so clients
+/// can branch on them without parsing `data.detail`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ErrorKind {
+    SandboxViolation,
+    NoProjectRoot,
+    InvalidPath,
+    NotFound,
+    IsDirectory,
+    Binary,
+    InvalidEncoding,
+    TooLarge,
+    Io,
+    PermissionDenied,
+    PermissionCancelled,
+    UnknownPermissionOption,
+    CliUnavailable,
+    LoginFailed,
+    LoginTimeout,
+    UnsupportedProtocolVersion,
+}
+
+impl ErrorKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            ErrorKind::SandboxViolation => "sandbox_violation",
+            ErrorKind::NoProjectRoot => "no_project_root",
+            ErrorKind::InvalidPath => "invalid_path",
+            ErrorKind::NotFound => "not_found",
+            ErrorKind::IsDirectory => "is_directory",
+            ErrorKind::Binary => "binary",
+            ErrorKind::InvalidEncoding => "invalid_encoding",
+            ErrorKind::TooLarge => "too_large",
+            ErrorKind::Io => "io",
+            ErrorKind::PermissionDenied => "permission_denied",
+            ErrorKind::PermissionCancelled => "permission_cancelled",
+            ErrorKind::UnknownPermissionOption => "unknown_permission_option",
+            ErrorKind::CliUnavailable => "cli_unavailable",
+            ErrorKind::LoginFailed => "login_failed",
+            ErrorKind::LoginTimeout => "login_timeout",
+            ErrorKind::UnsupportedProtocolVersion => "unsupported_protocol_version",
+        }
+    }
+}
+
+fn error_data(kind: ErrorKind, detail: impl Into<String>) -> Value {
+    json!({
+        "kind": kind.as_str(),
+        "detail": detail.into(),
+    })
+}
+
+fn has_error_kind(error: &acp::Error, kind: ErrorKind) -> bool {
+    error
+        .data
+        .as_ref()
+        .and_then(|data| data.get("kind"))
+        .and_then(Value::as_str)
+        == Some(kind.as_str())
+}
+
+// Permission outcomes outside of writes share the -32000 code and differ
+// only in `kind`; see `WriteRefusal` for the write codes.
+fn permission_error(kind: ErrorKind, message: &str) -> acp::Error {
+    acp::Error::new((-32000, message.to_string())).with_data(error_data(kind, message))
+}
+
+// Why a write did not go ahead after asking for permission. Each reason has
+// its own code so clients can tell them apart without reading `data`.
+#[derive(Clone, Copy, Debug)]
+enum WriteRefusal {
+    DeniedOnce,
+    DeniedAlways,
+    Cancelled,
+}
+
+impl BridgeSharedConfig {
+    fn write_refusal(&self, refusal: WriteRefusal) -> acp::Error {
+        let (offset, kind, message) = match refusal {
+            WriteRefusal::DeniedOnce => (0, ErrorKind::PermissionDenied, "Permission denied"),
+            WriteRefusal::DeniedAlways => (1, ErrorKind::PermissionDenied, "Permission denied"),
+            WriteRefusal::Cancelled => (
+                2,
+                ErrorKind::PermissionCancelled,
+                "Permission request cancelled",
+            ),
+        };
+        let code = self.permission_error_code_base.saturating_sub(offset);
+        acp::Error::new((code, message.to_string())).with_data(error_data(kind, message))
+    }
+}
+
+fn outside_root_error(path: &std::path::Path) -> acp::Error {
+    let system_path = SENSITIVE_SYSTEM_PREFIXES
+        .iter()
+        .any(|prefix| path.starts_with(prefix));
+    let mut data = error_data(ErrorKind::SandboxViolation, "path outside project root");
+    data["systemPath"] = json!(system_path);
+    acp::Error::internal_error().with_data(data)
+}
+
+// Rewrites absolute paths in the errors a connection is sent, for bridges
+// that should not reveal their filesystem layout: paths under a project root
+// become relative to it and anything else becomes `<path>`.
+struct PathRedaction {
+    roots: Vec<PathBuf>,
+}
+
+impl PathRedaction {
+    fn new(roots: &[PathBuf]) -> Self {
+        let mut expanded = Vec::new();
+        for root in roots {
+            expanded.push(normalize_lexically(root));
+            if let Ok(canonical) = root.canonicalize() {
+                expanded.push(canonical);
+            }
+        }
+        // Longest first, so nested roots are stripped as far as they go.
+        expanded.sort_by_key(|root| Reverse(root.as_os_str().len()));
+        expanded.dedup();
+        Self { roots: expanded }
+    }
+
+    fn redact_error(&self, error: &mut acp::Error) {
+        error.message = self.redact_text(&error.message);
+        if let Some(data) = error.data.as_mut() {
+            self.redact_value(data);
+        }
+    }
+
+    fn redact_value(&self, value: &mut Value) {
+        match value {
+            Value::String(text) => *text = self.redact_text(text),
+            Value::Array(items) => items.iter_mut().for_each(|item| self.redact_value(item)),
+            Value::Object(fields) => fields
+                .values_mut()
+                .for_each(|field| self.redact_value(field)),
+            _ => {}
+        }
+    }
+
+    fn redact_text(&self, text: &str) -> String {
+        static ABSOLUTE_PATH: OnceLock<Regex> = OnceLock::new();
+        let absolute_path = ABSOLUTE_PATH.get_or_init(|| {
+            Regex::new(r#"(^|[\s"'(=])((?:/|[A-Za-z]:\\)[^\s"'()<>,;]+)"#)
+                .expect("valid absolute path pattern")
+        });
+        absolute_path
+            .replace_all(text, |captures: &regex::Captures| {
+                format!("{}{}", &captures[1], self.relative_form(&captures[2]))
+            })
+            .into_owned()
+    }
+
+    fn relative_form(&self, path: &str) -> String {
+        self.roots
+            .iter()
+            .find_map(|root| std::path::Path::new(path).strip_prefix(root).ok())
+            .map(|relative| {
+                if relative.as_os_str().is_empty() {
+                    ".".to_string()
+                } else {
+                    relative.to_string_lossy().to_string()
+                }
+            })
+            .unwrap_or_else(|| "<path>".to_string())
+    }
+}
+
+// Resolves `.` and `..` without touching the filesystem so paths that do not
+// exist yet can still be checked for containment.
+fn normalize_lexically(path: &std::path::Path) -> PathBuf {
+    use std::path::Component;
+
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other.as_os_str()),
+        }
+    }
+    normalized
+}
+
+// Expands a leading `~` or `~user` and `$VAR` / `${VAR}` references. A
+// reference to an unset variable is left as written, since `$` is an
+// ordinary file name character (`Outer$Inner.class`, `$RECYCLE.BIN`). This
+// runs before any containment check, so whatever a path expands to is still
+// held to the project roots.
+fn expand_path(path: &str) -> Result<String, acp::Error> {
+    static ENV_VAR: OnceLock<Regex> = OnceLock::new();
+    let env_var = ENV_VAR.get_or_init(|| {
+        Regex::new(r"\$(?:\{([A-Za-z_][A-Za-z0-9_]*)\}|([A-Za-z_][A-Za-z0-9_]*))")
+            .expect("valid env var pattern")
+    });
+
+    let expanded = match path.strip_prefix('~') {
+        Some(rest) => {
+            let (user, rest) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+            let home = home_dir_of(user).ok_or_else(|| {
+                acp::Error::invalid_params().with_data(error_data(
+                    ErrorKind::InvalidPath,
+                    format!("cannot expand ~{user}"),
+                ))
+            })?;
+            format!("{}{rest}", home.display())
+        }
+        None => path.to_string(),
+    };
+
+    let expanded = env_var.replace_all(&expanded, |captures: &regex::Captures<'_>| {
+        let name = captures
+            .get(1)
+            .or_else(|| captures.get(2))
+            .map_or("", |name| name.as_str());
+        std::env::var(name).unwrap_or_else(|_| captures[0].to_string())
+    });
+    Ok(expanded.into_owned())
+}
+
+// `~` is the current user's `$HOME`; `~user` is looked up in /etc/passwd.
+fn home_dir_of(user: &str) -> Option<PathBuf> {
+    if user.is_empty() {
+        return std::env::var_os("HOME").map(PathBuf::from);
+    }
+    let passwd = std::fs::read_to_string("/etc/passwd").ok()?;
+    passwd.lines().find_map(|entry| {
+        let fields: Vec<&str> = entry.split(':').collect();
+        (fields.len() >= 6 && fields[0] == user).then(|| PathBuf::from(fields[5]))
+    })
+}
+
+// The optional `base` of fs/read_text_file and fs/write_text_file: a
+// directory that a relative `path` is taken from instead of the first root.
+fn base_param(params: &Value) -> Result<Option<&str>, acp::Error> {
+    match params.get("base") {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(base)) => Ok(Some(base)),
+        Some(_) => Err(acp::Error::invalid_params().with_data("base must be a string")),
+    }
+}
+
+// `base` is sandbox-checked on its own before `path` is joined to it, so a
+// base outside the roots is rejected even when the joined path would climb
+// back inside them.
+fn resolve_against_base(
+    roots: &SandboxRoots,
+    base: Option<&str>,
+    path: &str,
+    for_write: bool,
+) -> Result<PathBuf, acp::Error> {
+    let Some(base) = base else {
+        return validate_and_resolve_path(roots, path, for_write);
+    };
+    let base = validate_and_resolve_path(roots, base, false)?;
+    let joined = base.join(expand_path(path)?);
+    resolve_expanded_path(roots, &joined, for_write)
+}
+
+// The directories fs methods are confined to. Relative paths resolve against
+// the first read root even when the write set is the one enforced.
+struct SandboxRoots {
+    read: Vec<PathBuf>,
+    write: Vec<PathBuf>,
+}
+
+impl SandboxRoots {
+    fn enforced(&self, for_write: bool) -> &[PathBuf] {
+        if for_write {
+            &self.write
+        } else {
+            &self.read
+        }
+    }
+}
+
+// Project root sandboxing per RAT-LWS-REQ-044: paths are expanded first (see
+// `expand_path`), relative paths resolve against the first root, and the path
+// must stay inside some root both lexically and after symlinks are resolved.
+fn validate_and_resolve_path(
+    roots: &SandboxRoots,
+    path: &str,
+    for_write: bool,
+) -> Result<PathBuf, acp::Error> {
+    resolve_expanded_path(roots, Path::new(&expand_path(path)?), for_write)
+}
+
+// The part of `validate_and_resolve_path` after expansion, for paths that
+// have already been expanded once and must not be again.
+fn resolve_expanded_path(
+    roots: &SandboxRoots,
+    path: &Path,
+    for_write: bool,
+) -> Result<PathBuf, acp::Error> {
+    let base = roots.read.first().ok_or_else(|| {
+        acp::Error::internal_error().with_data(error_data(
+            ErrorKind::NoProjectRoot,
+            "no project root configured",
+        ))
+    })?;
+
+    let resolved_path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        base.join(path)
+    };
+
+    let roots = roots.enforced(for_write);
+    let lexical_roots: Vec<PathBuf> = roots.iter().map(|root| normalize_lexically(root)).collect();
+    let canonical_roots: Vec<PathBuf> = roots
+        .iter()
+        .filter_map(|root| root.canonicalize().ok())
+        .collect();
+
+    // Checked before canonicalizing so a missing file outside the roots is
+    // reported as a sandbox violation rather than leaking its absence.
+    let lexical_path = normalize_lexically(&resolved_path);
+    if !lexical_roots
+        .iter()
+        .chain(canonical_roots.iter())
+        .any(|root| lexical_path.starts_with(root))
+    {
+        return Err(outside_root_error(&lexical_path));
+    }
+
+    // Canonicalize path, handling the case where file doesn't exist for writes
+    let canonical_path = if for_write && !resolved_path.exists() {
+        // For write operations, canonicalize the parent directory since the file may not exist yet
+        let parent = resolved_path.parent().ok_or_else(|| {
+            acp::Error::internal_error()
+                .with_data(error_data(ErrorKind::InvalidPath, "invalid path"))
+        })?;
+        let canonical_parent = parent.canonicalize().map_err(|_| {
+            acp::Error::internal_error()
+                .with_data(error_data(ErrorKind::InvalidPath, "invalid path"))
+        })?;
+        let target = canonical_parent.join(resolved_path.file_name().ok_or_else(|| {
+            acp::Error::internal_error()
+                .with_data(error_data(ErrorKind::InvalidPath, "invalid path"))
+        })?);
+        // A dangling symlink does not "exist" either, but writing to it
+        // creates whatever it points at, so that is the path to check.
+        let dangling = std::fs::symlink_metadata(&target)
+            .is_ok_and(|metadata| metadata.file_type().is_symlink());
+        if dangling {
+            real_location(&target).ok_or_else(|| {
+                acp::Error::internal_error()
+                    .with_data(error_data(ErrorKind::InvalidPath, "invalid path"))
+            })?
+        } else {
+            target
+        }
+    } else {
+        resolved_path.canonicalize().map_err(|_| {
+            acp::Error::internal_error().with_data(error_data(
+                if for_write {
+                    ErrorKind::InvalidPath
+                } else {
+                    ErrorKind::NotFound
+                },
+                if for_write {
+                    "invalid path"
+                } else {
+                    "file not found"
+                },
+            ))
+        })?
+    };
+
+    // Symlinks may point anywhere, so containment is re-checked on the real path.
+    if !canonical_roots
+        .iter()
+        .any(|root| canonical_path.starts_with(root))
+    {
+        return Err(outside_root_error(&canonical_path));
+    }
+
+    Ok(canonical_path)
+}
+
+// Whole files recently read by `fs/read_text_file`, reused while their
+// modification time and size are unchanged. Least recently used entries go
+// first once `capacity` files are held.
+struct ReadCache {
+    capacity: usize,
+    entries: Mutex<ReadCacheEntries>,
+}
+
+// Larger files are read from disk each time rather than pinned in memory.
+const MAX_CACHED_FILE_BYTES: u64 = 1 << 20;
+
+#[derive(Default)]
+struct ReadCacheEntries {
+    files: HashMap<PathBuf, CachedFile>,
+    next_use: u64,
+}
+
+struct CachedFile {
+    bytes: Arc<Vec<u8>>,
+    modified: SystemTime,
+    len: u64,
+    last_used: u64,
+}
+
+impl ReadCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(ReadCacheEntries::default()),
+        }
+    }
+
+    // The file's bytes, from memory when it is unchanged since it was cached.
+    // `None` for files that cannot be cached, which take the uncached path.
+    fn load(
+        &self,
+        path: &std::path::Path,
+        max_bytes: Option<u64>,
+    ) -> Result<Option<Arc<Vec<u8>>>, acp::Error> {
+        let metadata = std::fs::metadata(path).map_err(|_| {
+            acp::Error::internal_error()
+                .with_data(error_data(ErrorKind::NotFound, "file not found"))
+        })?;
+        let len = metadata.len();
+        let Ok(modified) = metadata.modified() else {
+            return Ok(None);
+        };
+        if len > max_bytes.map_or(MAX_CACHED_FILE_BYTES, |max| max.min(MAX_CACHED_FILE_BYTES)) {
+            return Ok(None);
+        }
+
+        let mut entries = self.entries.lock().expect("read cache lock poisoned");
+        entries.next_use += 1;
+        let now = entries.next_use;
+        if let Some(cached) = entries.files.get_mut(path) {
+            if cached.modified == modified && cached.len == len {
+                cached.last_used = now;
+                return Ok(Some(cached.bytes.clone()));
+            }
+        }
+        drop(entries);
+
+        // Stamped with the metadata taken before reading, so a change that
+        // lands mid-read shows up as a new mtime on the next read.
+        let bytes = Arc::new(std::fs::read(path).map_err(|_| {
+            acp::Error::internal_error().with_data(error_data(ErrorKind::Io, "failed to read file"))
+        })?);
+        let mut entries = self.entries.lock().expect("read cache lock poisoned");
+        if !entries.files.contains_key(path) && entries.files.len() >= self.capacity {
+            let oldest = entries
+                .files
+                .iter()
+                .min_by_key(|(_, cached)| cached.last_used)
+                .map(|(path, _)| path.clone());
+            if let Some(oldest) = oldest {
+                entries.files.remove(&oldest);
+            }
+        }
+        entries.files.insert(
+            path.to_path_buf(),
+            CachedFile {
+                bytes: bytes.clone(),
+                modified,
+                len,
+                last_used: now,
+            },
+        );
+        Ok(Some(bytes))
+    }
+
+    fn invalidate(&self, path: &std::path::Path) {
+        self.entries
+            .lock()
+            .expect("read cache lock poisoned")
+            .files
+            .remove(path);
+    }
+}
+
+// The slice of a file returned by `fs/read_text_file`.
+struct TextWindow {
+    content: String,
+    total_lines: usize,
+    // More lines follow the returned window.
+    truncated: bool,
+    // The file's last line is terminated. Windows are joined with `\n` and
+    // never end in one, so clients need this to write a file back unchanged.
+    ends_with_newline: bool,
+}
+
+fn handle_read_text_file(
+    shared: &BridgeSharedConfig,
+    connection: &ConnectionInfo,
+    base: Option<&str>,
+    path: &str,
+    line_offset: Option<u32>,
+    line_limit: Option<u32>,
+    encoding: Option<&'static Encoding>,
+) -> Result<TextWindow, acp::Error> {
+    if let Some(name) = virtual_path(path) {
+        name?;
+        let content = shared
+            .virtual_files
+            .get(path)
+            .ok_or_else(virtual_not_found)?;
+        return decode_window(content.into_bytes(), line_offset, line_limit, None);
+    }
+
+    let roots = shared.sandbox_roots(connection)?;
+    let canonical_path = resolve_against_base(&roots, base, path, false)?;
+    let ranged = line_offset.is_some() || line_limit.is_some();
+
+    // Ranged reads stream below, so caching them would only load files
+    // they never needed in full.
+    if let Some(cache) = shared.read_cache.as_ref().filter(|_| !ranged) {
+        if let Some(bytes) = cache.load(&canonical_path, shared.max_read_bytes)? {
+            return decode_window(bytes.to_vec(), None, None, encoding);
+        }
+    }
+
+    let file = std::fs::File::open(&canonical_path).map_err(|_| {
+        acp::Error::internal_error().with_data(error_data(ErrorKind::NotFound, "file not found"))
+    })?;
+    let mut reader = BufReader::new(file);
+
+    // UTF-8 ranged reads stream line by line and only buffer the window, so
+    // they never load the whole file regardless of its size.
+    // UTF-16 and explicitly requested encodings have to be transcoded first
+    // and take the whole-file path.
+    let leading = reader.fill_buf().map_err(|_| {
+        acp::Error::internal_error().with_data(error_data(ErrorKind::Io, "failed to read file"))
+    })?;
+    let utf16 = matches!(
+        text_encoding_from_bom(leading),
+        Some(BomEncoding::Utf16Le | BomEncoding::Utf16Be)
+    );
+    if ranged && !utf16 && encoding.is_none() {
+        return apply_line_filter(reader, line_offset, line_limit);
+    }
 
-            // Convert from simple { sessionId, prompt } to ACP format
-            let session_id = params
-                .get("sessionId")
-                .and_then(|v| v.as_str())
-                .unwrap_or("")
-                .to_string();
-            let prompt_text = params
-                .get("prompt")
-                .and_then(|v| v.as_str())
-                .unwrap_or("")
-                .to_string();
-
-            let request = acp::PromptRequest {
-                session_id: acp::SessionId(session_id.into()),
-                prompt: vec![acp::ContentBlock::from(prompt_text)],
-                meta: None,
-            };
+    if let Some(max_read_bytes) = shared.max_read_bytes {
+        let len = reader
+            .get_ref()
+            .metadata()
+            .map_err(|_| {
+                acp::Error::internal_error()
+                    .with_data(error_data(ErrorKind::NotFound, "file not found"))
+            })?
+            .len();
+        if len > max_read_bytes {
+            return Err(acp::Error::internal_error().with_data(error_data(
+                ErrorKind::TooLarge,
+                format!(
+                "file exceeds max_read_bytes ({max_read_bytes} bytes); request a line range instead"
+            ),
+            )));
+        }
+    }
 
-            let notification_sender = Arc::new(WebSocketNotificationSender::new(stream.clone()));
-            let response = transport.prompt(request, notification_sender).await;
-            match response {
-                Ok(response) => {
-                    let result = serde_json::to_value(response)
-                        .map_err(|err| tungstenite::Error::Io(std::io::Error::other(err)))?;
-                    send_result_shared(&stream, id, result).await?;
-                }
-                Err(err) => {
-                    let error = err.into_rpc_error();
-                    send_error_shared(&stream, id, error).await?;
-                }
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes).map_err(|_| {
+        acp::Error::internal_error().with_data(error_data(ErrorKind::NotFound, "file not found"))
+    })?;
+    decode_window(bytes, line_offset, line_limit, encoding)
+}
+
+// Decodes a whole file and cuts the requested window from the text.
+fn decode_window(
+    bytes: Vec<u8>,
+    line_offset: Option<u32>,
+    line_limit: Option<u32>,
+    encoding: Option<&'static Encoding>,
+) -> Result<TextWindow, acp::Error> {
+    let content = match encoding {
+        // The caller named the encoding, so NUL-heavy encodings such as
+        // UTF-16 without a BOM are not mistaken for binary.
+        Some(encoding) => decode_text_as(&bytes, encoding)?,
+        None => {
+            if is_probably_binary(&bytes) {
+                return Err(acp::Error::internal_error()
+                    .with_data(error_data(ErrorKind::Binary, "binary file not supported")));
             }
+            decode_text(bytes)?
         }
-        "fs/read_text_file" => {
-            if !*initialized {
-                let error = acp::Error::method_not_found();
-                send_error_shared(&stream, id, error).await?;
-                return Ok(());
-            }
+    };
+    if line_offset.is_some() || line_limit.is_some() {
+        apply_line_filter(content.as_bytes(), line_offset, line_limit)
+    } else {
+        Ok(TextWindow {
+            total_lines: content.lines().count(),
+            ends_with_newline: content.ends_with('\n'),
+            content,
+            truncated: false,
+        })
+    }
+}
 
-            let params = value.get("params").cloned().unwrap_or_else(|| json!({}));
+const BINARY_SNIFF_BYTES: usize = 8192;
 
-            // Extract parameters
-            let path = match params.get("path").and_then(|v| v.as_str()) {
-                Some(path) => path,
-                None => {
-                    send_error_shared(
-                        &stream,
-                        id,
-                        acp::Error::invalid_params().with_data("missing or invalid path parameter"),
-                    )
-                    .await?;
-                    return Ok(());
-                }
-            };
+const BINARY_MAGIC_NUMBERS: &[&[u8]] = &[
+    b"\x89PNG\r\n\x1a\n",
+    b"GIF87a",
+    b"GIF89a",
+    b"\xff\xd8\xff",
+    b"%PDF-",
+    b"PK\x03\x04",
+    b"\x1f\x8b",
+    b"\x7fELF",
+    b"\xfe\xed\xfa\xce",
+    b"\xfe\xed\xfa\xcf",
+    b"\xcf\xfa\xed\xfe",
+    b"\xca\xfe\xba\xbe",
+    b"\x00asm",
+    b"7z\xbc\xaf\x27\x1c",
+    b"Rar!\x1a\x07",
+    b"SQLite format 3\x00",
+];
 
-            let line_offset = params
-                .get("line_offset")
-                .and_then(|v| v.as_u64())
-                .map(|v| v as u32);
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BomEncoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
 
-            let line_limit = params
-                .get("line_limit")
-                .and_then(|v| v.as_u64())
-                .map(|v| v as u32);
+fn text_encoding_from_bom(bytes: &[u8]) -> Option<BomEncoding> {
+    if bytes.starts_with(b"\xef\xbb\xbf") {
+        Some(BomEncoding::Utf8)
+    } else if bytes.starts_with(b"\xff\xfe") {
+        Some(BomEncoding::Utf16Le)
+    } else if bytes.starts_with(b"\xfe\xff") {
+        Some(BomEncoding::Utf16Be)
+    } else {
+        None
+    }
+}
 
-            match handle_read_text_file(path, line_offset, line_limit) {
-                Ok(content) => {
-                    let result = json!({
-                        "content": content
-                    });
-                    send_result_shared(&stream, id, result).await?;
-                }
-                Err(error) => {
-                    send_error_shared(&stream, id, error).await?;
-                }
+/// Sniffs the leading bytes of a file and reports whether it should be
+/// treated as binary rather than text.
+///
+/// Content starting with a UTF-8 or UTF-16 byte-order mark is always text.
+/// Otherwise well-known magic numbers, NUL bytes, or more than 10% control
+/// characters (other than whitespace, backspace and escape) mark it binary.
+pub fn is_probably_binary(bytes: &[u8]) -> bool {
+    let sample = &bytes[..bytes.len().min(BINARY_SNIFF_BYTES)];
+    if text_encoding_from_bom(sample).is_some() {
+        return false;
+    }
+    if BINARY_MAGIC_NUMBERS
+        .iter()
+        .any(|magic| sample.starts_with(magic))
+    {
+        return true;
+    }
+    if sample.contains(&0) {
+        return true;
+    }
+
+    let control_bytes = sample
+        .iter()
+        .filter(|&&byte| byte < 0x20 && !matches!(byte, b'\t' | b'\n' | b'\r' | 0x08 | 0x0c | 0x1b))
+        .count();
+    control_bytes * 10 > sample.len()
+}
+
+// Strict transcoding for a client-specified encoding; a matching BOM is
+// dropped and malformed input is an error rather than replaced.
+fn decode_text_as(bytes: &[u8], encoding: &'static Encoding) -> Result<String, acp::Error> {
+    let body = match Encoding::for_bom(bytes) {
+        Some((bom_encoding, bom_len)) if bom_encoding == encoding => &bytes[bom_len..],
+        _ => bytes,
+    };
+    encoding
+        .decode_without_bom_handling_and_without_replacement(body)
+        .map(|text| text.into_owned())
+        .ok_or_else(|| {
+            acp::Error::internal_error().with_data(error_data(
+                ErrorKind::InvalidEncoding,
+                format!("file contains invalid {}", encoding.name()),
+            ))
+        })
+}
+
+// UTF-16 content is transcoded (dropping its BOM); everything else must be
+// valid UTF-8 and free of NUL bytes beyond the sniffed prefix.
+fn decode_text(bytes: Vec<u8>) -> Result<String, acp::Error> {
+    let decode_utf16 = |body: &[u8], to_unit: fn([u8; 2]) -> u16| {
+        if !body.len().is_multiple_of(2) {
+            return Err(acp::Error::internal_error().with_data(error_data(
+                ErrorKind::InvalidEncoding,
+                "file contains invalid UTF-16",
+            )));
+        }
+        let units: Vec<u16> = body
+            .chunks_exact(2)
+            .map(|pair| to_unit([pair[0], pair[1]]))
+            .collect();
+        String::from_utf16(&units).map_err(|_| {
+            acp::Error::internal_error().with_data(error_data(
+                ErrorKind::InvalidEncoding,
+                "file contains invalid UTF-16",
+            ))
+        })
+    };
+
+    match text_encoding_from_bom(&bytes) {
+        Some(BomEncoding::Utf16Le) => decode_utf16(&bytes[2..], u16::from_le_bytes),
+        Some(BomEncoding::Utf16Be) => decode_utf16(&bytes[2..], u16::from_be_bytes),
+        Some(BomEncoding::Utf8) | None => {
+            if bytes.contains(&0) {
+                return Err(acp::Error::internal_error()
+                    .with_data(error_data(ErrorKind::Binary, "binary file not supported")));
             }
+            String::from_utf8(bytes).map_err(|_| {
+                acp::Error::internal_error().with_data(error_data(
+                    ErrorKind::InvalidEncoding,
+                    "file contains invalid UTF-8",
+                ))
+            })
         }
-        "fs/write_text_file" => {
-            if !*initialized {
-                let error = acp::Error::method_not_found();
-                send_error_shared(&stream, id, error).await?;
-                return Ok(());
+    }
+}
+
+// Mirrors `str::lines` semantics (`\n` or `\r\n` terminators, optional final
+// terminator) and joins the selected window with `\n`. `line_offset` is
+// 1-based, so 0 is rejected; `line_limit: 0` selects an empty window while
+// still counting the file's lines.
+fn apply_line_filter<R: BufRead>(
+    mut reader: R,
+    line_offset: Option<u32>,
+    line_limit: Option<u32>,
+) -> Result<TextWindow, acp::Error> {
+    let start_idx = match line_offset {
+        Some(0) => {
+            return Err(acp::Error::invalid_params()
+                .with_data("line_offset is 1-based and must be at least 1"))
+        }
+        Some(offset) => offset as usize - 1,
+        None => 0,
+    };
+    let limit = line_limit.map(|limit| limit as usize);
+
+    let read_error = |_| {
+        acp::Error::internal_error().with_data(error_data(ErrorKind::Io, "failed to read file"))
+    };
+
+    // Only the leading buffer is sniffed for binary content up front; lines
+    // inside the window are checked for NUL bytes as they are collected.
+    let leading = reader.fill_buf().map_err(read_error)?;
+    if is_probably_binary(leading) {
+        return Err(acp::Error::internal_error()
+            .with_data(error_data(ErrorKind::Binary, "binary file not supported")));
+    }
+    // An empty file has no lines, so every window into it is empty and none
+    // is out of range.
+    if leading.is_empty() {
+        return Ok(TextWindow {
+            content: String::new(),
+            total_lines: 0,
+            truncated: false,
+            ends_with_newline: false,
+        });
+    }
+
+    let mut lines = Vec::new();
+    let mut line = Vec::new();
+    let mut total_lines = 0usize;
+    let mut ends_with_newline = false;
+    loop {
+        // Lines outside the window are skipped without buffering; they are
+        // still counted so the result can report `totalLines`.
+        let window_full = limit.is_some_and(|limit| lines.len() >= limit);
+        if total_lines < start_idx || window_full {
+            match skip_line(&mut reader).map_err(read_error)? {
+                Some(terminated) => ends_with_newline = terminated,
+                None => break,
             }
+            total_lines += 1;
+            continue;
+        }
 
-            let params = value.get("params").cloned().unwrap_or_else(|| json!({}));
+        line.clear();
+        if reader.read_until(b'\n', &mut line).map_err(read_error)? == 0 {
+            break;
+        }
+        ends_with_newline = line.last() == Some(&b'\n');
+        if line.contains(&0) {
+            return Err(acp::Error::internal_error()
+                .with_data(error_data(ErrorKind::Binary, "binary file not supported")));
+        }
+        if line.last() == Some(&b'\n') {
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+        }
+        let text = String::from_utf8(line.clone()).map_err(|_| {
+            acp::Error::internal_error().with_data(error_data(
+                ErrorKind::InvalidEncoding,
+                "file contains invalid UTF-8",
+            ))
+        })?;
+        lines.push(text);
+        total_lines += 1;
+    }
 
-            // Extract parameters
-            let session_id = match params.get("sessionId").and_then(|v| v.as_str()) {
-                Some(session_id) => session_id,
-                None => {
-                    send_error_shared(
-                        &stream,
-                        id,
-                        acp::Error::invalid_params()
-                            .with_data("missing or invalid sessionId parameter"),
-                    )
-                    .await?;
-                    return Ok(());
-                }
-            };
+    Ok(TextWindow {
+        truncated: start_idx + lines.len() < total_lines,
+        content: lines.join("\n"),
+        total_lines,
+        ends_with_newline,
+    })
+}
 
-            let path = match params.get("path").and_then(|v| v.as_str()) {
-                Some(path) => path,
-                None => {
-                    send_error_shared(
-                        &stream,
-                        id,
-                        acp::Error::invalid_params().with_data("missing or invalid path parameter"),
-                    )
-                    .await?;
-                    return Ok(());
-                }
-            };
+// Skips one line without buffering it. `None` at end of input, otherwise
+// whether the line ended in `\n` rather than at end of input.
+fn skip_line<R: BufRead>(reader: &mut R) -> std::io::Result<Option<bool>> {
+    let mut skipped = false;
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(skipped.then_some(false));
+        }
+        skipped = true;
+        if let Some(newline) = buf.iter().position(|&byte| byte == b'\n') {
+            reader.consume(newline + 1);
+            return Ok(Some(true));
+        }
+        let len = buf.len();
+        reader.consume(len);
+    }
+}
 
-            let content = match params.get("content").and_then(|v| v.as_str()) {
-                Some(content) => content,
-                None => {
-                    send_error_shared(
-                        &stream,
-                        id,
-                        acp::Error::invalid_params()
-                            .with_data("missing or invalid content parameter"),
-                    )
-                    .await?;
-                    return Ok(());
-                }
+// The result `fs/read_text_file` answers a successful read with, after
+// `max_line_bytes` truncation.
+fn text_window_result(
+    shared: &BridgeSharedConfig,
+    window: TextWindow,
+    line_offset: Option<u32>,
+    with_line_numbers: bool,
+) -> Value {
+    let TextWindow {
+        content,
+        total_lines,
+        truncated,
+        ends_with_newline,
+    } = window;
+    let (content, truncated_lines) = match shared.max_line_bytes {
+        Some(max_line_bytes) => {
+            truncate_long_lines(&content, max_line_bytes, line_offset.unwrap_or(1))
+        }
+        None => (content, Vec::new()),
+    };
+    let mut result = json!({
+        "totalLines": total_lines,
+        "truncated": truncated,
+        "endsWithNewline": ends_with_newline,
+    });
+    if with_line_numbers {
+        result["lines"] = numbered_lines(&content, line_offset.unwrap_or(1));
+    } else {
+        result["content"] = json!(content);
+    }
+    if !truncated_lines.is_empty() {
+        result["truncatedLines"] = json!(truncated_lines);
+    }
+    result
+}
+
+// `with_line_numbers` output: each line of a window with its absolute 1-based
+// number, `first_line` being the number of the window's first line.
+fn numbered_lines(content: &str, first_line: u32) -> Value {
+    content
+        .lines()
+        .zip(first_line as u64..)
+        .map(|(text, number)| json!({"number": number, "text": text}))
+        .collect()
+}
+
+const LINE_TRUNCATION_MARKER: &str = "... [line truncated]";
+
+// Accepts the legacy `{ sessionId, prompt: "text" }` shape as well as the ACP
+// one, where `prompt` is an array of content blocks. A missing `sessionId` is
+// left empty for the session checks to judge; `_meta` is passed through.
+fn parse_prompt_params(params: &Value) -> Result<acp::PromptRequest, acp::Error> {
+    let session_id = match params.get("sessionId") {
+        None | Some(Value::Null) => "",
+        Some(Value::String(session_id)) => session_id,
+        Some(_) => return Err(acp::Error::invalid_params().with_data("sessionId must be a string")),
+    };
+    Ok(acp::PromptRequest {
+        session_id: acp::SessionId(session_id.into()),
+        prompt: prompt_blocks(params.get("prompt"))?,
+        meta: params.get("_meta").cloned(),
+    })
+}
+
+fn prompt_blocks(prompt: Option<&Value>) -> Result<Vec<acp::ContentBlock>, acp::Error> {
+    match prompt {
+        None | Some(Value::Null) => Ok(vec![acp::ContentBlock::from(String::new())]),
+        Some(Value::String(text)) => Ok(vec![acp::ContentBlock::from(text.clone())]),
+        Some(Value::Array(blocks)) => blocks
+            .iter()
+            .enumerate()
+            .map(|(index, block)| {
+                serde_json::from_value(block.clone()).map_err(|err| {
+                    acp::Error::invalid_params()
+                        .with_data(format!("invalid prompt block {index}: {err}"))
+                })
+            })
+            .collect(),
+        Some(_) => Err(acp::Error::invalid_params().with_data("prompt must be a string or array")),
+    }
+}
+
+// Counts the chars across every text block; non-text blocks do not count.
+fn prompt_char_count(prompt: &[acp::ContentBlock]) -> usize {
+    prompt
+        .iter()
+        .map(|block| match block {
+            acp::ContentBlock::Text(text) => text.text.chars().count(),
+            _ => 0,
+        })
+        .sum()
+}
+
+// Cuts every line longer than `max_line_bytes` at a char boundary and appends
+// a marker, keeping line terminators intact. Returns the 1-based numbers of the
+// truncated lines, counted from `first_line`.
+fn truncate_long_lines(
+    content: &str,
+    max_line_bytes: usize,
+    first_line: u32,
+) -> (String, Vec<u32>) {
+    let mut output = String::with_capacity(content.len());
+    let mut truncated = Vec::new();
+    for (index, segment) in content.split_inclusive('\n').enumerate() {
+        let body = segment
+            .strip_suffix('\n')
+            .map(|line| line.strip_suffix('\r').unwrap_or(line))
+            .unwrap_or(segment);
+        if body.len() <= max_line_bytes {
+            output.push_str(segment);
+            continue;
+        }
+
+        let mut cut = max_line_bytes;
+        while !body.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        output.push_str(&body[..cut]);
+        output.push_str(LINE_TRUNCATION_MARKER);
+        output.push_str(&segment[body.len()..]);
+        truncated.push(first_line.saturating_add(index as u32));
+    }
+    (output, truncated)
+}
+
+fn handle_stat(
+    shared: &BridgeSharedConfig,
+    connection: &ConnectionState,
+    path: &str,
+) -> Result<Value, acp::Error> {
+    let roots = shared.sandbox_roots(&connection.info)?;
+    let canonical_path = validate_and_resolve_path(&roots, path, false)?;
+    let metadata = std::fs::metadata(&canonical_path).map_err(|_| {
+        acp::Error::internal_error().with_data(error_data(ErrorKind::NotFound, "file not found"))
+    })?;
+
+    let modified_ms = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|elapsed| elapsed.as_millis() as u64);
+
+    Ok(json!({
+        "path": canonical_path.to_string_lossy(),
+        "isFile": metadata.is_file(),
+        "isDir": metadata.is_dir(),
+        "size": metadata.len(),
+        "modifiedMs": modified_ms,
+        "fileId": file_id(&metadata),
+    }))
+}
+
+// Answers from metadata alone. The sandbox check still runs first, so a path
+// outside the roots is an error rather than `exists: false`.
+fn handle_exists(
+    shared: &BridgeSharedConfig,
+    connection: &ConnectionState,
+    path: &str,
+) -> Result<Value, acp::Error> {
+    let roots = shared.sandbox_roots(&connection.info)?;
+    let canonical_path = match validate_and_resolve_path(&roots, path, false) {
+        Ok(canonical_path) => canonical_path,
+        Err(error) if has_error_kind(&error, ErrorKind::NotFound) => {
+            // The lexical check passed, but an in-root symlink along the way
+            // may lead outside, and "missing" there must not be reported.
+            let expanded = PathBuf::from(expand_path(path)?);
+            let resolved = match roots.read.first() {
+                Some(base) if expanded.is_relative() => base.join(expanded),
+                _ => expanded,
             };
+            let real = real_location(&resolved).unwrap_or(resolved);
+            if !roots
+                .read
+                .iter()
+                .filter_map(|root| root.canonicalize().ok())
+                .any(|root| real.starts_with(root))
+            {
+                return Err(outside_root_error(&real));
+            }
+            return Ok(json!({
+                "exists": false,
+                "isFile": false,
+                "isDir": false,
+            }));
+        }
+        Err(error) => return Err(error),
+    };
+    let metadata = std::fs::metadata(&canonical_path).ok();
+    Ok(json!({
+        "exists": metadata.is_some(),
+        "isFile": metadata.as_ref().is_some_and(|metadata| metadata.is_file()),
+        "isDir": metadata.as_ref().is_some_and(|metadata| metadata.is_dir()),
+    }))
+}
 
-            match handle_write_text_file(
-                stream.clone(),
-                shared,
-                transport,
-                session_id,
-                path,
-                content,
-            )
-            .await
+// Where a path that does not exist would be: its nearest existing ancestor
+// with symlinks resolved, plus the missing components. Dangling symlinks are
+// followed by hand. `None` when links loop or the path has no existing
+// ancestor.
+fn real_location(path: &Path) -> Option<PathBuf> {
+    let mut path = normalize_lexically(path);
+    let mut missing = Vec::new();
+    let mut hops = 0;
+    loop {
+        if let Ok(real) = path.canonicalize() {
+            return Some(
+                missing
+                    .iter()
+                    .rev()
+                    .fold(real, |real, name| real.join(name)),
+            );
+        }
+        match std::fs::read_link(&path) {
+            Ok(target) if hops < 40 => {
+                hops += 1;
+                path = normalize_lexically(&path.parent()?.join(target));
+            }
+            Ok(_) => return None,
+            Err(_) => {
+                missing.push(path.file_name()?.to_os_string());
+                path = path.parent()?.to_path_buf();
+            }
+        }
+    }
+}
+
+// Identifies the underlying file rather than the path, so a client can tell
+// that a renamed path still refers to the same file.
+#[cfg(unix)]
+fn file_id(metadata: &std::fs::Metadata) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+    Some(format!("{}:{}", metadata.dev(), metadata.ino()))
+}
+
+// The Windows equivalent (volume serial + file index) is not exposed by std on
+// stable, so other platforms report no id.
+#[cfg(not(unix))]
+fn file_id(_metadata: &std::fs::Metadata) -> Option<String> {
+    None
+}
+
+// Describes the rules `validate_and_resolve_path` applies, so clients can
+// explain a rejection instead of guessing.
+fn handle_sandbox_info(
+    shared: &BridgeSharedConfig,
+    connection: &ConnectionState,
+) -> Result<Value, acp::Error> {
+    let SandboxRoots { read, write } = shared.sandbox_roots(&connection.info)?;
+    let describe = |roots: &[PathBuf]| -> Vec<String> {
+        roots
+            .iter()
+            .map(|root| {
+                root.canonicalize()
+                    .unwrap_or_else(|_| normalize_lexically(root))
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect()
+    };
+    let roots = describe(&read);
+
+    Ok(json!({
+        "projectRoots": roots,
+        "writeRoots": describe(&write),
+        "pathMode": {
+            "absolute": true,
+            "relative": true,
+            "relativeBase": roots.first(),
+        },
+        "symlinks": {
+            "resolvedBeforeContainmentCheck": true,
+            "followedInWalks": shared.follow_symlinks,
+        },
+    }))
+}
+
+const DEFAULT_RECENT_FILES_LIMIT: usize = 20;
+
+fn handle_recent_files(
+    shared: &BridgeSharedConfig,
+    connection: &ConnectionState,
+    limit: usize,
+) -> Result<Value, acp::Error> {
+    let roots = shared.effective_project_roots(&connection.info)?;
+
+    // Min-heap of the `limit` newest files seen so far; the oldest sits on top
+    // and is evicted, so the whole tree is never sorted.
+    let mut newest: BinaryHeap<Reverse<(u128, PathBuf)>> = BinaryHeap::with_capacity(limit + 1);
+    let truncated = walk_project_files(shared, &roots, |path, metadata| {
+        if limit == 0 {
+            return;
+        }
+        let modified_ms = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |elapsed| elapsed.as_millis());
+        newest.push(Reverse((modified_ms, path.to_path_buf())));
+        if newest.len() > limit {
+            newest.pop();
+        }
+    });
+
+    let files: Vec<Value> = newest
+        .into_sorted_vec()
+        .into_iter()
+        .map(|Reverse((modified_ms, path))| {
+            json!({
+                "path": path.to_string_lossy(),
+                "modifiedMs": modified_ms as u64,
+            })
+        })
+        .collect();
+
+    Ok(json!({
+        "files": files,
+        "truncated": truncated,
+    }))
+}
+
+// Visits every regular file under `roots`, skipping `walk_ignore` names.
+// Returns true when `max_walk_entries` cut the walk short.
+fn walk_project_files(
+    shared: &BridgeSharedConfig,
+    roots: &[PathBuf],
+    mut visit: impl FnMut(&std::path::Path, &std::fs::Metadata),
+) -> bool {
+    let canonical_roots: Vec<PathBuf> = roots
+        .iter()
+        .filter_map(|root| root.canonicalize().ok())
+        .collect();
+    // Canonical directories already queued; a symlink resolving to one of
+    // these would revisit it (or loop forever), so it is skipped instead.
+    let mut visited_dirs: HashSet<PathBuf> = canonical_roots.iter().cloned().collect();
+    let mut pending: Vec<PathBuf> = canonical_roots.clone();
+    let mut visited_entries = 0usize;
+
+    while let Some(dir) = pending.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+
+        for entry in entries.flatten() {
+            if visited_entries >= shared.max_walk_entries {
+                return true;
+            }
+            visited_entries += 1;
+
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if shared
+                .walk_ignore
+                .iter()
+                .any(|pattern| glob_matches(pattern, &name))
             {
-                Ok(_) => {
-                    let result = json!({});
-                    send_result_shared(&stream, id, result).await?;
+                continue;
+            }
+
+            let file_type = match entry.file_type() {
+                Ok(file_type) => file_type,
+                Err(_) => continue,
+            };
+            let path = entry.path();
+
+            if file_type.is_symlink() {
+                if !shared.follow_symlinks {
+                    continue;
                 }
-                Err(error) => {
-                    send_error_shared(&stream, id, error).await?;
+                let target = match path.canonicalize() {
+                    Ok(target) => target,
+                    Err(_) => continue,
+                };
+                if !canonical_roots.iter().any(|root| target.starts_with(root)) {
+                    continue;
+                }
+                let metadata = match std::fs::metadata(&target) {
+                    Ok(metadata) => metadata,
+                    Err(_) => continue,
+                };
+                if metadata.is_dir() {
+                    if !visited_dirs.insert(target.clone()) {
+                        tracing::warn!(
+                            link = %path.display(),
+                            target = %target.display(),
+                            "skipping symlink loop during project walk"
+                        );
+                        continue;
+                    }
+                    pending.push(target);
+                } else if metadata.is_file() {
+                    visit(&path, &metadata);
+                }
+            } else if file_type.is_dir() {
+                if visited_dirs.insert(path.clone()) {
+                    pending.push(path);
+                }
+            } else if file_type.is_file() {
+                if let Ok(metadata) = entry.metadata() {
+                    visit(&path, &metadata);
                 }
             }
         }
-        "auth/cli_login" => match handle_auth_cli_login().await {
-            Ok(login_url) => {
-                let result = json!({
-                    "status": "started",
-                    "loginUrl": login_url,
-                });
-                send_result_shared(&stream, id, result).await?;
-            }
-            Err(error) => {
-                send_error_shared(&stream, id, error).await?;
-            }
-        },
-        _ => {
-            let error = acp::Error::method_not_found();
-            send_error_shared(&stream, id, error).await?;
+    }
+
+    false
+}
+
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = backtrack {
+            p = star_p + 1;
+            n = star_n + 1;
+            backtrack = Some((star_p, star_n + 1));
+        } else {
+            return false;
         }
     }
 
-    Ok(())
+    pattern[p..].iter().all(|&ch| ch == '*')
 }
 
-// TODO: Improve project root determination and overhaul sandboxing logic.
-// The current implementation blocks a set of hardcoded system directories
-// and resolves relative paths against the current working directory.
-// This approach is fragile and may allow directory traversal or
-// unintended access to files outside the intended project root.
-// Future work should compute the actual project root (e.g., via
-// environment variables, a .git directory, or a configuration file)
-// and enforce that all file accesses stay within that root.
-fn validate_and_resolve_path(path: &str, for_write: bool) -> Result<PathBuf, acp::Error> {
-    let path_buf = PathBuf::from(path);
-
-    // Implement project root sandboxing per RAT-LWS-REQ-044
-    // Block access to sensitive system paths
-    if path.starts_with("/etc/")
-        || path.starts_with("/var/")
-        || path.starts_with("/root/")
-        || path.starts_with("/usr/")
-        || path.starts_with("/boot/")
-        || path.starts_with("/proc/")
-    {
-        return Err(acp::Error::internal_error().with_data("path outside project root"));
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum WriteMode {
+    Overwrite,
+    Append,
+}
+
+impl WriteMode {
+    fn from_params(params: &Value) -> Result<Self, acp::Error> {
+        match params.get("mode") {
+            None | Some(Value::Null) => Ok(WriteMode::Overwrite),
+            Some(mode) => match mode.as_str() {
+                Some("overwrite") => Ok(WriteMode::Overwrite),
+                Some("append") => Ok(WriteMode::Append),
+                _ => Err(acp::Error::invalid_params()
+                    .with_data("mode must be \"overwrite\" or \"append\"")),
+            },
+        }
     }
 
-    // For relative paths, resolve against current working directory
-    let resolved_path = if path_buf.is_absolute() {
-        path_buf
-    } else {
-        std::env::current_dir()
-            .map_err(|_| acp::Error::internal_error().with_data("failed to get current directory"))?
-            .join(&path_buf)
+    fn as_str(self) -> &'static str {
+        match self {
+            WriteMode::Overwrite => "overwrite",
+            WriteMode::Append => "append",
+        }
+    }
+}
+
+struct WriteTextFileRequest<'a> {
+    // `None` only when `require_session_for_write` is off.
+    session_id: Option<&'a str>,
+    base: Option<&'a str>,
+    path: &'a str,
+    content: &'a str,
+    mode: WriteMode,
+    skip_if_unchanged: bool,
+}
+
+// Decisions are cached per path *and* mode so that approving appends never
+// silently authorizes an overwrite of the same file (or vice versa).
+fn permission_cache_key(canonical_path: &str, mode: WriteMode) -> String {
+    format!("{}:{canonical_path}", mode.as_str())
+}
+
+struct WriteTextFileOutcome {
+    canonical_path: PathBuf,
+    bytes_written: usize,
+    written: bool,
+}
+
+// True when performing the write would leave the file exactly as it is.
+fn write_is_noop(canonical_path: &std::path::Path, content: &str, mode: WriteMode) -> bool {
+    match mode {
+        WriteMode::Append => content.is_empty(),
+        WriteMode::Overwrite => {
+            std::fs::read(canonical_path).is_ok_and(|existing| existing == content.as_bytes())
+        }
+    }
+}
+
+fn write_contents(
+    canonical_path: &std::path::Path,
+    content: &str,
+    mode: WriteMode,
+    atomic: bool,
+) -> Result<WriteTextFileOutcome, acp::Error> {
+    let result = match mode {
+        _ if atomic => write_atomically(canonical_path, content, mode),
+        WriteMode::Overwrite => std::fs::write(canonical_path, content),
+        WriteMode::Append => std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(canonical_path)
+            .and_then(|mut file| file.write_all(content.as_bytes())),
     };
+    result.map_err(|_| {
+        acp::Error::internal_error().with_data(error_data(ErrorKind::Io, "failed to write file"))
+    })?;
+    Ok(WriteTextFileOutcome {
+        canonical_path: canonical_path.to_path_buf(),
+        bytes_written: content.len(),
+        written: true,
+    })
+}
 
-    // Canonicalize path, handling the case where file doesn't exist for writes
-    let canonical_path = if for_write && !resolved_path.exists() {
-        // For write operations, canonicalize the parent directory since the file may not exist yet
-        let parent = resolved_path
-            .parent()
-            .ok_or_else(|| acp::Error::internal_error().with_data("invalid path"))?;
-        let canonical_parent = parent
-            .canonicalize()
-            .map_err(|_| acp::Error::internal_error().with_data("invalid path"))?;
-        canonical_parent.join(
-            resolved_path
-                .file_name()
-                .ok_or_else(|| acp::Error::internal_error().with_data("invalid path"))?,
-        )
-    } else {
-        resolved_path.canonicalize().map_err(|_| {
-            acp::Error::internal_error().with_data(if for_write {
-                "invalid path"
-            } else {
-                "file not found"
-            })
-        })?
+// Stages the complete new contents (the old contents plus `content` when
+// appending) next to the target and renames them into place as the last
+// step. The staging file is removed if anything fails.
+fn write_atomically(path: &std::path::Path, content: &str, mode: WriteMode) -> std::io::Result<()> {
+    static STAGING_SEQ: AtomicU64 = AtomicU64::new(0);
+
+    let mut bytes = match mode {
+        WriteMode::Overwrite => Vec::new(),
+        WriteMode::Append => match std::fs::read(path) {
+            Ok(existing) => existing,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        },
     };
+    bytes.extend_from_slice(content.as_bytes());
 
-    // Additional safety check: ensure the canonical path doesn't escape to system directories
-    let canonical_str = canonical_path.to_string_lossy();
-    if canonical_str.starts_with("/etc/")
-        || canonical_str.starts_with("/var/")
-        || canonical_str.starts_with("/root/")
-        || canonical_str.starts_with("/usr/")
-        || canonical_str.starts_with("/boot/")
-        || canonical_str.starts_with("/proc/")
-    {
-        return Err(acp::Error::internal_error().with_data("path outside project root"));
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let staging = path.with_file_name(format!(
+        ".{file_name}.{}.{}.tmp",
+        std::process::id(),
+        STAGING_SEQ.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = std::fs::File::create(&staging)
+        .and_then(|mut file| {
+            file.write_all(&bytes)?;
+            if let Ok(metadata) = std::fs::metadata(path) {
+                file.set_permissions(metadata.permissions())?;
+            }
+            file.sync_all()
+        })
+        .and_then(|()| std::fs::rename(&staging, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&staging);
     }
-
-    Ok(canonical_path)
+    result
 }
 
-fn handle_read_text_file(
-    path: &str,
-    line_offset: Option<u32>,
-    line_limit: Option<u32>,
-) -> Result<String, acp::Error> {
-    let canonical_path = validate_and_resolve_path(path, false)?;
+fn shutdown_error() -> acp::Error {
+    acp::Error::internal_error().with_data("server shutting down")
+}
 
-    // First read as bytes to check for binary content
-    let bytes = std::fs::read(&canonical_path)
-        .map_err(|_| acp::Error::internal_error().with_data("file not found"))?;
+// A permission prompt may wait on a human indefinitely, so it is abandoned
+// once shutdown begins. An answer that lands after shutdown is discarded too,
+// which keeps writes from starting while the bridge is going away.
+async fn request_permission_until_shutdown(
+    stream: &SharedSink,
+    shared: &BridgeSharedConfig,
+    transport: &Arc<dyn AgentTransport>,
+    connection: &ConnectionState,
+    request: acp::RequestPermissionRequest,
+) -> Result<acp::RequestPermissionResponse, acp::Error> {
+    let options = request.options.clone();
+    let prompt = async {
+        match shared.permission_target {
+            PermissionTarget::Agent => {
+                transport
+                    .request_permission(request)
+                    .await
+                    .map_err(|err| match err {
+                        AgentTransportError::NotImplemented => err.into_rpc_error(),
+                        _ => acp::Error::internal_error().with_data("permission request failed"),
+                    })
+            }
+            PermissionTarget::Client => {
+                let params = serde_json::to_value(&request).map_err(|_| {
+                    acp::Error::internal_error().with_data("permission request failed")
+                })?;
+                let result = connection
+                    .request_client(stream, "session/request_permission", params)
+                    .await?;
+                serde_json::from_value(result).map_err(|err| {
+                    acp::Error::invalid_params()
+                        .with_data(format!("invalid permission response: {err}"))
+                })
+            }
+        }
+    };
 
-    // Check if it's likely a binary file (contains null bytes)
-    if bytes.contains(&0) {
-        return Err(acp::Error::internal_error().with_data("binary file not supported"));
+    let mut shutdown = shared.shutdown.clone();
+    let response = tokio::select! {
+        response = prompt => response,
+        Ok(_) = shutdown.wait_for(|stop| *stop) => return Err(shutdown_error()),
+    };
+    if *shared.shutdown.borrow() {
+        return Err(shutdown_error());
     }
+    if let Ok(response) = &response {
+        shared
+            .metrics
+            .record_permission_outcome(&response.outcome, &options);
+    }
+    response
+}
 
-    // Convert to string
-    let content = String::from_utf8(bytes)
-        .map_err(|_| acp::Error::internal_error().with_data("file contains invalid UTF-8"))?;
+type PermissionOutcome = Result<acp::RequestPermissionResponse, acp::Error>;
 
-    apply_line_filter(&content, line_offset, line_limit)
+// Whoever finds no prompt in flight for a key asks and publishes the outcome;
+// the entry is removed when the asker finishes or gives up.
+struct PermissionLead<'a> {
+    pending: &'a Mutex<HashMap<String, watch::Receiver<Option<PermissionOutcome>>>>,
+    key: String,
+    outcome: watch::Sender<Option<PermissionOutcome>>,
 }
 
-fn apply_line_filter(
-    content: &str,
-    line_offset: Option<u32>,
-    line_limit: Option<u32>,
-) -> Result<String, acp::Error> {
-    let lines: Vec<&str> = content.lines().collect();
+impl Drop for PermissionLead<'_> {
+    fn drop(&mut self) {
+        self.pending
+            .lock()
+            .expect("pending permissions lock poisoned")
+            .remove(&self.key);
+    }
+}
 
-    match (line_offset, line_limit) {
-        (Some(offset), Some(limit)) => {
-            let start_idx = (offset.saturating_sub(1) as usize).min(lines.len());
-            let end_idx = (start_idx + limit as usize).min(lines.len());
+enum PermissionTurn<'a> {
+    Lead(PermissionLead<'a>),
+    Follow(watch::Receiver<Option<PermissionOutcome>>),
+}
 
-            if start_idx >= lines.len() {
-                Ok(String::new())
-            } else {
-                Ok(lines[start_idx..end_idx].join("\n"))
-            }
+impl BridgeSharedConfig {
+    fn permission_turn(&self, key: &str) -> PermissionTurn<'_> {
+        let mut pending = self
+            .pending_permissions
+            .lock()
+            .expect("pending permissions lock poisoned");
+        if let Some(outcome) = pending.get(key) {
+            return PermissionTurn::Follow(outcome.clone());
         }
-        (Some(offset), None) => {
-            let start_idx = (offset.saturating_sub(1) as usize).min(lines.len());
+        let (outcome, receiver) = watch::channel(None);
+        pending.insert(key.to_string(), receiver);
+        PermissionTurn::Lead(PermissionLead {
+            pending: &self.pending_permissions,
+            key: key.to_string(),
+            outcome,
+        })
+    }
+}
 
-            if start_idx >= lines.len() {
-                Ok(String::new())
-            } else {
-                Ok(lines[start_idx..].join("\n"))
+// Writes that arrive while a prompt for the same path and mode is still open
+// share its outcome rather than asking the user a second time. If the prompt
+// is abandoned without an answer, the next waiter asks instead.
+async fn request_write_permission(
+    stream: &SharedSink,
+    shared: &BridgeSharedConfig,
+    transport: &Arc<dyn AgentTransport>,
+    connection: &ConnectionState,
+    cache_key: &str,
+    request: acp::RequestPermissionRequest,
+) -> PermissionOutcome {
+    loop {
+        match shared.permission_turn(cache_key) {
+            PermissionTurn::Follow(mut outcome) => {
+                if let Ok(outcome) = outcome.wait_for(Option::is_some).await {
+                    return outcome.clone().expect("waited for an outcome");
+                }
+            }
+            PermissionTurn::Lead(lead) => {
+                let outcome = request_permission_until_shutdown(
+                    stream,
+                    shared,
+                    transport,
+                    connection,
+                    request.clone(),
+                )
+                .await;
+                lead.outcome.send_replace(Some(outcome.clone()));
+                return outcome;
             }
         }
-        (None, Some(limit)) => {
-            let end_idx = (limit as usize).min(lines.len());
-            Ok(lines[..end_idx].join("\n"))
-        }
-        (None, None) => Ok(content.to_string()),
     }
 }
 
 // TODO: Refactor permission handling into a generic monadic abstraction so it can be more generally applied to different tools.
 async fn handle_write_text_file(
-    _stream: Arc<TokioMutex<WebSocketStream<TcpStream>>>,
+    stream: &SharedSink,
     shared: &BridgeSharedConfig,
     transport: &Arc<dyn AgentTransport>,
-    session_id: &str,
-    path: &str,
-    content: &str,
-) -> Result<(), acp::Error> {
+    connection: &ConnectionState,
+    request: WriteTextFileRequest<'_>,
+) -> Result<WriteTextFileOutcome, acp::Error> {
     use std::fs;
 
+    let WriteTextFileRequest {
+        session_id,
+        base,
+        path,
+        content,
+        mode,
+        skip_if_unchanged,
+    } = request;
+
+    if let Some(max_write_bytes) = shared.max_write_bytes {
+        if content.len() as u64 > max_write_bytes {
+            return Err(acp::Error::invalid_params().with_data(error_data(
+                ErrorKind::TooLarge,
+                format!("content exceeds max_write_bytes ({max_write_bytes} bytes)"),
+            )));
+        }
+    }
+
+    if let Some(name) = virtual_path(path) {
+        name?;
+        if !shared.allow_virtual_writes {
+            return Err(acp::Error::internal_error().with_data(error_data(
+                ErrorKind::SandboxViolation,
+                "virtual files are read-only",
+            )));
+        }
+        let changed = shared
+            .virtual_files
+            .replace(path, content, mode)
+            .ok_or_else(virtual_not_found)?;
+        return Ok(WriteTextFileOutcome {
+            canonical_path: PathBuf::from(path),
+            bytes_written: content.len(),
+            written: changed || !skip_if_unchanged,
+        });
+    }
+
     // First, check sandboxing
-    let canonical_path = validate_and_resolve_path(path, true)?;
-    let canonical_path_str = canonical_path.to_string_lossy().to_string();
+    let roots = shared.sandbox_roots(&connection.info)?;
+    let canonical_path = resolve_against_base(&roots, base, path, true)?;
+    // Checked before prompting: no answer could make this write succeed.
+    if canonical_path.is_dir() {
+        return Err(acp::Error::invalid_params()
+            .with_data(error_data(ErrorKind::IsDirectory, "path is a directory")));
+    }
+    let cache_key = permission_cache_key(&canonical_path.to_string_lossy(), mode);
+
+    // Comparing only needs read access, which the sandbox already grants, so
+    // an identical write is answered without touching the file or prompting.
+    if skip_if_unchanged && write_is_noop(&canonical_path, content, mode) {
+        return Ok(WriteTextFileOutcome {
+            canonical_path,
+            bytes_written: 0,
+            written: false,
+        });
+    }
 
     // Create parent directories if they don't exist
     if let Some(parent) = canonical_path.parent() {
         fs::create_dir_all(parent).map_err(|_| {
-            acp::Error::internal_error().with_data("failed to create parent directories")
+            acp::Error::internal_error().with_data(error_data(
+                ErrorKind::Io,
+                "failed to create parent directories",
+            ))
         })?;
     }
 
     // Check permission cache first
     let cached_decision = {
         let cache = shared.permission_cache.lock().await;
-        cache.get(&canonical_path_str).cloned()
+        cache.get(&cache_key).cloned()
     };
 
     match cached_decision {
         Some(PermissionDecision::AllowAlways) => {
             // Cached allow_always - proceed with write without requesting permission
-            fs::write(&canonical_path, content)
-                .map_err(|_| acp::Error::internal_error().with_data("failed to write file"))?;
-            return Ok(());
+            return write_contents(&canonical_path, content, mode, shared.atomic_writes);
         }
         Some(PermissionDecision::RejectAlways) => {
             // Cached reject_always - return error immediately
-            return Err(acp::Error::new((-32000, "Permission denied".to_string())));
+            return Err(shared.write_refusal(WriteRefusal::DeniedAlways));
         }
         None => {
             // No cached decision - request permission from agent
         }
     }
 
+    // Without a session there is nobody to ask, so the configured answer
+    // stands in for the prompt.
+    let Some(session_id) = session_id else {
+        return match shared.sessionless_write_decision {
+            PermissionDecision::AllowAlways => {
+                write_contents(&canonical_path, content, mode, shared.atomic_writes)
+            }
+            PermissionDecision::RejectAlways => {
+                Err(shared.write_refusal(WriteRefusal::DeniedAlways))
+            }
+        };
+    };
+
     // Request permission from the agent
+    let agent_session_id = shared.agent_session_id(session_id).await;
     let permission_request = acp::RequestPermissionRequest {
-        session_id: acp::SessionId(session_id.to_string().into()),
+        session_id: acp::SessionId(agent_session_id.into()),
         tool_call: acp::ToolCallUpdate {
             id: acp::ToolCallId("fs_write_text_file".to_string().into()),
             fields: acp::ToolCallUpdateFields {
                 kind: Some(acp::ToolKind::Edit),
-                title: Some(format!("Write file: {path}")),
+                title: Some(match mode {
+                    WriteMode::Overwrite => format!("Write file: {path}"),
+                    WriteMode::Append => format!("Append to file: {path}"),
+                }),
                 status: Some(acp::ToolCallStatus::InProgress),
                 ..Default::default()
             },
             meta: None,
         },
-        options: vec![
-            acp::PermissionOption {
-                id: acp::PermissionOptionId("allow_once".to_string().into()),
-                name: "Allow this write operation".to_string(),
-                kind: acp::PermissionOptionKind::AllowOnce,
-                meta: None,
-            },
-            acp::PermissionOption {
-                id: acp::PermissionOptionId("allow_always".to_string().into()),
-                name: "Allow all write operations".to_string(),
-                kind: acp::PermissionOptionKind::AllowAlways,
-                meta: None,
-            },
-            acp::PermissionOption {
-                id: acp::PermissionOptionId("reject_once".to_string().into()),
-                name: "Reject this write operation".to_string(),
-                kind: acp::PermissionOptionKind::RejectOnce,
-                meta: None,
-            },
-            acp::PermissionOption {
-                id: acp::PermissionOptionId("reject_always".to_string().into()),
-                name: "Reject all write operations".to_string(),
-                kind: acp::PermissionOptionKind::RejectAlways,
-                meta: None,
-            },
-        ],
+        options: shared.permission_options.options.clone(),
         meta: None,
     };
 
-    let permission_response = transport
-        .request_permission(permission_request)
-        .await
-        .map_err(|_| acp::Error::internal_error().with_data("permission request failed"))?;
+    let permission_response = request_write_permission(
+        stream,
+        shared,
+        transport,
+        connection,
+        &cache_key,
+        permission_request,
+    )
+    .await?;
 
     // Check the permission outcome and update cache
     match permission_response.outcome {
         acp::RequestPermissionOutcome::Selected { option_id } => {
-            match option_id.0.as_ref() {
-                "allow_once" => {
+            match permission_option_kind(&shared.permission_options.options, &option_id) {
+                Some(acp::PermissionOptionKind::AllowOnce) => {
                     // Permission granted for this write only, proceed with write
-                    fs::write(&canonical_path, content).map_err(|_| {
-                        acp::Error::internal_error().with_data("failed to write file")
-                    })?;
-                    Ok(())
+                    write_contents(&canonical_path, content, mode, shared.atomic_writes)
                 }
-                "allow_always" => {
+                Some(acp::PermissionOptionKind::AllowAlways) => {
                     // Permission granted always, cache the decision and proceed with write
-                    {
-                        let mut cache = shared.permission_cache.lock().await;
-                        cache.insert(canonical_path_str, PermissionDecision::AllowAlways);
-                    }
-                    fs::write(&canonical_path, content).map_err(|_| {
-                        acp::Error::internal_error().with_data("failed to write file")
-                    })?;
-                    Ok(())
+                    shared
+                        .remember_decision([cache_key], PermissionDecision::AllowAlways)
+                        .await;
+                    write_contents(&canonical_path, content, mode, shared.atomic_writes)
                 }
-                "reject_once" => {
+                Some(acp::PermissionOptionKind::RejectOnce) => {
                     // Permission denied for this write only
-                    Err(acp::Error::new((-32000, "Permission denied".to_string())))
+                    Err(shared.write_refusal(WriteRefusal::DeniedOnce))
                 }
-                "reject_always" => {
+                Some(acp::PermissionOptionKind::RejectAlways) => {
                     // Permission denied always, cache the decision
-                    {
-                        let mut cache = shared.permission_cache.lock().await;
-                        cache.insert(canonical_path_str, PermissionDecision::RejectAlways);
-                    }
-                    Err(acp::Error::new((-32000, "Permission denied".to_string())))
+                    shared
+                        .remember_decision([cache_key], PermissionDecision::RejectAlways)
+                        .await;
+                    Err(shared.write_refusal(WriteRefusal::DeniedAlways))
                 }
-                _ => {
+                None => {
                     // Unknown option
-                    Err(acp::Error::new((
-                        -32000,
-                        "Unknown permission option".to_string(),
-                    )))
+                    Err(permission_error(
+                        ErrorKind::UnknownPermissionOption,
+                        "Unknown permission option",
+                    ))
                 }
             }
         }
         acp::RequestPermissionOutcome::Cancelled => {
             // Permission request was cancelled
-            Err(acp::Error::new((
-                -32000,
-                "Permission request cancelled".to_string(),
-            )))
+            Err(shared.write_refusal(WriteRefusal::Cancelled))
         }
     }
 }
 
-async fn handle_auth_cli_login() -> Result<String, acp::Error> {
-    let (cli_path, args) = resolve_claude_login_command()?;
+// Forgets the standing decisions for one path (in every write mode) or, with
+// `all: true`, for every path.
+async fn handle_reset_permission(
+    shared: &BridgeSharedConfig,
+    connection: &ConnectionState,
+    params: &Value,
+) -> Result<Value, acp::Error> {
+    let removed = if params.get("all").and_then(|v| v.as_bool()) == Some(true) {
+        shared.forget_decisions(|_| true).await
+    } else {
+        let path = params.get("path").and_then(|v| v.as_str()).ok_or_else(|| {
+            acp::Error::invalid_params().with_data("expected a path or all: true")
+        })?;
+        // Resolved exactly as writes resolve it, so the keys line up.
+        let roots = shared.sandbox_roots(&connection.info)?;
+        let canonical_path = validate_and_resolve_path(&roots, path, true)?;
+        let canonical_path = canonical_path.to_string_lossy();
+        let keys = [WriteMode::Overwrite, WriteMode::Append]
+            .map(|mode| permission_cache_key(&canonical_path, mode));
+        shared
+            .forget_decisions(|key| keys.iter().any(|candidate| candidate == key))
+            .await
+    };
+    Ok(json!({ "removed": removed }))
+}
+
+// Maps the option a prompt answered with back to what it means.
+fn permission_option_kind(
+    options: &[acp::PermissionOption],
+    option_id: &acp::PermissionOptionId,
+) -> Option<acp::PermissionOptionKind> {
+    options
+        .iter()
+        .find(|option| option.id == *option_id)
+        .map(|option| option.kind)
+}
+
+// Asks once for a whole set of write targets and caches the answer per path,
+// so the writes that follow go through `handle_write_text_file` unprompted.
+async fn handle_permission_request_batch(
+    stream: &SharedSink,
+    shared: &BridgeSharedConfig,
+    transport: &Arc<dyn AgentTransport>,
+    connection: &ConnectionState,
+    session_id: &str,
+    paths: &[&str],
+    mode: WriteMode,
+) -> Result<Value, acp::Error> {
+    let roots = shared.sandbox_roots(&connection.info)?;
+    let canonical_paths = paths
+        .iter()
+        .map(|path| validate_and_resolve_path(&roots, path, true))
+        .collect::<Result<Vec<_>, _>>()?;
+    let options: Vec<acp::PermissionOption> = shared
+        .permission_options
+        .options
+        .iter()
+        .filter(|option| {
+            matches!(
+                option.kind,
+                acp::PermissionOptionKind::AllowAlways | acp::PermissionOptionKind::RejectAlways
+            )
+        })
+        .cloned()
+        .collect();
+    if options.is_empty() {
+        return Err(permission_error(
+            ErrorKind::UnknownPermissionOption,
+            "No allow_always or reject_always permission option to offer",
+        ));
+    }
+
+    let agent_session_id = shared.agent_session_id(session_id).await;
+    let permission_request = acp::RequestPermissionRequest {
+        session_id: acp::SessionId(agent_session_id.into()),
+        tool_call: acp::ToolCallUpdate {
+            id: acp::ToolCallId("fs_write_text_file_batch".to_string().into()),
+            fields: acp::ToolCallUpdateFields {
+                kind: Some(acp::ToolKind::Edit),
+                title: Some(match mode {
+                    WriteMode::Overwrite => format!("Write {} files", canonical_paths.len()),
+                    WriteMode::Append => format!("Append to {} files", canonical_paths.len()),
+                }),
+                status: Some(acp::ToolCallStatus::Pending),
+                locations: Some(
+                    canonical_paths
+                        .iter()
+                        .map(|path| acp::ToolCallLocation {
+                            path: path.clone(),
+                            line: None,
+                            meta: None,
+                        })
+                        .collect(),
+                ),
+                ..Default::default()
+            },
+            meta: None,
+        },
+        options: options.clone(),
+        meta: None,
+    };
+
+    let permission_response = request_permission_until_shutdown(
+        stream,
+        shared,
+        transport,
+        connection,
+        permission_request,
+    )
+    .await?;
+
+    let (decision, label) = match permission_response.outcome {
+        acp::RequestPermissionOutcome::Selected { option_id } => {
+            match permission_option_kind(&options, &option_id) {
+                Some(acp::PermissionOptionKind::AllowAlways) => {
+                    (PermissionDecision::AllowAlways, "allow")
+                }
+                Some(acp::PermissionOptionKind::RejectAlways) => {
+                    (PermissionDecision::RejectAlways, "reject")
+                }
+                _ => {
+                    return Err(permission_error(
+                        ErrorKind::UnknownPermissionOption,
+                        "Unknown permission option",
+                    ))
+                }
+            }
+        }
+        acp::RequestPermissionOutcome::Cancelled => {
+            return Err(permission_error(
+                ErrorKind::PermissionCancelled,
+                "Permission request cancelled",
+            ))
+        }
+    };
+
+    shared
+        .remember_decision(
+            canonical_paths
+                .iter()
+                .map(|path| permission_cache_key(&path.to_string_lossy(), mode)),
+            decision,
+        )
+        .await;
+
+    let paths: Vec<String> = canonical_paths
+        .iter()
+        .map(|path| path.to_string_lossy().to_string())
+        .collect();
+    Ok(json!({
+        "decision": label,
+        "paths": paths,
+    }))
+}
+
+enum LoginOutcome {
+    Started { login_url: String },
+    // The CLI exited cleanly, saying there was nothing to log in to.
+    AlreadyAuthenticated,
+}
 
-    let project_root = std::env::current_dir()
-        .map_err(|_| acp::Error::internal_error().with_data("failed to get current directory"))?;
+async fn handle_auth_cli_login(shared: &BridgeSharedConfig) -> Result<LoginOutcome, acp::Error> {
+    let LoginCommand {
+        program: cli_path,
+        args,
+    } = shared.cli_locator.locate()?;
+
+    let project_root = shared.working_dir()?;
 
     let pty_system = native_pty_system();
     let pair = pty_system
@@ -958,47 +6482,75 @@ async fn handle_auth_cli_login() -> Result<String, acp::Error> {
             pixel_height: 0,
         })
         .map_err(|err| {
-            acp::Error::internal_error().with_data(format!("failed to open pty: {err}"))
+            acp::Error::internal_error().with_data(error_data(
+                ErrorKind::LoginFailed,
+                format!("failed to open pty: {err}"),
+            ))
         })?;
 
     let cli_command = cli_path
         .to_str()
-        .ok_or_else(|| acp::Error::internal_error().with_data("invalid Claude CLI path"))?
+        .ok_or_else(|| {
+            acp::Error::internal_error().with_data(error_data(
+                ErrorKind::CliUnavailable,
+                "invalid login CLI path",
+            ))
+        })?
         .to_string();
 
-    let mut builder = CommandBuilder::new(cli_command);
+    let mut builder = CommandBuilder::new(&cli_command);
     for arg in &args {
         builder.arg(arg);
     }
-    builder.arg("/login");
     builder.cwd(&project_root);
     for (key, value) in std::env::vars() {
         builder.env(key, value);
     }
 
-    let child = pair.slave.spawn_command(builder).map_err(|err| {
-        acp::Error::internal_error().with_data(format!("failed to spawn login CLI: {err}"))
+    // The CLI inherits the bridge's environment, which may hold secrets, so
+    // only the program and its arguments are reported.
+    let mut child = pair.slave.spawn_command(builder).map_err(|err| {
+        tracing::warn!(cli_path = %cli_command, argv = ?args, error = %err, "failed to spawn login CLI");
+        let mut data = error_data(
+            ErrorKind::LoginFailed,
+            format!("failed to spawn login CLI {cli_command}: {err}"),
+        );
+        data["cliPath"] = json!(cli_command);
+        data["argv"] = json!(args);
+        acp::Error::internal_error().with_data(data)
     })?;
     drop(pair.slave);
 
     let mut reader = pair.master.try_clone_reader().map_err(|err| {
-        acp::Error::internal_error().with_data(format!("failed to clone pty reader: {err}"))
+        acp::Error::internal_error().with_data(error_data(
+            ErrorKind::LoginFailed,
+            format!("failed to clone pty reader: {err}"),
+        ))
     })?;
-    let mut writer = pair.master.take_writer().map_err(|err| {
-        acp::Error::internal_error().with_data(format!("failed to take pty writer: {err}"))
-    })?;
-
     let automation_stop = Arc::new(AtomicBool::new(false));
-    let writer_stop = automation_stop.clone();
-    let writer_thread = std::thread::spawn(move || {
-        while !writer_stop.load(Ordering::Relaxed) {
-            if writer.write_all(b"\r").is_err() {
-                break;
-            }
-            let _ = writer.flush();
-            std::thread::sleep(Duration::from_millis(250));
+    // Without automation the writer is never taken: dropping it would send
+    // the CLI a newline and EOF.
+    let writer_thread = match shared.login_automation.clone() {
+        Some(LoginAutomation { input, interval }) => {
+            let mut writer = pair.master.take_writer().map_err(|err| {
+                acp::Error::internal_error().with_data(error_data(
+                    ErrorKind::LoginFailed,
+                    format!("failed to take pty writer: {err}"),
+                ))
+            })?;
+            let writer_stop = automation_stop.clone();
+            Some(std::thread::spawn(move || {
+                while !writer_stop.load(Ordering::Relaxed) {
+                    if writer.write_all(&input).is_err() {
+                        break;
+                    }
+                    let _ = writer.flush();
+                    std::thread::sleep(interval);
+                }
+            }))
         }
-    });
+        None => None,
+    };
 
     let (tx, mut rx) = mpsc::unbounded_channel();
     let reader_stop = automation_stop.clone();
@@ -1025,33 +6577,74 @@ async fn handle_auth_cli_login() -> Result<String, acp::Error> {
             collected.push_str(&text);
             if let Some(url) = extract_login_url(&collected) {
                 capture_stop.store(true, Ordering::Relaxed);
-                return Ok::<String, acp::Error>(url);
+                return Ok(url);
             }
         }
 
         capture_stop.store(true, Ordering::Relaxed);
-        Err(acp::Error::internal_error().with_data("login CLI exited before emitting a login URL"))
+        // The output is kept so the caller can tell an already-authenticated
+        // exit from a failed one.
+        Err(collected)
     };
 
     let capture_result = timeout(Duration::from_secs(30), capture).await;
 
     automation_stop.store(true, Ordering::Relaxed);
-    let _ = writer_thread.join();
+    if let Some(writer_thread) = writer_thread {
+        let _ = writer_thread.join();
+    }
     let _ = reader_thread.join();
 
-    let result = capture_result.map_err(|_| {
-        acp::Error::internal_error().with_data("timed out waiting for Claude login URL")
+    let captured = capture_result.map_err(|_| {
+        acp::Error::internal_error().with_data(error_data(
+            ErrorKind::LoginTimeout,
+            "timed out waiting for Claude login URL",
+        ))
     })?;
 
-    // Detach the child process; the CLI continues running until the user completes login.
-    drop(child);
+    let output = match captured {
+        Ok(login_url) => {
+            // Detach the child process; the CLI continues running until the user completes login.
+            drop(child);
+            return Ok(LoginOutcome::Started { login_url });
+        }
+        Err(output) => output,
+    };
 
-    result
+    // The PTY reached EOF, so the CLI has exited or is about to.
+    let exit_status = tokio::task::spawn_blocking(move || child.wait());
+    let exited_cleanly = matches!(
+        timeout(Duration::from_secs(5), exit_status).await,
+        Ok(Ok(Ok(status))) if status.success()
+    );
+    if exited_cleanly && reports_already_authenticated(&output) {
+        return Ok(LoginOutcome::AlreadyAuthenticated);
+    }
+    Err(acp::Error::internal_error().with_data(error_data(
+        ErrorKind::LoginFailed,
+        "login CLI exited before emitting a login URL",
+    )))
+}
+
+fn reports_already_authenticated(output: &str) -> bool {
+    static ALREADY: OnceLock<Regex> = OnceLock::new();
+    ALREADY
+        .get_or_init(|| {
+            Regex::new(r"(?i)already\s+(?:logged\s+in|signed\s+in|authenticated)")
+                .expect("valid already-authenticated pattern")
+        })
+        .is_match(output)
 }
 
 // Global mutex to serialize CLI resolution during tests to prevent env var races
 static CLI_RESOLUTION_LOCK: OnceLock<Mutex<()>> = OnceLock::new();
 
+fn cli_unavailable_error() -> acp::Error {
+    const MESSAGE: &str = "Unable to locate Claude login CLI. Try installing @zed-industries/claude-code-acp or ensure `claude` is in PATH.";
+    acp::Error::new((-32000, MESSAGE.to_string()))
+        .with_data(error_data(ErrorKind::CliUnavailable, MESSAGE))
+}
+
 fn resolve_claude_login_command() -> Result<(PathBuf, Vec<String>), acp::Error> {
     // Serialize access to environment variables during resolution to prevent test interference
     let lock = CLI_RESOLUTION_LOCK.get_or_init(|| Mutex::new(()));
@@ -1059,7 +6652,7 @@ fn resolve_claude_login_command() -> Result<(PathBuf, Vec<String>), acp::Error>
 
     // Check for test failure mode
     if std::env::var("TEST_MODE_FAIL").is_ok() {
-        return Err(acp::Error::new((-32000, "Unable to locate Claude login CLI. Try installing @zed-industries/claude-code-acp or ensure `claude` is in PATH.".to_string())));
+        return Err(cli_unavailable_error());
     }
 
     // Check for test-specific override first (highest priority for tests)
@@ -1085,36 +6678,75 @@ fn resolve_claude_login_command() -> Result<(PathBuf, Vec<String>), acp::Error>
         return Ok((path, vec![]));
     }
 
-    Err(acp::Error::new((-32000, "Unable to locate Claude login CLI. Try installing @zed-industries/claude-code-acp or ensure `claude` is in PATH.".to_string())))
+    Err(cli_unavailable_error())
 }
 
 fn find_claude_code_cli_from_node_modules() -> Option<(PathBuf, Vec<String>)> {
-    // Look for the Claude Code CLI in node_modules, similar to Zed's approach
-    // Check if we have @zed-industries/claude-code-acp installed locally
-    let acp_entry = PathBuf::from("node_modules/@zed-industries/claude-code-acp/dist/index.js");
-    if acp_entry.exists() {
-        // Walk up to find the @anthropic-ai/claude-code/cli.js
-        let node_modules_dir = acp_entry
-            .parent() // dist
-            .and_then(|p| p.parent()) // @zed-industries/claude-code-acp
-            .and_then(|p| p.parent()) // @zed-industries
-            .and_then(|p| p.parent()); // node_modules
-
-        if let Some(node_modules_dir) = node_modules_dir {
-            let cli_js = node_modules_dir
-                .join("@anthropic-ai")
-                .join("claude-code")
-                .join("cli.js");
-            if cli_js.exists() {
-                return Some((
-                    PathBuf::from("node"),
-                    vec![cli_js.to_string_lossy().to_string()],
-                ));
-            }
-        }
+    // Look for the Claude Code CLI in node_modules, similar to Zed's approach:
+    // the nearest node_modules from the working directory up (covers parent
+    // workspaces), then global npm installs. `npm root -g` spawns a process,
+    // so it is only consulted once everything else has missed.
+    let cwd = std::env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join("node_modules"))
+        .chain(global_node_modules_dirs())
+        .chain(std::iter::once_with(npm_global_root).flatten())
+        .find_map(|node_modules| claude_code_cli_in(&node_modules))
+}
+
+fn claude_code_cli_in(node_modules: &std::path::Path) -> Option<(PathBuf, Vec<String>)> {
+    // Only trust a tree that actually has @zed-industries/claude-code-acp
+    // installed next to @anthropic-ai/claude-code/cli.js.
+    let acp_entry = node_modules
+        .join("@zed-industries")
+        .join("claude-code-acp")
+        .join("dist")
+        .join("index.js");
+    if !acp_entry.exists() {
+        return None;
     }
 
-    None
+    let cli_js = node_modules
+        .join("@anthropic-ai")
+        .join("claude-code")
+        .join("cli.js");
+    cli_js.exists().then(|| {
+        (
+            PathBuf::from("node"),
+            vec![cli_js.to_string_lossy().to_string()],
+        )
+    })
+}
+
+fn global_node_modules_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(prefix) = std::env::var_os("NPM_CONFIG_PREFIX") {
+        dirs.push(PathBuf::from(prefix).join("lib").join("node_modules"));
+    }
+    if let Some(home) = std::env::var_os("HOME") {
+        dirs.push(
+            PathBuf::from(home)
+                .join(".npm-global")
+                .join("lib")
+                .join("node_modules"),
+        );
+    }
+    dirs.push(PathBuf::from("/usr/local/lib/node_modules"));
+    dirs
+}
+
+fn npm_global_root() -> Option<PathBuf> {
+    let output = std::process::Command::new("npm")
+        .args(["root", "-g"])
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let root = String::from_utf8(output.stdout).ok()?;
+    let root = root.trim();
+    (!root.is_empty()).then(|| PathBuf::from(root))
 }
 
 fn extract_login_url(buffer: &str) -> Option<String> {
@@ -1141,17 +6773,64 @@ fn extract_login_url(buffer: &str) -> Option<String> {
     }
 }
 
-fn ensure_bridge_meta(response: &mut acp::InitializeResponse, bridge_id: &str) {
+fn ensure_bridge_meta(response: &mut acp::InitializeResponse, shared: &BridgeSharedConfig) {
     let mut meta = match response.meta.take() {
         Some(Value::Object(map)) => map,
         _ => Map::new(),
     };
-    meta.insert("bridgeId".to_string(), json!(bridge_id));
+    meta.insert("bridgeId".to_string(), json!(shared.bridge_id));
+    meta.insert(
+        "bridgeVersion".to_string(),
+        json!(env!("CARGO_PKG_VERSION")),
+    );
+    meta.insert(
+        "supportedProtocolVersions".to_string(),
+        json!(shared.supported_protocol_versions),
+    );
     response.meta = Some(Value::Object(meta));
 }
 
+// Adds `bridgeId` to a serialized response's `_meta`, keeping whatever the
+// agent put there (usage counts, model names and the like). A `_meta` that is
+// not an object has nowhere to put the key, so it is passed on untouched
+// rather than replaced.
+fn ensure_bridge_meta_value(response: &mut Value, bridge_id: &str) {
+    let Value::Object(response) = response else {
+        return;
+    };
+    let meta = response
+        .entry("_meta")
+        .or_insert_with(|| Value::Object(Map::new()));
+    if let Value::Object(meta) = meta {
+        meta.insert("bridgeId".to_string(), json!(bridge_id));
+    }
+}
+
+// The agent knows nothing of `auth/cli_login`, so the bridge lists it itself.
+fn ensure_cli_login_auth_method(response: &mut acp::InitializeResponse) {
+    let id = acp::AuthMethodId("cli_login".into());
+    if response.auth_methods.iter().any(|method| method.id == id) {
+        return;
+    }
+    response.auth_methods.push(acp::AuthMethod {
+        id,
+        name: "Log in with the CLI".to_string(),
+        description: Some("Runs the agent's CLI login flow via auth/cli_login.".to_string()),
+        meta: None,
+    });
+}
+
+// A response that fails to serialize is a method-level failure: the client
+// gets an internal error and the connection stays up. Only socket errors end
+// a connection.
+fn response_value(result: serde_json::Result<Value>) -> Result<Value, acp::Error> {
+    result.map_err(|err| {
+        acp::Error::internal_error().with_data(format!("failed to serialize response: {err}"))
+    })
+}
+
 async fn send_result(
-    stream: &mut WebSocketStream<TcpStream>,
+    stream: &mut WsSink,
     id: Value,
     result: Value,
 ) -> Result<(), tungstenite::Error> {
@@ -1164,10 +6843,13 @@ async fn send_result(
 }
 
 async fn send_error(
-    stream: &mut WebSocketStream<TcpStream>,
+    stream: &mut WsSink,
     id: Value,
-    error: acp::Error,
+    mut error: acp::Error,
 ) -> Result<(), tungstenite::Error> {
+    if let Some(redaction) = &stream.redaction {
+        redaction.redact_error(&mut error);
+    }
     let payload = json!({
         "jsonrpc": "2.0",
         "id": id,
@@ -1177,7 +6859,7 @@ async fn send_error(
 }
 
 async fn send_result_shared(
-    stream: &Arc<TokioMutex<WebSocketStream<TcpStream>>>,
+    stream: &SharedSink,
     id: Value,
     result: Value,
 ) -> Result<(), tungstenite::Error> {
@@ -1186,7 +6868,7 @@ async fn send_result_shared(
 }
 
 async fn send_error_shared(
-    stream: &Arc<TokioMutex<WebSocketStream<TcpStream>>>,
+    stream: &SharedSink,
     id: Value,
     error: acp::Error,
 ) -> Result<(), tungstenite::Error> {
@@ -1194,11 +6876,146 @@ async fn send_error_shared(
     send_error(&mut guard, id, error).await
 }
 
-async fn send_json(
-    stream: &mut WebSocketStream<TcpStream>,
-    payload: Value,
-) -> Result<(), tungstenite::Error> {
-    let text = serde_json::to_string(&payload)
-        .map_err(|err| tungstenite::Error::Io(std::io::Error::other(err)))?;
-    stream.send(Message::Text(text)).await
+async fn send_json(stream: &mut WsSink, payload: Value) -> Result<(), tungstenite::Error> {
+    let message = stream.encode_json(&payload)?;
+    stream.send(message).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Unserializable;
+
+    impl serde::Serialize for Unserializable {
+        fn serialize<S: serde::Serializer>(&self, _serializer: S) -> Result<S::Ok, S::Error> {
+            Err(serde::ser::Error::custom("unserializable"))
+        }
+    }
+
+    #[test]
+    fn serialization_failures_become_internal_errors() {
+        let error = response_value(serde_json::to_value(Unserializable))
+            .expect_err("serialization should fail");
+        assert_eq!(error.code, acp::Error::internal_error().code);
+        assert_eq!(
+            error.data,
+            Some(json!("failed to serialize response: unserializable"))
+        );
+    }
+
+    #[test]
+    fn redaction_relativizes_paths_under_roots_and_hides_the_rest() {
+        let redaction = PathRedaction::new(&[PathBuf::from("/srv/project")]);
+        assert_eq!(
+            redaction.redact_text("failed to open /srv/project/src/main.rs: denied"),
+            "failed to open src/main.rs: denied"
+        );
+        assert_eq!(
+            redaction.redact_text("cwd=/srv/project, home '/home/alice/.config'"),
+            "cwd=., home '<path>'"
+        );
+        assert_eq!(
+            redaction.redact_text("see https://example.com/docs and/or retry"),
+            "see https://example.com/docs and/or retry"
+        );
+    }
+
+    #[test]
+    fn prompt_params_accept_legacy_string_prompts() {
+        let request = parse_prompt_params(&json!({"sessionId": "s-1", "prompt": "hello"}))
+            .expect("legacy prompt should parse");
+        assert_eq!(request.session_id.0.as_ref(), "s-1");
+        assert!(matches!(
+            request.prompt.as_slice(),
+            [acp::ContentBlock::Text(text)] if text.text == "hello"
+        ));
+    }
+
+    #[test]
+    fn prompt_params_accept_acp_content_blocks() {
+        let request = parse_prompt_params(&json!({
+            "sessionId": "s-1",
+            "prompt": [
+                {"type": "text", "text": "look at this"},
+                {"type": "resource_link", "name": "main.rs", "uri": "file:///main.rs"},
+            ],
+            "_meta": {"trace": "abc"},
+        }))
+        .expect("ACP prompt should parse");
+        assert!(matches!(
+            request.prompt.as_slice(),
+            [acp::ContentBlock::Text(_), acp::ContentBlock::ResourceLink(link)]
+                if link.uri == "file:///main.rs"
+        ));
+        assert_eq!(request.meta, Some(json!({"trace": "abc"})));
+    }
+
+    #[test]
+    fn prompt_params_reject_malformed_blocks() {
+        let error = parse_prompt_params(&json!({
+            "sessionId": "s-1",
+            "prompt": [{"type": "text", "text": "ok"}, {"type": "hologram"}],
+        }))
+        .expect_err("unknown block types should be rejected");
+        assert_eq!(error.code, acp::Error::invalid_params().code);
+        let detail = error
+            .data
+            .as_ref()
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        assert!(
+            detail.starts_with("invalid prompt block 1:"),
+            "unexpected detail {detail:?}"
+        );
+
+        let error = parse_prompt_params(&json!({"sessionId": "s-1", "prompt": 42}))
+            .expect_err("non-array prompts should be rejected");
+        assert_eq!(error.data, Some(json!("prompt must be a string or array")));
+    }
+
+    #[test]
+    fn line_offset_zero_is_rejected() {
+        let error = apply_line_filter(&b"one\ntwo\n"[..], Some(0), None)
+            .err()
+            .expect("offset 0 should be rejected");
+        assert_eq!(error.code, acp::Error::invalid_params().code);
+    }
+
+    #[test]
+    fn line_limit_zero_selects_an_empty_window() {
+        let window = apply_line_filter(&b"one\ntwo\n"[..], Some(1), Some(0))
+            .expect("limit 0 is a valid empty window");
+        assert_eq!(window.content, "");
+        assert_eq!(window.total_lines, 2);
+        assert!(window.truncated);
+    }
+
+    #[test]
+    fn empty_input_yields_an_empty_window_for_any_range() {
+        let ranges = [
+            (None, None),
+            (Some(1), None),
+            (Some(5), None),
+            (None, Some(0)),
+            (None, Some(3)),
+            (Some(2), Some(10)),
+            (Some(u32::MAX), Some(u32::MAX)),
+        ];
+        for (line_offset, line_limit) in ranges {
+            let window = apply_line_filter(&b""[..], line_offset, line_limit)
+                .unwrap_or_else(|err| panic!("{line_offset:?}/{line_limit:?} failed: {err:?}"));
+            assert_eq!(window.content, "", "{line_offset:?}/{line_limit:?}");
+            assert_eq!(window.total_lines, 0);
+            assert!(!window.truncated, "{line_offset:?}/{line_limit:?}");
+        }
+    }
+
+    #[test]
+    fn empty_input_still_rejects_line_offset_zero() {
+        let error = apply_line_filter(&b""[..], Some(0), Some(1))
+            .err()
+            .expect("offset 0 is invalid whatever the file holds");
+        assert_eq!(error.code, acp::Error::invalid_params().code);
+    }
 }
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::env;
 use std::ffi::OsString;
 use std::fs;
@@ -6,6 +7,7 @@ use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use url::Url;
@@ -13,6 +15,9 @@ use url::Url;
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 
+#[cfg(unix)]
+use ct_bridge::serve_unix;
+
 use agent_client_protocol as acp;
 use async_tungstenite::tungstenite::{
     self,
@@ -21,9 +26,22 @@ use async_tungstenite::tungstenite::{
         header::{HeaderValue, ORIGIN, SEC_WEBSOCKET_PROTOCOL},
         Response,
     },
-    protocol::Message,
+    protocol::{
+        frame::{
+            coding::{CloseCode, Data, OpCode},
+            Frame,
+        },
+        Message,
+    },
+};
+use ct_bridge::{
+    is_probably_binary, serve, serve_with_listener, AgentTransport, AgentTransportError,
+    BridgeConfig, BridgeError, BridgeHandle, BridgeMetricsSnapshot, CliLocator, ConnectionInfo,
+    DuplicateSessionPolicy, HandshakeCheckOrder, JsonFilePermissionStore, LoginCommand,
+    MethodHandler, NotificationOverflow, PermissionOptions, PermissionStore, PermissionTarget,
+    PromptTimeoutMode, ReadOnlyOrigins, RequestIdAllocator, RetryingTransport, ShutdownToken,
+    StdioAgentTransport,
 };
-use ct_bridge::{serve, AgentTransport, AgentTransportError, BridgeConfig, BridgeHandle};
 use futures_util::{Sink, SinkExt, Stream, StreamExt};
 use serde_json::{json, Value};
 use tokio::sync::Mutex;
@@ -103,6 +121,16 @@ async fn bridge_handshake_accepts_initialize() {
         .get("_meta")
         .unwrap_or_else(|| panic!("missing _meta in {result:?}"));
     assert_eq!(meta.get("bridgeId"), Some(&json!(TEST_BRIDGE_ID)));
+    assert!(
+        meta.get("bridgeVersion")
+            .and_then(|v| v.as_str())
+            .is_some_and(|v| !v.is_empty()),
+        "missing bridgeVersion in {meta:?}"
+    );
+    assert_eq!(
+        meta.get("supportedProtocolVersions"),
+        Some(&json!([acp::VERSION]))
+    );
 
     let calls = agent.take_initialize_calls().await;
     // Maintains RAT-LWS-REQ-011 transparency by forwarding the initialize call unchanged.
@@ -118,6 +146,47 @@ async fn bridge_handshake_accepts_initialize() {
 
 // --- auth/cli_login tests ---
 
+#[tokio::test(flavor = "multi_thread")]
+async fn initialize_advertises_cli_login_auth_method_only_when_enabled() {
+    for enabled in [true, false] {
+        let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+        let harness = BridgeHarness::start_with(agent, move |config| {
+            config.enable_cli_login = enabled;
+        })
+        .await;
+        let (mut ws, _) = harness
+            .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
+            .await
+            .expect("handshake should succeed");
+        send_initialize_request(&mut ws).await;
+        let payload = parse_json(&next_message(&mut ws).await);
+        let auth_methods = payload
+            .get("result")
+            .and_then(|r| r.get("authMethods"))
+            .and_then(|m| m.as_array())
+            .unwrap_or_else(|| panic!("missing authMethods in {payload:?}"));
+        let cli_login = auth_methods
+            .iter()
+            .find(|method| method.get("id") == Some(&json!("cli_login")));
+        if enabled {
+            let cli_login = cli_login.expect("cli_login should be advertised");
+            assert!(cli_login.get("name").and_then(|n| n.as_str()).is_some());
+        } else {
+            assert!(
+                cli_login.is_none(),
+                "disabled cli_login leaked: {payload:?}"
+            );
+            let payload = call(&mut ws, "login", "auth/cli_login", json!({})).await;
+            assert_eq!(
+                payload.get("error").and_then(|e| e.get("code")),
+                Some(&json!(-32601))
+            );
+        }
+
+        harness.shutdown().await;
+    }
+}
+
 #[tokio::test]
 #[serial_test::serial]
 async fn auth_cli_login_resolves_claude_acp_bin_override() {
@@ -504,6 +573,48 @@ async fn auth_cli_login_errors_when_cli_unavailable() {
     harness.shutdown().await;
 }
 
+#[cfg(unix)]
+#[tokio::test]
+#[serial_test::serial]
+async fn auth_cli_login_spawn_failure_reports_the_cli_path() {
+    clean_auth_env();
+    let temp = TestTempDir::new("auth-cli-login-not-executable");
+    let claude_path = temp.path().join("claude");
+    fs::write(&claude_path, "#!/bin/sh\necho never\n").expect("write stub");
+    let mut permissions = fs::metadata(&claude_path)
+        .expect("stub metadata")
+        .permissions();
+    permissions.set_mode(0o644);
+    fs::set_permissions(&claude_path, permissions).expect("set stub permissions");
+    let claude_path = claude_path.to_string_lossy().to_string();
+    let _env_guard = EnvVarGuard::set_var("TEST_CLAUDE_CLI_PATH", claude_path.clone());
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start(agent).await;
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(
+        &mut ws,
+        "auth-not-executable",
+        "auth/cli_login",
+        Value::Null,
+    )
+    .await;
+    let data = payload
+        .pointer("/error/data")
+        .unwrap_or_else(|| panic!("spawning a non-executable CLI should fail, got {payload:?}"));
+    assert_eq!(data.get("kind"), Some(&json!("login_failed")));
+    assert_eq!(data.get("cliPath"), Some(&json!(claude_path)));
+    assert_eq!(data.get("argv"), Some(&json!(["/login"])));
+    let detail = data.get("detail").and_then(Value::as_str).unwrap_or("");
+    assert!(
+        detail.contains(&claude_path),
+        "detail should name the CLI path, got {detail:?}"
+    );
+
+    harness.shutdown().await;
+}
+
 #[tokio::test]
 #[serial_test::serial]
 async fn auth_cli_login_returns_immediately_before_process_completion() {
@@ -678,6 +789,297 @@ async fn auth_cli_login_resolves_package_from_workspace() {
     harness.shutdown().await;
 }
 
+// Lays out the packages `find_claude_code_cli_from_node_modules` looks for,
+// with a cli.js that prints a login URL and touches `sentinel` when run.
+fn install_fake_claude_code(node_modules: &Path, sentinel: &Path) {
+    let anthropic_dir = node_modules.join("@anthropic-ai").join("claude-code");
+    fs::create_dir_all(&anthropic_dir).expect("create anthropic dir");
+    let zed_adapter_dir = node_modules
+        .join("@zed-industries")
+        .join("claude-code-acp")
+        .join("dist");
+    fs::create_dir_all(&zed_adapter_dir).expect("create zed adapter dir");
+
+    let script_body = format!(
+        "#!/usr/bin/env node\nconsole.log('https://example.com/login');\nrequire('fs').writeFileSync('{}', 'invoked');\n",
+        sentinel.display()
+    );
+    fs::write(anthropic_dir.join("cli.js"), script_body).expect("create cli.js");
+    fs::write(zed_adapter_dir.join("index.js"), "// Zed ACP adapter").expect("create index.js");
+}
+
+async fn assert_cli_login_starts(sentinel: &Path) {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start(agent).await;
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(&mut ws, "auth-cli-login", "auth/cli_login", Value::Null).await;
+    let result = payload
+        .get("result")
+        .unwrap_or_else(|| panic!("auth/cli_login should resolve the CLI, got {payload:?}"));
+    assert_eq!(result.get("status"), Some(&json!("started")));
+    wait_for_path(sentinel).await;
+
+    harness.shutdown().await;
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn auth_cli_login_resolves_package_from_parent_directory() {
+    clean_auth_env();
+    let temp = TestTempDir::new("auth-cli-login-parent");
+    let sentinel = temp.path().join("parent-claude-invoked");
+    install_fake_claude_code(&temp.path().join("node_modules"), &sentinel);
+
+    let nested = temp.path().join("packages").join("app");
+    fs::create_dir_all(&nested).expect("create nested package");
+    let original_dir = env::current_dir().expect("get current dir");
+    env::set_current_dir(&nested).expect("change to nested package");
+    let _dir_guard = DirGuard {
+        original: original_dir,
+    };
+
+    assert_cli_login_starts(&sentinel).await;
+}
+
+#[tokio::test]
+#[serial_test::serial]
+async fn auth_cli_login_resolves_global_npm_install() {
+    clean_auth_env();
+    let temp = TestTempDir::new("auth-cli-login-global");
+    let sentinel = temp.path().join("global-claude-invoked");
+    let prefix = temp.path().join("npm-prefix");
+    install_fake_claude_code(&prefix.join("lib").join("node_modules"), &sentinel);
+    let _prefix_guard =
+        EnvVarGuard::set_var("NPM_CONFIG_PREFIX", prefix.to_string_lossy().to_string());
+
+    let project = temp.path().join("project");
+    fs::create_dir_all(&project).expect("create project dir");
+    let original_dir = env::current_dir().expect("get current dir");
+    env::set_current_dir(&project).expect("change to project dir");
+    let _dir_guard = DirGuard {
+        original: original_dir,
+    };
+
+    assert_cli_login_starts(&sentinel).await;
+}
+
+struct StubCliLocator {
+    command: LoginCommand,
+}
+
+impl CliLocator for StubCliLocator {
+    fn locate(&self) -> Result<LoginCommand, acp::Error> {
+        Ok(self.command.clone())
+    }
+}
+
+#[tokio::test]
+async fn auth_cli_login_uses_configured_cli_locator() {
+    let temp = TestTempDir::new("auth-cli-login-locator");
+    let args_path = temp.path().join("stub-args.txt");
+    let script_body = format!(
+        "#!/bin/sh\necho \"$@\" > \"{args}\"\necho 'https://example.com/login?via=locator'\nsleep 2\n",
+        args = args_path.display()
+    );
+    let stub = temp.write_bin_executable("other-agent-login", &script_body);
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.cli_locator = Arc::new(StubCliLocator {
+            command: LoginCommand {
+                program: stub,
+                args: vec!["--custom-login".to_string()],
+            },
+        });
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(&mut ws, "auth-locator", "auth/cli_login", Value::Null).await;
+    let result = payload
+        .get("result")
+        .unwrap_or_else(|| panic!("login via locator should start, got {payload:?}"));
+    assert_eq!(
+        result.get("loginUrl"),
+        Some(&json!("https://example.com/login?via=locator"))
+    );
+
+    wait_for_path(&args_path).await;
+    assert_eq!(
+        fs::read_to_string(&args_path)
+            .expect("read stub args")
+            .trim(),
+        "--custom-login",
+        "the locator's args replace the Claude-specific ones"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test]
+async fn project_root_anchors_relative_reads_and_the_login_cwd() {
+    let temp = TestTempDir::new("project-root-config");
+    let project = temp.path().join("project");
+    fs::create_dir_all(&project).expect("create project dir");
+    fs::write(project.join("notes.txt"), "from the project root").expect("write notes");
+    let cwd_path = temp.path().join("login-cwd.txt");
+    let script_body = format!(
+        "#!/bin/sh\npwd > \"{cwd}\"\necho 'https://example.com/login?via=root'\nsleep 2\n",
+        cwd = cwd_path.display()
+    );
+    let stub = temp.write_bin_executable("root-login", &script_body);
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let root = project.clone();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_root = root;
+        config.cli_locator = Arc::new(StubCliLocator {
+            command: LoginCommand {
+                program: stub,
+                args: Vec::new(),
+            },
+        });
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    let payload = call(
+        &mut ws,
+        "relative-read",
+        "fs/read_text_file",
+        json!({"sessionId": session_id, "path": "notes.txt"}),
+    )
+    .await;
+    assert_eq!(
+        payload.get("result").and_then(|r| r.get("content")),
+        Some(&json!("from the project root")),
+        "relative paths should resolve against project_root, got {payload:?}"
+    );
+
+    let payload = call(&mut ws, "root-login", "auth/cli_login", Value::Null).await;
+    assert!(
+        payload.get("result").is_some(),
+        "login should start, got {payload:?}"
+    );
+    wait_for_path(&cwd_path).await;
+    let recorded_cwd = fs::read_to_string(&cwd_path).expect("read recorded cwd");
+    assert_eq!(
+        Path::new(recorded_cwd.trim())
+            .canonicalize()
+            .expect("recorded cwd exists"),
+        project.canonicalize().expect("project dir exists"),
+        "login CLI should run from project_root"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test]
+async fn auth_cli_login_without_automation_leaves_pty_input_untouched() {
+    let temp = TestTempDir::new("auth-cli-login-no-automation");
+    let input_path = temp.path().join("stub-input.txt");
+    // Any automated keystroke arrives during the sleep and satisfies `read`.
+    let script_body = format!(
+        "#!/bin/bash\nsleep 0.5\nif IFS= read -r -t 0.5 line; then printf 'received' > \"{input}\"; fi\necho 'https://example.com/login?via=manual'\nsleep 2\n",
+        input = input_path.display()
+    );
+    let stub = temp.write_bin_executable("manual-login", &script_body);
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.cli_locator = Arc::new(StubCliLocator {
+            command: LoginCommand {
+                program: stub,
+                args: Vec::new(),
+            },
+        });
+        config.login_automation = None;
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(&mut ws, "auth-manual", "auth/cli_login", Value::Null).await;
+    let result = payload
+        .get("result")
+        .unwrap_or_else(|| panic!("login without automation should start, got {payload:?}"));
+    assert_eq!(
+        result.get("loginUrl"),
+        Some(&json!("https://example.com/login?via=manual"))
+    );
+    assert!(
+        !input_path.exists(),
+        "no input should reach the login CLI when automation is disabled"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test]
+async fn auth_cli_login_reports_already_authenticated_cli() {
+    let temp = TestTempDir::new("auth-cli-login-already");
+    let stub = temp.write_bin_executable(
+        "logged-in",
+        "#!/bin/sh\necho 'You are already logged in as test@example.com.'\nexit 0\n",
+    );
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.cli_locator = Arc::new(StubCliLocator {
+            command: LoginCommand {
+                program: stub,
+                args: Vec::new(),
+            },
+        });
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(&mut ws, "auth-already", "auth/cli_login", Value::Null).await;
+    assert_eq!(
+        payload.get("result"),
+        Some(&json!({"status": "already_authenticated"})),
+        "got {payload:?}"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test]
+async fn auth_cli_login_failing_exit_without_url_is_still_an_error() {
+    let temp = TestTempDir::new("auth-cli-login-already-failed");
+    // Same wording, but a non-zero exit means the CLI did not succeed.
+    let stub = temp.write_bin_executable(
+        "logged-in-failed",
+        "#!/bin/sh\necho 'Already logged in, but the session could not be refreshed.'\nexit 1\n",
+    );
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.cli_locator = Arc::new(StubCliLocator {
+            command: LoginCommand {
+                program: stub,
+                args: Vec::new(),
+            },
+        });
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(&mut ws, "auth-failed", "auth/cli_login", Value::Null).await;
+    assert_eq!(
+        payload
+            .get("error")
+            .and_then(|e| e.get("data"))
+            .and_then(|d| d.get("kind")),
+        Some(&json!("login_failed")),
+        "got {payload:?}"
+    );
+
+    harness.shutdown().await;
+}
+
 #[tokio::test]
 #[serial_test::serial]
 async fn auth_cli_login_extracts_login_url_from_real_cli() {
@@ -836,6 +1238,57 @@ fn clean_auth_env() {
     std::thread::yield_now();
 }
 
+// Minimal subscriber that records each event's fields as strings, so tests can
+// assert on tracing output without pulling in tracing-subscriber.
+#[derive(Clone, Default)]
+struct CapturedEvents(Arc<std::sync::Mutex<Vec<HashMap<String, String>>>>);
+
+impl CapturedEvents {
+    fn take(&self) -> Vec<HashMap<String, String>> {
+        std::mem::take(&mut *self.0.lock().expect("captured events lock"))
+    }
+}
+
+struct FieldCollector(HashMap<String, String>);
+
+impl tracing::field::Visit for FieldCollector {
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{value:?}"));
+    }
+}
+
+impl tracing::Subscriber for CapturedEvents {
+    fn enabled(&self, _metadata: &tracing::Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, _span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+        tracing::span::Id::from_u64(1)
+    }
+
+    fn record(&self, _span: &tracing::span::Id, _values: &tracing::span::Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &tracing::span::Id, _follows: &tracing::span::Id) {}
+
+    fn event(&self, event: &tracing::Event<'_>) {
+        let mut collector = FieldCollector(HashMap::new());
+        event.record(&mut collector);
+        self.0
+            .lock()
+            .expect("captured events lock")
+            .push(collector.0);
+    }
+
+    fn enter(&self, _span: &tracing::span::Id) {}
+
+    fn exit(&self, _span: &tracing::span::Id) {}
+}
+
 struct TestTempDir {
     path: PathBuf,
     bin: PathBuf,
@@ -903,666 +1356,819 @@ fn run_command(command: &mut Command, description: &str) {
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn bridge_handshake_rejects_disallowed_origin() {
+async fn bridge_serves_initialize_on_every_bind_address() {
     let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
-    let harness = BridgeHarness::start(agent.clone()).await;
-
-    // Enforces RAT-LWS-REQ-001 by denying origins outside the allow-list.
-    let err = harness
-        .connect(BLOCKED_ORIGIN, Some(SUBPROTOCOL))
-        .await
-        .expect_err("handshake must be rejected for disallowed origin");
+    let harness = BridgeHarness::start_with(agent.clone(), |config| {
+        config.bind_addrs = vec![
+            "127.0.0.1:0".parse().expect("ipv4 loopback"),
+            "[::1]:0".parse().expect("ipv6 loopback"),
+        ];
+    })
+    .await;
 
-    match err {
-        tungstenite::Error::Http(response) => {
-            assert!(
-                matches!(response.status().as_u16(), 403 | 426),
-                "expected 403 or 426, got {}",
-                response.status()
-            );
-        }
-        other => panic!("unexpected error: {other:?}"),
+    let addrs = harness.handle.local_addrs().to_vec();
+    assert_eq!(addrs.len(), 2, "one listener per bind address");
+    assert!(addrs[0].is_ipv4());
+    assert!(addrs[1].is_ipv6());
+    assert_eq!(harness.handle.local_addr(), Some(addrs[0]));
+
+    for (index, addr) in addrs.into_iter().enumerate() {
+        let (mut ws, _) = harness
+            .connect_to(addr, ALLOWED_ORIGIN, Some(SUBPROTOCOL))
+            .await
+            .unwrap_or_else(|err| panic!("handshake on {addr} should succeed: {err:?}"));
+
+        send_initialize_request(&mut ws).await;
+        let payload = parse_json(&next_message(&mut ws).await);
+        assert_eq!(payload.get("id"), Some(&json!("init-req")));
+        assert!(
+            payload.get("result").is_some(),
+            "initialize on listener {index} should succeed, got {payload:?}"
+        );
     }
 
-    assert!(
-        agent.take_initialize_calls().await.is_empty(),
-        "no initialize calls on reject"
-    );
-    // Ensures disallowed origins never reach the agent per RAT-LWS-REQ-001.
+    assert_eq!(agent.take_initialize_calls().await.len(), 2);
 
     harness.shutdown().await;
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn bridge_handshake_requires_subprotocol() {
-    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
-    let harness = BridgeHarness::start(agent.clone()).await;
-
-    // Enforces RAT-LWS-REQ-002/010: subprotocol must be negotiated.
-    let err = harness
-        .connect(ALLOWED_ORIGIN, None)
-        .await
-        .expect_err("handshake must fail without subprotocol");
+async fn serve_falls_back_to_next_port_when_bind_address_is_taken() {
+    let occupied = std::net::TcpListener::bind("127.0.0.1:0").expect("occupy a port");
+    let occupied_addr = occupied.local_addr().expect("occupied addr");
+    let fallback_port = std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("find a free port")
+        .port();
 
-    match err {
-        tungstenite::Error::Http(response) => {
-            assert!(
-                matches!(response.status().as_u16(), 400 | 426),
-                "expected 400/426 for missing subprotocol, got {}",
-                response.status()
-            );
-        }
-        other => panic!("unexpected error: {other:?}"),
-    }
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.bind_addrs = vec![occupied_addr];
+        config.fallback_ports = vec![occupied_addr.port(), fallback_port];
+    })
+    .await;
 
-    assert!(
-        agent.take_initialize_calls().await.is_empty(),
-        "no initialize calls on reject"
+    assert_eq!(
+        harness.handle.local_addr(),
+        Some(SocketAddr::from(([127, 0, 0, 1], fallback_port)))
     );
-    // Prevents missing subprotocol handshakes from invoking the agent, aligning with RAT-LWS-REQ-002.
+    let _ws = harness.connect_initialized().await;
 
+    drop(occupied);
     harness.shutdown().await;
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn bridge_handshake_rejects_other_methods_before_initialize() {
+async fn dropping_bridge_handle_stops_accepting_connections() {
+    let config = BridgeConfig {
+        bind_addrs: vec!["127.0.0.1:0".parse().expect("loopback address")],
+        ..BridgeConfig::with_generated_id()
+    };
     let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
-    let harness = BridgeHarness::start(agent.clone()).await;
-
-    // NOTE: Spec only mandates JSON-RPC transparency, so this test enforces a
-    // local policy (returning -32601 pre-initialize) that we may relax once the
-    // real bridge implementation lands; keep in mind it is stricter than spec.
-    let (mut ws, _) = harness
-        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
+    let handle = serve(config, agent).await.expect("bridge start");
+    let addr = handle.local_addr().expect("tcp listener");
+    tokio::net::TcpStream::connect(addr)
         .await
-        .expect("handshake should succeed");
+        .expect("bridge should accept while the handle is alive");
 
-    send_json_rpc(
-        &mut ws,
-        json!({
-            "jsonrpc": "2.0",
-            "id": "req-1",
-            "method": "session/new",
-            "params": {"foo": "bar"},
-        }),
-    )
-    .await;
+    drop(handle);
 
-    let message = next_message(&mut ws).await;
-    let payload = parse_json(&message);
+    timeout(TEST_TIMEOUT, async {
+        while tokio::net::TcpStream::connect(addr).await.is_ok() {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("listener should close after the handle is dropped");
+}
 
-    let error = payload
-        .get("error")
-        .unwrap_or_else(|| panic!("expected error payload, got {payload:?}"));
-    // NOTE: Hard-coding -32601 helps drive TDD right now but is not a
-    // requirement from spec.md; adjust if future bridge logic needs different
-    // error semantics while remaining spec-compliant.
+#[tokio::test(flavor = "multi_thread")]
+async fn serve_rejects_an_empty_bridge_id() {
+    for bridge_id in ["", "   "] {
+        let config = BridgeConfig {
+            bind_addrs: vec!["127.0.0.1:0".parse().expect("loopback address")],
+            bridge_id: bridge_id.to_string(),
+            ..BridgeConfig::default()
+        };
+        let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+        match serve(config, agent).await {
+            Err(BridgeError::Config(message)) => assert!(message.contains("bridge_id")),
+            Err(other) => panic!("expected a config error, got {other:?}"),
+            Ok(_) => panic!("an empty bridge_id should be rejected"),
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn generated_bridge_id_is_a_uuid_reported_in_initialize_meta() {
+    let generated = BridgeConfig::with_generated_id().bridge_id;
+    let parsed = uuid::Uuid::parse_str(&generated).expect("generated id should be a UUID");
+    assert_eq!(parsed.get_version_num(), 4);
+    assert_ne!(
+        generated,
+        BridgeConfig::with_generated_id().bridge_id,
+        "each call should generate a fresh id"
+    );
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let bridge_id = generated.clone();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.bridge_id = bridge_id;
+    })
+    .await;
+    let (mut ws, _) = harness
+        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
+        .await
+        .expect("handshake should succeed");
+    send_initialize_request(&mut ws).await;
+    let init = parse_json(&next_message(&mut ws).await);
     assert_eq!(
-        error.get("code"),
-        Some(&json!(-32601)),
-        "should return method not found"
+        init["result"]["_meta"]["bridgeId"],
+        json!(generated),
+        "{init}"
     );
 
+    harness.shutdown().await;
+}
+
+#[tokio::test]
+async fn shutdown_releases_the_bridge_id_for_a_restart() {
+    const SHARED_ID_WARNING: &str = "another bridge in this process already uses this bridge_id";
+    let events = CapturedEvents::default();
+    let _guard = tracing::subscriber::set_default(events.clone());
+    let start = || {
+        let config = BridgeConfig {
+            bind_addrs: vec!["127.0.0.1:0".parse().expect("valid addr")],
+            bridge_id: "restart-test".to_string(),
+            ..BridgeConfig::default()
+        };
+        serve(
+            config,
+            Arc::new(FakeAgentTransport::new(success_initialize_response())),
+        )
+    };
+    let warned = |events: Vec<HashMap<String, String>>| {
+        events
+            .iter()
+            .any(|fields| fields.get("message").map(String::as_str) == Some(SHARED_ID_WARNING))
+    };
+
+    let first = start().await.expect("first start");
+    first.shutdown().await.expect("first shutdown");
+    let second = start().await.expect("restart");
     assert!(
-        agent.take_initialize_calls().await.is_empty(),
-        "initialize must not be forwarded when non-initialize method received"
+        !warned(events.take()),
+        "a restart after shutdown should not see the old id in use"
     );
 
-    harness.shutdown().await;
+    // Two at once still do.
+    let third = start().await.expect("concurrent start");
+    assert!(warned(events.take()));
+
+    second.shutdown().await.expect("second shutdown");
+    third.shutdown().await.expect("third shutdown");
 }
 
-#[tokio::test(flavor = "multi_thread")]
-async fn bridge_forwards_session_new_after_initialize() {
+#[tokio::test]
+async fn serve_emits_startup_event_with_bound_port() {
+    let events = CapturedEvents::default();
+    let _guard = tracing::subscriber::set_default(events.clone());
+
+    let temp = TestTempDir::new("startup-event");
+    let config = BridgeConfig {
+        bind_addrs: vec!["127.0.0.1:0".parse().expect("valid addr")],
+        bridge_id: "startup-test".to_string(),
+        project_roots: vec![temp.path().to_path_buf()],
+        follow_symlinks: true,
+        ..BridgeConfig::default()
+    };
     let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
-    let harness = BridgeHarness::start(agent.clone()).await;
+    let handle = serve(config, agent).await.expect("bridge start");
+    let port = handle.local_addr().expect("tcp listener").port();
+
+    let startup = events
+        .take()
+        .into_iter()
+        .find(|fields| fields.get("message").map(String::as_str) == Some("bridge listening"))
+        .expect("startup event should be emitted");
+    assert_eq!(startup.get("port"), Some(&port.to_string()));
+    assert_eq!(
+        startup.get("bridge_id").map(String::as_str),
+        Some("startup-test")
+    );
 
-    let (mut ws, _) = harness
+    let summary: Value = serde_json::from_str(startup.get("summary").expect("summary field"))
+        .expect("summary should be JSON");
+    assert_eq!(
+        summary.get("localAddrs"),
+        Some(&json!([format!("127.0.0.1:{port}")]))
+    );
+    assert_eq!(
+        summary.get("projectRoots"),
+        Some(&json!([temp.path().to_string_lossy()]))
+    );
+    assert_eq!(summary.get("features"), Some(&json!(["follow_symlinks"])));
+
+    handle.shutdown().await.expect("shutdown");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn initialize_meta_overrides_agent_call_timeout_per_connection() {
+    let agent = Arc::new(FakeSlowAgentTransport {
+        delay: Duration::from_millis(500),
+    });
+    let harness = BridgeHarness::start_with(agent, |config| {
+        config.agent_call_timeout = Some(Duration::from_secs(5));
+    })
+    .await;
+
+    let (mut impatient, _) = harness
         .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
         .await
         .expect("handshake should succeed");
-
-    // First, send initialize
-    let initialize_request = acp::InitializeRequest {
-        protocol_version: acp::VERSION,
-        client_capabilities: acp::ClientCapabilities {
-            fs: acp::FileSystemCapability {
-                read_text_file: true,
-                write_text_file: true,
-                meta: None,
-            },
-            terminal: true,
-            meta: None,
-        },
-        meta: None,
-    };
-
-    send_json_rpc(
-        &mut ws,
+    let payload = call(
+        &mut impatient,
+        "init-impatient",
+        "initialize",
         json!({
-            "jsonrpc": "2.0",
-            "id": "init-1",
-            "method": "initialize",
-            "params": initialize_request,
+            "protocolVersion": 1,
+            "clientCapabilities": {},
+            "_meta": {"agentCallTimeoutMs": 100},
         }),
     )
     .await;
-
-    let message = next_message(&mut ws).await;
-    let payload = parse_json(&message);
-    assert_eq!(payload.get("id"), Some(&json!("init-1")));
     assert!(payload.get("result").is_some(), "initialize should succeed");
 
-    // Now, send session/new
-    let new_session_request = acp::NewSessionRequest {
-        cwd: PathBuf::from("/tmp"),
-        mcp_servers: vec![],
-        meta: None,
-    };
+    let started = std::time::Instant::now();
+    let payload = call(
+        &mut impatient,
+        "new-impatient",
+        "session/new",
+        json!({"cwd": "/tmp", "mcpServers": []}),
+    )
+    .await;
+    assert!(
+        started.elapsed() < Duration::from_millis(450),
+        "override should fire before the slow call finishes"
+    );
+    let data = payload
+        .get("error")
+        .and_then(|e| e.get("data"))
+        .and_then(|d| d.as_str())
+        .unwrap_or_else(|| panic!("session/new should time out, got {payload:?}"));
+    assert!(
+        data.contains("100 ms"),
+        "timeout should name the override, got {data:?}"
+    );
 
-    send_json_rpc(
-        &mut ws,
-        json!({
-            "jsonrpc": "2.0",
-            "id": "session-1",
-            "method": "session/new",
-            "params": new_session_request,
-        }),
+    // Other connections keep the global timeout and outlast the slow agent.
+    let mut patient = harness.connect_initialized().await;
+    let payload = call(
+        &mut patient,
+        "new-patient",
+        "session/new",
+        json!({"cwd": "/tmp", "mcpServers": []}),
     )
     .await;
+    assert!(
+        payload.get("result").is_some(),
+        "global timeout should allow the slow call, got {payload:?}"
+    );
 
-    let message = next_message(&mut ws).await;
-    let payload = parse_json(&message);
+    harness.shutdown().await;
+}
 
-    assert_eq!(payload.get("id"), Some(&json!("session-1")));
-    let result = payload
-        .get("result")
-        .unwrap_or_else(|| panic!("expected result, got {payload:?}"));
+#[tokio::test(flavor = "multi_thread")]
+async fn per_method_timeouts_fail_slow_agent_calls_with_agent_timeout() {
+    let agent = Arc::new(FakeSlowAgentTransport {
+        delay: Duration::from_millis(500),
+    });
+    let harness = BridgeHarness::start_with(agent, |config| {
+        config.agent_call_timeout = Some(Duration::from_secs(5));
+        config.new_session_timeout = Some(Duration::from_millis(100));
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let payload = call(
+        &mut ws,
+        "slow-new",
+        "session/new",
+        json!({"cwd": "/tmp", "mcpServers": []}),
+    )
+    .await;
+    let error = payload
+        .get("error")
+        .unwrap_or_else(|| panic!("session/new should time out, got {payload:?}"));
+    assert_eq!(error.get("code"), Some(&json!(-32008)));
+    assert_eq!(error.get("message"), Some(&json!("agent timeout")));
     assert_eq!(
-        result.get("sessionId"),
-        Some(&json!("test-session-id")),
-        "should relay agent's sessionId"
+        error.get("data"),
+        Some(&json!("agent call timed out after 100 ms"))
     );
+    harness.shutdown().await;
 
-    let calls = agent.take_new_session_calls().await;
-    assert_eq!(calls.len(), 1, "session/new should be forwarded to agent");
-
+    let agent = Arc::new(FakeThinkingAgentTransport {
+        delay: Duration::from_millis(500),
+        updates: 1,
+    });
+    let harness = BridgeHarness::start_with(agent, |config| {
+        config.prompt_timeout = Some(Duration::from_millis(100));
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+    let payload = call(
+        &mut ws,
+        "slow-prompt",
+        "session/prompt",
+        json!({"sessionId": session_id, "prompt": "hi"}),
+    )
+    .await;
+    assert_eq!(
+        payload.pointer("/error/code"),
+        Some(&json!(-32008)),
+        "prompt should time out, got {payload:?}"
+    );
     harness.shutdown().await;
 }
 
-// Tests for session/prompt streaming notifications (RAT-LWS-REQ-031)
-// These tests will fail until streaming functionality is implemented
 #[tokio::test(flavor = "multi_thread")]
-async fn bridge_streams_session_prompt_updates() {
-    let agent = Arc::new(FakeStreamingAgentTransport::new(
-        success_initialize_response(),
-    ));
-    let harness = BridgeHarness::start(agent.clone()).await;
-
-    let (mut ws, _) = harness
-        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
-        .await
-        .expect("handshake should succeed");
-
-    // Initialize first
-    send_initialize_request(&mut ws).await;
-    let _init_response = next_message(&mut ws).await;
-
-    // Create a session first
-    send_session_new_request(&mut ws).await;
-    let session_response = next_message(&mut ws).await;
-    let session_payload = parse_json(&session_response);
-    let session_id = session_payload
-        .get("result")
-        .and_then(|r| r.get("sessionId"))
-        .and_then(|s| s.as_str())
-        .expect("should have sessionId");
+async fn cancel_request_notification_cancels_the_prompt_with_that_id() {
+    let agent = Arc::new(FakeThinkingAgentTransport {
+        delay: Duration::from_secs(30),
+        updates: 1,
+    });
+    let harness = BridgeHarness::start(agent).await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
 
-    // Send session/prompt request - this should trigger streaming
     send_json_rpc(
         &mut ws,
         json!({
             "jsonrpc": "2.0",
-            "id": "prompt-1",
+            "id": "p1",
             "method": "session/prompt",
-            "params": {
-                "sessionId": session_id,
-                "prompt": "Hello, please help me with something"
-            }
+            "params": {"sessionId": session_id, "prompt": "hi"},
         }),
     )
     .await;
+    // Cancelling some other id leaves the prompt running.
+    send_json_rpc(
+        &mut ws,
+        json!({"jsonrpc": "2.0", "method": "$/cancelRequest", "params": {"id": "p2"}}),
+    )
+    .await;
+    send_json_rpc(
+        &mut ws,
+        json!({"jsonrpc": "2.0", "method": "$/cancelRequest", "params": {"id": "p1"}}),
+    )
+    .await;
 
-    // Expect to receive multiple session/update notifications
-    let mut update_count = 0;
-    let mut final_response_received = false;
+    let payload = parse_json(&next_message(&mut ws).await);
+    assert_eq!(payload.get("id"), Some(&json!("p1")), "got {payload:?}");
+    assert_eq!(
+        payload.pointer("/result/stopReason"),
+        Some(&json!("cancelled")),
+        "the prompt should resolve cancelled, got {payload:?}"
+    );
 
-    // Collect streaming updates until we get the final response
-    for _ in 0..10 {
-        // max 10 messages to avoid infinite loop
-        let message = next_message(&mut ws).await;
-        let payload = parse_json(&message);
+    // The connection keeps serving requests afterwards.
+    let payload = call(&mut ws, "after-cancel", "bridge/capabilities", json!({})).await;
+    assert!(payload.get("result").is_some(), "got {payload:?}");
 
-        if payload.get("method").and_then(|m| m.as_str()) == Some("session/update") {
-            // Verify session/update notification format per RAT-LWS-REQ-011
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn idle_prompt_timeout_spares_prompts_that_keep_streaming() {
+    for (mode, succeeds) in [
+        (PromptTimeoutMode::Overall, false),
+        (PromptTimeoutMode::Idle, true),
+    ] {
+        // Streams for about 400 ms, never pausing for more than 80 ms.
+        let agent = Arc::new(FakeThinkingAgentTransport {
+            delay: Duration::from_millis(80),
+            updates: 5,
+        });
+        let harness = BridgeHarness::start_with(agent, move |config| {
+            config.prompt_timeout = Some(Duration::from_millis(250));
+            config.prompt_timeout_mode = mode;
+        })
+        .await;
+        let mut ws = harness.connect_initialized().await;
+        let session_id = new_session_id(&mut ws).await;
+
+        let payload = call(
+            &mut ws,
+            "streaming-prompt",
+            "session/prompt",
+            json!({"sessionId": session_id, "prompt": "hi"}),
+        )
+        .await;
+        if succeeds {
             assert!(
-                payload.get("params").is_some(),
-                "session/update must have params"
+                payload.get("result").is_some(),
+                "{mode:?} should let a streaming prompt finish, got {payload:?}"
             );
-            update_count += 1;
-        } else if payload.get("id") == Some(&json!("prompt-1")) {
-            // This should be the final response
-            let result = payload
-                .get("result")
-                .expect("final response should have result");
-            assert!(
-                result.get("stopReason").is_some(),
-                "final response must contain stopReason per spec"
+        } else {
+            assert_eq!(
+                payload.pointer("/error/code"),
+                Some(&json!(-32008)),
+                "{mode:?} should bound the whole prompt, got {payload:?}"
             );
-            final_response_received = true;
-            break;
         }
+
+        harness.shutdown().await;
     }
+}
 
-    assert!(
-        update_count > 0,
-        "should receive at least one session/update notification"
-    );
-    assert!(
-        final_response_received,
-        "should receive final response with stopReason"
+#[tokio::test(flavor = "multi_thread")]
+async fn idle_prompt_timeout_fires_on_a_silent_agent_despite_heartbeats() {
+    let agent = Arc::new(FakeThinkingAgentTransport {
+        delay: Duration::from_millis(500),
+        updates: 1,
+    });
+    let harness = BridgeHarness::start_with(agent, |config| {
+        config.prompt_timeout = Some(Duration::from_millis(150));
+        config.prompt_timeout_mode = PromptTimeoutMode::Idle;
+        // Heartbeats are not agent activity and must not keep it alive.
+        config.prompt_heartbeat_interval = Some(Duration::from_millis(20));
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    let payload = call(
+        &mut ws,
+        "silent-prompt",
+        "session/prompt",
+        json!({"sessionId": session_id, "prompt": "hi"}),
+    )
+    .await;
+    assert_eq!(
+        payload.pointer("/error/code"),
+        Some(&json!(-32008)),
+        "a silent agent should time out, got {payload:?}"
     );
 
     harness.shutdown().await;
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn bridge_forwards_session_prompt_transparently() {
-    let agent = Arc::new(FakeStreamingAgentTransport::new(
+async fn bridge_metrics_snapshot_counts_connections_requests_and_permissions() {
+    let temp = TestTempDir::new("bridge-metrics");
+    let agent = Arc::new(FakePermissionAgentTransport::new(
         success_initialize_response(),
     ));
-    let harness = BridgeHarness::start(agent.clone()).await;
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent.clone(), move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
 
-    let (mut ws, _) = harness
-        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
-        .await
-        .expect("handshake should succeed");
+    assert!(
+        harness
+            .connect(BLOCKED_ORIGIN, Some(SUBPROTOCOL))
+            .await
+            .is_err(),
+        "blocked origin should be rejected"
+    );
 
-    // Initialize and create session
-    send_initialize_request(&mut ws).await;
-    let _init_response = next_message(&mut ws).await;
-    send_session_new_request(&mut ws).await;
-    let _session_response = next_message(&mut ws).await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+    let _ = call(&mut ws, "unknown", "bogus/method", json!({})).await;
 
-    let test_prompt = "Test prompt for transparency";
-    send_json_rpc(
-        &mut ws,
-        json!({
-            "jsonrpc": "2.0",
-            "id": "prompt-transparency",
-            "method": "session/prompt",
-            "params": {
-                "sessionId": "test-session-id",
-                "prompt": test_prompt
-            }
-        }),
-    )
-    .await;
+    for (index, option) in ["allow_once", "reject_once"].iter().enumerate() {
+        agent
+            .configure_permission_response(acp::RequestPermissionResponse {
+                outcome: acp::RequestPermissionOutcome::Selected {
+                    option_id: acp::PermissionOptionId((*option).into()),
+                },
+                meta: None,
+            })
+            .await;
+        let _ = call(
+            &mut ws,
+            &format!("metrics-write-{index}"),
+            "fs/write_text_file",
+            json!({
+                "sessionId": session_id,
+                "path": format!("metrics-{index}.txt"),
+                "content": "x",
+            }),
+        )
+        .await;
+    }
 
-    // Wait for any response (the test will fail because method doesn't exist yet)
-    let _response = next_message(&mut ws).await;
+    let snapshot = harness.metrics();
+    assert_eq!(snapshot.active_connections, 1);
+    assert_eq!(snapshot.handshake_rejections, 1);
+    assert_eq!(snapshot.permission_grants, 1);
+    assert_eq!(snapshot.permission_denials, 1);
+    let requests = |method: &str| snapshot.requests_by_method.get(method).copied();
+    assert_eq!(requests("initialize"), Some(1));
+    assert_eq!(requests("session/new"), Some(1));
+    assert_eq!(requests("fs/write_text_file"), Some(2));
+    assert_eq!(requests("other"), Some(1));
+
+    drop(ws);
+    timeout(TEST_TIMEOUT, async {
+        while harness.metrics().active_connections != 0 {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("closed connection should stop counting as active");
 
-    // Verify the agent received the request transparently (RAT-LWS-REQ-011)
-    let prompt_calls = agent.take_prompt_calls().await;
-    assert_eq!(
-        prompt_calls.len(),
-        1,
-        "session/prompt should be forwarded to agent"
-    );
-    assert_eq!(prompt_calls[0].prompt, test_prompt);
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_handshake_rejects_missing_origin_by_default() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start(agent).await;
+
+    let err = harness
+        .connect_with_origin(harness.addr, None, Some(SUBPROTOCOL))
+        .await
+        .expect_err("handshake without an Origin must be rejected by default");
+    match err {
+        tungstenite::Error::Http(response) => assert_eq!(response.status().as_u16(), 403),
+        other => panic!("unexpected error: {other:?}"),
+    }
 
     harness.shutdown().await;
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn bridge_session_update_preserves_json_rpc_format() {
-    let agent = Arc::new(FakeStreamingAgentTransport::new(
-        success_initialize_response(),
-    ));
-    let harness = BridgeHarness::start(agent.clone()).await;
+async fn bridge_handshake_allows_missing_origin_when_configured() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start_with(agent, |config| {
+        config.allow_missing_origin = true;
+    })
+    .await;
 
     let (mut ws, _) = harness
-        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
+        .connect_with_origin(harness.addr, None, Some(SUBPROTOCOL))
         .await
-        .expect("handshake should succeed");
-
-    // Initialize and setup session
+        .expect("native clients without an Origin should be accepted");
     send_initialize_request(&mut ws).await;
-    let _init_response = next_message(&mut ws).await;
-    send_session_new_request(&mut ws).await;
-    let _session_response = next_message(&mut ws).await;
+    let payload = parse_json(&next_message(&mut ws).await);
+    assert!(
+        payload.get("result").is_some(),
+        "initialize should succeed, got {payload:?}"
+    );
 
-    // Configure agent to send specific notifications
-    agent
-        .configure_streaming_updates(vec![
-            json!({
-                "sessionId": "test-session-id",
-                "chunk": {"type": "text", "content": "Hello"},
-                "index": 0
-            }),
-            json!({
-                "sessionId": "test-session-id",
-                "chunk": {"type": "text", "content": " world"},
-                "index": 1
-            }),
-        ])
-        .await;
+    // A present Origin is still checked against the allow-list.
+    let err = harness
+        .connect(BLOCKED_ORIGIN, Some(SUBPROTOCOL))
+        .await
+        .expect_err("disallowed origins stay rejected");
+    match err {
+        tungstenite::Error::Http(response) => assert_eq!(response.status().as_u16(), 403),
+        other => panic!("unexpected error: {other:?}"),
+    }
 
-    // Send prompt request
-    send_json_rpc(
-        &mut ws,
-        json!({
-            "jsonrpc": "2.0",
-            "id": "streaming-test",
-            "method": "session/prompt",
-            "params": {
-                "sessionId": "test-session-id",
-                "prompt": "Say hello"
-            }
-        }),
-    )
-    .await;
+    harness.shutdown().await;
+}
 
-    // Verify session/update notifications preserve JSON-RPC format
-    for expected_index in 0..2 {
-        let message = next_message(&mut ws).await;
-        let payload = parse_json(&message);
+#[tokio::test(flavor = "multi_thread")]
+async fn origin_validator_replaces_the_static_allow_list() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start_with(agent, |config| {
+        config.origin_validator =
+            Some(Arc::new(|origin: &str| origin.contains(".trusted.example")));
+    })
+    .await;
 
-        // RAT-LWS-REQ-011: JSON-RPC notification format preserved
-        assert_eq!(payload.get("jsonrpc"), Some(&json!("2.0")));
-        assert_eq!(payload.get("method"), Some(&json!("session/update")));
-        assert!(payload.get("params").is_some());
-        assert!(payload.get("id").is_none()); // notifications don't have id
+    let (_ws, response) = harness
+        .connect("https://app.trusted.example", Some(SUBPROTOCOL))
+        .await
+        .expect("a matching origin should be accepted");
+    assert_eq!(response.status(), 101);
 
-        let params = payload.get("params").unwrap();
-        assert_eq!(params.get("index"), Some(&json!(expected_index)));
+    for origin in [BLOCKED_ORIGIN, ALLOWED_ORIGIN] {
+        let result = harness.connect(origin, Some(SUBPROTOCOL)).await;
+        assert!(
+            matches!(&result, Err(tungstenite::Error::Http(response)) if response.status() == 403),
+            "{origin} should be rejected by the validator, got {result:?}"
+        );
     }
 
     harness.shutdown().await;
 }
 
-fn success_initialize_response() -> acp::InitializeResponse {
-    acp::InitializeResponse {
-        protocol_version: acp::VERSION,
-        agent_capabilities: acp::AgentCapabilities::default(),
-        auth_methods: Vec::new(),
-        meta: None,
-    }
-}
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_handshake_check_order_decides_the_rejection_for_double_failures() {
+    for (order, expected_status) in [
+        (HandshakeCheckOrder::OriginFirst, 403),
+        (HandshakeCheckOrder::SubprotocolFirst, 426),
+    ] {
+        let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+        let harness = BridgeHarness::start_with(agent, move |config| {
+            config.handshake_check_order = order;
+        })
+        .await;
 
-struct FakeAgentState {
-    initialize_calls: Vec<acp::InitializeRequest>,
-    initialize_response: acp::InitializeResponse,
-    new_session_calls: Vec<acp::NewSessionRequest>,
-    new_session_response: acp::NewSessionResponse,
-}
+        let err = harness
+            .connect(BLOCKED_ORIGIN, None)
+            .await
+            .expect_err("a client failing both checks must be rejected");
+        match err {
+            tungstenite::Error::Http(response) => assert_eq!(
+                response.status().as_u16(),
+                expected_status,
+                "{order:?} should report its first check"
+            ),
+            other => panic!("unexpected error: {other:?}"),
+        }
 
-// Represents a session/prompt request that needs to be implemented
-#[derive(Clone, Debug)]
-struct PromptRequest {
-    prompt: String,
+        harness.shutdown().await;
+    }
 }
 
-struct FakeStreamingAgentState {
-    initialize_calls: Vec<acp::InitializeRequest>,
-    initialize_response: acp::InitializeResponse,
-    new_session_calls: Vec<acp::NewSessionRequest>,
-    new_session_response: acp::NewSessionResponse,
-    prompt_calls: Vec<PromptRequest>,
-    streaming_updates: Vec<Value>,
-}
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_handshake_rejects_disallowed_origin() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start(agent.clone()).await;
 
-#[derive(Clone)]
-struct FakeAgentTransport {
-    state: Arc<Mutex<FakeAgentState>>,
-}
+    // Enforces RAT-LWS-REQ-001 by denying origins outside the allow-list.
+    let err = harness
+        .connect(BLOCKED_ORIGIN, Some(SUBPROTOCOL))
+        .await
+        .expect_err("handshake must be rejected for disallowed origin");
 
-impl FakeAgentTransport {
-    fn new(initialize_response: acp::InitializeResponse) -> Self {
-        Self {
-            state: Arc::new(Mutex::new(FakeAgentState {
-                initialize_calls: Vec::new(),
-                initialize_response,
-                new_session_calls: Vec::new(),
-                new_session_response: acp::NewSessionResponse {
-                    session_id: acp::SessionId("test-session-id".into()),
-                    modes: None,
-                    meta: None,
-                },
-            })),
+    match err {
+        tungstenite::Error::Http(response) => {
+            assert!(
+                matches!(response.status().as_u16(), 403 | 426),
+                "expected 403 or 426, got {}",
+                response.status()
+            );
         }
+        other => panic!("unexpected error: {other:?}"),
     }
 
-    async fn take_initialize_calls(&self) -> Vec<acp::InitializeRequest> {
-        let mut state = self.state.lock().await;
-        std::mem::take(&mut state.initialize_calls)
-    }
+    assert!(
+        agent.take_initialize_calls().await.is_empty(),
+        "no initialize calls on reject"
+    );
+    // Ensures disallowed origins never reach the agent per RAT-LWS-REQ-001.
 
-    async fn take_new_session_calls(&self) -> Vec<acp::NewSessionRequest> {
-        let mut state = self.state.lock().await;
-        std::mem::take(&mut state.new_session_calls)
-    }
+    harness.shutdown().await;
 }
 
-impl AgentTransport for FakeAgentTransport {
-    fn initialize(
-        &self,
-        request: acp::InitializeRequest,
-    ) -> Pin<Box<dyn Future<Output = Result<acp::InitializeResponse, AgentTransportError>> + Send>>
-    {
-        let state = self.state.clone();
-        Box::pin(async move {
-            let mut guard = state.lock().await;
-            guard.initialize_calls.push(request);
-            Ok(guard.initialize_response.clone())
-        })
-    }
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_handshake_requires_subprotocol() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start(agent.clone()).await;
 
-    fn new_session(
-        &self,
-        request: acp::NewSessionRequest,
-    ) -> Pin<Box<dyn Future<Output = Result<acp::NewSessionResponse, AgentTransportError>> + Send>>
-    {
-        let state = self.state.clone();
-        Box::pin(async move {
-            let mut guard = state.lock().await;
-            guard.new_session_calls.push(request);
-            Ok(guard.new_session_response.clone())
-        })
-    }
+    // Enforces RAT-LWS-REQ-002/010: subprotocol must be negotiated.
+    let err = harness
+        .connect(ALLOWED_ORIGIN, None)
+        .await
+        .expect_err("handshake must fail without subprotocol");
 
-    fn prompt(
-        &self,
-        _request: acp::PromptRequest,
-        _notification_sender: Arc<dyn ct_bridge::NotificationSender>,
-    ) -> Pin<Box<dyn Future<Output = Result<acp::PromptResponse, AgentTransportError>> + Send>>
-    {
-        Box::pin(async move { Err(AgentTransportError::NotImplemented) })
+    match err {
+        tungstenite::Error::Http(response) => {
+            assert!(
+                matches!(response.status().as_u16(), 400 | 426),
+                "expected 400/426 for missing subprotocol, got {}",
+                response.status()
+            );
+        }
+        other => panic!("unexpected error: {other:?}"),
     }
 
-    fn request_permission(
-        &self,
-        _request: acp::RequestPermissionRequest,
-    ) -> Pin<
-        Box<
-            dyn Future<Output = Result<acp::RequestPermissionResponse, AgentTransportError>> + Send,
-        >,
-    > {
-        Box::pin(async move { Err(AgentTransportError::NotImplemented) })
-    }
+    assert!(
+        agent.take_initialize_calls().await.is_empty(),
+        "no initialize calls on reject"
+    );
+    // Prevents missing subprotocol handshakes from invoking the agent, aligning with RAT-LWS-REQ-002.
+
+    harness.shutdown().await;
 }
 
-#[derive(Clone)]
-struct FakeStreamingAgentTransport {
-    state: Arc<Mutex<FakeStreamingAgentState>>,
-}
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_handshake_echoes_the_first_client_offered_subprotocol() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start_with(agent, |config| {
+        config.expected_subprotocol = "v1".into();
+        config.additional_subprotocols = vec!["v2".into()];
+    })
+    .await;
 
-#[allow(dead_code)]
-impl FakeStreamingAgentTransport {
-    fn new(initialize_response: acp::InitializeResponse) -> Self {
-        Self {
-            state: Arc::new(Mutex::new(FakeStreamingAgentState {
-                initialize_calls: Vec::new(),
-                initialize_response,
-                new_session_calls: Vec::new(),
-                new_session_response: acp::NewSessionResponse {
-                    session_id: acp::SessionId("test-session-id".into()),
-                    modes: None,
-                    meta: None,
-                },
-                prompt_calls: Vec::new(),
-                streaming_updates: Vec::new(),
-            })),
-        }
+    for (offer, echoed) in [("v2, v1", "v2"), ("v1, v2", "v1"), ("v3, v1", "v1")] {
+        let (_ws, response) = harness
+            .connect(ALLOWED_ORIGIN, Some(offer))
+            .await
+            .expect("handshake should succeed");
+        assert_eq!(
+            response
+                .headers()
+                .get(SEC_WEBSOCKET_PROTOCOL)
+                .and_then(|value| value.to_str().ok()),
+            Some(echoed),
+            "offer {offer:?} should select the client's first supported protocol"
+        );
     }
 
-    async fn take_initialize_calls(&self) -> Vec<acp::InitializeRequest> {
-        let mut state = self.state.lock().await;
-        std::mem::take(&mut state.initialize_calls)
-    }
+    harness.shutdown().await;
+}
 
-    async fn take_new_session_calls(&self) -> Vec<acp::NewSessionRequest> {
-        let mut state = self.state.lock().await;
-        std::mem::take(&mut state.new_session_calls)
-    }
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_handshake_subprotocol_matching_is_case_sensitive_by_default() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let miscased = SUBPROTOCOL.to_uppercase();
 
-    async fn take_prompt_calls(&self) -> Vec<PromptRequest> {
-        let mut state = self.state.lock().await;
-        std::mem::take(&mut state.prompt_calls)
-    }
+    let harness = BridgeHarness::start(agent.clone()).await;
+    let err = harness
+        .connect(ALLOWED_ORIGIN, Some(&miscased))
+        .await
+        .expect_err("a mis-cased subprotocol must be refused");
+    assert!(
+        matches!(err, tungstenite::Error::Http(ref response) if response.status() == 426),
+        "got {err:?}"
+    );
+    harness.shutdown().await;
 
-    async fn configure_streaming_updates(&self, updates: Vec<Value>) {
-        let mut state = self.state.lock().await;
-        state.streaming_updates = updates;
-    }
+    let harness = BridgeHarness::start_with(agent, |config| {
+        config.subprotocol_case_sensitive = false;
+    })
+    .await;
+    let (_ws, response) = harness
+        .connect(ALLOWED_ORIGIN, Some(&miscased))
+        .await
+        .expect("case-insensitive matching accepts the mis-cased offer");
+    assert_eq!(
+        response
+            .headers()
+            .get(SEC_WEBSOCKET_PROTOCOL)
+            .and_then(|value| value.to_str().ok()),
+        Some(miscased.as_str()),
+        "the client's spelling is echoed"
+    );
+    harness.shutdown().await;
 }
 
-impl AgentTransport for FakeStreamingAgentTransport {
-    fn initialize(
-        &self,
-        request: acp::InitializeRequest,
-    ) -> Pin<Box<dyn Future<Output = Result<acp::InitializeResponse, AgentTransportError>> + Send>>
-    {
-        let state = self.state.clone();
-        Box::pin(async move {
-            let mut guard = state.lock().await;
-            guard.initialize_calls.push(request);
-            Ok(guard.initialize_response.clone())
-        })
-    }
-
-    fn new_session(
-        &self,
-        request: acp::NewSessionRequest,
-    ) -> Pin<Box<dyn Future<Output = Result<acp::NewSessionResponse, AgentTransportError>> + Send>>
-    {
-        let state = self.state.clone();
-        Box::pin(async move {
-            let mut guard = state.lock().await;
-            guard.new_session_calls.push(request);
-            Ok(guard.new_session_response.clone())
-        })
-    }
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_handshake_rejects_other_methods_before_initialize() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start(agent.clone()).await;
 
-    fn prompt(
-        &self,
-        request: acp::PromptRequest,
-        notification_sender: Arc<dyn ct_bridge::NotificationSender>,
-    ) -> Pin<Box<dyn Future<Output = Result<acp::PromptResponse, AgentTransportError>> + Send>>
-    {
-        let state = self.state.clone();
-        Box::pin(async move {
-            let mut guard = state.lock().await;
-            // Extract prompt text - for simplicity, assume first content block is text
-            let prompt_text =
-                if let Some(acp::ContentBlock::Text(text_content)) = request.prompt.first() {
-                    text_content.text.clone()
-                } else {
-                    "unknown prompt".to_string()
-                };
+    // NOTE: Spec only mandates JSON-RPC transparency, so this test enforces a
+    // local policy (returning -32601 pre-initialize) that we may relax once the
+    // real bridge implementation lands; keep in mind it is stricter than spec.
+    let (mut ws, _) = harness
+        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
+        .await
+        .expect("handshake should succeed");
 
-            guard.prompt_calls.push(PromptRequest {
-                prompt: prompt_text,
-            });
+    send_json_rpc(
+        &mut ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": "req-1",
+            "method": "session/new",
+            "params": {"foo": "bar"},
+        }),
+    )
+    .await;
 
-            // Send any configured streaming updates
-            let streaming_updates = guard.streaming_updates.clone();
-            let has_configured_updates = !streaming_updates.is_empty();
-            drop(guard); // Release the lock before sending notifications
+    let message = next_message(&mut ws).await;
+    let payload = parse_json(&message);
 
-            // Send session/update notifications for each streaming update
-            for update in streaming_updates {
-                if let Err(e) = notification_sender
-                    .send_notification("session/update", update)
-                    .await
-                {
-                    eprintln!("Failed to send session/update notification: {e:?}");
-                }
-            }
+    let error = payload
+        .get("error")
+        .unwrap_or_else(|| panic!("expected error payload, got {payload:?}"));
+    // NOTE: Hard-coding -32601 helps drive TDD right now but is not a
+    // requirement from spec.md; adjust if future bridge logic needs different
+    // error semantics while remaining spec-compliant.
+    assert_eq!(
+        error.get("code"),
+        Some(&json!(-32601)),
+        "should return method not found"
+    );
 
-            // If no specific updates were configured, send some default streaming updates
-            if !has_configured_updates {
-                // Send a few default session/update notifications
-                let default_updates = vec![
-                    json!({
-                        "sessionId": request.session_id.0,
-                        "chunk": {"type": "text", "content": "Thinking"},
-                        "index": 0
-                    }),
-                    json!({
-                        "sessionId": request.session_id.0,
-                        "chunk": {"type": "text", "content": "..."},
-                        "index": 1
-                    }),
-                    json!({
-                        "sessionId": request.session_id.0,
-                        "chunk": {"type": "text", "content": " about your request"},
-                        "index": 2
-                    }),
-                ];
+    assert!(
+        agent.take_initialize_calls().await.is_empty(),
+        "initialize must not be forwarded when non-initialize method received"
+    );
 
-                for update in default_updates {
-                    if let Err(e) = notification_sender
-                        .send_notification("session/update", update)
-                        .await
-                    {
-                        eprintln!("Failed to send default session/update notification: {e:?}");
-                    }
-                }
-            }
+    harness.shutdown().await;
+}
 
-            // Return a simple response with stopReason
-            use agent_client_protocol as acp;
-            Ok(acp::PromptResponse {
-                stop_reason: acp::StopReason::EndTurn,
-                meta: None,
-            })
-        })
-    }
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_forwards_session_new_after_initialize() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start(agent.clone()).await;
 
-    fn request_permission(
-        &self,
-        _request: acp::RequestPermissionRequest,
-    ) -> Pin<
-        Box<
-            dyn Future<Output = Result<acp::RequestPermissionResponse, AgentTransportError>> + Send,
-        >,
-    > {
-        Box::pin(async move { Err(AgentTransportError::NotImplemented) })
-    }
-}
+    let (mut ws, _) = harness
+        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
+        .await
+        .expect("handshake should succeed");
 
-// Helper functions for the new streaming tests
-async fn send_initialize_request(ws: &mut WsStream) {
+    // First, send initialize
     let initialize_request = acp::InitializeRequest {
         protocol_version: acp::VERSION,
         client_capabilities: acp::ClientCapabilities {
@@ -1578,18 +2184,22 @@ async fn send_initialize_request(ws: &mut WsStream) {
     };
 
     send_json_rpc(
-        ws,
+        &mut ws,
         json!({
             "jsonrpc": "2.0",
-            "id": "init-req",
+            "id": "init-1",
             "method": "initialize",
             "params": initialize_request,
         }),
     )
     .await;
-}
 
-async fn send_session_new_request(ws: &mut WsStream) {
+    let message = next_message(&mut ws).await;
+    let payload = parse_json(&message);
+    assert_eq!(payload.get("id"), Some(&json!("init-1")));
+    assert!(payload.get("result").is_some(), "initialize should succeed");
+
+    // Now, send session/new
     let new_session_request = acp::NewSessionRequest {
         cwd: PathBuf::from("/tmp"),
         mcp_servers: vec![],
@@ -1597,266 +2207,5977 @@ async fn send_session_new_request(ws: &mut WsStream) {
     };
 
     send_json_rpc(
-        ws,
+        &mut ws,
         json!({
             "jsonrpc": "2.0",
-            "id": "session-new",
+            "id": "session-1",
             "method": "session/new",
             "params": new_session_request,
         }),
     )
     .await;
-}
 
-struct BridgeHarness {
-    handle: BridgeHandle,
-    addr: SocketAddr,
-    _agent: Arc<dyn AgentTransport>,
+    let message = next_message(&mut ws).await;
+    let payload = parse_json(&message);
+
+    assert_eq!(payload.get("id"), Some(&json!("session-1")));
+    let result = payload
+        .get("result")
+        .unwrap_or_else(|| panic!("expected result, got {payload:?}"));
+    assert_eq!(
+        result.get("sessionId"),
+        Some(&json!("test-session-id")),
+        "should relay agent's sessionId"
+    );
+
+    let calls = agent.take_new_session_calls().await;
+    assert_eq!(calls.len(), 1, "session/new should be forwarded to agent");
+
+    harness.shutdown().await;
 }
 
-impl BridgeHarness {
-    async fn start(agent: Arc<dyn AgentTransport>) -> Self {
-        let config = BridgeConfig {
-            bind_addr: "127.0.0.1:0".parse().expect("loopback address"),
-            allowed_origins: vec![ALLOWED_ORIGIN.into()],
-            expected_subprotocol: SUBPROTOCOL.into(),
-            bridge_id: TEST_BRIDGE_ID.into(),
-        };
+// FakeAgentTransport hands out the same sessionId every time, which lets these
+// tests exercise the bridge's duplicate-session handling.
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_rejects_duplicate_session_id_by_default() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start(agent.clone()).await;
 
-        let handle = serve(config, agent.clone()).await.expect("bridge start");
-        let addr = handle.local_addr();
+    let (mut ws, _) = harness
+        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
+        .await
+        .expect("handshake should succeed");
 
-        Self {
-            handle,
-            addr,
-            _agent: agent,
+    send_initialize_request(&mut ws).await;
+    let _init_response = next_message(&mut ws).await;
+
+    send_session_new_request(&mut ws).await;
+    let first = parse_json(&next_message(&mut ws).await);
+    assert_eq!(
+        first
+            .get("result")
+            .and_then(|r| r.get("sessionId"))
+            .and_then(|s| s.as_str()),
+        Some("test-session-id")
+    );
+
+    send_session_new_request(&mut ws).await;
+    let second = parse_json(&next_message(&mut ws).await);
+    assert_eq!(second.get("id"), Some(&json!("session-new")));
+    let error = second
+        .get("error")
+        .unwrap_or_else(|| panic!("duplicate session id must be rejected, got {second:?}"));
+    assert_eq!(error.get("code"), Some(&json!(-32603)));
+    assert_eq!(
+        error.get("data"),
+        Some(&json!("agent returned duplicate session id"))
+    );
+
+    assert_eq!(agent.take_new_session_calls().await.len(), 2);
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_aliases_duplicate_session_id_when_configured() {
+    let agent = Arc::new(FakeStreamingAgentTransport::new(
+        success_initialize_response(),
+    ));
+    let harness = BridgeHarness::start_with(agent.clone(), |config| {
+        config.duplicate_session_policy = DuplicateSessionPolicy::Alias;
+    })
+    .await;
+
+    let (mut ws, _) = harness
+        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
+        .await
+        .expect("handshake should succeed");
+
+    send_initialize_request(&mut ws).await;
+    let _init_response = next_message(&mut ws).await;
+
+    send_session_new_request(&mut ws).await;
+    let first = parse_json(&next_message(&mut ws).await);
+    send_session_new_request(&mut ws).await;
+    let second = parse_json(&next_message(&mut ws).await);
+
+    let first_id = first
+        .get("result")
+        .and_then(|r| r.get("sessionId"))
+        .and_then(|s| s.as_str())
+        .expect("first session id")
+        .to_string();
+    let alias = second
+        .get("result")
+        .and_then(|r| r.get("sessionId"))
+        .and_then(|s| s.as_str())
+        .unwrap_or_else(|| panic!("aliased session/new should succeed, got {second:?}"))
+        .to_string();
+    assert_eq!(first_id, "test-session-id");
+    assert_ne!(
+        alias, first_id,
+        "alias must not collide with tracked session"
+    );
+
+    send_json_rpc(
+        &mut ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": "prompt-alias",
+            "method": "session/prompt",
+            "params": {"sessionId": alias, "prompt": "via alias"}
+        }),
+    )
+    .await;
+
+    // The default streaming updates echo the sessionId the agent was given.
+    loop {
+        let payload = parse_json(&next_message(&mut ws).await);
+        if payload.get("method") == Some(&json!("session/update")) {
+            assert_eq!(
+                payload.get("params").and_then(|p| p.get("sessionId")),
+                Some(&json!("test-session-id")),
+                "alias must be translated back to the agent's session id"
+            );
+        } else if payload.get("id") == Some(&json!("prompt-alias")) {
+            assert!(payload.get("result").is_some(), "prompt via alias succeeds");
+            break;
         }
     }
+    let prompt_calls = agent.take_prompt_calls().await;
+    assert_eq!(prompt_calls.len(), 1);
+    assert_eq!(prompt_calls[0].prompt, "via alias");
 
-    async fn connect(
-        &self,
-        origin: &str,
-        subprotocol: Option<&str>,
-    ) -> Result<(WsStream, Response<Option<Vec<u8>>>), tungstenite::Error> {
-        let url = format!("ws://{}/", self.addr);
-        let mut request = url.into_client_request()?;
-        request
-            .headers_mut()
-            .insert(ORIGIN, HeaderValue::from_str(origin).expect("valid origin"));
-        if let Some(proto) = subprotocol {
-            request.headers_mut().insert(
-                SEC_WEBSOCKET_PROTOCOL,
-                HeaderValue::from_str(proto).expect("valid subprotocol"),
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn session_responses_carry_bridge_id_alongside_agent_meta() {
+    let harness = BridgeHarness::start(Arc::new(FakeMetaAgentTransport::default())).await;
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(
+        &mut ws,
+        "new-meta",
+        "session/new",
+        json!({"cwd": "/", "mcpServers": []}),
+    )
+    .await;
+    assert_eq!(
+        payload.get("result").and_then(|r| r.get("_meta")),
+        Some(&json!({"agentTrace": "new-session", "bridgeId": TEST_BRIDGE_ID})),
+        "got {payload:?}"
+    );
+
+    let payload = call(
+        &mut ws,
+        "prompt-meta",
+        "session/prompt",
+        json!({"sessionId": "meta-session", "prompt": "hi"}),
+    )
+    .await;
+    assert_eq!(
+        payload.get("result").and_then(|r| r.get("_meta")),
+        Some(&json!({"agentTrace": "prompt", "bridgeId": TEST_BRIDGE_ID})),
+        "got {payload:?}"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn silent_prompt_emits_heartbeats_until_the_agent_speaks() {
+    let agent = Arc::new(FakeThinkingAgentTransport {
+        delay: Duration::from_millis(450),
+        updates: 1,
+    });
+    let harness = BridgeHarness::start_with(agent, |config| {
+        config.prompt_heartbeat_interval = Some(Duration::from_millis(100));
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    send_json_rpc(
+        &mut ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": "thinking-prompt",
+            "method": "session/prompt",
+            "params": {"sessionId": session_id, "prompt": "think hard"},
+        }),
+    )
+    .await;
+
+    let mut heartbeats = 0;
+    let mut saw_update = false;
+    loop {
+        let payload = parse_json(&next_message(&mut ws).await);
+        if payload.get("id") == Some(&json!("thinking-prompt")) {
+            assert!(payload.get("result").is_some(), "got {payload:?}");
+            break;
+        }
+        assert_eq!(payload.get("method"), Some(&json!("session/update")));
+        let params = payload.get("params").expect("update params");
+        assert_eq!(params.get("sessionId"), Some(&json!(session_id)));
+        if params.pointer("/update/type") == Some(&json!("heartbeat")) {
+            assert!(!saw_update, "no heartbeat should follow the agent's output");
+            assert_eq!(
+                params.pointer("/_meta/requestId"),
+                Some(&json!("thinking-prompt"))
+            );
+            heartbeats += 1;
+        } else {
+            assert_eq!(
+                params.pointer("/update/sessionUpdate"),
+                Some(&json!("agent_message_chunk"))
+            );
+            saw_update = true;
+        }
+    }
+    assert!(saw_update, "the agent's update should still arrive");
+    assert!(
+        heartbeats >= 1,
+        "a silent gap longer than the interval should produce a heartbeat"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_list_sessions_reports_each_session_with_its_cwd() {
+    for list_all_sessions in [false, true] {
+        let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+        let harness = BridgeHarness::start_with(agent, move |config| {
+            config.duplicate_session_policy = DuplicateSessionPolicy::Alias;
+            config.list_all_sessions = list_all_sessions;
+        })
+        .await;
+        let mut ws = harness.connect_initialized().await;
+        let mut other = harness.connect_initialized().await;
+
+        let mut created = Vec::new();
+        for (on_other, cwd) in [
+            (false, "/work/alpha"),
+            (false, "/work/beta"),
+            (true, "/work/other"),
+        ] {
+            let payload = call(
+                if on_other { &mut other } else { &mut ws },
+                &format!("new-{cwd}"),
+                "session/new",
+                json!({"cwd": cwd, "mcpServers": []}),
+            )
+            .await;
+            let session_id = payload
+                .pointer("/result/sessionId")
+                .and_then(Value::as_str)
+                .unwrap_or_else(|| panic!("session/new should succeed, got {payload:?}"))
+                .to_string();
+            created.push((session_id, cwd));
+        }
+        if !list_all_sessions {
+            created.pop();
+        }
+
+        let payload = call(&mut ws, "list", "bridge/list_sessions", json!({})).await;
+        let sessions = payload
+            .get("result")
+            .and_then(Value::as_array)
+            .unwrap_or_else(|| panic!("expected a session list, got {payload:?}"));
+        let listed: Vec<(String, &str)> = sessions
+            .iter()
+            .map(|session| {
+                (
+                    session["sessionId"]
+                        .as_str()
+                        .expect("sessionId")
+                        .to_string(),
+                    session["cwd"].as_str().expect("cwd"),
+                )
+            })
+            .collect();
+        assert_eq!(listed, created, "list_all_sessions = {list_all_sessions}");
+        for session in sessions {
+            let created_ms = session["createdMs"].as_u64().expect("createdMs");
+            let last_activity_ms = session["lastActivityMs"].as_u64().expect("lastActivityMs");
+            assert!(created_ms > 0 && last_activity_ms >= created_ms);
+        }
+
+        harness.shutdown().await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn prompt_response_meta_reaches_client_unchanged() {
+    let usage = json!({
+        "model": "example-model-1",
+        "usage": {
+            "inputTokens": 1200,
+            "outputTokens": 345,
+            "cache": { "readTokens": 800, "writeTokens": 0 },
+        },
+        "toolCalls": ["read", "edit"],
+        "cost": 0.0125,
+        "truncated": false,
+        "note": null,
+    });
+    let cases = [
+        usage.clone(),
+        // Not an object, so there is no room for bridgeId; it must still
+        // arrive as sent.
+        json!("agent-specific"),
+    ];
+    for prompt_meta in cases {
+        let agent = Arc::new(FakeMetaAgentTransport {
+            prompt_meta: prompt_meta.clone(),
+        });
+        let harness = BridgeHarness::start(agent).await;
+        let mut ws = harness.connect_initialized().await;
+
+        let payload = call(
+            &mut ws,
+            "prompt-usage",
+            "session/prompt",
+            json!({"sessionId": "meta-session", "prompt": "hi"}),
+        )
+        .await;
+        let meta = payload
+            .get("result")
+            .and_then(|r| r.get("_meta"))
+            .unwrap_or_else(|| panic!("prompt should return meta, got {payload:?}"));
+        match prompt_meta.as_object() {
+            Some(sent) => {
+                let mut expected = sent.clone();
+                expected.insert("bridgeId".to_string(), json!(TEST_BRIDGE_ID));
+                assert_eq!(meta, &Value::Object(expected));
+            }
+            None => assert_eq!(meta, &prompt_meta),
+        }
+
+        harness.shutdown().await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn session_close_forgets_the_session_and_tells_the_agent() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start(agent.clone()).await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    let payload = call(
+        &mut ws,
+        "close",
+        "session/close",
+        json!({"sessionId": session_id}),
+    )
+    .await;
+    assert!(payload.get("result").is_some(), "got {payload:?}");
+    let calls = agent.take_close_session_calls().await;
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0].0.as_ref(), "test-session-id");
+
+    let listed = call(&mut ws, "list", "bridge/list_sessions", json!({})).await;
+    assert_eq!(listed.get("result"), Some(&json!([])), "got {listed:?}");
+
+    let payload = call(
+        &mut ws,
+        "prompt-closed",
+        "session/prompt",
+        json!({"sessionId": session_id, "prompt": "still there?"}),
+    )
+    .await;
+    let error = payload
+        .get("error")
+        .unwrap_or_else(|| panic!("prompting a closed session should fail, got {payload:?}"));
+    assert_eq!(error.get("code"), Some(&json!(-32602)));
+    assert_eq!(error.get("data"), Some(&json!("unknown session id")));
+
+    let payload = call(
+        &mut ws,
+        "close-again",
+        "session/close",
+        json!({"sessionId": session_id}),
+    )
+    .await;
+    assert_eq!(
+        payload.get("error").and_then(|e| e.get("data")),
+        Some(&json!("unknown session id")),
+        "a session closes once, got {payload:?}"
+    );
+    assert!(agent.take_close_session_calls().await.is_empty());
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn session_set_mode_forwards_to_agent_for_known_sessions() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start(agent.clone()).await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    let payload = call(
+        &mut ws,
+        "set-mode",
+        "session/set_mode",
+        json!({"sessionId": session_id, "modeId": "architect"}),
+    )
+    .await;
+    assert!(payload.get("result").is_some(), "got {payload:?}");
+    let calls = agent.take_set_mode_calls().await;
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0].session_id.0.as_ref(), "test-session-id");
+    assert_eq!(calls[0].mode_id.0.as_ref(), "architect");
+
+    let payload = call(
+        &mut ws,
+        "set-mode-unknown",
+        "session/set_mode",
+        json!({"sessionId": "never-created", "modeId": "architect"}),
+    )
+    .await;
+    assert_eq!(
+        payload.get("error").and_then(|e| e.get("code")),
+        Some(&json!(-32602)),
+        "unknown sessions are rejected, got {payload:?}"
+    );
+    assert!(agent.take_set_mode_calls().await.is_empty());
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_tags_session_updates_with_prompt_request_id() {
+    let agent = Arc::new(FakeStreamingAgentTransport::new(
+        success_initialize_response(),
+    ));
+    let harness = BridgeHarness::start(agent).await;
+    let mut ws = harness.connect_initialized().await;
+    send_session_new_request(&mut ws).await;
+    let _session_response = next_message(&mut ws).await;
+
+    send_json_rpc(
+        &mut ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": "p-1",
+            "method": "session/prompt",
+            "params": {"sessionId": "test-session-id", "prompt": "correlate me"}
+        }),
+    )
+    .await;
+
+    let mut updates = 0;
+    loop {
+        let payload = parse_json(&next_message(&mut ws).await);
+        if payload.get("method") == Some(&json!("session/update")) {
+            updates += 1;
+            assert_eq!(
+                payload
+                    .get("params")
+                    .and_then(|p| p.get("_meta"))
+                    .and_then(|m| m.get("requestId")),
+                Some(&json!("p-1")),
+                "update should carry the originating prompt id, got {payload:?}"
             );
+        } else if payload.get("id") == Some(&json!("p-1")) {
+            break;
+        }
+    }
+    assert!(updates > 0, "expected at least one session/update");
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn client_disconnect_cancels_in_flight_prompt() {
+    let agent = Arc::new(FakeHangingAgentTransport::default());
+    let harness = BridgeHarness::start(agent.clone()).await;
+    let mut ws = harness.connect_initialized().await;
+    send_session_new_request(&mut ws).await;
+    let _session_response = next_message(&mut ws).await;
+
+    send_json_rpc(
+        &mut ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": "p-hang",
+            "method": "session/prompt",
+            "params": {"sessionId": "hanging-session", "prompt": "never ends"}
+        }),
+    )
+    .await;
+    let update = parse_json(&next_message(&mut ws).await);
+    assert_eq!(update.get("method"), Some(&json!("session/update")));
+
+    drop(ws);
+
+    timeout(TEST_TIMEOUT, async {
+        while !agent.prompt_dropped.load(Ordering::SeqCst) {
+            sleep(Duration::from_millis(10)).await;
         }
+    })
+    .await
+    .expect("prompt future should be dropped once the client disconnects");
+
+    let sender = agent
+        .notification_sender
+        .lock()
+        .await
+        .clone()
+        .expect("prompt stashed its notification sender");
+    let result = sender
+        .send_notification("session/update", json!({"chunk": "too late"}))
+        .await;
+    assert!(
+        matches!(result, Err(AgentTransportError::Disconnected)),
+        "notifications after a disconnect should fail, got {result:?}"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn second_initialize_on_a_connection_is_rejected() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start(agent.clone()).await;
+    let mut ws = harness.connect_initialized().await;
+
+    send_initialize_request(&mut ws).await;
+    let payload = parse_json(&next_message(&mut ws).await);
+    let error = payload
+        .get("error")
+        .unwrap_or_else(|| panic!("second initialize should fail, got {payload:?}"));
+    assert_eq!(error.get("code"), Some(&json!(-32600)));
+    assert_eq!(
+        error.get("data"),
+        Some(&json!("connection already initialized"))
+    );
+    assert_eq!(
+        agent.take_initialize_calls().await.len(),
+        1,
+        "the agent only sees the first initialize"
+    );
+
+    // The connection stays initialized.
+    send_session_new_request(&mut ws).await;
+    let payload = parse_json(&next_message(&mut ws).await);
+    assert!(
+        payload.get("result").is_some(),
+        "session/new should still work, got {payload:?}"
+    );
+
+    // A fresh connection initializes on its own.
+    let _other = harness.connect_initialized().await;
+    assert_eq!(agent.take_initialize_calls().await.len(), 1);
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn initialize_rejects_unsupported_protocol_versions_with_the_supported_list() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start(agent.clone()).await;
+    let (mut ws, _) = harness
+        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
+        .await
+        .expect("handshake should succeed");
+
+    let payload = call(
+        &mut ws,
+        "init-future",
+        "initialize",
+        json!({"protocolVersion": 999, "clientCapabilities": {}}),
+    )
+    .await;
+    let error = payload
+        .get("error")
+        .unwrap_or_else(|| panic!("version 999 should be refused, got {payload:?}"));
+    assert_eq!(error.get("code"), Some(&json!(-32602)));
+    assert_eq!(
+        error.pointer("/data/kind"),
+        Some(&json!("unsupported_protocol_version"))
+    );
+    assert_eq!(error.pointer("/data/requestedVersion"), Some(&json!(999)));
+    assert_eq!(error.pointer("/data/supportedVersions"), Some(&json!([1])));
+    assert!(
+        agent.take_initialize_calls().await.is_empty(),
+        "unsupported versions never reach the agent"
+    );
+
+    // The connection can retry with a supported version.
+    send_initialize_request(&mut ws).await;
+    let payload = parse_json(&next_message(&mut ws).await);
+    assert!(payload.get("result").is_some(), "got {payload:?}");
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn configured_protocol_versions_are_advertised_and_enforced() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start_with(agent, |config| {
+        config.supported_protocol_versions = vec![acp::V0, acp::V1];
+    })
+    .await;
+    let (mut ws, _) = harness
+        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
+        .await
+        .expect("handshake should succeed");
+
+    let payload = call(
+        &mut ws,
+        "init-future",
+        "initialize",
+        json!({"protocolVersion": 2, "clientCapabilities": {}}),
+    )
+    .await;
+    assert_eq!(
+        payload.pointer("/error/data/supportedVersions"),
+        Some(&json!([0, 1])),
+        "got {payload:?}"
+    );
+
+    send_initialize_request(&mut ws).await;
+    let payload = parse_json(&next_message(&mut ws).await);
+    assert_eq!(
+        payload.pointer("/result/_meta/supportedProtocolVersions"),
+        Some(&json!([0, 1])),
+        "got {payload:?}"
+    );
+    let payload = call(&mut ws, "caps", "bridge/capabilities", json!({})).await;
+    assert_eq!(
+        payload.pointer("/result/supportedProtocolVersions"),
+        Some(&json!([0, 1])),
+        "got {payload:?}"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shutdown_token_lets_a_transport_end_a_prompt_early() {
+    let agent = Arc::new(FakeShutdownAwareAgentTransport::default());
+    let harness = BridgeHarness::start(agent.clone()).await;
+    let token = harness.handle.shutdown_token();
+    assert!(!token.is_cancelled());
+    let mut ws = harness.connect_initialized().await;
+
+    send_json_rpc(
+        &mut ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": "prompt-until-shutdown",
+            "method": "session/prompt",
+            "params": {"sessionId": "shutdown-session", "prompt": "take your time"},
+        }),
+    )
+    .await;
+    timeout(TEST_TIMEOUT, async {
+        while !agent.prompt_started.load(Ordering::SeqCst) {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("the prompt should reach the agent");
+
+    harness.shutdown().await;
+    assert!(token.is_cancelled());
+    let payload = parse_json(&next_message(&mut ws).await);
+    assert_eq!(payload.get("id"), Some(&json!("prompt-until-shutdown")));
+    assert_eq!(
+        payload.pointer("/result/stopReason"),
+        Some(&json!("cancelled")),
+        "the transport should end the turn once shutdown starts, got {payload:?}"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn panicking_connection_is_counted_and_server_keeps_accepting() {
+    let harness = BridgeHarness::start(Arc::new(FakePanickingAgentTransport)).await;
+    let mut ws = harness.connect_initialized().await;
+
+    send_session_new_request(&mut ws).await;
+    let closed = timeout(TEST_TIMEOUT, ws.next())
+        .await
+        .expect("the crashed connection should close");
+    assert!(
+        !matches!(closed, Some(Ok(Message::Text(_)))),
+        "no response should follow the panic, got {closed:?}"
+    );
+
+    timeout(TEST_TIMEOUT, async {
+        while harness.metrics().connection_panics == 0 {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("the panic should be recorded");
+    assert_eq!(harness.metrics().connection_panics, 1);
+    assert_eq!(harness.metrics().active_connections, 0);
+
+    let mut ws = harness.connect_initialized().await;
+    let payload = call(&mut ws, "ping-after-panic", "bridge/ping", Value::Null).await;
+    assert_eq!(
+        payload.get("result").and_then(|r| r.get("pong")),
+        Some(&json!(true))
+    );
+
+    harness.shutdown().await;
+}
+
+// Answers with the params it was called with.
+struct EchoMethodHandler;
+
+impl MethodHandler for EchoMethodHandler {
+    fn handle(
+        &self,
+        _connection: &ConnectionInfo,
+        params: Value,
+    ) -> Pin<Box<dyn Future<Output = Result<Value, acp::Error>> + Send>> {
+        Box::pin(async move { Ok(params) })
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn method_handlers_serve_embedder_methods() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start_with(agent, |config| {
+        config
+            .method_handlers
+            .insert("app/echo".into(), Arc::new(EchoMethodHandler));
+    })
+    .await;
+    let (mut ws, _) = harness
+        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
+        .await
+        .expect("handshake should succeed");
+
+    let params = json!({"text": "hello", "nested": {"n": 1}});
+    let payload = call(&mut ws, "echo-early", "app/echo", params.clone()).await;
+    assert_eq!(
+        payload.pointer("/error/code"),
+        Some(&json!(-32601)),
+        "custom methods wait for initialize like built-in ones, got {payload:?}"
+    );
+
+    send_initialize_request(&mut ws).await;
+    let _init_response = next_message(&mut ws).await;
+    let payload = call(&mut ws, "echo", "app/echo", params.clone()).await;
+    assert_eq!(payload.get("result"), Some(&params), "got {payload:?}");
+
+    let payload = call(&mut ws, "caps", "bridge/capabilities", Value::Null).await;
+    let methods = payload
+        .pointer("/result/methods")
+        .and_then(Value::as_array)
+        .expect("methods array");
+    assert!(methods.contains(&json!("app/echo")), "got {methods:?}");
+
+    let payload = call(&mut ws, "unknown", "app/unknown", Value::Null).await;
+    assert_eq!(payload.pointer("/error/code"), Some(&json!(-32601)));
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_capabilities_reflect_live_config_before_initialize() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start_with(agent, |config| {
+        config.enable_cli_login = false;
+        config.max_message_bytes = Some(4096);
+    })
+    .await;
+    let (mut ws, _) = harness
+        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
+        .await
+        .expect("handshake should succeed");
+
+    let payload = call(&mut ws, "caps", "bridge/capabilities", Value::Null).await;
+    let result = payload
+        .get("result")
+        .unwrap_or_else(|| panic!("capabilities should answer pre-initialize, got {payload:?}"));
+    assert_eq!(result.get("bridgeId"), Some(&json!(TEST_BRIDGE_ID)));
+    let methods: Vec<&str> = result
+        .get("methods")
+        .and_then(|m| m.as_array())
+        .expect("methods array")
+        .iter()
+        .filter_map(|m| m.as_str())
+        .collect();
+    assert!(methods.contains(&"fs/read_text_file"));
+    assert!(methods.contains(&"bridge/capabilities"));
+    assert!(
+        !methods.contains(&"auth/cli_login"),
+        "disabled methods are not advertised, got {methods:?}"
+    );
+    assert_eq!(
+        result.get("limits").and_then(|l| l.get("maxMessageBytes")),
+        Some(&json!(4096))
+    );
+    assert_eq!(
+        result.get("features").and_then(|f| f.get("subprotocol")),
+        Some(&json!(SUBPROTOCOL))
+    );
+    assert_eq!(
+        result.get("features").and_then(|f| f.get("compression")),
+        Some(&json!(false))
+    );
+
+    // The advertised limit is the one enforced.
+    send_json_rpc(
+        &mut ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": "too-big",
+            "method": "bridge/ping",
+            "params": {"padding": "x".repeat(8192)},
+        }),
+    )
+    .await;
+    let closed = timeout(TEST_TIMEOUT, ws.next())
+        .await
+        .expect("oversized message should end the connection");
+    assert!(
+        !matches!(closed, Some(Ok(Message::Text(_)))),
+        "oversized message should not be answered, got {closed:?}"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn forwarded_initialize_carries_a_trace_id_in_meta() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start(agent.clone()).await;
+    let (mut ws, _) = harness
+        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
+        .await
+        .expect("handshake should succeed");
+
+    let payload = call(
+        &mut ws,
+        "init-traced",
+        "initialize",
+        json!({
+            "protocolVersion": acp::VERSION,
+            "clientCapabilities": {},
+            "_meta": { "client": "trace-test" },
+        }),
+    )
+    .await;
+    assert!(payload.get("result").is_some(), "got {payload:?}");
+
+    let calls = agent.take_initialize_calls().await;
+    assert_eq!(calls.len(), 1);
+    let meta = calls[0].meta.as_ref().expect("forwarded meta");
+    assert!(
+        meta.get("traceId")
+            .and_then(Value::as_str)
+            .is_some_and(|trace_id| !trace_id.is_empty()),
+        "missing traceId in {meta:?}"
+    );
+    assert_eq!(
+        meta.get("client"),
+        Some(&json!("trace-test")),
+        "client meta must survive"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test]
+async fn handled_requests_are_logged_with_peer_address() {
+    let events = CapturedEvents::default();
+    let _guard = tracing::subscriber::set_default(events.clone());
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start(agent).await;
+    let mut ws = harness.connect_initialized().await;
+    let payload = call(&mut ws, "audit-ping", "bridge/ping", Value::Null).await;
+    assert!(payload.get("result").is_some(), "ping failed: {payload:?}");
+
+    let mut handled = Vec::new();
+    for _ in 0..50 {
+        handled.extend(events.take().into_iter().filter(|fields| {
+            fields.get("message").map(String::as_str) == Some("request handled")
+                && fields.get("method").map(String::as_str) == Some("bridge/ping")
+        }));
+        if !handled.is_empty() {
+            break;
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+    let event = handled.first().expect("ping should be logged once handled");
+    let peer: SocketAddr = event
+        .get("peer")
+        .expect("peer field")
+        .parse()
+        .expect("peer should be a socket address");
+    assert!(peer.ip().is_loopback(), "unexpected peer {peer}");
+    assert_ne!(
+        peer.port(),
+        harness.handle.local_addr().expect("tcp listener").port()
+    );
+    assert!(
+        event
+            .get("trace_id")
+            .is_some_and(|trace_id| !trace_id.is_empty()),
+        "handled requests should log their trace id: {event:?}"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn healthz_answers_plain_http_gets_with_json() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start(agent).await;
+
+    let mut stream =
+        tokio::net::TcpStream::connect(harness.handle.local_addr().expect("tcp listener"))
+            .await
+            .expect("connect to the bridge");
+    stream
+        .write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .expect("send health check");
+    let mut response = String::new();
+    timeout(TEST_TIMEOUT, stream.read_to_string(&mut response))
+        .await
+        .expect("health check timed out")
+        .expect("read health check response");
+
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .unwrap_or_else(|| panic!("malformed response {response:?}"));
+    assert!(head.starts_with("HTTP/1.1 200 "), "got {head:?}");
+    assert!(
+        head.to_ascii_lowercase()
+            .contains("content-type: application/json"),
+        "got {head:?}"
+    );
+    let body: Value = serde_json::from_str(body).expect("JSON body");
+    assert_eq!(body.get("status"), Some(&json!("ok")));
+    assert_eq!(body.get("bridgeId"), Some(&json!(TEST_BRIDGE_ID)));
+    assert_eq!(harness.metrics().handshake_rejections, 0);
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn healthz_with_an_upgrade_header_still_opens_a_websocket() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start(agent).await;
+
+    let url = format!(
+        "ws://{}/healthz",
+        harness.handle.local_addr().expect("tcp listener")
+    );
+    let mut request = url.into_client_request().expect("client request");
+    request
+        .headers_mut()
+        .insert(ORIGIN, HeaderValue::from_static(ALLOWED_ORIGIN));
+    request.headers_mut().insert(
+        SEC_WEBSOCKET_PROTOCOL,
+        HeaderValue::from_static(SUBPROTOCOL),
+    );
+    let (mut ws, response) = async_tungstenite::tokio::connect_async(request)
+        .await
+        .expect("handshake should succeed");
+    assert_eq!(response.status(), 101);
+
+    send_initialize_request(&mut ws).await;
+    let payload = parse_json(&next_message(&mut ws).await);
+    assert!(payload.get("result").is_some(), "got {payload:?}");
+
+    harness.shutdown().await;
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn serve_unix_completes_initialize_over_a_unix_socket() {
+    let temp = TestTempDir::new("ct-bridge-unix");
+    let socket_path = temp.path().join("bridge.sock");
+    let config = BridgeConfig {
+        allowed_origins: vec![ALLOWED_ORIGIN.into()],
+        expected_subprotocol: SUBPROTOCOL.into(),
+        bridge_id: TEST_BRIDGE_ID.into(),
+        ..BridgeConfig::default()
+    };
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let handle = serve_unix(&socket_path, config, agent)
+        .await
+        .expect("bridge start");
+    assert_eq!(handle.local_path(), Some(socket_path.as_path()));
+    assert!(handle.local_addrs().is_empty());
+    assert_eq!(handle.local_addr(), None);
+
+    let connect = |origin: &'static str| {
+        let socket_path = socket_path.clone();
+        async move {
+            let stream = tokio::net::UnixStream::connect(&socket_path)
+                .await
+                .expect("connect to the bridge socket");
+            let mut request = "ws://localhost/"
+                .into_client_request()
+                .expect("client request");
+            let headers = request.headers_mut();
+            headers.insert(ORIGIN, HeaderValue::from_static(origin));
+            headers.insert(
+                SEC_WEBSOCKET_PROTOCOL,
+                HeaderValue::from_static(SUBPROTOCOL),
+            );
+            async_tungstenite::tokio::client_async(request, stream).await
+        }
+    };
+
+    let rejected = connect(BLOCKED_ORIGIN).await;
+    assert!(
+        matches!(rejected, Err(tungstenite::Error::Http(ref response)) if response.status() == 403),
+        "origin checks must still apply over a Unix socket: {rejected:?}"
+    );
+
+    let (mut ws, _) = connect(ALLOWED_ORIGIN)
+        .await
+        .expect("handshake should succeed");
+    send_json_rpc(
+        &mut ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": "unix-init",
+            "method": "initialize",
+            "params": {"protocolVersion": 1, "clientCapabilities": {}},
+        }),
+    )
+    .await;
+    let payload = parse_json(&next_message(&mut ws).await);
+    assert_eq!(payload.get("id"), Some(&json!("unix-init")));
+    assert_eq!(
+        payload.pointer("/result/_meta/bridgeId"),
+        Some(&json!(TEST_BRIDGE_ID)),
+        "initialize should complete over the socket: {payload:?}"
+    );
+
+    drop(ws);
+    handle.shutdown().await.expect("bridge shutdown");
+    assert!(
+        !socket_path.exists(),
+        "shutdown should remove the socket file"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serve_with_listener_uses_the_provided_listener() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind listener");
+    let addr = listener.local_addr().expect("listener address");
+    let config = BridgeConfig {
+        allowed_origins: vec![ALLOWED_ORIGIN.into()],
+        expected_subprotocol: SUBPROTOCOL.into(),
+        bridge_id: TEST_BRIDGE_ID.into(),
+        ..BridgeConfig::default()
+    };
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let handle = serve_with_listener(listener, config, agent)
+        .await
+        .expect("bridge start");
+    assert_eq!(handle.local_addr(), Some(addr));
+    assert_eq!(handle.local_addrs(), &[addr]);
+
+    let mut request = format!("ws://{addr}/")
+        .into_client_request()
+        .expect("client request");
+    request
+        .headers_mut()
+        .insert(ORIGIN, HeaderValue::from_static(ALLOWED_ORIGIN));
+    request.headers_mut().insert(
+        SEC_WEBSOCKET_PROTOCOL,
+        HeaderValue::from_static(SUBPROTOCOL),
+    );
+    let (mut ws, _) = async_tungstenite::tokio::connect_async(request)
+        .await
+        .expect("handshake should succeed");
+    send_initialize_request(&mut ws).await;
+    let payload = parse_json(&next_message(&mut ws).await);
+    assert!(
+        payload.get("result").is_some(),
+        "initialize should complete on the provided listener: {payload:?}"
+    );
+
+    drop(ws);
+    handle.shutdown().await.expect("bridge shutdown");
+}
+
+// Minimal ACP agent: answers initialize and session/new, and turns every
+// prompt into one session/update echoing its text before ending the turn.
+const ECHO_AGENT_SCRIPT: &str = r#"#!/bin/sh
+while IFS= read -r line; do
+  id=$(printf '%s\n' "$line" | sed -n 's/.*"id":\([0-9][0-9]*\).*/\1/p')
+  case "$line" in
+    *'"method":"initialize"'*)
+      printf '{"jsonrpc":"2.0","id":%s,"result":{"protocolVersion":1}}\n' "$id"
+      ;;
+    *'"method":"session/new"'*)
+      printf '{"jsonrpc":"2.0","id":%s,"result":{"sessionId":"echo-session"}}\n' "$id"
+      ;;
+    *'"method":"session/prompt"'*)
+      text=$(printf '%s\n' "$line" | sed -n 's/.*"text":"\([^"]*\)".*/\1/p')
+      printf '{"jsonrpc":"2.0","method":"session/update","params":{"sessionId":"echo-session","update":{"sessionUpdate":"agent_message_chunk","content":{"type":"text","text":"%s"}}}}\n' "$text"
+      printf '{"jsonrpc":"2.0","id":%s,"result":{"stopReason":"end_turn"}}\n' "$id"
+      ;;
+  esac
+done
+"#;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stdio_transport_drives_a_real_agent_process() {
+    let temp = TestTempDir::new("ct-bridge-echo-agent");
+    let script = temp.write_bin_executable("echo-agent", ECHO_AGENT_SCRIPT);
+    let agent = Arc::new(
+        StdioAgentTransport::spawn(&script, std::iter::empty::<&str>()).expect("spawn echo agent"),
+    );
+    let harness = BridgeHarness::start(agent).await;
+    let (mut ws, _) = harness
+        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
+        .await
+        .expect("handshake should succeed");
+
+    send_initialize_request(&mut ws).await;
+    let init = parse_json(&next_message(&mut ws).await);
+    assert_eq!(init["result"]["protocolVersion"], json!(1), "{init}");
+
+    send_session_new_request(&mut ws).await;
+    let session = parse_json(&next_message(&mut ws).await);
+    assert_eq!(
+        session["result"]["sessionId"],
+        json!("echo-session"),
+        "{session}"
+    );
+
+    send_json_rpc(
+        &mut ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": "prompt-1",
+            "method": "session/prompt",
+            "params": {
+                "sessionId": "echo-session",
+                "prompt": [{ "type": "text", "text": "hello agent" }]
+            }
+        }),
+    )
+    .await;
+
+    let update = parse_json(&next_message(&mut ws).await);
+    assert_eq!(update["method"], json!("session/update"), "{update}");
+    assert_eq!(
+        update["params"]["update"]["content"]["text"],
+        json!("hello agent")
+    );
+
+    let response = parse_json(&next_message(&mut ws).await);
+    assert_eq!(response["id"], json!("prompt-1"));
+    assert_eq!(
+        response["result"]["stopReason"],
+        json!("end_turn"),
+        "{response}"
+    );
+
+    harness.shutdown().await;
+}
+
+// Like `ECHO_AGENT_SCRIPT`, but each prompt makes the agent ask the client
+// for permission, read a file in the project, try one outside it and write
+// the selected option back, before reporting what it got.
+const TOOL_AGENT_SCRIPT: &str = r#"#!/bin/sh
+while IFS= read -r line; do
+  id=$(printf '%s\n' "$line" | sed -n 's/.*"id":\([0-9][0-9]*\).*/\1/p')
+  case "$line" in
+    *'"method":"initialize"'*)
+      printf '{"jsonrpc":"2.0","id":%s,"result":{"protocolVersion":1}}\n' "$id"
+      ;;
+    *'"method":"session/new"'*)
+      printf '{"jsonrpc":"2.0","id":%s,"result":{"sessionId":"tool-session"}}\n' "$id"
+      ;;
+    *'"method":"session/prompt"'*)
+      prompt_id=$id
+      printf '{"jsonrpc":"2.0","id":"agent-perm","method":"session/request_permission","params":{"sessionId":"tool-session","toolCall":{"toolCallId":"call-1"},"options":[{"optionId":"allow","name":"Allow","kind":"allow_once"}]}}\n'
+      IFS= read -r answer
+      option=$(printf '%s\n' "$answer" | sed -n 's/.*"optionId":"\([^"]*\)".*/\1/p')
+      printf '{"jsonrpc":"2.0","id":"agent-read","method":"fs/read_text_file","params":{"sessionId":"tool-session","path":"@ROOT@/notes.txt"}}\n'
+      IFS= read -r answer
+      content=$(printf '%s\n' "$answer" | sed -n 's/.*"content":"\([^"]*\)".*/\1/p')
+      printf '{"jsonrpc":"2.0","id":"agent-escape","method":"fs/read_text_file","params":{"sessionId":"tool-session","path":"/etc/hostname"}}\n'
+      IFS= read -r answer
+      refusal=$(printf '%s\n' "$answer" | sed -n 's/.*"kind":"\([^"]*\)".*/\1/p')
+      printf '{"jsonrpc":"2.0","id":"agent-write","method":"fs/write_text_file","params":{"sessionId":"tool-session","path":"@ROOT@/agent.txt","content":"%s"}}\n' "$option"
+      IFS= read -r answer
+      printf '{"jsonrpc":"2.0","method":"session/update","params":{"sessionId":"tool-session","update":{"sessionUpdate":"agent_message_chunk","content":{"type":"text","text":"%s %s %s"}}}}\n' "$option" "$content" "$refusal"
+      printf '{"jsonrpc":"2.0","id":%s,"result":{"stopReason":"end_turn"}}\n' "$prompt_id"
+      ;;
+  esac
+done
+"#;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stdio_transport_serves_agent_requests_mid_prompt() {
+    let temp = TestTempDir::new("ct-bridge-tool-agent");
+    let root = temp.path().join("project");
+    fs::create_dir_all(&root).expect("create project root");
+    fs::write(root.join("notes.txt"), "agent-visible").expect("write fixture");
+    let script = temp.write_bin_executable(
+        "tool-agent",
+        &TOOL_AGENT_SCRIPT.replace("@ROOT@", &root.to_string_lossy()),
+    );
+    let agent = Arc::new(
+        StdioAgentTransport::spawn(&script, std::iter::empty::<&str>()).expect("spawn tool agent"),
+    );
+    let project_root = root.clone();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_roots = vec![project_root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    send_json_rpc(
+        &mut ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": "tool-prompt",
+            "method": "session/prompt",
+            "params": {
+                "sessionId": session_id,
+                "prompt": [{ "type": "text", "text": "use your tools" }]
+            }
+        }),
+    )
+    .await;
+
+    // The agent's permission prompt is relayed to the client.
+    let request = parse_json(&next_message(&mut ws).await);
+    assert_eq!(
+        request["method"],
+        json!("session/request_permission"),
+        "{request}"
+    );
+    assert_eq!(request["params"]["sessionId"], json!(session_id));
+    assert_eq!(request["params"]["options"][0]["optionId"], json!("allow"));
+    send_json_rpc(
+        &mut ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": request["id"],
+            "result": {"outcome": {"outcome": "selected", "optionId": "allow"}},
+        }),
+    )
+    .await;
+
+    let update = parse_json(&next_message(&mut ws).await);
+    assert_eq!(update["method"], json!("session/update"), "{update}");
+    assert_eq!(
+        update["params"]["update"]["content"]["text"],
+        json!("allow agent-visible sandbox_violation"),
+        "the agent should see the client's answer, the file it read, and the sandbox refusal"
+    );
+    let response = parse_json(&next_message(&mut ws).await);
+    assert_eq!(response["id"], json!("tool-prompt"));
+    assert_eq!(response["result"]["stopReason"], json!("end_turn"));
+    assert_eq!(
+        fs::read_to_string(root.join("agent.txt")).expect("agent write"),
+        "allow"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stdio_transport_refuses_a_second_prompt_for_a_busy_session() {
+    let temp = TestTempDir::new("ct-bridge-busy-session");
+    // The echo agent, but slow enough to answer that a second prompt
+    // arrives while the first is running.
+    let script = temp.write_bin_executable(
+        "slow-echo-agent",
+        &ECHO_AGENT_SCRIPT.replace(
+            "*'\"method\":\"session/prompt\"'*)\n",
+            "*'\"method\":\"session/prompt\"'*)\n      sleep 0.5\n",
+        ),
+    );
+    let agent = Arc::new(
+        StdioAgentTransport::spawn(&script, std::iter::empty::<&str>()).expect("spawn echo agent"),
+    );
+    let harness = BridgeHarness::start(agent).await;
+    let mut first = harness.connect_initialized().await;
+    let mut second = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut first).await;
+    let prompt = |id: &str, text: &str| {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "session/prompt",
+            "params": {
+                "sessionId": session_id,
+                "prompt": [{ "type": "text", "text": text }]
+            }
+        })
+    };
+
+    send_json_rpc(&mut first, prompt("first-prompt", "first")).await;
+    sleep(Duration::from_millis(100)).await;
+    let payload = call(
+        &mut second,
+        "second-prompt",
+        "session/prompt",
+        prompt("unused", "second")["params"].clone(),
+    )
+    .await;
+    assert!(
+        payload.get("error").is_some(),
+        "a busy session should refuse another prompt, got {payload:?}"
+    );
+
+    // The first prompt keeps its updates.
+    let update = parse_json(&next_message(&mut first).await);
+    assert_eq!(
+        update.pointer("/params/update/content/text"),
+        Some(&json!("first")),
+        "got {update:?}"
+    );
+    let response = parse_json(&next_message(&mut first).await);
+    assert_eq!(response["id"], json!("first-prompt"));
+    assert_eq!(response["result"]["stopReason"], json!("end_turn"));
+
+    harness.shutdown().await;
+}
+
+// Each prompt makes the agent write `@ROOT@/agent.txt` and report the error
+// kind it got back, or "ok".
+const WRITING_AGENT_SCRIPT: &str = r#"#!/bin/sh
+while IFS= read -r line; do
+  id=$(printf '%s\n' "$line" | sed -n 's/.*"id":\([0-9][0-9]*\).*/\1/p')
+  case "$line" in
+    *'"method":"initialize"'*)
+      printf '{"jsonrpc":"2.0","id":%s,"result":{"protocolVersion":1}}\n' "$id"
+      ;;
+    *'"method":"session/new"'*)
+      printf '{"jsonrpc":"2.0","id":%s,"result":{"sessionId":"writer-session"}}\n' "$id"
+      ;;
+    *'"method":"session/prompt"'*)
+      printf '{"jsonrpc":"2.0","id":"agent-write","method":"fs/write_text_file","params":{"sessionId":"writer-session","path":"@ROOT@/agent.txt","content":"from agent"}}\n'
+      IFS= read -r answer
+      kind=$(printf '%s\n' "$answer" | sed -n 's/.*"kind":"\([^"]*\)".*/\1/p')
+      printf '{"jsonrpc":"2.0","method":"session/update","params":{"sessionId":"writer-session","update":{"sessionUpdate":"agent_message_chunk","content":{"type":"text","text":"%s"}}}}\n' "${kind:-ok}"
+      printf '{"jsonrpc":"2.0","id":%s,"result":{"stopReason":"end_turn"}}\n' "$id"
+      ;;
+  esac
+done
+"#;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stdio_transport_agent_writes_respect_standing_rejections() {
+    let temp = TestTempDir::new("ct-bridge-writing-agent");
+    let root = temp.path().join("project");
+    fs::create_dir_all(&root).expect("create project root");
+    let script = temp.write_bin_executable(
+        "writing-agent",
+        &WRITING_AGENT_SCRIPT.replace("@ROOT@", &root.to_string_lossy()),
+    );
+    // The batch prompt below is answered with a standing rejection.
+    let agent = StdioAgentTransport::spawn(&script, std::iter::empty::<&str>())
+        .expect("spawn writing agent")
+        .with_permission_handler(Arc::new(|_request| {
+            Box::pin(async {
+                Ok(acp::RequestPermissionResponse {
+                    outcome: acp::RequestPermissionOutcome::Selected {
+                        option_id: acp::PermissionOptionId("reject_always".into()),
+                    },
+                    meta: None,
+                })
+            })
+        }));
+    let project_root = root.clone();
+    let harness = BridgeHarness::start_with(Arc::new(agent), move |config| {
+        config.project_roots = vec![project_root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    let payload = call(
+        &mut ws,
+        "reject-batch",
+        "permission/request_batch",
+        json!({"sessionId": session_id, "paths": ["agent.txt"]}),
+    )
+    .await;
+    assert_eq!(
+        payload.pointer("/result/decision"),
+        Some(&json!("reject")),
+        "got {payload:?}"
+    );
+
+    send_json_rpc(
+        &mut ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": "writer-prompt",
+            "method": "session/prompt",
+            "params": {
+                "sessionId": session_id,
+                "prompt": [{ "type": "text", "text": "write it" }]
+            }
+        }),
+    )
+    .await;
+    let update = parse_json(&next_message(&mut ws).await);
+    assert_eq!(
+        update.pointer("/params/update/content/text"),
+        Some(&json!("permission_denied")),
+        "got {update:?}"
+    );
+    let response = parse_json(&next_message(&mut ws).await);
+    assert_eq!(response["id"], json!("writer-prompt"));
+    assert!(!root.join("agent.txt").exists());
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_ping_answers_before_initialize() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start(agent.clone()).await;
+    let (mut ws, _) = harness
+        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
+        .await
+        .expect("handshake should succeed");
+
+    let payload = call(&mut ws, "ping-1", "bridge/ping", json!({})).await;
+    let result = payload
+        .get("result")
+        .unwrap_or_else(|| panic!("ping should succeed before initialize, got {payload:?}"));
+    assert_eq!(result.get("pong"), Some(&json!(true)));
+    assert_eq!(result.get("bridgeId"), Some(&json!(TEST_BRIDGE_ID)));
+    assert!(result.get("version").and_then(|v| v.as_str()).is_some());
+    assert!(
+        agent.take_initialize_calls().await.is_empty(),
+        "ping must not touch the transport"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_ping_can_be_disabled() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start_with(agent, |config| {
+        config.enable_ping = false;
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(&mut ws, "ping-off", "bridge/ping", json!({})).await;
+    assert_eq!(
+        payload.get("error").and_then(|e| e.get("code")),
+        Some(&json!(-32601))
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_declines_permessage_deflate_and_serves_uncompressed() {
+    let temp = TestTempDir::new("ws-deflate-offer");
+    let path = temp.path().join("large.txt");
+    let contents = "0123456789abcdef\n".repeat(16 * 1024);
+    fs::write(&path, &contents).expect("write fixture");
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+
+    let mut request = format!("ws://{}/", harness.addr)
+        .into_client_request()
+        .expect("client request");
+    let headers = request.headers_mut();
+    headers.insert(ORIGIN, HeaderValue::from_static(ALLOWED_ORIGIN));
+    headers.insert(
+        SEC_WEBSOCKET_PROTOCOL,
+        HeaderValue::from_static(SUBPROTOCOL),
+    );
+    headers.insert(
+        "Sec-WebSocket-Extensions",
+        HeaderValue::from_static("permessage-deflate; client_max_window_bits"),
+    );
+    let (mut ws, response) = async_tungstenite::tokio::connect_async(request)
+        .await
+        .expect("offering compression must not fail the handshake");
+    assert!(
+        response.headers().get("Sec-WebSocket-Extensions").is_none(),
+        "no extension is negotiated"
+    );
+
+    send_initialize_request(&mut ws).await;
+    let _init_response = next_message(&mut ws).await;
+    let payload = call(
+        &mut ws,
+        "read-large",
+        "fs/read_text_file",
+        json!({"path": path}),
+    )
+    .await;
+    assert_eq!(
+        payload.get("result").and_then(|r| r.get("content")),
+        Some(&json!(contents))
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_reassembles_fragmented_text_messages() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start(agent).await;
+    let mut ws = harness.connect_initialized().await;
+
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": "fragmented",
+        "method": "bridge/ping",
+    })
+    .to_string()
+    .into_bytes();
+    let (head, tail) = request.split_at(request.len() / 2);
+    ws.send(Message::Frame(Frame::message(
+        head.to_vec(),
+        OpCode::Data(Data::Text),
+        false,
+    )))
+    .await
+    .expect("send first fragment");
+    ws.send(Message::Frame(Frame::message(
+        tail.to_vec(),
+        OpCode::Data(Data::Continue),
+        true,
+    )))
+    .await
+    .expect("send final fragment");
+
+    let payload = parse_json(&next_message(&mut ws).await);
+    assert_eq!(payload.get("id"), Some(&json!("fragmented")));
+    assert_eq!(
+        payload.get("result").and_then(|r| r.get("pong")),
+        Some(&json!(true)),
+        "fragments should be parsed as one request, got {payload:?}"
+    );
+
+    // A continuation with nothing to continue is a protocol error.
+    ws.send(Message::Frame(Frame::message(
+        b"orphan".to_vec(),
+        OpCode::Data(Data::Continue),
+        true,
+    )))
+    .await
+    .expect("send stray continuation");
+    let closed = timeout(TEST_TIMEOUT, async {
+        loop {
+            match ws.next().await {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            }
+        }
+    })
+    .await;
+    assert!(
+        closed.is_ok(),
+        "stray continuation should end the connection"
+    );
+
+    harness.shutdown().await;
+}
+
+// Skips anything else the bridge sends and returns the close frame's code.
+async fn next_close_code(ws: &mut WsStream) -> Option<CloseCode> {
+    timeout(TEST_TIMEOUT, async {
+        loop {
+            match ws.next().await {
+                Some(Ok(Message::Close(frame))) => return frame.map(|frame| frame.code),
+                Some(Ok(_)) => continue,
+                Some(Err(err)) => panic!("expected a close frame, got {err:?}"),
+                None => panic!("connection ended without a close frame"),
+            }
+        }
+    })
+    .await
+    .expect("close frame timed out")
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn protocol_errors_close_the_connection_with_a_code() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start(agent).await;
+
+    let mut ws = harness.connect_initialized().await;
+    ws.send(Message::Frame(Frame::message(
+        b"orphan".to_vec(),
+        OpCode::Data(Data::Continue),
+        true,
+    )))
+    .await
+    .expect("send stray continuation");
+    assert_eq!(next_close_code(&mut ws).await, Some(CloseCode::Protocol));
+
+    let mut ws = harness.connect_initialized().await;
+    ws.send(Message::Text("{not json".into()))
+        .await
+        .expect("send invalid JSON");
+    let payload = parse_json(&next_message(&mut ws).await);
+    assert_eq!(
+        payload.get("error").and_then(|e| e.get("code")),
+        Some(&json!(-32700)),
+        "the parse error is reported first, got {payload:?}"
+    );
+    assert_eq!(next_close_code(&mut ws).await, Some(CloseCode::Invalid));
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn streamed_updates_keep_a_connection_from_idling_out() {
+    let agent = Arc::new(FakeThinkingAgentTransport {
+        delay: Duration::from_millis(100),
+        updates: 8,
+    });
+    let harness = BridgeHarness::start_with(agent, |config| {
+        config.connection_idle_timeout = Some(Duration::from_millis(300));
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    // The client stays silent for the whole prompt, well past the idle
+    // timeout, while the agent streams updates.
+    send_json_rpc(
+        &mut ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": "streaming-prompt",
+            "method": "session/prompt",
+            "params": {"sessionId": session_id, "prompt": "stream"},
+        }),
+    )
+    .await;
+
+    let mut updates = 0;
+    loop {
+        let payload = parse_json(&next_message(&mut ws).await);
+        if payload.get("id") == Some(&json!("streaming-prompt")) {
+            assert!(payload.get("result").is_some(), "got {payload:?}");
+            break;
+        }
+        assert_eq!(payload.get("method"), Some(&json!("session/update")));
+        updates += 1;
+    }
+    assert_eq!(updates, 8);
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn closes_are_counted_by_reason() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start_with(agent, |config| {
+        config.connection_idle_timeout = Some(Duration::from_millis(300));
+    })
+    .await;
+
+    let mut idle = harness.connect_initialized().await;
+    assert_eq!(next_close_code(&mut idle).await, Some(CloseCode::Away));
+
+    let mut closing = harness.connect_initialized().await;
+    closing.close(None).await.expect("close the connection");
+    while let Some(Ok(_)) = timeout(TEST_TIMEOUT, closing.next())
+        .await
+        .expect("close handshake timed out")
+    {}
+
+    let mut invalid = harness.connect_initialized().await;
+    invalid
+        .send(Message::Text("{not json".into()))
+        .await
+        .expect("send invalid JSON");
+    assert_eq!(
+        next_close_code(&mut invalid).await,
+        Some(CloseCode::Invalid)
+    );
+
+    let expected: std::collections::BTreeMap<String, u64> = [
+        ("idle_timeout".to_string(), 1),
+        ("invalid_json".to_string(), 1),
+        ("normal".to_string(), 1),
+    ]
+    .into();
+    timeout(TEST_TIMEOUT, async {
+        while harness.metrics().closes_by_reason != expected {
+            sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap_or_else(|_| {
+        panic!(
+            "close reasons were {:?}",
+            harness.metrics().closes_by_reason
+        )
+    });
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn retrying_transport_hides_transient_agent_failures() {
+    let flaky = Arc::new(FakeFlakyAgentTransport::new(2));
+    let agent = Arc::new(RetryingTransport::new(
+        flaky.clone(),
+        2,
+        Duration::from_millis(5),
+    ));
+    let harness = BridgeHarness::start(agent).await;
+    let (mut ws, _) = harness
+        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
+        .await
+        .expect("handshake should succeed");
+
+    send_initialize_request(&mut ws).await;
+    let payload = parse_json(&next_message(&mut ws).await);
+    assert!(
+        payload.get("result").is_some(),
+        "initialize should succeed after retries, got {payload:?}"
+    );
+    assert_eq!(new_session_id(&mut ws).await, "flaky-session");
+    assert_eq!(flaky.calls.load(Ordering::SeqCst), 4);
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_drops_unknown_notifications_but_answers_unknown_requests() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start(agent).await;
+    let mut ws = harness.connect_initialized().await;
+
+    send_json_rpc(
+        &mut ws,
+        json!({"jsonrpc": "2.0", "method": "bogus/notification", "params": {}}),
+    )
+    .await;
+
+    // Messages are handled in order, so a reply to the notification would
+    // arrive before the reply to this request.
+    send_json_rpc(
+        &mut ws,
+        json!({"jsonrpc": "2.0", "id": "bogus-1", "method": "bogus/method", "params": {}}),
+    )
+    .await;
+    let payload = parse_json(&next_message(&mut ws).await);
+    assert_eq!(
+        payload.get("id"),
+        Some(&json!("bogus-1")),
+        "notification must not be answered, got {payload:?}"
+    );
+    assert_eq!(
+        payload.get("error").and_then(|e| e.get("code")),
+        Some(&json!(-32601))
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_rejects_requests_without_jsonrpc_2_0() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start(agent).await;
+    let mut ws = harness.connect_initialized().await;
+
+    for (request_id, request) in [
+        (
+            "missing-version",
+            json!({"id": "missing-version", "method": "session/new", "params": {}}),
+        ),
+        (
+            "wrong-version",
+            json!({"jsonrpc": "1.0", "id": "wrong-version", "method": "session/new", "params": {}}),
+        ),
+    ] {
+        send_json_rpc(&mut ws, request).await;
+        let payload = parse_json(&next_message(&mut ws).await);
+        assert_eq!(payload.get("id"), Some(&json!(request_id)));
+        assert_eq!(
+            payload.get("error").and_then(|e| e.get("code")),
+            Some(&json!(-32600)),
+            "expected invalid request, got {payload:?}"
+        );
+    }
+
+    // The shared helpers send well-formed 2.0 requests.
+    let session_id = new_session_id(&mut ws).await;
+    assert!(!session_id.is_empty());
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_reports_unimplemented_transport_methods_as_method_not_found() {
+    let temp = TestTempDir::new("transport-not-implemented");
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    let payload = call(
+        &mut ws,
+        "prompt-unsupported",
+        "session/prompt",
+        json!({"sessionId": session_id, "prompt": "hello"}),
+    )
+    .await;
+    let error = payload
+        .get("error")
+        .unwrap_or_else(|| panic!("prompt should fail, got {payload:?}"));
+    assert_eq!(error.get("code"), Some(&json!(-32601)));
+
+    // The permission prompt behind a write goes through the transport too.
+    let payload = call(
+        &mut ws,
+        "write-unsupported",
+        "fs/write_text_file",
+        json!({
+            "sessionId": session_id,
+            "path": temp.path().join("out.txt").to_string_lossy(),
+            "content": "x",
+        }),
+    )
+    .await;
+    assert_eq!(
+        payload.get("error").and_then(|e| e.get("code")),
+        Some(&json!(-32601)),
+        "unsupported permission prompts should not look like internal errors"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_rejects_prompts_longer_than_max_prompt_chars() {
+    let agent = Arc::new(FakeStreamingAgentTransport::new(
+        success_initialize_response(),
+    ));
+    let harness = BridgeHarness::start_with(agent.clone(), |config| {
+        config.max_prompt_chars = Some(10);
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    let payload = call(
+        &mut ws,
+        "prompt-long",
+        "session/prompt",
+        json!({"sessionId": session_id, "prompt": "eleven char"}),
+    )
+    .await;
+    let error = payload
+        .get("error")
+        .unwrap_or_else(|| panic!("over-limit prompt should be rejected, got {payload:?}"));
+    assert_eq!(error.get("code"), Some(&json!(-32602)));
+    assert_eq!(error.get("data"), Some(&json!("prompt too long")));
+
+    // Each block fits on its own; together they exceed the limit.
+    let payload = call(
+        &mut ws,
+        "prompt-blocks",
+        "session/prompt",
+        json!({
+            "sessionId": session_id,
+            "prompt": [
+                {"type": "text", "text": "héllo"},
+                {"type": "text", "text": "wörld!"},
+            ],
+        }),
+    )
+    .await;
+    assert_eq!(
+        payload.get("error").and_then(|e| e.get("data")),
+        Some(&json!("prompt too long")),
+        "limit applies to the sum of all text blocks, got {payload:?}"
+    );
+    assert!(
+        agent.take_prompt_calls().await.is_empty(),
+        "transport must not see over-limit prompts"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_rejects_session_ids_not_matching_configured_pattern() {
+    const UUID_PATTERN: &str = "[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}";
+    let agent = Arc::new(FakeStreamingAgentTransport::new(
+        success_initialize_response(),
+    ));
+    let harness = BridgeHarness::start_with(agent.clone(), |config| {
+        config.session_id_pattern = Some(UUID_PATTERN.to_string());
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(
+        &mut ws,
+        "prompt-malformed",
+        "session/prompt",
+        json!({"sessionId": "not-a-uuid", "prompt": "hello"}),
+    )
+    .await;
+    let error = payload
+        .get("error")
+        .unwrap_or_else(|| panic!("malformed id should be rejected, got {payload:?}"));
+    assert_eq!(error.get("code"), Some(&json!(-32602)));
+    assert_eq!(error.get("data"), Some(&json!("malformed session id")));
+    assert!(
+        agent.take_prompt_calls().await.is_empty(),
+        "transport must not see malformed ids"
+    );
+
+    let payload = call(
+        &mut ws,
+        "prompt-uuid",
+        "session/prompt",
+        json!({
+            "sessionId": "123e4567-e89b-12d3-a456-426614174000",
+            "prompt": "hello",
+        }),
+    )
+    .await;
+    assert!(
+        payload.get("result").is_some(),
+        "well-formed id should pass, got {payload:?}"
+    );
+
+    harness.shutdown().await;
+
+    let config = BridgeConfig {
+        bridge_id: TEST_BRIDGE_ID.into(),
+        session_id_pattern: Some("(unclosed".to_string()),
+        ..BridgeConfig::default()
+    };
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    assert!(
+        matches!(serve(config, agent).await, Err(BridgeError::Config(_))),
+        "an invalid pattern should fail at startup"
+    );
+}
+
+// Tests for session/prompt streaming notifications (RAT-LWS-REQ-031)
+// These tests will fail until streaming functionality is implemented
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_streams_session_prompt_updates() {
+    let agent = Arc::new(FakeStreamingAgentTransport::new(
+        success_initialize_response(),
+    ));
+    let harness = BridgeHarness::start(agent.clone()).await;
+
+    let (mut ws, _) = harness
+        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
+        .await
+        .expect("handshake should succeed");
+
+    // Initialize first
+    send_initialize_request(&mut ws).await;
+    let _init_response = next_message(&mut ws).await;
+
+    // Create a session first
+    send_session_new_request(&mut ws).await;
+    let session_response = next_message(&mut ws).await;
+    let session_payload = parse_json(&session_response);
+    let session_id = session_payload
+        .get("result")
+        .and_then(|r| r.get("sessionId"))
+        .and_then(|s| s.as_str())
+        .expect("should have sessionId");
+
+    // Send session/prompt request - this should trigger streaming
+    send_json_rpc(
+        &mut ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": "prompt-1",
+            "method": "session/prompt",
+            "params": {
+                "sessionId": session_id,
+                "prompt": "Hello, please help me with something"
+            }
+        }),
+    )
+    .await;
+
+    // Expect to receive multiple session/update notifications
+    let mut update_count = 0;
+    let mut final_response_received = false;
+
+    // Collect streaming updates until we get the final response
+    for _ in 0..10 {
+        // max 10 messages to avoid infinite loop
+        let message = next_message(&mut ws).await;
+        let payload = parse_json(&message);
+
+        if payload.get("method").and_then(|m| m.as_str()) == Some("session/update") {
+            // Verify session/update notification format per RAT-LWS-REQ-011
+            assert!(
+                payload.get("params").is_some(),
+                "session/update must have params"
+            );
+            update_count += 1;
+        } else if payload.get("id") == Some(&json!("prompt-1")) {
+            // This should be the final response
+            let result = payload
+                .get("result")
+                .expect("final response should have result");
+            assert!(
+                result.get("stopReason").is_some(),
+                "final response must contain stopReason per spec"
+            );
+            final_response_received = true;
+            break;
+        }
+    }
+
+    assert!(
+        update_count > 0,
+        "should receive at least one session/update notification"
+    );
+    assert!(
+        final_response_received,
+        "should receive final response with stopReason"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_delivers_many_notifications_intact_and_in_order() {
+    let agent = Arc::new(FakeStreamingAgentTransport::new(
+        success_initialize_response(),
+    ));
+    // Lengths vary so a reused send buffer that is not fully cleared between
+    // messages would leave trailing bytes behind.
+    let updates: Vec<Value> = (0..500)
+        .map(|index| {
+            json!({
+                "sessionId": "test-session-id",
+                "index": index,
+                "chunk": "x".repeat((index * 37) % 300),
+            })
+        })
+        .collect();
+    agent.configure_streaming_updates(updates).await;
+    let harness = BridgeHarness::start(agent.clone()).await;
+    let (mut ws, _) = harness
+        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
+        .await
+        .expect("handshake should succeed");
+
+    send_initialize_request(&mut ws).await;
+    let _ = next_message(&mut ws).await;
+    send_session_new_request(&mut ws).await;
+    let _ = next_message(&mut ws).await;
+
+    send_json_rpc(
+        &mut ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": "flood",
+            "method": "session/prompt",
+            "params": { "sessionId": "test-session-id", "prompt": "go" }
+        }),
+    )
+    .await;
+
+    for index in 0..500usize {
+        let update = parse_json(&next_message(&mut ws).await);
+        assert_eq!(update["method"], json!("session/update"));
+        assert_eq!(update["params"]["index"], json!(index));
+        assert_eq!(
+            update["params"]["chunk"],
+            json!("x".repeat((index * 37) % 300))
+        );
+    }
+    let response = parse_json(&next_message(&mut ws).await);
+    assert_eq!(response["id"], json!("flood"));
+    assert!(response["result"]["stopReason"].is_string(), "{response}");
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn notification_batches_arrive_as_separate_parseable_messages() {
+    let agent = Arc::new(FakeStreamingAgentTransport::new(
+        success_initialize_response(),
+    ));
+    agent
+        .configure_batched_updates(
+            (0..3)
+                .map(|index| json!({"sessionId": "test-session-id", "index": index}))
+                .collect(),
+        )
+        .await;
+    let harness = BridgeHarness::start(agent.clone()).await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    send_json_rpc(
+        &mut ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": "batched",
+            "method": "session/prompt",
+            "params": { "sessionId": session_id, "prompt": "go" }
+        }),
+    )
+    .await;
+
+    for index in 0..3 {
+        let update = parse_json(&next_message(&mut ws).await);
+        assert_eq!(update["method"], json!("session/update"), "{update}");
+        assert_eq!(update["params"]["index"], json!(index));
+        assert_eq!(
+            update["params"]["_meta"]["requestId"],
+            json!("batched"),
+            "batched updates are still tagged with their request"
+        );
+    }
+    let response = parse_json(&next_message(&mut ws).await);
+    assert_eq!(response["id"], json!("batched"));
+    assert_eq!(response["result"]["stopReason"], json!("end_turn"));
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn slow_reader_loses_oldest_notifications_under_drop_oldest() {
+    const UPDATES: usize = 1000;
+    // Large enough that the socket buffers fill long before the agent is done.
+    let filler = "x".repeat(32 * 1024);
+    let agent = Arc::new(FakeStreamingAgentTransport::new(
+        success_initialize_response(),
+    ));
+    agent
+        .configure_streaming_updates(
+            (0..UPDATES)
+                .map(|index| {
+                    json!({"sessionId": "test-session-id", "index": index, "filler": filler})
+                })
+                .collect(),
+        )
+        .await;
+    let harness = BridgeHarness::start_with(agent.clone(), |config| {
+        config.notification_queue_capacity = 4;
+        config.notification_overflow = NotificationOverflow::DropOldest;
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    send_json_rpc(
+        &mut ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": "flood",
+            "method": "session/prompt",
+            "params": { "sessionId": session_id, "prompt": "go" }
+        }),
+    )
+    .await;
+    // Not reading lets the bridge's writes back up behind this client.
+    sleep(Duration::from_millis(500)).await;
+
+    let mut indices = Vec::new();
+    let response = loop {
+        let payload = parse_json(&next_message(&mut ws).await);
+        if payload.get("id") == Some(&json!("flood")) {
+            break payload;
+        }
+        let index = payload["params"]["index"].as_u64().expect("update index");
+        indices.push(index as usize);
+    };
+    assert_eq!(response["result"]["stopReason"], json!("end_turn"));
+    assert!(
+        indices.len() < UPDATES,
+        "a full queue should have shed notifications, got all {UPDATES}"
+    );
+    assert!(
+        indices.windows(2).all(|pair| pair[0] < pair[1]),
+        "surviving notifications keep their order"
+    );
+    assert_eq!(
+        indices.last(),
+        Some(&(UPDATES - 1)),
+        "the newest notification is never the one dropped"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_forwards_session_prompt_transparently() {
+    let agent = Arc::new(FakeStreamingAgentTransport::new(
+        success_initialize_response(),
+    ));
+    let harness = BridgeHarness::start(agent.clone()).await;
+
+    let (mut ws, _) = harness
+        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
+        .await
+        .expect("handshake should succeed");
+
+    // Initialize and create session
+    send_initialize_request(&mut ws).await;
+    let _init_response = next_message(&mut ws).await;
+    send_session_new_request(&mut ws).await;
+    let _session_response = next_message(&mut ws).await;
+
+    let test_prompt = "Test prompt for transparency";
+    send_json_rpc(
+        &mut ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": "prompt-transparency",
+            "method": "session/prompt",
+            "params": {
+                "sessionId": "test-session-id",
+                "prompt": test_prompt
+            }
+        }),
+    )
+    .await;
+
+    // Wait for any response (the test will fail because method doesn't exist yet)
+    let _response = next_message(&mut ws).await;
+
+    // Verify the agent received the request transparently (RAT-LWS-REQ-011)
+    let prompt_calls = agent.take_prompt_calls().await;
+    assert_eq!(
+        prompt_calls.len(),
+        1,
+        "session/prompt should be forwarded to agent"
+    );
+    assert_eq!(prompt_calls[0].prompt, test_prompt);
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_session_update_preserves_json_rpc_format() {
+    let agent = Arc::new(FakeStreamingAgentTransport::new(
+        success_initialize_response(),
+    ));
+    let harness = BridgeHarness::start(agent.clone()).await;
+
+    let (mut ws, _) = harness
+        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
+        .await
+        .expect("handshake should succeed");
+
+    // Initialize and setup session
+    send_initialize_request(&mut ws).await;
+    let _init_response = next_message(&mut ws).await;
+    send_session_new_request(&mut ws).await;
+    let _session_response = next_message(&mut ws).await;
+
+    // Configure agent to send specific notifications
+    agent
+        .configure_streaming_updates(vec![
+            json!({
+                "sessionId": "test-session-id",
+                "chunk": {"type": "text", "content": "Hello"},
+                "index": 0
+            }),
+            json!({
+                "sessionId": "test-session-id",
+                "chunk": {"type": "text", "content": " world"},
+                "index": 1
+            }),
+        ])
+        .await;
+
+    // Send prompt request
+    send_json_rpc(
+        &mut ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": "streaming-test",
+            "method": "session/prompt",
+            "params": {
+                "sessionId": "test-session-id",
+                "prompt": "Say hello"
+            }
+        }),
+    )
+    .await;
+
+    // Verify session/update notifications preserve JSON-RPC format
+    for expected_index in 0..2 {
+        let message = next_message(&mut ws).await;
+        let payload = parse_json(&message);
+
+        // RAT-LWS-REQ-011: JSON-RPC notification format preserved
+        assert_eq!(payload.get("jsonrpc"), Some(&json!("2.0")));
+        assert_eq!(payload.get("method"), Some(&json!("session/update")));
+        assert!(payload.get("params").is_some());
+        assert!(payload.get("id").is_none()); // notifications don't have id
+
+        let params = payload.get("params").unwrap();
+        assert_eq!(params.get("index"), Some(&json!(expected_index)));
+    }
+
+    harness.shutdown().await;
+}
+
+fn success_initialize_response() -> acp::InitializeResponse {
+    acp::InitializeResponse {
+        protocol_version: acp::VERSION,
+        agent_capabilities: acp::AgentCapabilities::default(),
+        auth_methods: Vec::new(),
+        meta: None,
+    }
+}
+
+struct FakeAgentState {
+    initialize_calls: Vec<acp::InitializeRequest>,
+    initialize_response: acp::InitializeResponse,
+    new_session_calls: Vec<acp::NewSessionRequest>,
+    new_session_response: acp::NewSessionResponse,
+    set_mode_calls: Vec<acp::SetSessionModeRequest>,
+    close_session_calls: Vec<acp::SessionId>,
+}
+
+// Represents a session/prompt request that needs to be implemented
+#[derive(Clone, Debug)]
+struct PromptRequest {
+    prompt: String,
+}
+
+struct FakeStreamingAgentState {
+    initialize_calls: Vec<acp::InitializeRequest>,
+    initialize_response: acp::InitializeResponse,
+    new_session_calls: Vec<acp::NewSessionRequest>,
+    new_session_response: acp::NewSessionResponse,
+    prompt_calls: Vec<PromptRequest>,
+    streaming_updates: Vec<Value>,
+    batch_updates: bool,
+}
+
+#[derive(Clone)]
+struct FakeAgentTransport {
+    state: Arc<Mutex<FakeAgentState>>,
+}
+
+impl FakeAgentTransport {
+    fn new(initialize_response: acp::InitializeResponse) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(FakeAgentState {
+                initialize_calls: Vec::new(),
+                initialize_response,
+                new_session_calls: Vec::new(),
+                new_session_response: acp::NewSessionResponse {
+                    session_id: acp::SessionId("test-session-id".into()),
+                    modes: None,
+                    meta: None,
+                },
+                set_mode_calls: Vec::new(),
+                close_session_calls: Vec::new(),
+            })),
+        }
+    }
+
+    async fn take_initialize_calls(&self) -> Vec<acp::InitializeRequest> {
+        let mut state = self.state.lock().await;
+        std::mem::take(&mut state.initialize_calls)
+    }
+
+    async fn take_new_session_calls(&self) -> Vec<acp::NewSessionRequest> {
+        let mut state = self.state.lock().await;
+        std::mem::take(&mut state.new_session_calls)
+    }
+
+    async fn take_set_mode_calls(&self) -> Vec<acp::SetSessionModeRequest> {
+        let mut state = self.state.lock().await;
+        std::mem::take(&mut state.set_mode_calls)
+    }
+
+    async fn take_close_session_calls(&self) -> Vec<acp::SessionId> {
+        let mut state = self.state.lock().await;
+        std::mem::take(&mut state.close_session_calls)
+    }
+}
+
+impl AgentTransport for FakeAgentTransport {
+    fn initialize(
+        &self,
+        request: acp::InitializeRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::InitializeResponse, AgentTransportError>> + Send>>
+    {
+        let state = self.state.clone();
+        Box::pin(async move {
+            let mut guard = state.lock().await;
+            guard.initialize_calls.push(request);
+            Ok(guard.initialize_response.clone())
+        })
+    }
+
+    fn new_session(
+        &self,
+        request: acp::NewSessionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::NewSessionResponse, AgentTransportError>> + Send>>
+    {
+        let state = self.state.clone();
+        Box::pin(async move {
+            let mut guard = state.lock().await;
+            guard.new_session_calls.push(request);
+            Ok(guard.new_session_response.clone())
+        })
+    }
+
+    fn prompt(
+        &self,
+        _request: acp::PromptRequest,
+        _notification_sender: Arc<dyn ct_bridge::NotificationSender>,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::PromptResponse, AgentTransportError>> + Send>>
+    {
+        Box::pin(async move { Err(AgentTransportError::NotImplemented) })
+    }
+
+    fn request_permission(
+        &self,
+        _request: acp::RequestPermissionRequest,
+    ) -> Pin<
+        Box<
+            dyn Future<Output = Result<acp::RequestPermissionResponse, AgentTransportError>> + Send,
+        >,
+    > {
+        Box::pin(async move { Err(AgentTransportError::NotImplemented) })
+    }
+    fn set_mode(
+        &self,
+        request: acp::SetSessionModeRequest,
+    ) -> Pin<
+        Box<dyn Future<Output = Result<acp::SetSessionModeResponse, AgentTransportError>> + Send>,
+    > {
+        let state = self.state.clone();
+        Box::pin(async move {
+            state.lock().await.set_mode_calls.push(request);
+            Ok(acp::SetSessionModeResponse::default())
+        })
+    }
+
+    fn close_session(
+        &self,
+        session_id: acp::SessionId,
+    ) -> Pin<Box<dyn Future<Output = Result<(), AgentTransportError>> + Send>> {
+        let state = self.state.clone();
+        Box::pin(async move {
+            state.lock().await.close_session_calls.push(session_id);
+            Ok(())
+        })
+    }
+}
+
+// Answers `initialize` at once but takes `delay` to create a session.
+struct FakeSlowAgentTransport {
+    delay: Duration,
+}
+
+impl AgentTransport for FakeSlowAgentTransport {
+    fn initialize(
+        &self,
+        _request: acp::InitializeRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::InitializeResponse, AgentTransportError>> + Send>>
+    {
+        Box::pin(async move { Ok(success_initialize_response()) })
+    }
+
+    fn new_session(
+        &self,
+        _request: acp::NewSessionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::NewSessionResponse, AgentTransportError>> + Send>>
+    {
+        let delay = self.delay;
+        Box::pin(async move {
+            sleep(delay).await;
+            Ok(acp::NewSessionResponse {
+                session_id: acp::SessionId("slow-session".into()),
+                modes: None,
+                meta: None,
+            })
+        })
+    }
+
+    fn prompt(
+        &self,
+        _request: acp::PromptRequest,
+        _notification_sender: Arc<dyn ct_bridge::NotificationSender>,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::PromptResponse, AgentTransportError>> + Send>>
+    {
+        Box::pin(async move { Err(AgentTransportError::NotImplemented) })
+    }
+
+    fn request_permission(
+        &self,
+        _request: acp::RequestPermissionRequest,
+    ) -> Pin<
+        Box<
+            dyn Future<Output = Result<acp::RequestPermissionResponse, AgentTransportError>> + Send,
+        >,
+    > {
+        Box::pin(async move { Err(AgentTransportError::NotImplemented) })
+    }
+}
+
+// Stays silent for `delay` before each of `updates` updates, then finishes
+// the prompt.
+struct FakeThinkingAgentTransport {
+    delay: Duration,
+    updates: usize,
+}
+
+impl AgentTransport for FakeThinkingAgentTransport {
+    fn initialize(
+        &self,
+        _request: acp::InitializeRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::InitializeResponse, AgentTransportError>> + Send>>
+    {
+        Box::pin(async move { Ok(success_initialize_response()) })
+    }
+
+    fn new_session(
+        &self,
+        _request: acp::NewSessionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::NewSessionResponse, AgentTransportError>> + Send>>
+    {
+        Box::pin(async move {
+            Ok(acp::NewSessionResponse {
+                session_id: acp::SessionId("thinking-session".into()),
+                modes: None,
+                meta: None,
+            })
+        })
+    }
+
+    fn prompt(
+        &self,
+        request: acp::PromptRequest,
+        notification_sender: Arc<dyn ct_bridge::NotificationSender>,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::PromptResponse, AgentTransportError>> + Send>>
+    {
+        let delay = self.delay;
+        let updates = self.updates;
+        Box::pin(async move {
+            for _ in 0..updates {
+                sleep(delay).await;
+                notification_sender
+                    .send_notification(
+                        "session/update",
+                        json!({
+                            "sessionId": request.session_id.0.as_ref(),
+                            "update": {
+                                "sessionUpdate": "agent_message_chunk",
+                                "content": {"type": "text", "text": "done thinking"},
+                            },
+                        }),
+                    )
+                    .await?;
+            }
+            Ok(acp::PromptResponse {
+                stop_reason: acp::StopReason::EndTurn,
+                meta: None,
+            })
+        })
+    }
+
+    fn request_permission(
+        &self,
+        _request: acp::RequestPermissionRequest,
+    ) -> Pin<
+        Box<
+            dyn Future<Output = Result<acp::RequestPermissionResponse, AgentTransportError>> + Send,
+        >,
+    > {
+        Box::pin(async move { Err(AgentTransportError::NotImplemented) })
+    }
+}
+
+// Streams one update, then never finishes the prompt. Records when the bridge
+// drops the prompt future and keeps the notification sender for later use.
+#[derive(Clone, Default)]
+struct FakeHangingAgentTransport {
+    prompt_dropped: Arc<AtomicBool>,
+    notification_sender: Arc<Mutex<Option<Arc<dyn ct_bridge::NotificationSender>>>>,
+}
+
+struct SetOnDrop(Arc<AtomicBool>);
+
+impl Drop for SetOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+impl AgentTransport for FakeHangingAgentTransport {
+    fn initialize(
+        &self,
+        _request: acp::InitializeRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::InitializeResponse, AgentTransportError>> + Send>>
+    {
+        Box::pin(async move { Ok(success_initialize_response()) })
+    }
+
+    fn new_session(
+        &self,
+        _request: acp::NewSessionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::NewSessionResponse, AgentTransportError>> + Send>>
+    {
+        Box::pin(async move {
+            Ok(acp::NewSessionResponse {
+                session_id: acp::SessionId("hanging-session".into()),
+                modes: None,
+                meta: None,
+            })
+        })
+    }
+
+    fn prompt(
+        &self,
+        request: acp::PromptRequest,
+        notification_sender: Arc<dyn ct_bridge::NotificationSender>,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::PromptResponse, AgentTransportError>> + Send>>
+    {
+        let prompt_dropped = self.prompt_dropped.clone();
+        let stash = self.notification_sender.clone();
+        Box::pin(async move {
+            let _guard = SetOnDrop(prompt_dropped);
+            *stash.lock().await = Some(notification_sender.clone());
+            notification_sender
+                .send_notification(
+                    "session/update",
+                    json!({"sessionId": request.session_id.0, "chunk": "working"}),
+                )
+                .await?;
+            std::future::pending::<()>().await;
+            unreachable!("the prompt never completes")
+        })
+    }
+
+    fn request_permission(
+        &self,
+        _request: acp::RequestPermissionRequest,
+    ) -> Pin<
+        Box<
+            dyn Future<Output = Result<acp::RequestPermissionResponse, AgentTransportError>> + Send,
+        >,
+    > {
+        Box::pin(async move { Err(AgentTransportError::NotImplemented) })
+    }
+}
+
+// Holds every prompt open until the bridge shuts down, then ends the turn
+// as cancelled.
+#[derive(Clone, Default)]
+struct FakeShutdownAwareAgentTransport {
+    shutdown: Arc<std::sync::Mutex<Option<ShutdownToken>>>,
+    prompt_started: Arc<AtomicBool>,
+}
+
+impl AgentTransport for FakeShutdownAwareAgentTransport {
+    fn attach_shutdown(&self, shutdown: ShutdownToken) {
+        *self.shutdown.lock().expect("token lock") = Some(shutdown);
+    }
+
+    fn initialize(
+        &self,
+        _request: acp::InitializeRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::InitializeResponse, AgentTransportError>> + Send>>
+    {
+        Box::pin(async move { Ok(success_initialize_response()) })
+    }
+
+    fn new_session(
+        &self,
+        _request: acp::NewSessionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::NewSessionResponse, AgentTransportError>> + Send>>
+    {
+        Box::pin(async move { Err(AgentTransportError::NotImplemented) })
+    }
+
+    fn prompt(
+        &self,
+        _request: acp::PromptRequest,
+        _notification_sender: Arc<dyn ct_bridge::NotificationSender>,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::PromptResponse, AgentTransportError>> + Send>>
+    {
+        let shutdown = self
+            .shutdown
+            .lock()
+            .expect("token lock")
+            .clone()
+            .expect("serve attaches the shutdown token");
+        let prompt_started = self.prompt_started.clone();
+        Box::pin(async move {
+            prompt_started.store(true, Ordering::SeqCst);
+            shutdown.cancelled().await;
+            Ok(acp::PromptResponse {
+                stop_reason: acp::StopReason::Cancelled,
+                meta: None,
+            })
+        })
+    }
+
+    fn request_permission(
+        &self,
+        _request: acp::RequestPermissionRequest,
+    ) -> Pin<
+        Box<
+            dyn Future<Output = Result<acp::RequestPermissionResponse, AgentTransportError>> + Send,
+        >,
+    > {
+        Box::pin(async move { Err(AgentTransportError::NotImplemented) })
+    }
+}
+
+// Initializes normally, then panics inside any session/new call.
+struct FakePanickingAgentTransport;
+
+impl AgentTransport for FakePanickingAgentTransport {
+    fn initialize(
+        &self,
+        _request: acp::InitializeRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::InitializeResponse, AgentTransportError>> + Send>>
+    {
+        Box::pin(async move { Ok(success_initialize_response()) })
+    }
+
+    fn new_session(
+        &self,
+        _request: acp::NewSessionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::NewSessionResponse, AgentTransportError>> + Send>>
+    {
+        Box::pin(async move { panic!("agent transport blew up") })
+    }
+
+    fn prompt(
+        &self,
+        _request: acp::PromptRequest,
+        _notification_sender: Arc<dyn ct_bridge::NotificationSender>,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::PromptResponse, AgentTransportError>> + Send>>
+    {
+        Box::pin(async move { Err(AgentTransportError::NotImplemented) })
+    }
+
+    fn request_permission(
+        &self,
+        _request: acp::RequestPermissionRequest,
+    ) -> Pin<
+        Box<
+            dyn Future<Output = Result<acp::RequestPermissionResponse, AgentTransportError>> + Send,
+        >,
+    > {
+        Box::pin(async move { Err(AgentTransportError::NotImplemented) })
+    }
+}
+
+// Fails the first `failures` initialize/new_session calls with a transient
+// internal error, then succeeds.
+struct FakeFlakyAgentTransport {
+    failures: AtomicU32,
+    calls: AtomicU32,
+}
+
+impl FakeFlakyAgentTransport {
+    fn new(failures: u32) -> Self {
+        Self {
+            failures: AtomicU32::new(failures),
+            calls: AtomicU32::new(0),
+        }
+    }
+
+    fn fail_transiently(&self) -> Result<(), AgentTransportError> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        let remaining = self
+            .failures
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+        match remaining {
+            Ok(_) => Err(AgentTransportError::Internal("agent not ready".into())),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+impl AgentTransport for FakeFlakyAgentTransport {
+    fn initialize(
+        &self,
+        _request: acp::InitializeRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::InitializeResponse, AgentTransportError>> + Send>>
+    {
+        let result = self
+            .fail_transiently()
+            .map(|()| success_initialize_response());
+        Box::pin(async move { result })
+    }
+
+    fn new_session(
+        &self,
+        _request: acp::NewSessionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::NewSessionResponse, AgentTransportError>> + Send>>
+    {
+        let result = self.fail_transiently().map(|()| acp::NewSessionResponse {
+            session_id: acp::SessionId("flaky-session".into()),
+            modes: None,
+            meta: None,
+        });
+        Box::pin(async move { result })
+    }
+
+    fn prompt(
+        &self,
+        _request: acp::PromptRequest,
+        _notification_sender: Arc<dyn ct_bridge::NotificationSender>,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::PromptResponse, AgentTransportError>> + Send>>
+    {
+        Box::pin(async move { Err(AgentTransportError::NotImplemented) })
+    }
+
+    fn request_permission(
+        &self,
+        _request: acp::RequestPermissionRequest,
+    ) -> Pin<
+        Box<
+            dyn Future<Output = Result<acp::RequestPermissionResponse, AgentTransportError>> + Send,
+        >,
+    > {
+        Box::pin(async move { Err(AgentTransportError::NotImplemented) })
+    }
+}
+
+// Tags every session/new and session/prompt response with agent-owned meta.
+struct FakeMetaAgentTransport {
+    prompt_meta: Value,
+}
+
+impl Default for FakeMetaAgentTransport {
+    fn default() -> Self {
+        Self {
+            prompt_meta: json!({"agentTrace": "prompt"}),
+        }
+    }
+}
+
+impl AgentTransport for FakeMetaAgentTransport {
+    fn initialize(
+        &self,
+        _request: acp::InitializeRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::InitializeResponse, AgentTransportError>> + Send>>
+    {
+        Box::pin(async move { Ok(success_initialize_response()) })
+    }
+
+    fn new_session(
+        &self,
+        _request: acp::NewSessionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::NewSessionResponse, AgentTransportError>> + Send>>
+    {
+        Box::pin(async move {
+            Ok(acp::NewSessionResponse {
+                session_id: acp::SessionId("meta-session".into()),
+                modes: None,
+                meta: Some(json!({"agentTrace": "new-session"})),
+            })
+        })
+    }
+
+    fn prompt(
+        &self,
+        _request: acp::PromptRequest,
+        _notification_sender: Arc<dyn ct_bridge::NotificationSender>,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::PromptResponse, AgentTransportError>> + Send>>
+    {
+        let meta = self.prompt_meta.clone();
+        Box::pin(async move {
+            Ok(acp::PromptResponse {
+                stop_reason: acp::StopReason::EndTurn,
+                meta: Some(meta),
+            })
+        })
+    }
+
+    fn request_permission(
+        &self,
+        _request: acp::RequestPermissionRequest,
+    ) -> Pin<
+        Box<
+            dyn Future<Output = Result<acp::RequestPermissionResponse, AgentTransportError>> + Send,
+        >,
+    > {
+        Box::pin(async move { Err(AgentTransportError::NotImplemented) })
+    }
+}
+
+#[derive(Clone)]
+struct FakeStreamingAgentTransport {
+    state: Arc<Mutex<FakeStreamingAgentState>>,
+}
+
+#[allow(dead_code)]
+impl FakeStreamingAgentTransport {
+    fn new(initialize_response: acp::InitializeResponse) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(FakeStreamingAgentState {
+                initialize_calls: Vec::new(),
+                initialize_response,
+                new_session_calls: Vec::new(),
+                new_session_response: acp::NewSessionResponse {
+                    session_id: acp::SessionId("test-session-id".into()),
+                    modes: None,
+                    meta: None,
+                },
+                prompt_calls: Vec::new(),
+                streaming_updates: Vec::new(),
+                batch_updates: false,
+            })),
+        }
+    }
+
+    async fn take_initialize_calls(&self) -> Vec<acp::InitializeRequest> {
+        let mut state = self.state.lock().await;
+        std::mem::take(&mut state.initialize_calls)
+    }
+
+    async fn take_new_session_calls(&self) -> Vec<acp::NewSessionRequest> {
+        let mut state = self.state.lock().await;
+        std::mem::take(&mut state.new_session_calls)
+    }
+
+    async fn take_prompt_calls(&self) -> Vec<PromptRequest> {
+        let mut state = self.state.lock().await;
+        std::mem::take(&mut state.prompt_calls)
+    }
+
+    async fn configure_streaming_updates(&self, updates: Vec<Value>) {
+        let mut state = self.state.lock().await;
+        state.streaming_updates = updates;
+    }
+
+    // Delivers the configured updates through one `send_batch` call.
+    async fn configure_batched_updates(&self, updates: Vec<Value>) {
+        let mut state = self.state.lock().await;
+        state.streaming_updates = updates;
+        state.batch_updates = true;
+    }
+}
+
+impl AgentTransport for FakeStreamingAgentTransport {
+    fn initialize(
+        &self,
+        request: acp::InitializeRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::InitializeResponse, AgentTransportError>> + Send>>
+    {
+        let state = self.state.clone();
+        Box::pin(async move {
+            let mut guard = state.lock().await;
+            guard.initialize_calls.push(request);
+            Ok(guard.initialize_response.clone())
+        })
+    }
+
+    fn new_session(
+        &self,
+        request: acp::NewSessionRequest,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::NewSessionResponse, AgentTransportError>> + Send>>
+    {
+        let state = self.state.clone();
+        Box::pin(async move {
+            let mut guard = state.lock().await;
+            guard.new_session_calls.push(request);
+            Ok(guard.new_session_response.clone())
+        })
+    }
+
+    fn prompt(
+        &self,
+        request: acp::PromptRequest,
+        notification_sender: Arc<dyn ct_bridge::NotificationSender>,
+    ) -> Pin<Box<dyn Future<Output = Result<acp::PromptResponse, AgentTransportError>> + Send>>
+    {
+        let state = self.state.clone();
+        Box::pin(async move {
+            let mut guard = state.lock().await;
+            // Extract prompt text - for simplicity, assume first content block is text
+            let prompt_text =
+                if let Some(acp::ContentBlock::Text(text_content)) = request.prompt.first() {
+                    text_content.text.clone()
+                } else {
+                    "unknown prompt".to_string()
+                };
+
+            guard.prompt_calls.push(PromptRequest {
+                prompt: prompt_text,
+            });
+
+            // Send any configured streaming updates
+            let streaming_updates = guard.streaming_updates.clone();
+            let has_configured_updates = !streaming_updates.is_empty();
+            let batch_updates = guard.batch_updates;
+            drop(guard); // Release the lock before sending notifications
+
+            if batch_updates {
+                let batch = streaming_updates
+                    .into_iter()
+                    .map(|update| ("session/update".to_string(), update))
+                    .collect();
+                notification_sender
+                    .send_batch(batch)
+                    .await
+                    .map_err(|e| AgentTransportError::Internal(format!("{e:?}")))?;
+                return Ok(acp::PromptResponse {
+                    stop_reason: acp::StopReason::EndTurn,
+                    meta: None,
+                });
+            }
+
+            // Send session/update notifications for each streaming update
+            for update in streaming_updates {
+                if let Err(e) = notification_sender
+                    .send_notification("session/update", update)
+                    .await
+                {
+                    eprintln!("Failed to send session/update notification: {e:?}");
+                }
+            }
+
+            // If no specific updates were configured, send some default streaming updates
+            if !has_configured_updates {
+                // Send a few default session/update notifications
+                let default_updates = vec![
+                    json!({
+                        "sessionId": request.session_id.0,
+                        "chunk": {"type": "text", "content": "Thinking"},
+                        "index": 0
+                    }),
+                    json!({
+                        "sessionId": request.session_id.0,
+                        "chunk": {"type": "text", "content": "..."},
+                        "index": 1
+                    }),
+                    json!({
+                        "sessionId": request.session_id.0,
+                        "chunk": {"type": "text", "content": " about your request"},
+                        "index": 2
+                    }),
+                ];
+
+                for update in default_updates {
+                    if let Err(e) = notification_sender
+                        .send_notification("session/update", update)
+                        .await
+                    {
+                        eprintln!("Failed to send default session/update notification: {e:?}");
+                    }
+                }
+            }
+
+            // Return a simple response with stopReason
+            use agent_client_protocol as acp;
+            Ok(acp::PromptResponse {
+                stop_reason: acp::StopReason::EndTurn,
+                meta: None,
+            })
+        })
+    }
+
+    fn request_permission(
+        &self,
+        _request: acp::RequestPermissionRequest,
+    ) -> Pin<
+        Box<
+            dyn Future<Output = Result<acp::RequestPermissionResponse, AgentTransportError>> + Send,
+        >,
+    > {
+        Box::pin(async move { Err(AgentTransportError::NotImplemented) })
+    }
+}
+
+// Helper functions for the new streaming tests
+async fn send_initialize_request(ws: &mut WsStream) {
+    let initialize_request = acp::InitializeRequest {
+        protocol_version: acp::VERSION,
+        client_capabilities: acp::ClientCapabilities {
+            fs: acp::FileSystemCapability {
+                read_text_file: true,
+                write_text_file: true,
+                meta: None,
+            },
+            terminal: true,
+            meta: None,
+        },
+        meta: None,
+    };
+
+    send_json_rpc(
+        ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": "init-req",
+            "method": "initialize",
+            "params": initialize_request,
+        }),
+    )
+    .await;
+}
+
+async fn send_session_new_request(ws: &mut WsStream) {
+    let new_session_request = acp::NewSessionRequest {
+        cwd: PathBuf::from("/tmp"),
+        mcp_servers: vec![],
+        meta: None,
+    };
+
+    send_json_rpc(
+        ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": "session-new",
+            "method": "session/new",
+            "params": new_session_request,
+        }),
+    )
+    .await;
+}
+
+struct BridgeHarness {
+    handle: BridgeHandle,
+    addr: SocketAddr,
+    _agent: Arc<dyn AgentTransport>,
+}
+
+impl BridgeHarness {
+    async fn start(agent: Arc<dyn AgentTransport>) -> Self {
+        Self::start_with(agent, |_| {}).await
+    }
+
+    async fn start_with(
+        agent: Arc<dyn AgentTransport>,
+        configure: impl FnOnce(&mut BridgeConfig),
+    ) -> Self {
+        let mut config = BridgeConfig {
+            bind_addrs: vec!["127.0.0.1:0".parse().expect("loopback address")],
+            allowed_origins: vec![ALLOWED_ORIGIN.into()],
+            expected_subprotocol: SUBPROTOCOL.into(),
+            bridge_id: TEST_BRIDGE_ID.into(),
+            ..BridgeConfig::default()
+        };
+        configure(&mut config);
+
+        let handle = serve(config, agent.clone()).await.expect("bridge start");
+        let addr = handle.local_addr().expect("tcp listener");
+
+        Self {
+            handle,
+            addr,
+            _agent: agent,
+        }
+    }
+
+    async fn connect(
+        &self,
+        origin: &str,
+        subprotocol: Option<&str>,
+    ) -> Result<(WsStream, Response<Option<Vec<u8>>>), tungstenite::Error> {
+        self.connect_to(self.addr, origin, subprotocol).await
+    }
+
+    async fn connect_to(
+        &self,
+        addr: SocketAddr,
+        origin: &str,
+        subprotocol: Option<&str>,
+    ) -> Result<(WsStream, Response<Option<Vec<u8>>>), tungstenite::Error> {
+        self.connect_with_origin(addr, Some(origin), subprotocol)
+            .await
+    }
+
+    async fn connect_with_origin(
+        &self,
+        addr: SocketAddr,
+        origin: Option<&str>,
+        subprotocol: Option<&str>,
+    ) -> Result<(WsStream, Response<Option<Vec<u8>>>), tungstenite::Error> {
+        let url = format!("ws://{addr}/");
+        let mut request = url.into_client_request()?;
+        if let Some(origin) = origin {
+            request
+                .headers_mut()
+                .insert(ORIGIN, HeaderValue::from_str(origin).expect("valid origin"));
+        }
+        if let Some(proto) = subprotocol {
+            request.headers_mut().insert(
+                SEC_WEBSOCKET_PROTOCOL,
+                HeaderValue::from_str(proto).expect("valid subprotocol"),
+            );
+        }
+
+        async_tungstenite::tokio::connect_async(request).await
+    }
+
+    async fn connect_initialized(&self) -> WsStream {
+        let (mut ws, _) = self
+            .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
+            .await
+            .expect("handshake should succeed");
+        send_initialize_request(&mut ws).await;
+        let _init_response = next_message(&mut ws).await;
+        ws
+    }
+
+    fn metrics(&self) -> BridgeMetricsSnapshot {
+        self.handle.metrics()
+    }
+
+    async fn shutdown(self) {
+        let _ = self.handle.shutdown().await;
+    }
+}
+
+// Sends a request and returns the response carrying the same id, skipping any
+// notifications that arrive in between.
+async fn call(ws: &mut WsStream, id: &str, method: &str, params: Value) -> Value {
+    send_json_rpc(
+        ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        }),
+    )
+    .await;
+
+    loop {
+        let payload = parse_json(&next_message(ws).await);
+        if payload.get("id") == Some(&json!(id)) {
+            return payload;
+        }
+    }
+}
+
+async fn send_json_rpc<S>(stream: &mut S, value: Value)
+where
+    S: Sink<Message, Error = tungstenite::Error> + Unpin,
+{
+    let message = Message::Text(value.to_string());
+    stream
+        .send(message)
+        .await
+        .expect("sending JSON-RPC frame should succeed");
+}
+
+async fn next_message<S>(stream: &mut S) -> Message
+where
+    S: Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
+{
+    timeout(TEST_TIMEOUT, stream.next())
+        .await
+        .expect("websocket response timed out")
+        .expect("stream ended unexpectedly")
+        .expect("failed to receive message")
+}
+
+fn parse_json(message: &Message) -> Value {
+    match message {
+        Message::Text(text) => serde_json::from_str(text).expect("valid JSON text"),
+        Message::Binary(bytes) => serde_json::from_slice(bytes).expect("valid JSON binary frame"),
+        other => panic!("expected text/binary frame, got {other:?}"),
+    }
+}
+
+// Tests for fs/read_text_file capability per RAT-LWS-REQ-040
+// These tests will fail until fs/read_text_file is implemented
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn fs_read_text_file_basic_functionality() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start(agent.clone()).await;
+
+    let (mut ws, _) = harness
+        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
+        .await
+        .expect("handshake should succeed");
+
+    // Initialize first
+    send_initialize_request(&mut ws).await;
+    let _init_response = next_message(&mut ws).await;
+
+    // Test basic fs/read_text_file request
+    send_json_rpc(
+        &mut ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": "read-1",
+            "method": "fs/read_text_file",
+            "params": {
+                "path": "tests/fs_test_file.md"
+            }
+        }),
+    )
+    .await;
+
+    let message = next_message(&mut ws).await;
+    let payload = parse_json(&message);
+
+    assert_eq!(payload.get("id"), Some(&json!("read-1")));
+
+    // Verify we get the expected file content
+    let result = payload
+        .get("result")
+        .expect("fs/read_text_file should return success result when implemented");
+    assert!(
+        result.get("content").is_some(),
+        "result should contain file content"
+    );
+    let content = result
+        .get("content")
+        .unwrap()
+        .as_str()
+        .expect("content should be a string");
+    assert!(
+        content.contains("In the hush of dawn, love whispers soft as dew"),
+        "should contain first line of poem"
+    );
+    assert!(
+        content.contains("And in its gentle hold, true peace is found."),
+        "should contain last line of poem"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn fs_read_text_file_with_line_offset_and_limit() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start(agent.clone()).await;
+
+    let (mut ws, _) = harness
+        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
+        .await
+        .expect("handshake should succeed");
+
+    // Initialize first
+    send_initialize_request(&mut ws).await;
+    let _init_response = next_message(&mut ws).await;
+
+    // Test fs/read_text_file with line offset and limit per RAT-LWS-REQ-040
+    send_json_rpc(
+        &mut ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": "read-offset-1",
+            "method": "fs/read_text_file",
+            "params": {
+                "path": "tests/fs_test_file.md",
+                "line_offset": 5,
+                "line_limit": 10
+            }
+        }),
+    )
+    .await;
+
+    let message = next_message(&mut ws).await;
+    let payload = parse_json(&message);
+
+    assert_eq!(payload.get("id"), Some(&json!("read-offset-1")));
+
+    // Verify we get the limited file content
+    let result = payload
+        .get("result")
+        .expect("fs/read_text_file should return success result when implemented");
+    assert!(
+        result.get("content").is_some(),
+        "result should contain limited file content"
+    );
+    let content = result
+        .get("content")
+        .unwrap()
+        .as_str()
+        .expect("content should be a string");
+
+    // Verify that only the requested lines are returned (lines 5-14, 10 lines total)
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(lines.len(), 10, "should return exactly 10 lines");
+    assert!(
+        content.contains("Love is the fire that warms the coldest night"),
+        "should contain line 6 (offset from line 5)"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_read_text_file_enforces_project_root_sandbox() {
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start(agent.clone()).await;
+
+    let (mut ws, _) = harness
+        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
+        .await
+        .expect("handshake should succeed");
+
+    // Initialize first
+    send_initialize_request(&mut ws).await;
+    let _init_response = next_message(&mut ws).await;
+
+    // Test reading file outside project root - should be rejected per RAT-LWS-REQ-044
+    send_json_rpc(
+        &mut ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": "read-oob-1",
+            "method": "fs/read_text_file",
+            "params": {
+                "path": "/etc/passwd"
+            }
+        }),
+    )
+    .await;
+
+    let message = next_message(&mut ws).await;
+    let payload = parse_json(&message);
+
+    assert_eq!(payload.get("id"), Some(&json!("read-oob-1")));
+
+    // This should return an error for out-of-bounds access (not method not found)
+    let error = payload
+        .get("error")
+        .expect("should have error for out-of-bounds access");
+    let error_code = error
+        .get("code")
+        .and_then(|c| c.as_i64())
+        .expect("error should have numeric code");
+    // Should be permission denied (e.g., -32000) or similar, not method not found (-32601)
+    assert_ne!(
+        error_code, -32601,
+        "should be permission error, not method not found"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_read_text_file_uses_per_origin_project_roots() {
+    const WIDGET_ORIGIN: &str = "http://widget.localhost:5173";
+    let temp = TestTempDir::new("fs-origin-roots");
+    let widget_root = temp.path().join("widget");
+    fs::create_dir(&widget_root).expect("create widget root");
+    let secret = temp.path().join("secret.txt");
+    fs::write(&secret, "admin only").expect("write fixture");
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let workspace = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.allowed_origins.push(WIDGET_ORIGIN.into());
+        config.project_roots = vec![workspace];
+        config
+            .origin_project_roots
+            .insert(WIDGET_ORIGIN.into(), vec![widget_root]);
+    })
+    .await;
+
+    let mut admin = harness.connect_initialized().await;
+    let payload = call(
+        &mut admin,
+        "read-admin",
+        "fs/read_text_file",
+        json!({"path": secret}),
+    )
+    .await;
+    assert_eq!(
+        payload.get("result").and_then(|r| r.get("content")),
+        Some(&json!("admin only")),
+        "the default roots cover the workspace, got {payload:?}"
+    );
+
+    let (mut widget, _) = harness
+        .connect(WIDGET_ORIGIN, Some(SUBPROTOCOL))
+        .await
+        .expect("handshake should succeed");
+    send_initialize_request(&mut widget).await;
+    let _init_response = next_message(&mut widget).await;
+    let payload = call(
+        &mut widget,
+        "read-widget",
+        "fs/read_text_file",
+        json!({"path": secret}),
+    )
+    .await;
+    assert!(
+        payload.get("error").is_some(),
+        "widget origin is confined to its subdirectory, got {payload:?}"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn read_only_origin_policy_denies_writes_for_that_origin_only() {
+    const VIEWER_ORIGIN: &str = "http://viewer.localhost:5173";
+    let temp = TestTempDir::new("fs-write-read-only-origin");
+    let agent = Arc::new(FakePermissionAgentTransport::new(
+        success_initialize_response(),
+    ));
+    agent
+        .configure_permission_response(acp::RequestPermissionResponse {
+            outcome: acp::RequestPermissionOutcome::Selected {
+                option_id: acp::PermissionOptionId("allow_once".into()),
+            },
+            meta: None,
+        })
+        .await;
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.allowed_origins.push(VIEWER_ORIGIN.into());
+        config.project_roots = vec![root];
+        config.request_policy = Arc::new(ReadOnlyOrigins {
+            origins: vec![VIEWER_ORIGIN.into()],
+        });
+    })
+    .await;
+
+    let (mut viewer, _) = harness
+        .connect(VIEWER_ORIGIN, Some(SUBPROTOCOL))
+        .await
+        .expect("handshake should succeed");
+    send_initialize_request(&mut viewer).await;
+    let _init_response = next_message(&mut viewer).await;
+    let viewer_path = temp.path().join("viewer.txt");
+    let payload = call(
+        &mut viewer,
+        "write-viewer",
+        "fs/write_text_file",
+        json!({"sessionId": "viewer-session", "path": viewer_path, "content": "x"}),
+    )
+    .await;
+    let error = payload
+        .get("error")
+        .unwrap_or_else(|| panic!("read-only origin should be denied, got {payload:?}"));
+    assert_eq!(
+        error.get("data").and_then(|d| d.get("kind")),
+        Some(&json!("permission_denied"))
+    );
+    assert!(!viewer_path.exists());
+
+    let mut editor = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut editor).await;
+    let editor_path = temp.path().join("editor.txt");
+    let payload = call(
+        &mut editor,
+        "write-editor",
+        "fs/write_text_file",
+        json!({"sessionId": session_id, "path": editor_path, "content": "x"}),
+    )
+    .await;
+    assert!(
+        payload.get("result").is_some(),
+        "other origins keep write access, got {payload:?}"
+    );
+    assert_eq!(fs::read_to_string(&editor_path).expect("read write"), "x");
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn fs_read_text_file_expands_tilde_inside_sandbox() {
+    let temp = TestTempDir::new("fs-read-tilde");
+    fs::write(temp.path().join("notes.txt"), "from home").expect("write fixture");
+    let _home = EnvVarGuard::set_var("HOME", temp.path().to_string_lossy().to_string());
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(
+        &mut ws,
+        "read-tilde",
+        "fs/read_text_file",
+        json!({"path": "~/notes.txt"}),
+    )
+    .await;
+    assert_eq!(
+        payload.get("result").and_then(|r| r.get("content")),
+        Some(&json!("from home")),
+        "~ should expand to $HOME, got {payload:?}"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn fs_read_text_file_sandboxes_env_var_expansions() {
+    let temp = TestTempDir::new("fs-read-env-escape");
+    let _outside = EnvVarGuard::set_var("CT_BRIDGE_TEST_OUTSIDE", "/etc".to_string());
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    for (request_id, path) in [
+        ("read-env", "$CT_BRIDGE_TEST_OUTSIDE/passwd"),
+        ("read-env-braced", "${CT_BRIDGE_TEST_OUTSIDE}/passwd"),
+    ] {
+        let payload = call(
+            &mut ws,
+            request_id,
+            "fs/read_text_file",
+            json!({"path": path}),
+        )
+        .await;
+        let data = payload
+            .get("error")
+            .and_then(|e| e.get("data"))
+            .unwrap_or_else(|| panic!("{path} must not escape the sandbox, got {payload:?}"));
+        assert_eq!(
+            data.get("detail"),
+            Some(&json!("path outside project root"))
+        );
+        assert_eq!(data.get("kind"), Some(&json!("sandbox_violation")));
+        assert_eq!(data.get("systemPath"), Some(&json!(true)));
+    }
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_read_text_file_keeps_unset_variables_literal() {
+    let temp = TestTempDir::new("fs-read-literal-dollar");
+    fs::write(temp.path().join("Outer$Inner.class"), "nested class").expect("write fixture");
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(
+        &mut ws,
+        "read-dollar",
+        "fs/read_text_file",
+        json!({"path": "Outer$Inner.class"}),
+    )
+    .await;
+    assert_eq!(
+        payload.get("result").and_then(|r| r.get("content")),
+        Some(&json!("nested class")),
+        "an unset $Inner is part of the file name, got {payload:?}"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_read_text_file_tags_system_paths_outside_root() {
+    let temp = TestTempDir::new("fs-read-outside-root");
+    let project = temp.path().join("project");
+    let elsewhere = temp.path().join("elsewhere.txt");
+    fs::create_dir_all(&project).expect("create project root");
+    fs::write(&elsewhere, "not in the project").expect("write fixture");
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let root = project.clone();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    // RAT-LWS-REQ-044: containment rejects the read, with a system-path hint.
+    let payload = call(
+        &mut ws,
+        "read-passwd",
+        "fs/read_text_file",
+        json!({"path": "/etc/passwd"}),
+    )
+    .await;
+    let data = payload
+        .get("error")
+        .and_then(|e| e.get("data"))
+        .unwrap_or_else(|| panic!("expected sandbox error, got {payload:?}"));
+    assert_eq!(
+        data.get("detail"),
+        Some(&json!("path outside project root"))
+    );
+    assert_eq!(data.get("kind"), Some(&json!("sandbox_violation")));
+    assert_eq!(data.get("systemPath"), Some(&json!(true)));
+
+    // Traversal out of the root is caught even though the target exists.
+    let payload = call(
+        &mut ws,
+        "read-elsewhere",
+        "fs/read_text_file",
+        json!({"path": "../elsewhere.txt"}),
+    )
+    .await;
+    let data = payload
+        .get("error")
+        .and_then(|e| e.get("data"))
+        .unwrap_or_else(|| panic!("expected sandbox error, got {payload:?}"));
+    assert_eq!(
+        data.get("detail"),
+        Some(&json!("path outside project root"))
+    );
+    assert_eq!(data.get("kind"), Some(&json!("sandbox_violation")));
+    assert_eq!(data.get("systemPath"), Some(&json!(false)));
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_sandbox_info_reports_configured_roots() {
+    let temp = TestTempDir::new("fs-sandbox-info");
+    let first = temp.path().join("first");
+    let second = temp.path().join("second");
+    fs::create_dir_all(&first).expect("create first root");
+    fs::create_dir_all(&second).expect("create second root");
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let roots = vec![first.clone(), second.join("..").join("second")];
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_roots = roots;
+        config.follow_symlinks = true;
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(&mut ws, "sandbox-info", "fs/sandbox_info", json!({})).await;
+    let result = payload
+        .get("result")
+        .unwrap_or_else(|| panic!("sandbox_info should succeed, got {payload:?}"));
+
+    let expected: Vec<String> = [&first, &second]
+        .iter()
+        .map(|root| {
+            root.canonicalize()
+                .expect("canonical root")
+                .to_string_lossy()
+                .to_string()
+        })
+        .collect();
+    assert_eq!(result.get("projectRoots"), Some(&json!(expected)));
+    assert_eq!(
+        result.get("pathMode").and_then(|m| m.get("relativeBase")),
+        Some(&json!(expected[0]))
+    );
+    assert_eq!(
+        result
+            .get("symlinks")
+            .and_then(|m| m.get("followedInWalks")),
+        Some(&json!(true))
+    );
+
+    harness.shutdown().await;
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_stat_file_id_survives_rename() {
+    let temp = TestTempDir::new("fs-stat-file-id");
+    let original = temp.path().join("before.txt");
+    let renamed = temp.path().join("after.txt");
+    fs::write(&original, "same file").expect("write fixture");
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let before = call(&mut ws, "stat-1", "fs/stat", json!({"path": original})).await;
+    let before = before
+        .get("result")
+        .unwrap_or_else(|| panic!("stat should succeed, got {before:?}"));
+    assert_eq!(before.get("isFile"), Some(&json!(true)));
+    assert_eq!(before.get("size"), Some(&json!(9)));
+    let file_id = before.get("fileId").cloned().expect("fileId present");
+    assert!(file_id.is_string(), "fileId should be set on unix");
+
+    fs::rename(&original, &renamed).expect("rename fixture");
+
+    let after = call(&mut ws, "stat-2", "fs/stat", json!({"path": renamed})).await;
+    assert_eq!(
+        after.get("result").and_then(|r| r.get("fileId")),
+        Some(&file_id),
+        "a rename must not change the file id"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_exists_reports_an_existing_file() {
+    let temp = TestTempDir::new("fs-exists-file");
+    let file = temp.path().join("present.txt");
+    fs::write(&file, "contents").expect("write fixture");
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let response = call(&mut ws, "exists-1", "fs/exists", json!({"path": file})).await;
+    assert_eq!(
+        response.get("result"),
+        Some(&json!({"exists": true, "isFile": true, "isDir": false})),
+        "got {response:?}"
+    );
+    let response = call(&mut ws, "exists-2", "fs/exists", json!({"path": "bin"})).await;
+    assert_eq!(
+        response.get("result"),
+        Some(&json!({"exists": true, "isFile": false, "isDir": true})),
+        "got {response:?}"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_exists_reports_a_missing_path_without_error() {
+    let temp = TestTempDir::new("fs-exists-missing");
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let response = call(
+        &mut ws,
+        "exists-1",
+        "fs/exists",
+        json!({"path": "nested/missing.txt"}),
+    )
+    .await;
+    assert_eq!(
+        response.get("result"),
+        Some(&json!({"exists": false, "isFile": false, "isDir": false})),
+        "got {response:?}"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_exists_rejects_paths_outside_the_sandbox() {
+    let temp = TestTempDir::new("fs-exists-escape");
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    // Whether or not these exist, the answer must not reveal it.
+    for (id, path) in [("escape-1", "../"), ("escape-2", "../no-such-sibling")] {
+        let response = call(&mut ws, id, "fs/exists", json!({"path": path})).await;
+        assert!(response.get("result").is_none(), "got {response:?}");
+        assert_eq!(
+            response
+                .get("error")
+                .and_then(|e| e.get("data"))
+                .and_then(|d| d.get("kind")),
+            Some(&json!("sandbox_violation")),
+            "got {response:?}"
+        );
+    }
+
+    harness.shutdown().await;
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_write_text_file_checks_where_a_dangling_symlink_points() {
+    use std::os::unix::fs::symlink;
+
+    let temp = TestTempDir::new("fs-write-dangling-symlink");
+    let root = temp.path().join("project");
+    let outside = temp.path().join("outside");
+    fs::create_dir_all(&root).expect("create project root");
+    fs::create_dir_all(&outside).expect("create outside dir");
+    symlink(outside.join("pwned.txt"), root.join("link.txt")).expect("escaping link");
+    symlink(root.join("target.txt"), root.join("inner-link.txt")).expect("in-root link");
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let project = root.clone();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_roots = vec![project];
+        config.require_session_for_write = false;
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(
+        &mut ws,
+        "write-escape",
+        "fs/write_text_file",
+        json!({"path": root.join("link.txt"), "content": "payload"}),
+    )
+    .await;
+    assert_eq!(
+        payload.pointer("/error/data/kind"),
+        Some(&json!("sandbox_violation")),
+        "got {payload:?}"
+    );
+    assert!(!outside.join("pwned.txt").exists());
+
+    let payload = call(
+        &mut ws,
+        "write-inside",
+        "fs/write_text_file",
+        json!({"path": root.join("inner-link.txt"), "content": "kept"}),
+    )
+    .await;
+    assert_eq!(payload.pointer("/result/written"), Some(&json!(true)));
+    assert_eq!(
+        fs::read_to_string(root.join("target.txt")).expect("link target"),
+        "kept"
+    );
+
+    harness.shutdown().await;
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_exists_rejects_missing_paths_behind_escaping_symlinks() {
+    use std::os::unix::fs::symlink;
+
+    let temp = TestTempDir::new("fs-exists-symlink-escape");
+    let root = temp.path().join("project");
+    let outside = temp.path().join("outside");
+    fs::create_dir_all(root.join("inner")).expect("create project root");
+    fs::create_dir_all(&outside).expect("create outside dir");
+    symlink(&outside, root.join("link")).expect("link to outside dir");
+    symlink(outside.join("gone"), root.join("dangling")).expect("dangling link");
+    symlink(root.join("inner"), root.join("inner-link")).expect("in-root link");
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let project = root.clone();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_roots = vec![project];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    for (id, path) in [
+        ("escape-1", "link/missing"),
+        ("escape-2", "dangling"),
+        ("escape-3", "dangling/deeper"),
+    ] {
+        let response = call(&mut ws, id, "fs/exists", json!({"path": path})).await;
+        assert_eq!(
+            response.pointer("/error/data/kind"),
+            Some(&json!("sandbox_violation")),
+            "{path} resolves outside the root, got {response:?}"
+        );
+    }
+
+    let response = call(
+        &mut ws,
+        "inside",
+        "fs/exists",
+        json!({"path": "inner-link/missing"}),
+    )
+    .await;
+    assert_eq!(
+        response.get("result"),
+        Some(&json!({"exists": false, "isFile": false, "isDir": false})),
+        "got {response:?}"
+    );
+
+    harness.shutdown().await;
+}
+
+async fn new_session_id(ws: &mut WsStream) -> String {
+    send_session_new_request(ws).await;
+    let payload = parse_json(&next_message(ws).await);
+    payload
+        .get("result")
+        .and_then(|r| r.get("sessionId"))
+        .and_then(|s| s.as_str())
+        .expect("should have sessionId")
+        .to_string()
+}
+
+fn allow_once_response() -> acp::RequestPermissionResponse {
+    acp::RequestPermissionResponse {
+        outcome: acp::RequestPermissionOutcome::Selected {
+            option_id: acp::PermissionOptionId("allow_once".into()),
+        },
+        meta: None,
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_write_text_file_presents_and_honors_custom_permission_options() {
+    let temp = TestTempDir::new("fs-write-custom-options");
+    let file_path = temp.path().join("out.txt");
+
+    let agent = Arc::new(FakePermissionAgentTransport::new(
+        success_initialize_response(),
+    ));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent.clone(), move |config| {
+        config.project_roots = vec![root];
+        config.permission_options = PermissionOptions {
+            options: vec![
+                acp::PermissionOption {
+                    id: acp::PermissionOptionId("permitir".into()),
+                    name: "Permitir".to_string(),
+                    kind: acp::PermissionOptionKind::AllowAlways,
+                    meta: None,
+                },
+                acp::PermissionOption {
+                    id: acp::PermissionOptionId("rechazar".into()),
+                    name: "Rechazar".to_string(),
+                    kind: acp::PermissionOptionKind::RejectOnce,
+                    meta: None,
+                },
+            ],
+        };
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    agent
+        .configure_permission_response(acp::RequestPermissionResponse {
+            outcome: acp::RequestPermissionOutcome::Selected {
+                option_id: acp::PermissionOptionId("permitir".into()),
+            },
+            meta: None,
+        })
+        .await;
+    for index in 0..2 {
+        let payload = call(
+            &mut ws,
+            &format!("custom-write-{index}"),
+            "fs/write_text_file",
+            json!({
+                "sessionId": session_id,
+                "path": file_path.to_string_lossy(),
+                "content": format!("take {index}"),
+            }),
+        )
+        .await;
+        assert!(
+            payload.get("result").is_some(),
+            "custom allow option should permit the write, got {payload:?}"
+        );
+    }
+    assert_eq!(
+        fs::read_to_string(&file_path).expect("read written file"),
+        "take 1"
+    );
+
+    // The second write rode on the cached allow-always decision.
+    let permission_calls = agent.take_permission_calls().await;
+    assert_eq!(permission_calls.len(), 1);
+    let presented: Vec<(&str, &str)> = permission_calls[0]
+        .options
+        .iter()
+        .map(|option| (option.id.0.as_ref(), option.name.as_str()))
+        .collect();
+    assert_eq!(
+        presented,
+        [("permitir", "Permitir"), ("rechazar", "Rechazar")]
+    );
+    assert_eq!(harness.metrics().permission_grants, 1);
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_write_text_file_rejects_content_over_max_write_bytes() {
+    let temp = TestTempDir::new("fs-write-max-bytes");
+    let file_path = temp.path().join("out.txt");
+    let agent = Arc::new(FakePermissionAgentTransport::new(
+        success_initialize_response(),
+    ));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent.clone(), move |config| {
+        config.project_roots = vec![root];
+        config.max_write_bytes = Some(4);
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    let payload = call(
+        &mut ws,
+        "write-oversized",
+        "fs/write_text_file",
+        json!({"sessionId": session_id, "path": file_path, "content": "too long"}),
+    )
+    .await;
+    let error = payload
+        .get("error")
+        .unwrap_or_else(|| panic!("oversized content should be rejected, got {payload:?}"));
+    assert_eq!(error.get("code"), Some(&json!(-32602)));
+    assert_eq!(
+        error.get("data").and_then(|d| d.get("kind")),
+        Some(&json!("too_large"))
+    );
+    assert!(
+        agent.take_permission_calls().await.is_empty(),
+        "the size check happens before any permission prompt"
+    );
+    assert!(!file_path.exists());
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_write_text_file_atomic_writes_leave_no_partial_files() {
+    let temp = TestTempDir::new("fs-write-atomic");
+    let workspace = temp.path().join("workspace");
+    fs::create_dir(&workspace).expect("create workspace");
+    let file_path = workspace.join("notes.txt");
+    fs::write(&file_path, "old").expect("write fixture");
+    let blocked_path = workspace.join("blocked");
+
+    let agent = Arc::new(FakePermissionAgentTransport::new(
+        success_initialize_response(),
+    ));
+    agent
+        .configure_permission_response(acp::RequestPermissionResponse {
+            outcome: acp::RequestPermissionOutcome::Selected {
+                option_id: acp::PermissionOptionId("allow_once".into()),
+            },
+            meta: None,
+        })
+        .await;
+    let root = workspace.clone();
+    let harness = BridgeHarness::start_with(agent.clone(), move |config| {
+        config.project_roots = vec![root];
+        config.atomic_writes = true;
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    let payload = call(
+        &mut ws,
+        "write-atomic",
+        "fs/write_text_file",
+        json!({"sessionId": session_id, "path": file_path, "content": "new"}),
+    )
+    .await;
+    assert!(
+        payload.get("result").is_some(),
+        "atomic write should succeed, got {payload:?}"
+    );
+    assert_eq!(fs::read_to_string(&file_path).expect("read file"), "new");
+
+    let payload = call(
+        &mut ws,
+        "write-atomic-append",
+        "fs/write_text_file",
+        json!({"sessionId": session_id, "path": file_path, "content": "er", "mode": "append"}),
+    )
+    .await;
+    assert!(
+        payload.get("result").is_some(),
+        "atomic append should succeed, got {payload:?}"
+    );
+    assert_eq!(fs::read_to_string(&file_path).expect("read file"), "newer");
+
+    // A non-empty directory cannot be replaced by a rename. It appears only
+    // while the prompt is open, after directory targets have been turned
+    // away, so this write fails at the last step.
+    agent
+        .delay_permission_responses(Duration::from_millis(200))
+        .await;
+    agent.take_permission_calls().await;
+    send_json_rpc(
+        &mut ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": "write-atomic-blocked",
+            "method": "fs/write_text_file",
+            "params": {"sessionId": session_id, "path": blocked_path, "content": "partial"},
+        }),
+    )
+    .await;
+    agent.wait_for_permission_call().await;
+    fs::create_dir(&blocked_path).expect("create blocking directory");
+    fs::write(blocked_path.join("keep.txt"), "kept").expect("write blocking file");
+    let payload = parse_json(&next_message(&mut ws).await);
+    assert_eq!(
+        payload
+            .get("error")
+            .and_then(|e| e.get("data"))
+            .and_then(|d| d.get("kind")),
+        Some(&json!("io")),
+        "the rename should fail, got {payload:?}"
+    );
+    assert_eq!(
+        fs::read_to_string(blocked_path.join("keep.txt")).expect("read blocking file"),
+        "kept"
+    );
+
+    let mut entries: Vec<String> = fs::read_dir(&workspace)
+        .expect("list workspace")
+        .map(|entry| {
+            entry
+                .expect("workspace entry")
+                .file_name()
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect();
+    entries.sort();
+    assert_eq!(
+        entries,
+        ["blocked", "notes.txt"],
+        "no staging files should be left behind"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_write_text_file_persists_standing_decisions_across_restarts() {
+    let temp = TestTempDir::new("fs-write-permission-store");
+    let store_path = temp.path().join("permissions.json");
+    let file_path = temp.path().join("out.txt");
+
+    let agent = Arc::new(FakePermissionAgentTransport::new(
+        success_initialize_response(),
+    ));
+    let start = |agent: Arc<FakePermissionAgentTransport>| {
+        let root = temp.path().to_path_buf();
+        let store_path = store_path.clone();
+        BridgeHarness::start_with(agent, move |config| {
+            config.project_roots = vec![root];
+            config.permission_store = Some(Arc::new(JsonFilePermissionStore::new(store_path)));
+        })
+    };
+    let write = |session_id: &str, index: usize| {
+        json!({
+            "sessionId": session_id,
+            "path": file_path.to_string_lossy(),
+            "content": format!("take {index}"),
+        })
+    };
+
+    let harness = start(agent.clone()).await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+    agent
+        .configure_permission_response(acp::RequestPermissionResponse {
+            outcome: acp::RequestPermissionOutcome::Selected {
+                option_id: acp::PermissionOptionId("allow_always".into()),
+            },
+            meta: None,
+        })
+        .await;
+    let payload = call(
+        &mut ws,
+        "write-0",
+        "fs/write_text_file",
+        write(&session_id, 0),
+    )
+    .await;
+    assert!(payload.get("result").is_some(), "got {payload:?}");
+    assert_eq!(agent.take_permission_calls().await.len(), 1);
+    harness.shutdown().await;
+
+    // A fresh bridge over the same file starts with the decision loaded.
+    let harness = start(agent.clone()).await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+    let payload = call(
+        &mut ws,
+        "write-1",
+        "fs/write_text_file",
+        write(&session_id, 1),
+    )
+    .await;
+    assert!(payload.get("result").is_some(), "got {payload:?}");
+    assert!(
+        agent.take_permission_calls().await.is_empty(),
+        "persisted allow_always should skip the prompt"
+    );
+    assert_eq!(fs::read_to_string(&file_path).expect("read"), "take 1");
+    harness.shutdown().await;
+
+    fs::write(&store_path, "{ not json").expect("corrupt store");
+    assert!(JsonFilePermissionStore::new(&store_path).load().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bridge_reset_permission_forgets_reject_always() {
+    let temp = TestTempDir::new("fs-write-reset-permission");
+    let file_path = temp.path().join("out.txt");
+
+    let agent = Arc::new(FakePermissionAgentTransport::new(
+        success_initialize_response(),
+    ));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent.clone(), move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+    let write = json!({
+        "sessionId": session_id,
+        "path": file_path.to_string_lossy(),
+        "content": "x",
+    });
+
+    agent
+        .configure_permission_response(acp::RequestPermissionResponse {
+            outcome: acp::RequestPermissionOutcome::Selected {
+                option_id: acp::PermissionOptionId("reject_always".into()),
+            },
+            meta: None,
+        })
+        .await;
+    for index in 0..2 {
+        let payload = call(
+            &mut ws,
+            &format!("rejected-{index}"),
+            "fs/write_text_file",
+            write.clone(),
+        )
+        .await;
+        assert!(payload.get("error").is_some(), "got {payload:?}");
+    }
+    assert_eq!(
+        agent.take_permission_calls().await.len(),
+        1,
+        "reject_always is cached after the first prompt"
+    );
+
+    // Relative and absolute spellings resolve to the same cache entry.
+    let payload = call(
+        &mut ws,
+        "reset",
+        "bridge/reset_permission",
+        json!({"path": "out.txt"}),
+    )
+    .await;
+    assert_eq!(
+        payload.get("result"),
+        Some(&json!({"removed": 1})),
+        "got {payload:?}"
+    );
+
+    agent
+        .configure_permission_response(allow_once_response())
+        .await;
+    let payload = call(&mut ws, "retry", "fs/write_text_file", write).await;
+    assert!(payload.get("result").is_some(), "got {payload:?}");
+    assert_eq!(
+        agent.take_permission_calls().await.len(),
+        1,
+        "the retry should prompt afresh"
+    );
+
+    let payload = call(
+        &mut ws,
+        "reset-all",
+        "bridge/reset_permission",
+        json!({"all": true}),
+    )
+    .await;
+    assert_eq!(payload.get("result"), Some(&json!({"removed": 0})));
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_write_text_file_reports_permission_denial_kind() {
+    let temp = TestTempDir::new("fs-write-denied-kind");
+    let file_path = temp.path().join("out.txt");
+
+    let agent = Arc::new(FakePermissionAgentTransport::new(
+        success_initialize_response(),
+    ));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent.clone(), move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    agent
+        .configure_permission_response(acp::RequestPermissionResponse {
+            outcome: acp::RequestPermissionOutcome::Selected {
+                option_id: acp::PermissionOptionId("reject_once".into()),
+            },
+            meta: None,
+        })
+        .await;
+    let payload = call(
+        &mut ws,
+        "denied",
+        "fs/write_text_file",
+        json!({
+            "sessionId": session_id,
+            "path": file_path.to_string_lossy(),
+            "content": "x",
+        }),
+    )
+    .await;
+    let error = payload
+        .get("error")
+        .unwrap_or_else(|| panic!("rejected write should fail, got {payload:?}"));
+    assert_eq!(error.get("code"), Some(&json!(-32010)));
+    assert_eq!(
+        error.get("data").and_then(|d| d.get("kind")),
+        Some(&json!("permission_denied"))
+    );
+    assert!(!file_path.exists());
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_write_text_file_permission_codes_follow_configured_base() {
+    let temp = TestTempDir::new("fs-write-code-base");
+    let agent = Arc::new(FakePermissionAgentTransport::new(
+        success_initialize_response(),
+    ));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent.clone(), move |config| {
+        config.project_roots = vec![root];
+        config.permission_error_code_base = -32100;
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    let outcomes = [
+        (
+            acp::RequestPermissionOutcome::Selected {
+                option_id: acp::PermissionOptionId("reject_once".into()),
+            },
+            -32100,
+        ),
+        (acp::RequestPermissionOutcome::Cancelled, -32102),
+        (
+            acp::RequestPermissionOutcome::Selected {
+                option_id: acp::PermissionOptionId("reject_always".into()),
+            },
+            -32101,
+        ),
+    ];
+    for (index, (outcome, expected_code)) in outcomes.into_iter().enumerate() {
+        agent
+            .configure_permission_response(acp::RequestPermissionResponse {
+                outcome,
+                meta: None,
+            })
+            .await;
+        let payload = call(
+            &mut ws,
+            &format!("write-{index}"),
+            "fs/write_text_file",
+            json!({
+                "sessionId": session_id,
+                "path": "out.txt",
+                "content": "x",
+            }),
+        )
+        .await;
+        assert_eq!(
+            payload.get("error").and_then(|e| e.get("code")),
+            Some(&json!(expected_code)),
+            "got {payload:?}"
+        );
+    }
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_write_text_file_appends_in_append_mode() {
+    let temp = TestTempDir::new("fs-write-append");
+    let file_path = temp.path().join("chunks.txt");
+    fs::write(&file_path, "first\n").expect("write initial chunk");
+
+    let agent = Arc::new(FakePermissionAgentTransport::new(
+        success_initialize_response(),
+    ));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent.clone(), move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    agent
+        .configure_permission_response(allow_once_response())
+        .await;
+    let payload = call(
+        &mut ws,
+        "append-1",
+        "fs/write_text_file",
+        json!({
+            "sessionId": session_id,
+            "path": file_path.to_string_lossy(),
+            "content": "second\n",
+            "mode": "append",
+        }),
+    )
+    .await;
+    assert!(
+        payload.get("result").is_some(),
+        "append should succeed, got {payload:?}"
+    );
+
+    assert_eq!(
+        fs::read_to_string(&file_path).expect("read appended file"),
+        "first\nsecond\n"
+    );
+    let permission_calls = agent.take_permission_calls().await;
+    assert_eq!(permission_calls.len(), 1);
+    assert!(
+        permission_calls[0]
+            .tool_call
+            .fields
+            .title
+            .as_deref()
+            .is_some_and(|title| title.starts_with("Append to file")),
+        "permission prompt should mention appending"
+    );
+
+    let payload = call(
+        &mut ws,
+        "append-bad-mode",
+        "fs/write_text_file",
+        json!({
+            "sessionId": session_id,
+            "path": file_path.to_string_lossy(),
+            "content": "third\n",
+            "mode": "truncate",
+        }),
+    )
+    .await;
+    assert_eq!(
+        payload.get("error").and_then(|e| e.get("code")),
+        Some(&json!(-32602))
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_write_text_file_overwrites_by_default_and_scopes_cache_by_mode() {
+    let temp = TestTempDir::new("fs-write-overwrite");
+    let file_path = temp.path().join("target.txt");
+    fs::write(&file_path, "original contents").expect("write initial file");
+
+    let agent = Arc::new(FakePermissionAgentTransport::new(
+        success_initialize_response(),
+    ));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent.clone(), move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    // Approve appends permanently; this must not cover overwrites.
+    agent
+        .configure_permission_response(acp::RequestPermissionResponse {
+            outcome: acp::RequestPermissionOutcome::Selected {
+                option_id: acp::PermissionOptionId("allow_always".into()),
+            },
+            meta: None,
+        })
+        .await;
+    let payload = call(
+        &mut ws,
+        "append-always",
+        "fs/write_text_file",
+        json!({
+            "sessionId": session_id,
+            "path": file_path.to_string_lossy(),
+            "content": "!",
+            "mode": "append",
+        }),
+    )
+    .await;
+    assert!(payload.get("result").is_some(), "append should succeed");
+    assert_eq!(agent.take_permission_calls().await.len(), 1);
+
+    agent
+        .configure_permission_response(allow_once_response())
+        .await;
+    let payload = call(
+        &mut ws,
+        "overwrite-default",
+        "fs/write_text_file",
+        json!({
+            "sessionId": session_id,
+            "path": file_path.to_string_lossy(),
+            "content": "replaced",
+        }),
+    )
+    .await;
+    assert!(payload.get("result").is_some(), "overwrite should succeed");
+    assert_eq!(
+        agent.take_permission_calls().await.len(),
+        1,
+        "append approval must not authorize an overwrite"
+    );
+    assert_eq!(
+        fs::read_to_string(&file_path).expect("read overwritten file"),
+        "replaced"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_write_text_file_reports_bytes_written_and_canonical_path() {
+    let temp = TestTempDir::new("fs-write-outcome");
+    let agent = Arc::new(FakePermissionAgentTransport::new(
+        success_initialize_response(),
+    ));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent.clone(), move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    agent
+        .configure_permission_response(allow_once_response())
+        .await;
+    let content = "héllo, wörld\n";
+    let payload = call(
+        &mut ws,
+        "write-outcome",
+        "fs/write_text_file",
+        json!({
+            "sessionId": session_id,
+            "path": "out.txt",
+            "content": content,
+        }),
+    )
+    .await;
+    let result = payload
+        .get("result")
+        .unwrap_or_else(|| panic!("write should succeed, got {payload:?}"));
+
+    assert_eq!(result.get("bytesWritten"), Some(&json!(content.len())));
+    let canonical_path = PathBuf::from(
+        result
+            .get("canonicalPath")
+            .and_then(|p| p.as_str())
+            .expect("canonicalPath should be a string"),
+    );
+    let canonical_root = temp.path().canonicalize().expect("canonical temp root");
+    assert!(canonical_path.is_absolute());
+    assert!(
+        canonical_path.starts_with(&canonical_root),
+        "{canonical_path:?} should live under {canonical_root:?}"
+    );
+    assert_eq!(canonical_path, canonical_root.join("out.txt"));
+    assert_eq!(
+        fs::read_to_string(&canonical_path).expect("read written file"),
+        content
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_write_text_file_skips_identical_content_when_requested() {
+    let temp = TestTempDir::new("fs-write-unchanged");
+    let file_path = temp.path().join("stable.txt");
+    fs::write(&file_path, "unchanged contents\n").expect("write fixture");
+    let old_mtime = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    set_mtime(&file_path, old_mtime);
+
+    // No permission response is configured, so reaching the prompt would fail.
+    let agent = Arc::new(FakePermissionAgentTransport::new(
+        success_initialize_response(),
+    ));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent.clone(), move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    let payload = call(
+        &mut ws,
+        "write-unchanged",
+        "fs/write_text_file",
+        json!({
+            "sessionId": session_id,
+            "path": file_path.to_string_lossy(),
+            "content": "unchanged contents\n",
+            "skip_if_unchanged": true,
+        }),
+    )
+    .await;
+    let result = payload
+        .get("result")
+        .unwrap_or_else(|| panic!("identical write should succeed, got {payload:?}"));
+    assert_eq!(result.get("written"), Some(&json!(false)));
+    assert_eq!(result.get("unchanged"), Some(&json!(true)));
+    assert!(agent.take_permission_calls().await.is_empty());
+    assert_eq!(
+        fs::metadata(&file_path)
+            .expect("stat fixture")
+            .modified()
+            .expect("mtime"),
+        old_mtime,
+        "skipped write must not bump the mtime"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn permission_request_batch_preapproves_every_listed_path() {
+    let temp = TestTempDir::new("permission-batch");
+    let agent = Arc::new(FakePermissionAgentTransport::new(
+        success_initialize_response(),
+    ));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent.clone(), move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    agent
+        .configure_permission_response(acp::RequestPermissionResponse {
+            outcome: acp::RequestPermissionOutcome::Selected {
+                option_id: acp::PermissionOptionId("allow_always".into()),
+            },
+            meta: None,
+        })
+        .await;
+    let paths = ["a.rs", "b.rs", "c.rs"];
+    let payload = call(
+        &mut ws,
+        "batch-1",
+        "permission/request_batch",
+        json!({"sessionId": session_id, "paths": paths}),
+    )
+    .await;
+    let result = payload
+        .get("result")
+        .unwrap_or_else(|| panic!("batch request should succeed, got {payload:?}"));
+    assert_eq!(result.get("decision"), Some(&json!("allow")));
+
+    let permission_calls = agent.take_permission_calls().await;
+    assert_eq!(permission_calls.len(), 1, "one prompt covers the batch");
+    assert_eq!(
+        permission_calls[0]
+            .tool_call
+            .fields
+            .locations
+            .as_ref()
+            .map(Vec::len),
+        Some(paths.len())
+    );
+
+    for (index, path) in paths.iter().enumerate() {
+        let payload = call(
+            &mut ws,
+            &format!("batch-write-{index}"),
+            "fs/write_text_file",
+            json!({"sessionId": session_id, "path": path, "content": "fn main() {}\n"}),
+        )
+        .await;
+        assert!(
+            payload.get("result").is_some(),
+            "pre-approved write to {path} should succeed, got {payload:?}"
+        );
+    }
+    assert!(
+        agent.take_permission_calls().await.is_empty(),
+        "pre-approved writes must not prompt individually"
+    );
+    for path in paths {
+        assert!(temp.path().join(path).exists(), "{path} should be written");
+    }
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn permission_request_batch_offers_the_configured_standing_options() {
+    let temp = TestTempDir::new("permission-batch-options");
+    let agent = Arc::new(FakePermissionAgentTransport::new(
+        success_initialize_response(),
+    ));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent.clone(), move |config| {
+        config.project_roots = vec![root];
+        let option = |id: &str, kind| acp::PermissionOption {
+            id: acp::PermissionOptionId(id.into()),
+            name: id.to_string(),
+            kind,
+            meta: None,
+        };
+        config.permission_options = PermissionOptions {
+            options: vec![
+                option("una-vez", acp::PermissionOptionKind::AllowOnce),
+                option("siempre", acp::PermissionOptionKind::AllowAlways),
+                option("nunca", acp::PermissionOptionKind::RejectAlways),
+            ],
+        };
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    agent
+        .configure_permission_response(acp::RequestPermissionResponse {
+            outcome: acp::RequestPermissionOutcome::Selected {
+                option_id: acp::PermissionOptionId("nunca".into()),
+            },
+            meta: None,
+        })
+        .await;
+    let payload = call(
+        &mut ws,
+        "batch-custom",
+        "permission/request_batch",
+        json!({"sessionId": session_id, "paths": ["a.rs", "b.rs"]}),
+    )
+    .await;
+    assert_eq!(
+        payload.pointer("/result/decision"),
+        Some(&json!("reject")),
+        "got {payload:?}"
+    );
+    let permission_calls = agent.take_permission_calls().await;
+    let offered: Vec<&str> = permission_calls[0]
+        .options
+        .iter()
+        .map(|option| option.id.0.as_ref())
+        .collect();
+    assert_eq!(offered, ["siempre", "nunca"]);
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_write_text_file_pending_permission_resolves_on_shutdown() {
+    let temp = TestTempDir::new("fs-write-shutdown");
+    let file_path = temp.path().join("never.txt");
+    let agent = Arc::new(FakePermissionAgentTransport::new(
+        success_initialize_response(),
+    ));
+    agent.hold_permission_responses().await;
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent.clone(), move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    send_json_rpc(
+        &mut ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": "write-during-shutdown",
+            "method": "fs/write_text_file",
+            "params": {
+                "sessionId": session_id,
+                "path": file_path.to_string_lossy(),
+                "content": "too late",
+            }
+        }),
+    )
+    .await;
+    agent.wait_for_permission_call().await;
+
+    harness.shutdown().await;
+
+    let payload = parse_json(&next_message(&mut ws).await);
+    assert_eq!(payload.get("id"), Some(&json!("write-during-shutdown")));
+    assert_eq!(
+        payload.get("error").and_then(|e| e.get("data")),
+        Some(&json!("server shutting down")),
+        "pending write should fail with the shutdown error, got {payload:?}"
+    );
+    assert!(!file_path.exists(), "no write may happen after shutdown");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_write_text_file_without_a_session_uses_the_sessionless_decision() {
+    let temp = TestTempDir::new("fs-write-sessionless");
+    let file_path = temp.path().join("notes.txt");
+    let agent = Arc::new(FakePermissionAgentTransport::new(
+        success_initialize_response(),
+    ));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent.clone(), move |config| {
+        config.project_roots = vec![root];
+        config.require_session_for_write = false;
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(
+        &mut ws,
+        "write-sessionless",
+        "fs/write_text_file",
+        json!({"path": file_path, "content": "trusted"}),
+    )
+    .await;
+    assert_eq!(
+        payload.pointer("/result/written"),
+        Some(&json!(true)),
+        "sessionless writes should be allowed, got {payload:?}"
+    );
+    assert_eq!(
+        fs::read_to_string(&file_path).expect("read written file"),
+        "trusted"
+    );
+    assert!(
+        agent.take_permission_calls().await.is_empty(),
+        "sessionless writes are never prompted"
+    );
+
+    // Outside the sandbox is still refused.
+    let payload = call(
+        &mut ws,
+        "write-sessionless-outside",
+        "fs/write_text_file",
+        json!({"path": "/etc/ct-bridge-sessionless.txt", "content": "nope"}),
+    )
+    .await;
+    assert_eq!(
+        payload.pointer("/error/data/kind"),
+        Some(&json!("sandbox_violation")),
+        "got {payload:?}"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_read_text_files_reports_each_path_on_its_own() {
+    let temp = TestTempDir::new("fs-read-batch");
+    let first = temp.path().join("first.txt");
+    let second = temp.path().join("second.txt");
+    fs::write(&first, "one\n").expect("write fixture");
+    fs::write(&second, "two").expect("write fixture");
+    let missing = temp.path().join("missing.txt");
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(
+        Arc::new(FakeAgentTransport::new(success_initialize_response())),
+        move |config| {
+            config.project_roots = vec![root];
+            config.max_read_batch = 4;
+        },
+    )
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(
+        &mut ws,
+        "read-batch",
+        "fs/read_text_files",
+        json!({"paths": [first, missing, "/etc/hostname", second]}),
+    )
+    .await;
+    let results = payload
+        .pointer("/result/results")
+        .and_then(Value::as_array)
+        .unwrap_or_else(|| panic!("expected per-path results, got {payload:?}"));
+    assert_eq!(results.len(), 4);
+    assert_eq!(results[0].get("path"), Some(&json!(first)));
+    assert_eq!(results[0].get("content"), Some(&json!("one\n")));
+    assert_eq!(results[0].get("endsWithNewline"), Some(&json!(true)));
+    assert_eq!(results[1].get("path"), Some(&json!(missing)));
+    assert_eq!(
+        results[1].pointer("/error/data/kind"),
+        Some(&json!("not_found"))
+    );
+    assert!(results[1].get("content").is_none());
+    assert_eq!(
+        results[2].pointer("/error/data/kind"),
+        Some(&json!("sandbox_violation"))
+    );
+    assert_eq!(results[3].get("content"), Some(&json!("two")));
+
+    let payload = call(
+        &mut ws,
+        "read-batch-too-big",
+        "fs/read_text_files",
+        json!({"paths": [first, first, first, first, first]}),
+    )
+    .await;
+    assert_eq!(payload.pointer("/error/code"), Some(&json!(-32602)));
+    assert_eq!(
+        payload.pointer("/error/data/kind"),
+        Some(&json!("too_large")),
+        "got {payload:?}"
+    );
+
+    let payload = call(
+        &mut ws,
+        "read-batch-invalid",
+        "fs/read_text_files",
+        json!({"paths": [first, 7]}),
+    )
+    .await;
+    assert_eq!(payload.pointer("/error/code"), Some(&json!(-32602)));
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_read_text_file_cache_holds_only_small_whole_file_reads() {
+    let temp = TestTempDir::new("fs-read-cache-scope");
+    let small = temp.path().join("small.txt");
+    let large = temp.path().join("large.txt");
+    fs::write(&small, "one\ntwo\n").expect("write fixture");
+    fs::write(&large, "a".repeat(2 << 20)).expect("write fixture");
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(
+        Arc::new(FakeAgentTransport::new(success_initialize_response())),
+        move |config| {
+            config.project_roots = vec![root];
+            config.read_cache_capacity = 4;
+        },
+    )
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    // Rewrites a file with same-size content and its old mtime, which a
+    // cached copy would hide.
+    let rewrite = |path: &Path, content: String| {
+        let modified = fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .expect("read mtime");
+        fs::write(path, content).expect("overwrite fixture");
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_modified(modified))
+            .expect("set mtime");
+    };
+
+    let payload = call(
+        &mut ws,
+        "ranged-1",
+        "fs/read_text_file",
+        json!({"path": small, "line_offset": 2, "line_limit": 1}),
+    )
+    .await;
+    assert_eq!(payload.pointer("/result/content"), Some(&json!("two")));
+    rewrite(&small, "one\nTWO\n".into());
+    let payload = call(
+        &mut ws,
+        "ranged-2",
+        "fs/read_text_file",
+        json!({"path": small, "line_offset": 2, "line_limit": 1}),
+    )
+    .await;
+    assert_eq!(
+        payload.pointer("/result/content"),
+        Some(&json!("TWO")),
+        "ranged reads should go to disk, got {payload:?}"
+    );
+
+    let payload = call(
+        &mut ws,
+        "large-1",
+        "fs/read_text_file",
+        json!({"path": large}),
+    )
+    .await;
+    assert!(
+        payload.pointer("/result/content").is_some(),
+        "got {payload:?}"
+    );
+    rewrite(&large, "b".repeat(2 << 20));
+    let payload = call(
+        &mut ws,
+        "large-2",
+        "fs/read_text_file",
+        json!({"path": large}),
+    )
+    .await;
+    let content = payload
+        .pointer("/result/content")
+        .and_then(Value::as_str)
+        .unwrap_or_else(|| panic!("got {payload:?}"));
+    assert!(
+        content.starts_with('b'),
+        "files over the cache cap should not be cached"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_read_text_file_cache_is_validated_by_mtime_and_invalidated_by_writes() {
+    let temp = TestTempDir::new("fs-read-cache");
+    let file_path = temp.path().join("cached.txt");
+    fs::write(&file_path, "aaaa").expect("write fixture");
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(
+        Arc::new(FakePermissionAgentTransport::new(
+            success_initialize_response(),
+        )),
+        move |config| {
+            config.project_roots = vec![root];
+            config.read_cache_capacity = 4;
+            config.require_session_for_write = false;
+        },
+    )
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let set_modified = |time: SystemTime| {
+        fs::File::options()
+            .write(true)
+            .open(&file_path)
+            .and_then(|file| file.set_modified(time))
+            .expect("set mtime");
+    };
+    let modified = || {
+        fs::metadata(&file_path)
+            .and_then(|metadata| metadata.modified())
+            .expect("read mtime")
+    };
+
+    let payload = call(
+        &mut ws,
+        "read-1",
+        "fs/read_text_file",
+        json!({"path": file_path}),
+    )
+    .await;
+    assert_eq!(payload.pointer("/result/content"), Some(&json!("aaaa")));
+
+    // Same size and mtime: served from the cache.
+    let original = modified();
+    fs::write(&file_path, "bbbb").expect("overwrite fixture");
+    set_modified(original);
+    let payload = call(
+        &mut ws,
+        "read-2",
+        "fs/read_text_file",
+        json!({"path": file_path}),
+    )
+    .await;
+    assert_eq!(
+        payload.pointer("/result/content"),
+        Some(&json!("aaaa")),
+        "unchanged mtime should hit the cache, got {payload:?}"
+    );
+
+    // A new mtime is a miss.
+    let later = original + Duration::from_secs(10);
+    fs::write(&file_path, "cccc").expect("overwrite fixture");
+    set_modified(later);
+    let payload = call(
+        &mut ws,
+        "read-3",
+        "fs/read_text_file",
+        json!({"path": file_path}),
+    )
+    .await;
+    assert_eq!(
+        payload.pointer("/result/content"),
+        Some(&json!("cccc")),
+        "a changed mtime should miss the cache, got {payload:?}"
+    );
+
+    // Writes through the bridge drop the entry even if the mtime is reused.
+    let payload = call(
+        &mut ws,
+        "write",
+        "fs/write_text_file",
+        json!({"path": file_path.clone(), "content": "dddd"}),
+    )
+    .await;
+    assert_eq!(payload.pointer("/result/written"), Some(&json!(true)));
+    set_modified(later);
+    let payload = call(
+        &mut ws,
+        "read-4",
+        "fs/read_text_file",
+        json!({"path": file_path}),
+    )
+    .await;
+    assert_eq!(
+        payload.pointer("/result/content"),
+        Some(&json!("dddd")),
+        "a bridge write should invalidate the cache, got {payload:?}"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_read_text_file_serves_registered_virtual_files() {
+    let harness = BridgeHarness::start_with(
+        Arc::new(FakePermissionAgentTransport::new(
+            success_initialize_response(),
+        )),
+        |config| config.require_session_for_write = false,
+    )
+    .await;
+    let files = harness.handle.virtual_files();
+    assert!(files.register("mem://buffer1", "one\ntwo\nthree\n"));
+    assert!(
+        !files.register("mem://../etc/passwd", "nope"),
+        "names outside the virtual namespace are refused"
+    );
+    assert!(!files.register("/tmp/buffer1", "nope"));
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(
+        &mut ws,
+        "read-virtual",
+        "fs/read_text_file",
+        json!({"path": "mem://buffer1", "line_offset": 2, "line_limit": 1}),
+    )
+    .await;
+    assert_eq!(
+        payload.pointer("/result/content"),
+        Some(&json!("two")),
+        "got {payload:?}"
+    );
+    assert_eq!(payload.pointer("/result/totalLines"), Some(&json!(3)));
+
+    let payload = call(
+        &mut ws,
+        "read-unknown",
+        "fs/read_text_file",
+        json!({"path": "mem://buffer2"}),
+    )
+    .await;
+    assert_eq!(
+        payload.pointer("/error/data/kind"),
+        Some(&json!("not_found")),
+        "got {payload:?}"
+    );
+
+    let payload = call(
+        &mut ws,
+        "write-virtual",
+        "fs/write_text_file",
+        json!({"path": "mem://buffer1", "content": "changed"}),
+    )
+    .await;
+    assert_eq!(
+        payload.pointer("/error/data/kind"),
+        Some(&json!("sandbox_violation")),
+        "virtual writes are refused by default, got {payload:?}"
+    );
+    assert_eq!(
+        files.get("mem://buffer1").as_deref(),
+        Some("one\ntwo\nthree\n")
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_write_text_file_updates_virtual_files_when_allowed() {
+    let harness = BridgeHarness::start_with(
+        Arc::new(FakePermissionAgentTransport::new(
+            success_initialize_response(),
+        )),
+        |config| {
+            config.require_session_for_write = false;
+            config.allow_virtual_writes = true;
+        },
+    )
+    .await;
+    let files = harness.handle.virtual_files();
+    assert!(files.register("mem://buffer1", "draft"));
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(
+        &mut ws,
+        "write-virtual",
+        "fs/write_text_file",
+        json!({"path": "mem://buffer1", "content": "final"}),
+    )
+    .await;
+    assert_eq!(
+        payload.pointer("/result/written"),
+        Some(&json!(true)),
+        "got {payload:?}"
+    );
+    assert_eq!(files.get("mem://buffer1").as_deref(), Some("final"));
+
+    let payload = call(
+        &mut ws,
+        "read-virtual",
+        "fs/read_text_file",
+        json!({"path": "mem://buffer1"}),
+    )
+    .await;
+    assert_eq!(payload.pointer("/result/content"), Some(&json!("final")));
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_write_text_file_rejects_directories_without_prompting() {
+    let temp = TestTempDir::new("fs-write-directory");
+    let dir_path = temp.path().join("src");
+    fs::create_dir_all(&dir_path).expect("create directory");
+    let agent = Arc::new(FakePermissionAgentTransport::new(
+        success_initialize_response(),
+    ));
+    agent
+        .configure_permission_response(allow_once_response())
+        .await;
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent.clone(), move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    let payload = call(
+        &mut ws,
+        "write-dir",
+        "fs/write_text_file",
+        json!({"sessionId": session_id, "path": dir_path, "content": "oops"}),
+    )
+    .await;
+    let error = payload
+        .get("error")
+        .unwrap_or_else(|| panic!("writing a directory should fail, got {payload:?}"));
+    assert_eq!(error.get("code"), Some(&json!(-32602)));
+    assert_eq!(error.pointer("/data/kind"), Some(&json!("is_directory")));
+    assert!(
+        agent.take_permission_calls().await.is_empty(),
+        "no prompt for a write that cannot succeed"
+    );
+    assert!(dir_path.is_dir());
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn redact_paths_keeps_absolute_paths_out_of_error_data() {
+    let temp = TestTempDir::new("fs-redact-paths");
+    let root = temp.path().to_path_buf();
+    let outside = env::temp_dir().join("ct-bridge-redact-outside.txt");
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_roots = vec![root];
+        config.redact_paths = true;
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    for (id, path) in [("etc", PathBuf::from("/etc/passwd")), ("outside", outside)] {
+        let payload = call(
+            &mut ws,
+            id,
+            "fs/read_text_file",
+            json!({"sessionId": "redact-session", "path": path}),
+        )
+        .await;
+        let error = payload
+            .get("error")
+            .unwrap_or_else(|| panic!("reading outside the root should fail, got {payload:?}"));
+        assert_eq!(
+            error.pointer("/data/kind"),
+            Some(&json!("sandbox_violation"))
+        );
+        let rendered = error.to_string();
+        assert!(
+            !rendered.contains("\"/") && !rendered.contains(&path.to_string_lossy().to_string()),
+            "error should not reveal absolute paths: {rendered}"
+        );
+    }
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_write_roots_confine_writes_but_not_reads() {
+    let temp = TestTempDir::new("fs-write-roots");
+    let generated = temp.path().join("generated");
+    fs::create_dir_all(&generated).expect("create generated dir");
+    let source = temp.path().join("main.rs");
+    fs::write(&source, "fn main() {}\n").expect("write source");
+    let agent = Arc::new(FakePermissionAgentTransport::new(
+        success_initialize_response(),
+    ));
+    agent
+        .configure_permission_response(allow_once_response())
+        .await;
+    let root = temp.path().to_path_buf();
+    let write_root = generated.clone();
+    let harness = BridgeHarness::start_with(agent.clone(), move |config| {
+        config.project_roots = vec![root];
+        config.write_roots = vec![write_root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
 
-        async_tungstenite::tokio::connect_async(request).await
-    }
+    let read = call(
+        &mut ws,
+        "read-source",
+        "fs/read_text_file",
+        json!({"sessionId": session_id, "path": "main.rs"}),
+    )
+    .await;
+    assert_eq!(
+        read.pointer("/result/content"),
+        Some(&json!("fn main() {}\n")),
+        "reads anywhere in the project roots still work: {read:?}"
+    );
 
-    async fn shutdown(self) {
-        let _ = self.handle.shutdown().await;
-    }
-}
+    let rejected = call(
+        &mut ws,
+        "write-source",
+        "fs/write_text_file",
+        json!({"sessionId": session_id, "path": "main.rs", "content": "oops"}),
+    )
+    .await;
+    assert_eq!(
+        rejected.pointer("/error/data/kind"),
+        Some(&json!("sandbox_violation")),
+        "writes outside write_roots must fail: {rejected:?}"
+    );
+    assert!(
+        agent.take_permission_calls().await.is_empty(),
+        "no prompt for a write outside write_roots"
+    );
+    assert_eq!(
+        fs::read_to_string(&source).expect("read source"),
+        "fn main() {}\n"
+    );
 
-async fn send_json_rpc<S>(stream: &mut S, value: Value)
-where
-    S: Sink<Message, Error = tungstenite::Error> + Unpin,
-{
-    let message = Message::Text(value.to_string());
-    stream
-        .send(message)
-        .await
-        .expect("sending JSON-RPC frame should succeed");
-}
+    // Relative paths still resolve against the project root.
+    let written = call(
+        &mut ws,
+        "write-generated",
+        "fs/write_text_file",
+        json!({"sessionId": session_id, "path": "generated/out.rs", "content": "// out\n"}),
+    )
+    .await;
+    assert!(
+        written.get("result").is_some(),
+        "writes inside write_roots should succeed: {written:?}"
+    );
+    assert_eq!(
+        fs::read_to_string(generated.join("out.rs")).expect("read generated file"),
+        "// out\n"
+    );
 
-async fn next_message<S>(stream: &mut S) -> Message
-where
-    S: Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
-{
-    timeout(TEST_TIMEOUT, stream.next())
-        .await
-        .expect("websocket response timed out")
-        .expect("stream ended unexpectedly")
-        .expect("failed to receive message")
+    harness.shutdown().await;
 }
 
-fn parse_json(message: &Message) -> Value {
-    match message {
-        Message::Text(text) => serde_json::from_str(text).expect("valid JSON text"),
-        Message::Binary(bytes) => serde_json::from_slice(bytes).expect("valid JSON binary frame"),
-        other => panic!("expected text/binary frame, got {other:?}"),
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_write_text_file_concurrent_writes_to_one_path_share_a_prompt() {
+    let temp = TestTempDir::new("fs-write-coalesce");
+    let file_path = temp.path().join("shared.txt");
+    let agent = Arc::new(FakePermissionAgentTransport::new(
+        success_initialize_response(),
+    ));
+    agent
+        .configure_permission_response(allow_once_response())
+        .await;
+    agent
+        .delay_permission_responses(Duration::from_millis(300))
+        .await;
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent.clone(), move |config| {
+        config.project_roots = vec![root];
+        // The fake agent hands every session the same id.
+        config.duplicate_session_policy = DuplicateSessionPolicy::Alias;
+    })
+    .await;
+    let mut first = harness.connect_initialized().await;
+    let first_session = new_session_id(&mut first).await;
+    let mut second = harness.connect_initialized().await;
+    let second_session = new_session_id(&mut second).await;
+
+    for (ws, session_id, id) in [
+        (&mut first, &first_session, "first-write"),
+        (&mut second, &second_session, "second-write"),
+    ] {
+        send_json_rpc(
+            ws,
+            json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": "fs/write_text_file",
+                "params": {
+                    "sessionId": session_id,
+                    "path": file_path.to_string_lossy(),
+                    "content": id,
+                }
+            }),
+        )
+        .await;
     }
-}
 
-// Tests for fs/read_text_file capability per RAT-LWS-REQ-040
-// These tests will fail until fs/read_text_file is implemented
+    for (ws, id) in [(&mut first, "first-write"), (&mut second, "second-write")] {
+        let payload = parse_json(&next_message(ws).await);
+        assert_eq!(payload.get("id"), Some(&json!(id)));
+        assert!(
+            payload.get("result").is_some(),
+            "write sharing the approval should succeed, got {payload:?}"
+        );
+    }
+    assert_eq!(
+        agent.take_permission_calls().await.len(),
+        1,
+        "concurrent writes to one path should prompt once"
+    );
+    assert!(file_path.exists());
+
+    harness.shutdown().await;
+}
 
 #[tokio::test(flavor = "multi_thread")]
-#[serial_test::serial]
-async fn fs_read_text_file_basic_functionality() {
+async fn fs_write_text_file_asks_client_for_permission_when_configured() {
+    let temp = TestTempDir::new("fs-write-client-permission");
+    let file_path = temp.path().join("approved.txt");
+    // FakeAgentTransport cannot answer permission prompts, so success proves
+    // the request went to the WebSocket client.
     let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
-    let harness = BridgeHarness::start(agent.clone()).await;
-
-    let (mut ws, _) = harness
-        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
-        .await
-        .expect("handshake should succeed");
-
-    // Initialize first
-    send_initialize_request(&mut ws).await;
-    let _init_response = next_message(&mut ws).await;
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_roots = vec![root];
+        config.permission_target = PermissionTarget::Client;
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
 
-    // Test basic fs/read_text_file request
     send_json_rpc(
         &mut ws,
         json!({
             "jsonrpc": "2.0",
-            "id": "read-1",
-            "method": "fs/read_text_file",
+            "id": "client-approved-write",
+            "method": "fs/write_text_file",
             "params": {
-                "path": "tests/fs_test_file.md"
+                "sessionId": session_id,
+                "path": file_path.to_string_lossy(),
+                "content": "approved by the user",
             }
         }),
     )
     .await;
 
-    let message = next_message(&mut ws).await;
-    let payload = parse_json(&message);
-
-    assert_eq!(payload.get("id"), Some(&json!("read-1")));
-
-    // Verify we get the expected file content
-    let result = payload
-        .get("result")
-        .expect("fs/read_text_file should return success result when implemented");
-    assert!(
-        result.get("content").is_some(),
-        "result should contain file content"
+    let prompt = parse_json(&next_message(&mut ws).await);
+    assert_eq!(
+        prompt.get("method"),
+        Some(&json!("session/request_permission"))
     );
-    let content = result
-        .get("content")
-        .unwrap()
-        .as_str()
-        .expect("content should be a string");
+    let prompt_id = prompt
+        .get("id")
+        .and_then(|id| id.as_str())
+        .expect("permission prompt should carry an id")
+        .to_string();
     assert!(
-        content.contains("In the hush of dawn, love whispers soft as dew"),
-        "should contain first line of poem"
+        prompt_id.starts_with("bridge-"),
+        "bridge ids are namespaced, got {prompt_id}"
+    );
+    assert_eq!(
+        prompt.get("params").and_then(|p| p.get("sessionId")),
+        Some(&json!(session_id))
     );
+    assert!(!file_path.exists(), "nothing is written before approval");
+
+    send_json_rpc(
+        &mut ws,
+        json!({
+            "jsonrpc": "2.0",
+            "id": prompt_id,
+            "result": {"outcome": {"outcome": "selected", "optionId": "allow_once"}},
+        }),
+    )
+    .await;
+
+    let payload = parse_json(&next_message(&mut ws).await);
+    assert_eq!(payload.get("id"), Some(&json!("client-approved-write")));
     assert!(
-        content.contains("And in its gentle hold, true peace is found."),
-        "should contain last line of poem"
+        payload.get("result").is_some(),
+        "write should complete after client approval, got {payload:?}"
+    );
+    assert_eq!(
+        fs::read_to_string(&file_path).expect("read approved file"),
+        "approved by the user"
     );
 
     harness.shutdown().await;
 }
 
 #[tokio::test(flavor = "multi_thread")]
-#[serial_test::serial]
-async fn fs_read_text_file_with_line_offset_and_limit() {
-    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
-    let harness = BridgeHarness::start(agent.clone()).await;
+async fn fs_text_file_paths_resolve_against_a_base_inside_the_sandbox() {
+    let temp = TestTempDir::new("fs-base-inside");
+    fs::create_dir_all(temp.path().join("src")).expect("create src dir");
+    fs::write(temp.path().join("src/lib.rs"), "pub fn answer() {}\n").expect("write fixture");
 
-    let (mut ws, _) = harness
-        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
-        .await
-        .expect("handshake should succeed");
+    let agent = Arc::new(FakePermissionAgentTransport::new(
+        success_initialize_response(),
+    ));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent.clone(), move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
 
-    // Initialize first
-    send_initialize_request(&mut ws).await;
-    let _init_response = next_message(&mut ws).await;
+    let read = call(
+        &mut ws,
+        "read-base",
+        "fs/read_text_file",
+        json!({"base": "src", "path": "lib.rs"}),
+    )
+    .await;
+    assert_eq!(
+        read.get("result").and_then(|r| r.get("content")),
+        Some(&json!("pub fn answer() {}\n")),
+        "got {read:?}"
+    );
 
-    // Test fs/read_text_file with line offset and limit per RAT-LWS-REQ-040
-    send_json_rpc(
+    agent
+        .configure_permission_response(allow_once_response())
+        .await;
+    let write = call(
         &mut ws,
+        "write-base",
+        "fs/write_text_file",
         json!({
-            "jsonrpc": "2.0",
-            "id": "read-offset-1",
-            "method": "fs/read_text_file",
-            "params": {
-                "path": "tests/fs_test_file.md",
-                "line_offset": 5,
-                "line_limit": 10
-            }
+            "sessionId": session_id,
+            "base": "src",
+            "path": "main.rs",
+            "content": "fn main() {}\n",
         }),
     )
     .await;
+    assert!(write.get("result").is_some(), "got {write:?}");
+    assert_eq!(
+        fs::read_to_string(temp.path().join("src/main.rs")).expect("read written file"),
+        "fn main() {}\n"
+    );
 
-    let message = next_message(&mut ws).await;
-    let payload = parse_json(&message);
+    harness.shutdown().await;
+}
 
-    assert_eq!(payload.get("id"), Some(&json!("read-offset-1")));
+#[tokio::test(flavor = "multi_thread")]
+#[serial_test::serial]
+async fn fs_read_text_file_expands_a_path_under_base_only_once() {
+    let temp = TestTempDir::new("fs-base-expand-once");
+    let nested = temp.path().join("src").join("$CT_BRIDGE_TEST_SECOND");
+    fs::create_dir_all(&nested).expect("create fixture dir");
+    fs::write(nested.join("notes.txt"), "expanded once").expect("write fixture");
+    let _first = EnvVarGuard::set_var("CT_BRIDGE_TEST_FIRST", "$CT_BRIDGE_TEST_SECOND".to_string());
+    let _second = EnvVarGuard::set_var("CT_BRIDGE_TEST_SECOND", "elsewhere".to_string());
 
-    // Verify we get the limited file content
-    let result = payload
-        .get("result")
-        .expect("fs/read_text_file should return success result when implemented");
-    assert!(
-        result.get("content").is_some(),
-        "result should contain limited file content"
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(
+        &mut ws,
+        "read-base-expand",
+        "fs/read_text_file",
+        json!({"base": "src", "path": "$CT_BRIDGE_TEST_FIRST/notes.txt"}),
+    )
+    .await;
+    assert_eq!(
+        payload.get("result").and_then(|r| r.get("content")),
+        Some(&json!("expanded once")),
+        "the value of $CT_BRIDGE_TEST_FIRST must not be expanded again, got {payload:?}"
     );
-    let content = result
-        .get("content")
-        .unwrap()
-        .as_str()
-        .expect("content should be a string");
 
-    // Verify that only the requested lines are returned (lines 5-14, 10 lines total)
-    let lines: Vec<&str> = content.lines().collect();
-    assert_eq!(lines.len(), 10, "should return exactly 10 lines");
-    assert!(
-        content.contains("Love is the fire that warms the coldest night"),
-        "should contain line 6 (offset from line 5)"
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_text_file_rejects_a_base_outside_the_sandbox() {
+    let temp = TestTempDir::new("fs-base-outside");
+    fs::write(temp.path().join("inside.txt"), "inside").expect("write fixture");
+    let root_name = temp
+        .path()
+        .file_name()
+        .and_then(|name| name.to_str())
+        .expect("temp dir name")
+        .to_string();
+
+    let agent = Arc::new(FakePermissionAgentTransport::new(
+        success_initialize_response(),
+    ));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent.clone(), move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+    let session_id = new_session_id(&mut ws).await;
+
+    // Joined, these point back inside the root, but the base alone does not.
+    let path = format!("{root_name}/inside.txt");
+    let read = call(
+        &mut ws,
+        "read-escape",
+        "fs/read_text_file",
+        json!({"base": "..", "path": path}),
+    )
+    .await;
+    let write = call(
+        &mut ws,
+        "write-escape",
+        "fs/write_text_file",
+        json!({
+            "sessionId": session_id,
+            "base": "..",
+            "path": path,
+            "content": "overwritten",
+        }),
+    )
+    .await;
+    for payload in [read, write] {
+        assert_eq!(
+            payload
+                .get("error")
+                .and_then(|e| e.get("data"))
+                .and_then(|d| d.get("kind")),
+            Some(&json!("sandbox_violation")),
+            "got {payload:?}"
+        );
+    }
+    assert_eq!(
+        fs::read_to_string(temp.path().join("inside.txt")).expect("read fixture"),
+        "inside"
     );
+    assert!(agent.take_permission_calls().await.is_empty());
 
     harness.shutdown().await;
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn fs_read_text_file_enforces_project_root_sandbox() {
+async fn fs_read_text_file_returns_empty_content_for_empty_files() {
+    let temp = TestTempDir::new("fs-read-empty");
+    let file_path = temp.path().join("empty.txt");
+    fs::write(&file_path, "").expect("write fixture");
+
     let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
-    let harness = BridgeHarness::start(agent.clone()).await;
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let params = [
+        json!({"path": file_path}),
+        json!({"path": file_path, "line_offset": 3}),
+        json!({"path": file_path, "line_offset": 1, "line_limit": 5}),
+    ];
+    for (index, params) in params.into_iter().enumerate() {
+        let payload = call(
+            &mut ws,
+            &format!("empty-{index}"),
+            "fs/read_text_file",
+            params,
+        )
+        .await;
+        let result = payload
+            .get("result")
+            .unwrap_or_else(|| panic!("empty read should succeed, got {payload:?}"));
+        assert_eq!(result.get("content"), Some(&json!("")));
+        assert_eq!(result.get("totalLines"), Some(&json!(0)));
+        assert_eq!(result.get("truncated"), Some(&json!(false)));
+    }
 
-    let (mut ws, _) = harness
-        .connect(ALLOWED_ORIGIN, Some(SUBPROTOCOL))
-        .await
-        .expect("handshake should succeed");
+    harness.shutdown().await;
+}
 
-    // Initialize first
-    send_initialize_request(&mut ws).await;
-    let _init_response = next_message(&mut ws).await;
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_read_text_file_windows_report_whether_the_file_ends_with_a_newline() {
+    let temp = TestTempDir::new("fs-read-trailing-newline");
+    let terminated = temp.path().join("terminated.txt");
+    let unterminated = temp.path().join("unterminated.txt");
+    fs::write(&terminated, "one\ntwo\nthree\n").expect("write terminated fixture");
+    fs::write(&unterminated, "one\ntwo\nthree").expect("write unterminated fixture");
 
-    // Test reading file outside project root - should be rejected per RAT-LWS-REQ-044
-    send_json_rpc(
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    for (path, expected) in [(&terminated, true), (&unterminated, false)] {
+        // The tail window, a window that stops short of the end, and the
+        // whole file all report the file's own ending.
+        for params in [
+            json!({"path": path, "line_offset": 2}),
+            json!({"path": path, "line_offset": 1, "line_limit": 1}),
+            json!({"path": path}),
+        ] {
+            let payload = call(&mut ws, "read-ending", "fs/read_text_file", params.clone()).await;
+            let result = payload
+                .get("result")
+                .unwrap_or_else(|| panic!("read should succeed, got {payload:?}"));
+            assert_eq!(
+                result.get("endsWithNewline"),
+                Some(&json!(expected)),
+                "{params} -> {result}"
+            );
+            assert_eq!(result.get("totalLines"), Some(&json!(3)));
+        }
+
+        let payload = call(
+            &mut ws,
+            "read-tail",
+            "fs/read_text_file",
+            json!({"path": path, "line_offset": 2}),
+        )
+        .await;
+        assert_eq!(
+            payload.pointer("/result/content"),
+            Some(&json!("two\nthree")),
+            "windows are joined the same way either way"
+        );
+    }
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_read_text_file_numbers_lines_from_the_window_offset() {
+    let temp = TestTempDir::new("fs-read-numbered");
+    let file_path = temp.path().join("numbered.txt");
+    fs::write(&file_path, "one\ntwo\r\nthree\nfour\nfive\nsix\n").expect("write fixture");
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(
         &mut ws,
-        json!({
-            "jsonrpc": "2.0",
-            "id": "read-oob-1",
-            "method": "fs/read_text_file",
-            "params": {
-                "path": "/etc/passwd"
-            }
-        }),
+        "numbered-window",
+        "fs/read_text_file",
+        json!({"path": file_path, "line_offset": 3, "line_limit": 3, "with_line_numbers": true}),
     )
     .await;
+    let result = payload
+        .get("result")
+        .unwrap_or_else(|| panic!("numbered read should succeed, got {payload:?}"));
+    assert_eq!(
+        result.get("lines"),
+        Some(&json!([
+            {"number": 3, "text": "three"},
+            {"number": 4, "text": "four"},
+            {"number": 5, "text": "five"},
+        ]))
+    );
+    assert!(result.get("content").is_none(), "lines replace content");
+    assert_eq!(result.get("totalLines"), Some(&json!(6)));
+    assert_eq!(result.get("truncated"), Some(&json!(true)));
 
-    let message = next_message(&mut ws).await;
-    let payload = parse_json(&message);
-
-    assert_eq!(payload.get("id"), Some(&json!("read-oob-1")));
+    let payload = call(
+        &mut ws,
+        "numbered-whole",
+        "fs/read_text_file",
+        json!({"path": file_path, "with_line_numbers": true}),
+    )
+    .await;
+    let lines = payload
+        .pointer("/result/lines")
+        .and_then(Value::as_array)
+        .unwrap_or_else(|| panic!("expected numbered lines, got {payload:?}"));
+    let numbers: Vec<u64> = lines
+        .iter()
+        .map(|line| line["number"].as_u64().expect("line number"))
+        .collect();
+    assert_eq!(numbers, (1..=6).collect::<Vec<u64>>());
+    assert_eq!(lines[1]["text"], json!("two"), "line endings are stripped");
 
-    // This should return an error for out-of-bounds access (not method not found)
-    let error = payload
-        .get("error")
-        .expect("should have error for out-of-bounds access");
-    let error_code = error
-        .get("code")
-        .and_then(|c| c.as_i64())
-        .expect("error should have numeric code");
-    // Should be permission denied (e.g., -32000) or similar, not method not found (-32601)
-    assert_ne!(
-        error_code, -32601,
-        "should be permission error, not method not found"
-    );
+    let payload = call(
+        &mut ws,
+        "flat",
+        "fs/read_text_file",
+        json!({"path": file_path, "line_offset": 3, "line_limit": 1}),
+    )
+    .await;
+    assert_eq!(payload.pointer("/result/content"), Some(&json!("three")));
+    assert!(payload.pointer("/result/lines").is_none());
 
     harness.shutdown().await;
 }
@@ -1961,6 +8282,184 @@ async fn fs_read_text_file_rejects_binary_files() {
     harness.shutdown().await;
 }
 
+fn utf16le_with_bom(text: &str) -> Vec<u8> {
+    let mut bytes = vec![0xFF, 0xFE];
+    for unit in text.encode_utf16() {
+        bytes.extend_from_slice(&unit.to_le_bytes());
+    }
+    bytes
+}
+
+#[test]
+fn request_id_allocator_is_monotonic_and_unique_across_reconnects() {
+    let first_connection = RequestIdAllocator::new(7);
+    let first: Vec<String> = (0..3).map(|_| first_connection.next_id()).collect();
+    assert_eq!(first, ["bridge-7-1", "bridge-7-2", "bridge-7-3"]);
+
+    // A reconnect gets a fresh connection id and therefore a fresh namespace.
+    let reconnected = RequestIdAllocator::new(8);
+    let second: Vec<String> = (0..3).map(|_| reconnected.next_id()).collect();
+
+    let mut all: Vec<&String> = first.iter().chain(second.iter()).collect();
+    all.sort();
+    all.dedup();
+    assert_eq!(all.len(), 6, "ids must never repeat across connections");
+    assert!(first
+        .iter()
+        .chain(second.iter())
+        .all(|id| id.starts_with("bridge-")));
+}
+
+#[test]
+fn is_probably_binary_classifies_crafted_samples() {
+    // Plain and multi-byte UTF-8 text.
+    assert!(!is_probably_binary(
+        b"fn main() {\n\tprintln!(\"hi\");\r\n}\n"
+    ));
+    assert!(!is_probably_binary("naïve café — ünïcödé\n".as_bytes()));
+    assert!(!is_probably_binary(b""));
+    // ANSI-colored logs keep their escape bytes.
+    assert!(!is_probably_binary(b"\x1b[31merror\x1b[0m: failed\n"));
+
+    // UTF-16 text is full of NUL bytes but carries a BOM.
+    assert!(!is_probably_binary(&utf16le_with_bom("hello\nworld\n")));
+    assert!(!is_probably_binary(&[0xFE, 0xFF, 0x00, b'h', 0x00, b'i']));
+
+    // Magic numbers flag binaries even when no NUL appears in the sample.
+    assert!(is_probably_binary(b"\x89PNG\r\n\x1a\nIHDR"));
+    assert!(is_probably_binary(b"\x7fELF\x02\x01\x01"));
+    assert!(is_probably_binary(b"PK\x03\x04zipped"));
+    assert!(is_probably_binary(b"\x1f\x8b\x08compressed"));
+
+    // NUL bytes without a BOM.
+    assert!(is_probably_binary(b"text\0with nul"));
+
+    // Dense control characters without NUL bytes.
+    assert!(is_probably_binary(&[
+        0x01, 0x02, 0x03, b'a', 0x04, 0x05, b'b', 0x06, 0x07, 0x0e
+    ]));
+    // A stray control byte in otherwise normal text stays text.
+    assert!(!is_probably_binary(
+        b"mostly ordinary text with one \x07 bell in it\n"
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_read_text_file_transcodes_utf16_text() {
+    let temp = TestTempDir::new("fs-read-utf16");
+    let path = temp.path().join("notes.txt");
+    fs::write(&path, utf16le_with_bom("first\nsecond\nthird\n")).expect("write fixture");
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(
+        &mut ws,
+        "read-utf16",
+        "fs/read_text_file",
+        json!({"path": path}),
+    )
+    .await;
+    assert_eq!(
+        payload.get("result").and_then(|r| r.get("content")),
+        Some(&json!("first\nsecond\nthird\n")),
+        "UTF-16 text should be transcoded, got {payload:?}"
+    );
+
+    let payload = call(
+        &mut ws,
+        "read-utf16-range",
+        "fs/read_text_file",
+        json!({"path": path, "line_offset": 2, "line_limit": 1}),
+    )
+    .await;
+    assert_eq!(
+        payload.get("result").and_then(|r| r.get("content")),
+        Some(&json!("second"))
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_read_text_file_transcodes_requested_encoding() {
+    let temp = TestTempDir::new("fs-read-latin1");
+    let path = temp.path().join("legacy.txt");
+    fs::write(&path, b"caf\xe9\nna\xefve\n").expect("write fixture");
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(
+        &mut ws,
+        "read-latin1",
+        "fs/read_text_file",
+        json!({"path": path, "encoding": "latin1"}),
+    )
+    .await;
+    assert_eq!(
+        payload.get("result").and_then(|r| r.get("content")),
+        Some(&json!("café\nnaïve\n")),
+        "latin-1 text should be transcoded, got {payload:?}"
+    );
+
+    let payload = call(
+        &mut ws,
+        "read-latin1-range",
+        "fs/read_text_file",
+        json!({"path": path, "encoding": "latin1", "line_offset": 2}),
+    )
+    .await;
+    assert_eq!(
+        payload.get("result").and_then(|r| r.get("content")),
+        Some(&json!("naïve"))
+    );
+
+    let payload = call(
+        &mut ws,
+        "read-latin1-as-utf8",
+        "fs/read_text_file",
+        json!({"path": path}),
+    )
+    .await;
+    assert_eq!(
+        payload
+            .get("error")
+            .and_then(|e| e.get("data"))
+            .and_then(|d| d.get("kind")),
+        Some(&json!("invalid_encoding")),
+        "without the param the file is read as UTF-8, got {payload:?}"
+    );
+
+    let payload = call(
+        &mut ws,
+        "read-unknown-encoding",
+        "fs/read_text_file",
+        json!({"path": path, "encoding": "klingon"}),
+    )
+    .await;
+    let error = payload
+        .get("error")
+        .unwrap_or_else(|| panic!("unknown encodings should be rejected, got {payload:?}"));
+    assert_eq!(error.get("code"), Some(&json!(-32602)));
+    assert_eq!(
+        error.get("data"),
+        Some(&json!("unsupported encoding: \"klingon\""))
+    );
+
+    harness.shutdown().await;
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn fs_read_text_file_handles_out_of_bounds_line_parameters() {
     let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
@@ -2021,6 +8520,378 @@ async fn fs_read_text_file_handles_out_of_bounds_line_parameters() {
     harness.shutdown().await;
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_read_text_file_ranged_read_stops_early() {
+    let temp = TestTempDir::new("fs-read-early-stop");
+    let path = temp.path().join("huge.log");
+    // The tail is far larger than max_read_bytes and is not valid UTF-8; a
+    // ranged read that stops at the window never decodes it.
+    let mut contents = b"line 1\nline 2\nline 3\nline 4\n".to_vec();
+    contents.extend(std::iter::repeat_n(0xFFu8, 1 << 20));
+    fs::write(&path, contents).expect("write fixture");
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.max_read_bytes = Some(1024);
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(
+        &mut ws,
+        "read-window",
+        "fs/read_text_file",
+        json!({"path": path, "line_offset": 2, "line_limit": 2}),
+    )
+    .await;
+    let result = payload
+        .get("result")
+        .unwrap_or_else(|| panic!("ranged read should succeed, got {payload:?}"));
+    assert_eq!(result.get("content"), Some(&json!("line 2\nline 3")));
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_read_text_file_reports_total_lines_and_truncation() {
+    let temp = TestTempDir::new("fs-read-truncation");
+    let path = temp.path().join("five.txt");
+    fs::write(&path, "one\ntwo\nthree\nfour\nfive\n").expect("write fixture");
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let cases = [
+        (
+            "read-full",
+            json!({"path": path}),
+            "one\ntwo\nthree\nfour\nfive\n",
+            false,
+        ),
+        (
+            "read-limited",
+            json!({"path": path, "line_offset": 2, "line_limit": 2}),
+            "two\nthree",
+            true,
+        ),
+        (
+            "read-tail",
+            json!({"path": path, "line_offset": 4, "line_limit": 10}),
+            "four\nfive",
+            false,
+        ),
+    ];
+    for (request_id, params, content, truncated) in cases {
+        let payload = call(&mut ws, request_id, "fs/read_text_file", params).await;
+        let result = payload
+            .get("result")
+            .unwrap_or_else(|| panic!("{request_id} should succeed, got {payload:?}"));
+        assert_eq!(result.get("content"), Some(&json!(content)), "{request_id}");
+        assert_eq!(result.get("totalLines"), Some(&json!(5)), "{request_id}");
+        assert_eq!(
+            result.get("truncated"),
+            Some(&json!(truncated)),
+            "{request_id}"
+        );
+    }
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_read_text_file_rejects_oversized_whole_file_reads() {
+    let temp = TestTempDir::new("fs-read-oversize");
+    let small = temp.path().join("small.txt");
+    let large = temp.path().join("large.txt");
+    fs::write(&small, "tiny\n").expect("write small fixture");
+    fs::write(&large, "x".repeat(4096)).expect("write large fixture");
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.max_read_bytes = Some(1024);
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(
+        &mut ws,
+        "read-small",
+        "fs/read_text_file",
+        json!({"path": small}),
+    )
+    .await;
+    assert_eq!(
+        payload.get("result").and_then(|r| r.get("content")),
+        Some(&json!("tiny\n"))
+    );
+
+    let payload = call(
+        &mut ws,
+        "read-large",
+        "fs/read_text_file",
+        json!({"path": large}),
+    )
+    .await;
+    let error = payload
+        .get("error")
+        .unwrap_or_else(|| panic!("oversized read should fail, got {payload:?}"));
+    let data = error.get("data").cloned().unwrap_or_default();
+    assert_eq!(data.get("kind"), Some(&json!("too_large")));
+    let detail = data
+        .get("detail")
+        .and_then(|d| d.as_str())
+        .unwrap_or_default();
+    assert!(
+        detail.contains("max_read_bytes"),
+        "error should name the limit, got {data:?}"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_read_text_file_truncates_lines_over_max_line_bytes() {
+    let temp = TestTempDir::new("fs-read-long-line");
+    let file_path = temp.path().join("bundle.min.js");
+    let giant = "a".repeat(64 * 1024);
+    fs::write(&file_path, format!("header\n{giant}\nfooter\n")).expect("write fixture");
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.max_line_bytes = Some(16);
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(
+        &mut ws,
+        "read-long-line",
+        "fs/read_text_file",
+        json!({"path": file_path}),
+    )
+    .await;
+    let result = payload
+        .get("result")
+        .unwrap_or_else(|| panic!("read should succeed, got {payload:?}"));
+    let content = result
+        .get("content")
+        .and_then(|c| c.as_str())
+        .expect("content should be a string");
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "header");
+    assert!(
+        lines[1].starts_with(&"a".repeat(16)) && lines[1].len() < 64,
+        "giant line should be cut short, got {} bytes",
+        lines[1].len()
+    );
+    assert!(lines[1].contains("truncated"), "truncation marker missing");
+    assert_eq!(lines[2], "footer");
+    assert_eq!(result.get("truncatedLines"), Some(&json!([2])));
+
+    let payload = call(
+        &mut ws,
+        "read-long-line-ranged",
+        "fs/read_text_file",
+        json!({"path": file_path, "line_offset": 2, "line_limit": 2}),
+    )
+    .await;
+    assert_eq!(
+        payload.get("result").and_then(|r| r.get("truncatedLines")),
+        Some(&json!([2])),
+        "ranged reads report absolute line numbers"
+    );
+
+    harness.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fs_read_text_file_returns_long_lines_whole_without_max_line_bytes() {
+    let temp = TestTempDir::new("fs-read-long-line-unlimited");
+    let file_path = temp.path().join("bundle.min.js");
+    let giant = "a".repeat(64 * 1024);
+    fs::write(&file_path, format!("{giant}\n")).expect("write fixture");
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let root = temp.path().to_path_buf();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.max_line_bytes = None;
+        config.project_roots = vec![root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(
+        &mut ws,
+        "read-long-line",
+        "fs/read_text_file",
+        json!({"path": file_path}),
+    )
+    .await;
+    let result = payload
+        .get("result")
+        .unwrap_or_else(|| panic!("read should succeed, got {payload:?}"));
+    assert_eq!(result.get("content"), Some(&json!(format!("{giant}\n"))));
+    assert!(result.get("truncatedLines").is_none(), "got {result:?}");
+
+    harness.shutdown().await;
+}
+
+fn set_mtime(path: &Path, time: SystemTime) {
+    fs::File::options()
+        .write(true)
+        .open(path)
+        .expect("open for mtime")
+        .set_modified(time)
+        .expect("set mtime");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn project_recent_files_returns_newest_first() {
+    let temp = TestTempDir::new("project-recent-files");
+    let root = temp.path().join("project");
+    fs::create_dir_all(root.join("src")).expect("create src");
+    fs::create_dir_all(root.join("node_modules")).expect("create node_modules");
+
+    let now = SystemTime::now();
+    let files = [
+        ("old.txt", 400),
+        ("src/middle.rs", 300),
+        ("src/newer.rs", 200),
+        ("node_modules/ignored.js", 0),
+    ];
+    for (name, age_secs) in files {
+        let path = root.join(name);
+        fs::write(&path, name).expect("write fixture");
+        set_mtime(&path, now - Duration::from_secs(age_secs));
+    }
+    // Touching the oldest file moves it to the front.
+    set_mtime(&root.join("old.txt"), now - Duration::from_secs(100));
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let project_root = root.clone();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_roots = vec![project_root];
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(
+        &mut ws,
+        "recent-1",
+        "project/recent_files",
+        json!({"limit": 2}),
+    )
+    .await;
+    let result = payload
+        .get("result")
+        .unwrap_or_else(|| panic!("recent_files should succeed, got {payload:?}"));
+    let paths: Vec<&str> = result
+        .get("files")
+        .and_then(|f| f.as_array())
+        .expect("files array")
+        .iter()
+        .map(|file| file.get("path").and_then(|p| p.as_str()).expect("path"))
+        .collect();
+    assert_eq!(
+        paths,
+        vec![
+            root.join("old.txt").to_string_lossy(),
+            root.join("src/newer.rs").to_string_lossy(),
+        ]
+    );
+    assert_eq!(result.get("truncated"), Some(&json!(false)));
+
+    let payload = call(&mut ws, "recent-2", "project/recent_files", json!({})).await;
+    let files = payload
+        .get("result")
+        .and_then(|r| r.get("files"))
+        .and_then(|f| f.as_array())
+        .expect("files array");
+    assert_eq!(files.len(), 3, "ignored directories must not be walked");
+    let mtimes: Vec<u64> = files
+        .iter()
+        .map(|file| {
+            file.get("modifiedMs")
+                .and_then(|m| m.as_u64())
+                .expect("mtime")
+        })
+        .collect();
+    assert!(mtimes.windows(2).all(|pair| pair[0] >= pair[1]));
+
+    harness.shutdown().await;
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn project_walk_terminates_on_symlink_loops() {
+    use std::os::unix::fs::symlink;
+
+    let temp = TestTempDir::new("project-symlink-loop");
+    let root = temp.path().join("project");
+    fs::create_dir_all(root.join("a")).expect("create a");
+    fs::create_dir_all(root.join("b")).expect("create b");
+    fs::write(root.join("a/one.txt"), "one").expect("write a/one.txt");
+    fs::write(root.join("b/two.txt"), "two").expect("write b/two.txt");
+    // a/to_b -> b and b/to_a -> a form a cycle.
+    symlink(root.join("b"), root.join("a/to_b")).expect("link a/to_b");
+    symlink(root.join("a"), root.join("b/to_a")).expect("link b/to_a");
+
+    let agent = Arc::new(FakeAgentTransport::new(success_initialize_response()));
+    let project_root = root.clone();
+    let harness = BridgeHarness::start_with(agent, move |config| {
+        config.project_roots = vec![project_root];
+        config.follow_symlinks = true;
+    })
+    .await;
+    let mut ws = harness.connect_initialized().await;
+
+    let payload = call(&mut ws, "recent-loop", "project/recent_files", json!({})).await;
+    let mut paths: Vec<String> = payload
+        .get("result")
+        .and_then(|r| r.get("files"))
+        .and_then(|f| f.as_array())
+        .unwrap_or_else(|| panic!("walk should terminate with files, got {payload:?}"))
+        .iter()
+        .map(|file| {
+            file.get("path")
+                .and_then(|p| p.as_str())
+                .expect("path")
+                .to_string()
+        })
+        .collect();
+    paths.sort();
+
+    let canonical_root = root.canonicalize().expect("canonical root");
+    assert_eq!(
+        paths,
+        vec![
+            canonical_root
+                .join("a/one.txt")
+                .to_string_lossy()
+                .to_string(),
+            canonical_root
+                .join("b/two.txt")
+                .to_string_lossy()
+                .to_string(),
+        ],
+        "each real file is listed once and the loop is skipped"
+    );
+
+    harness.shutdown().await;
+}
+
 // FakePermissionAgentTransport for permission gating tests
 
 struct FakePermissionAgentState {
@@ -2030,6 +8901,8 @@ struct FakePermissionAgentState {
     new_session_response: acp::NewSessionResponse,
     permission_calls: Vec<acp::RequestPermissionRequest>,
     permission_response: Option<acp::RequestPermissionResponse>,
+    hold_permission_responses: bool,
+    permission_delay: Option<Duration>,
 }
 
 #[derive(Clone)]
@@ -2052,6 +8925,8 @@ impl FakePermissionAgentTransport {
                 },
                 permission_calls: Vec::new(),
                 permission_response: None,
+                hold_permission_responses: false,
+                permission_delay: None,
             })),
         }
     }
@@ -2071,6 +8946,26 @@ impl FakePermissionAgentTransport {
         std::mem::take(&mut state.permission_calls)
     }
 
+    async fn hold_permission_responses(&self) {
+        let mut state = self.state.lock().await;
+        state.hold_permission_responses = true;
+    }
+
+    async fn delay_permission_responses(&self, delay: Duration) {
+        let mut state = self.state.lock().await;
+        state.permission_delay = Some(delay);
+    }
+
+    async fn wait_for_permission_call(&self) {
+        timeout(TEST_TIMEOUT, async {
+            while self.state.lock().await.permission_calls.is_empty() {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("permission request never reached the agent");
+    }
+
     async fn configure_permission_response(&self, response: acp::RequestPermissionResponse) {
         let mut state = self.state.lock().await;
         state.permission_response = Some(response);
@@ -2125,6 +9020,17 @@ impl AgentTransport for FakePermissionAgentTransport {
         Box::pin(async move {
             let mut guard = state.lock().await;
             guard.permission_calls.push(request);
+            if guard.hold_permission_responses {
+                // Simulates a human who never answers the prompt.
+                drop(guard);
+                return std::future::pending().await;
+            }
+            if let Some(delay) = guard.permission_delay {
+                // Simulates a human taking a moment to answer.
+                drop(guard);
+                sleep(delay).await;
+                guard = state.lock().await;
+            }
             match guard.permission_response.clone() {
                 Some(response) => Ok(response),
                 None => Err(AgentTransportError::Internal(
@@ -2290,11 +9196,7 @@ async fn fs_write_text_file_rejects_on_permission_deny() {
         .get("code")
         .and_then(|c| c.as_i64())
         .expect("error should have numeric code");
-    // Should be permission denied, not method not found
-    assert_ne!(
-        error_code, -32601,
-        "should be permission denied error, not method not found"
-    );
+    assert_eq!(error_code, -32010, "a one-off denial has its own code");
 
     // Verify permission was requested before denial
     let permission_calls = agent.take_permission_calls().await;
@@ -2368,11 +9270,7 @@ async fn fs_write_text_file_handles_permission_cancellation() {
         .get("code")
         .and_then(|c| c.as_i64())
         .expect("error should have numeric code");
-    // Should be cancellation error, not method not found
-    assert_ne!(
-        error_code, -32601,
-        "should be cancellation error, not method not found"
-    );
+    assert_eq!(error_code, -32012, "a cancelled prompt has its own code");
 
     // Verify permission was requested before cancellation
     let permission_calls = agent.take_permission_calls().await;
@@ -2599,11 +9497,7 @@ async fn fs_write_text_file_permission_flow_with_reject_always() {
         .get("code")
         .and_then(|c| c.as_i64())
         .expect("error should have numeric code");
-    // Should be permission denied, not method not found
-    assert_ne!(
-        error_code, -32601,
-        "should be permission denied error, not method not found"
-    );
+    assert_eq!(error_code, -32011, "a standing denial has its own code");
 
     // Verify permission was requested and includes reject_always option
     let permission_calls = agent.take_permission_calls().await;
@@ -2885,9 +9779,14 @@ async fn fs_write_text_file_caches_reject_always_permission() {
     let message = next_message(&mut ws).await;
     let payload = parse_json(&message);
     assert_eq!(payload.get("id"), Some(&json!("write-reject-cache-2")));
-    let _error = payload
+    let error = payload
         .get("error")
         .expect("second write should fail immediately due to cached reject_always");
+    assert_eq!(
+        error.get("code"),
+        Some(&json!(-32011)),
+        "a cached denial reports the standing-denial code"
+    );
 
     // Verify NO additional permission requests were made
     let permission_calls = agent.take_permission_calls().await;